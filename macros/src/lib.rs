@@ -0,0 +1,280 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Procedural macros for the `java-properties` crate.
+//!
+//! These are re-exported by `java-properties` when its `macros` feature is enabled, and should be
+//! used from there.
+//!
+//! A procedural macro cannot depend on the crate that re-exports it, so this crate carries its own
+//! copy of the parser.  It follows the parser in `java-properties` exactly; the tests in
+//! `java-properties` check that the two agree.
+
+#![warn(missing_docs)]
+
+use encoding_rs::Encoding;
+use encoding_rs::WINDOWS_1252;
+use proc_macro::TokenStream;
+use quote::quote;
+use regex_lite::Regex;
+use std::path::PathBuf;
+use syn::parse::Parse;
+use syn::parse::ParseStream;
+use syn::parse_macro_input;
+use syn::Ident;
+use syn::LitStr;
+use syn::Token;
+
+struct Args {
+    path: LitStr,
+    encoding: Option<LitStr>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        let mut encoding = None;
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let name: Ident = input.parse()?;
+            if name != "encoding" {
+                return Err(syn::Error::new(name.span(), "expected `encoding`"));
+            }
+            input.parse::<Token![=]>()?;
+            encoding = Some(input.parse()?);
+            input.parse::<Option<Token![,]>>()?;
+        }
+        Ok(Args { path, encoding })
+    }
+}
+
+/// Parses a properties file at compile time and expands to a `&'static [(&'static str, &'static str)]`.
+///
+/// See the documentation in `java-properties` for details.
+#[proc_macro]
+pub fn include_properties(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as Args);
+    match expand(&args) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(args: &Args) -> syn::Result<proc_macro2::TokenStream> {
+    let encoding = match &args.encoding {
+        None => WINDOWS_1252,
+        Some(label) => Encoding::for_label(label.value().as_bytes()).ok_or_else(|| {
+            syn::Error::new(
+                label.span(),
+                format!("unknown encoding: {:?}", label.value()),
+            )
+        })?,
+    };
+    let mut path = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default());
+    path.push(args.path.value());
+    let bytes = std::fs::read(&path).map_err(|e| {
+        syn::Error::new(
+            args.path.span(),
+            format!("couldn't read {}: {}", path.display(), e),
+        )
+    })?;
+    let (text, _, _) = encoding.decode(&bytes);
+    let pairs = parse(&text).map_err(|(line_number, message)| {
+        syn::Error::new(
+            args.path.span(),
+            format!(
+                "{}: {} (line_number = {})",
+                path.display(),
+                message,
+                line_number
+            ),
+        )
+    })?;
+    let keys = pairs.iter().map(|(k, _)| k);
+    let values = pairs.iter().map(|(_, v)| v);
+    let path = path.to_string_lossy();
+    Ok(quote! {
+        {
+            // Makes the compiler rebuild when the file changes.
+            const _: &[u8] = ::core::include_bytes!(#path);
+            &[#((#keys, #values)),*]
+        }
+    })
+}
+
+/// Parses the decoded contents of a properties file.
+///
+/// Each key appears once, at the position of its first occurrence, with the value of its last occurrence.
+fn parse(text: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+    let line_re = Regex::new(
+        r"(?x)
+      ^
+      [\x20\t\r\n\x0c]*
+      (?:
+        [\x23!]
+        [\x20\t\r\n\x0c]*
+        (.*?)
+        [\x20\t\r\n\x0c]*
+      |
+        (
+          (?:[^\\:=\x20\t\r\n\x0c]|\\.)*
+          (?:\\$)?
+        )
+        (?:
+          (?:
+            [\x20\t\r\n\x0c]*[:=][\x20\t\r\n\x0c]*
+          |
+            [\x20\t\r\n\x0c]+
+          )
+          (
+            (?:[^\\]|\\.)*?
+            (?:\\$)?
+          )
+        )?
+      )
+      $
+    ",
+    )
+    .unwrap();
+    let comment_re = Regex::new("^[ \t\r\n\x0c]*[#!]").unwrap();
+
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    let mut natural_lines = natural_lines(text).into_iter();
+    while let Some((line_number, first)) = natural_lines.next() {
+        let mut logical = first.to_string();
+        if !comment_re.is_match(first) {
+            let mut line = first;
+            while count_ending_backslashes(line) % 2 == 1 {
+                logical.pop();
+                match natural_lines.next() {
+                    Some((_, next)) => {
+                        logical.push_str(next.trim_start());
+                        line = next;
+                    }
+                    // Like the streaming parser, a continuation at the end of input ends the line.
+                    None => break,
+                }
+            }
+        }
+        let c = line_re
+            .captures(&logical)
+            .expect("line pattern matches all strings");
+        if c.get(1).is_some() {
+            continue;
+        }
+        let key = c.get(2).map_or("", |m| m.as_str());
+        let value = match c.get(3) {
+            Some(m) => m.as_str(),
+            None if !key.is_empty() => "",
+            None => continue,
+        };
+        let key = unescape(key, line_number)?;
+        let value = unescape(value, line_number)?;
+        match pairs.iter_mut().find(|(k, _)| *k == key) {
+            Some(pair) => pair.1 = value,
+            None => pairs.push((key, value)),
+        }
+    }
+    Ok(pairs)
+}
+
+fn natural_lines(text: &str) -> Vec<(usize, &str)> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(['\r', '\n']) {
+        lines.push((lines.len() + 1, &rest[..i]));
+        let len = if rest[i..].starts_with("\r\n") { 2 } else { 1 };
+        rest = &rest[i + len..];
+    }
+    // Like the streaming parser, the text after the last line terminator is always a line.
+    lines.push((lines.len() + 1, rest));
+    lines
+}
+
+fn count_ending_backslashes(s: &str) -> usize {
+    s.chars().rev().take_while(|&c| c == '\\').count()
+}
+
+fn unescape_low_surrogate(iter: &mut std::str::Chars) -> Option<u16> {
+    if iter.next() != Some('\\') || iter.next() != Some('u') {
+        return None;
+    }
+    let digits: String = iter.take(4).collect();
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(&digits, 16)
+        .ok()
+        .filter(|x| (0xDC00..=0xDFFF).contains(x))
+}
+
+fn unescape(s: &str, line_number: usize) -> Result<String, (usize, String)> {
+    let mut buf = String::new();
+    let mut iter = s.chars();
+    while let Some(c) = iter.next() {
+        if c != '\\' {
+            buf.push(c);
+            continue;
+        }
+        match iter.next() {
+            Some('t') => buf.push('\t'),
+            Some('n') => buf.push('\n'),
+            Some('f') => buf.push('\x0c'),
+            Some('r') => buf.push('\r'),
+            Some('u') => {
+                let digits: String = iter.by_ref().take(4).collect();
+                if digits.chars().count() < 4 {
+                    return Err((
+                        line_number,
+                        "Malformed \\uxxxx encoding: not enough digits.".to_string(),
+                    ));
+                }
+                let val = u16::from_str_radix(&digits, 16).map_err(|_| {
+                    (
+                        line_number,
+                        "Malformed \\uxxxx encoding: not hex.".to_string(),
+                    )
+                })?;
+                let code_point = match val {
+                    0xD800..=0xDBFF => {
+                        let mut lookahead = iter.clone();
+                        match unescape_low_surrogate(&mut lookahead) {
+                            Some(low) => {
+                                iter = lookahead;
+                                0x10000
+                                    + ((u32::from(val) - 0xD800) << 10)
+                                    + (u32::from(low) - 0xDC00)
+                            }
+                            None => {
+                                return Err((
+                                    line_number,
+                                    "Malformed \\uxxxx encoding: unpaired surrogate.".to_string(),
+                                ))
+                            }
+                        }
+                    }
+                    0xDC00..=0xDFFF => {
+                        return Err((
+                            line_number,
+                            "Malformed \\uxxxx encoding: unpaired surrogate.".to_string(),
+                        ))
+                    }
+                    _ => u32::from(val),
+                };
+                match std::char::from_u32(code_point) {
+                    Some(c) => buf.push(c),
+                    None => {
+                        return Err((
+                            line_number,
+                            "Malformed \\uxxxx encoding: invalid character.".to_string(),
+                        ))
+                    }
+                }
+            }
+            Some(c) => buf.push(c),
+            None => {
+                // Java replaces a dangling backslash with a NUL byte.
+                buf.push('\x00');
+                break;
+            }
+        }
+    }
+    Ok(buf)
+}
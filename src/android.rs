@@ -0,0 +1,169 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Support for Android `build.prop` and system property files.
+//!
+//! These files hold one `key=value` assignment per line, with `#` comments.  Unlike Java properties
+//! files, there are no backslash escapes or line continuations, `=` is the only separator, and
+//! spaces inside values are kept literally.  Whitespace around keys and values is ignored.
+//!
+//! [`PropertiesIter`](crate::PropertiesIter) and [`PropertiesWriter`](crate::PropertiesWriter)
+//! handle these files when set to [`Dialect::AndroidProp`]; [`read_build_prop`] and
+//! [`write_build_prop`] cover the common cases.
+
+use crate::sanitize_unescaped;
+use crate::Dialect;
+use crate::KVSeparator;
+use crate::Line;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Write;
+
+/// Parses a line of a `build.prop` file.  Returns `None` for blank lines.
+pub(crate) fn parse_line(line: &str, line_number: usize) -> Result<Option<Line>, PropertiesError> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if let Some(comment) = line.strip_prefix('#') {
+        return Ok(Some(Line::mk_comment(
+            line_number,
+            comment.trim_start().to_string(),
+        )));
+    }
+    match line.find('=') {
+        Some(i) if !line[..i].trim_end().is_empty() => Ok(Some(Line::mk_pair(
+            line_number,
+            line[..i].trim_end().to_string(),
+            line[i + 1..].trim_start().to_string(),
+            KVSeparator::Equals,
+        ))),
+        Some(_) => Err(PropertiesError::new(
+            PropertiesErrorKind::Syntax,
+            "Missing key in build.prop assignment",
+            None,
+            Some(line_number),
+        )),
+        None => Err(PropertiesError::new(
+            PropertiesErrorKind::Syntax,
+            "Missing '=' in build.prop assignment",
+            None,
+            Some(line_number),
+        )),
+    }
+}
+
+/// Checks that a key can be written to a `build.prop` file and read back unchanged.
+pub(crate) fn check_key(key: &str) -> Result<(), PropertiesError> {
+    if key.is_empty()
+        || key.starts_with('#')
+        || key != key.trim()
+        || key.contains(['=', '\r', '\n'])
+    {
+        return Err(PropertiesError::new(
+            PropertiesErrorKind::InvalidKey,
+            format!("Key {:?} can't be written to a build.prop file", key),
+            None,
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// Replaces the characters of a key which [`check_key`] rejects with `_`.
+pub(crate) fn sanitize_key(key: &str) -> String {
+    sanitize_unescaped(key, |c| matches!(c, '=' | '\r' | '\n'), &['#'])
+}
+
+/// Checks that a value can be written to a `build.prop` file and read back unchanged.
+pub(crate) fn check_value(key: &str, value: &str) -> Result<(), PropertiesError> {
+    if value != value.trim() || value.contains(['\r', '\n']) {
+        return Err(PropertiesError::new(
+            PropertiesErrorKind::InvalidValue,
+            format!(
+                "Value for key {:?} can't be written to a build.prop file",
+                key
+            ),
+            None,
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a UTF-8 `build.prop` file into a hash map.
+///
+/// ```
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let input = "# begin build properties\nro.product.model=Pixel 7 Pro\nro.build.id=C:\\x\n";
+/// let map = java_properties::android::read_build_prop(input.as_bytes())?;
+/// assert_eq!(map["ro.product.model"], "Pixel 7 Pro");
+/// assert_eq!(map["ro.build.id"], "C:\\x");
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_build_prop<R: Read>(input: R) -> Result<HashMap<String, String>, PropertiesError> {
+    Dialect::AndroidProp.read_utf8(input)
+}
+
+/// Writes key/value pairs to a UTF-8 `build.prop` file, in the order given.
+pub fn write_build_prop<W, I, K, V>(writer: W, pairs: I) -> Result<(), PropertiesError>
+where
+    W: Write,
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    Dialect::AndroidProp.write_utf8(writer, pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_line;
+    use super::read_build_prop;
+    use super::write_build_prop;
+    use crate::check_parsed_lines;
+    use crate::parsed_comment;
+    use crate::parsed_pair;
+
+    #[test]
+    fn parse() {
+        let data = [
+            ("", None),
+            ("  # c ", parsed_comment("c")),
+            ("a=1", parsed_pair("a", "1")),
+            (" a . b = x  y ", parsed_pair("a . b", "x  y")),
+            ("a=", parsed_pair("a", "")),
+            ("a=b=c", parsed_pair("a", "b=c")),
+            ("a=\\u0041\\", parsed_pair("a", "\\u0041\\")),
+            ("a=x # y", parsed_pair("a", "x # y")),
+            ("a:b", Some(Err(()))),
+            ("=1", Some(Err(()))),
+        ];
+        check_parsed_lines(&data, |input| parse_line(input, 1));
+    }
+
+    #[test]
+    fn round_trip() {
+        let pairs = [
+            ("ro.product.model", "Pixel 7 Pro"),
+            ("persist.sys.empty", ""),
+            ("ro.path", "C:\\dir\\u0041 #1"),
+        ];
+        let mut buf = Vec::new();
+        write_build_prop(&mut buf, pairs.iter().cloned()).unwrap();
+        assert_eq!(
+            String::from_utf8(buf.clone()).unwrap(),
+            "ro.product.model=Pixel 7 Pro\npersist.sys.empty=\nro.path=C:\\dir\\u0041 #1\n"
+        );
+        let map = read_build_prop(&buf[..]).unwrap();
+        for (k, v) in pairs.iter() {
+            assert_eq!(map[*k], *v);
+        }
+
+        assert!(write_build_prop(Vec::new(), vec![("a=b", "1")]).is_err());
+        assert!(write_build_prop(Vec::new(), vec![(" a", "1")]).is_err());
+        assert!(write_build_prop(Vec::new(), vec![("a", "1 ")]).is_err());
+        assert!(write_build_prop(Vec::new(), vec![("a", "1\n2")]).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Reading and writing properties files asynchronously with tokio.
+//!
+//! [`AsyncPropertiesIter`] and [`AsyncPropertiesWriter`] mirror [`PropertiesIter`](crate::PropertiesIter)
+//! and [`PropertiesWriter`], but work with `tokio::io::AsyncRead` and `tokio::io::AsyncWrite`.
+//!
+//! This module is only available with the `tokio` feature.
+//!
+//! ```
+//! use java_properties::AsyncPropertiesIter;
+//! use java_properties::AsyncPropertiesWriter;
+//!
+//! # async fn run() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let mut buf = Vec::new();
+//! let mut writer = AsyncPropertiesWriter::new(&mut buf);
+//! writer.write("greeting", "hello world").await?;
+//! writer.finish().await?;
+//!
+//! let mut pairs = Vec::new();
+//! AsyncPropertiesIter::new(&buf[..])
+//!     .read_into(|k, v| pairs.push((k, v)))
+//!     .await?;
+//! assert_eq!(pairs, [("greeting".to_string(), "hello world".to_string())]);
+//! # Ok(())
+//! # }
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(run()).unwrap();
+//! ```
+
+use crate::count_ending_backslashes;
+use crate::is_comment_line;
+use crate::parse_line;
+use crate::parsed_line_to_line;
+use crate::Line;
+use crate::LineContent;
+use crate::LineEnding;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use crate::PropertiesWriter;
+use encoding_rs::CoderResult;
+use encoding_rs::Decoder;
+use encoding_rs::Encoding;
+use encoding_rs::WINDOWS_1252;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+
+const BUFFER_SIZE: usize = 8192;
+
+/// Parses a properties file from an asynchronous stream and iterates over its contents.
+///
+/// Parsing follows the same rules as a [`PropertiesIter`](crate::PropertiesIter) with the default
+/// options.
+/// Note that once `next` returns an error, the result of further calls is undefined.
+pub struct AsyncPropertiesIter<R: AsyncRead + Unpin> {
+    reader: R,
+    decoder: Decoder,
+    buffer: Vec<u8>,
+    // Decoded text which has not been split into natural lines yet.
+    text: String,
+    eof: bool,
+    last_line_read: bool,
+    line_count: usize,
+    // The logical line being assembled from continuation lines, and its line number (0 if none).
+    logical: String,
+    logical_line_number: usize,
+}
+
+impl<R: AsyncRead + Unpin> AsyncPropertiesIter<R> {
+    /// Parses properties from the given stream.
+    pub fn new(input: R) -> Self {
+        Self::new_with_encoding(input, WINDOWS_1252)
+    }
+
+    /// Parses properties from the given stream in the given encoding.
+    /// Note that the Java properties specification specifies ISO-8859-1 encoding
+    /// (a.k.a. windows-1252) for properties files; in most cases, `new` should be
+    /// called instead.
+    pub fn new_with_encoding(input: R, encoding: &'static Encoding) -> Self {
+        AsyncPropertiesIter {
+            reader: input,
+            decoder: encoding.new_decoder(),
+            buffer: vec![0; BUFFER_SIZE],
+            text: String::new(),
+            eof: false,
+            last_line_read: false,
+            line_count: 0,
+            logical: String::new(),
+            logical_line_number: 0,
+        }
+    }
+
+    /// Returns the next line, or `None` at the end of the input.
+    pub async fn next(&mut self) -> Option<Result<Line, PropertiesError>> {
+        loop {
+            if let Some((line_number, line)) = self.next_logical_line() {
+                if let Some(parsed_line) = parse_line(&line) {
+                    return Some(parsed_line_to_line(parsed_line, line_number));
+                }
+                continue;
+            }
+            if self.eof {
+                return None;
+            }
+            if let Err(e) = self.fill().await {
+                return Some(Err(e));
+            }
+        }
+    }
+
+    /// Calls `f` for each key/value pair.
+    ///
+    /// Line numbers and comments are ignored.
+    /// On the first error, the error is returned.
+    /// Note that `f` may have already been called at this point.
+    pub async fn read_into<F: FnMut(String, String)>(
+        &mut self,
+        mut f: F,
+    ) -> Result<(), PropertiesError> {
+        while let Some(line) = self.next().await {
+            if let LineContent::KVPair(key, value) = line?.consume_content() {
+                f(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    async fn fill(&mut self) -> Result<(), PropertiesError> {
+        let n = self.reader.read(&mut self.buffer).await.map_err(|e| {
+            PropertiesError::new(
+                PropertiesErrorKind::Io,
+                "I/O error",
+                Some(Box::new(e)),
+                Some(self.line_count + 1),
+            )
+        })?;
+        self.eof = n == 0;
+        if let Some(needed) = self.decoder.max_utf8_buffer_length(n) {
+            self.text.reserve(needed);
+        }
+        let (result, _, _) =
+            self.decoder
+                .decode_to_string(&self.buffer[..n], &mut self.text, self.eof);
+        // With enough space reserved, the whole input is always consumed.
+        debug_assert_eq!(result, CoderResult::InputEmpty);
+        Ok(())
+    }
+
+    /// Splits the next natural line off the decoded text, if a complete one is available.
+    fn next_natural_line(&mut self) -> Option<(usize, String)> {
+        match self.text.find(['\r', '\n']) {
+            Some(i) => {
+                let len = if self.text[i..].starts_with("\r\n") {
+                    2
+                } else if &self.text[i..] == "\r" && !self.eof {
+                    // This may be the first half of a CRLF.
+                    return None;
+                } else {
+                    1
+                };
+                let line = self.text[..i].to_string();
+                self.text.drain(..i + len);
+                self.line_count += 1;
+                Some((self.line_count, line))
+            }
+            None if self.eof && !self.last_line_read => {
+                self.last_line_read = true;
+                self.line_count += 1;
+                Some((self.line_count, std::mem::take(&mut self.text)))
+            }
+            None => None,
+        }
+    }
+
+    /// Joins natural lines into the next logical line, if a complete one is available.
+    /// Like `PropertiesIter`, a continuation at the end of the input ends the logical line.
+    fn next_logical_line(&mut self) -> Option<(usize, String)> {
+        while let Some((line_number, line)) = self.next_natural_line() {
+            let first = self.logical_line_number == 0;
+            if first {
+                self.logical_line_number = line_number;
+                self.logical.push_str(&line);
+            } else {
+                self.logical.push_str(line.trim_start());
+            }
+            if (first && is_comment_line(&line)) || count_ending_backslashes(&line) % 2 != 1 {
+                let line_number = std::mem::take(&mut self.logical_line_number);
+                return Some((line_number, std::mem::take(&mut self.logical)));
+            }
+            self.logical.pop();
+        }
+        if self.last_line_read && self.logical_line_number != 0 {
+            let line_number = std::mem::take(&mut self.logical_line_number);
+            return Some((line_number, std::mem::take(&mut self.logical)));
+        }
+        None
+    }
+}
+
+/// Writes to a properties file on an asynchronous stream.
+///
+/// Output is identical to [`PropertiesWriter`].
+pub struct AsyncPropertiesWriter<W: AsyncWrite + Unpin> {
+    // Formats and encodes lines into memory, which are then written to `writer`.
+    inner: PropertiesWriter<Vec<u8>>,
+    writer: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncPropertiesWriter<W> {
+    /// Writes to the given stream.
+    pub fn new(writer: W) -> Self {
+        Self::new_with_encoding(writer, WINDOWS_1252)
+    }
+
+    /// Writes to the given stream in the given encoding.
+    /// Note that the Java properties specification specifies ISO-8859-1 encoding
+    /// for properties files; in most cases, `new` should be called instead.
+    pub fn new_with_encoding(writer: W, encoding: &'static Encoding) -> Self {
+        AsyncPropertiesWriter {
+            inner: PropertiesWriter::new_with_encoding(Vec::new(), encoding),
+            writer,
+        }
+    }
+
+    async fn write_buffered(&mut self) -> Result<(), PropertiesError> {
+        let buffer = &mut self.inner.writer.writer;
+        self.writer.write_all(buffer).await?;
+        buffer.clear();
+        Ok(())
+    }
+
+    /// Writes a comment to the file.
+    pub async fn write_comment(&mut self, comment: &str) -> Result<(), PropertiesError> {
+        self.inner.write_comment(comment)?;
+        self.write_buffered().await
+    }
+
+    /// Writes a key/value pair to the file.
+    pub async fn write(&mut self, key: &str, value: &str) -> Result<(), PropertiesError> {
+        self.inner.write(key, value)?;
+        self.write_buffered().await
+    }
+
+    /// Writes a key with an empty value and no separator.
+    pub async fn write_key_only(&mut self, key: &str) -> Result<(), PropertiesError> {
+        self.inner.write_key_only(key)?;
+        self.write_buffered().await
+    }
+
+    /// Flushes the underlying stream.
+    pub async fn flush(&mut self) -> Result<(), PropertiesError> {
+        self.write_buffered().await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Sets the comment prefix.  See [`PropertiesWriter::set_comment_prefix`].
+    pub fn set_comment_prefix(&mut self, prefix: &str) -> Result<(), PropertiesError> {
+        self.inner.set_comment_prefix(prefix)
+    }
+
+    /// Sets the key/value separator.  See [`PropertiesWriter::set_kv_separator`].
+    pub fn set_kv_separator(&mut self, separator: &str) -> Result<(), PropertiesError> {
+        self.inner.set_kv_separator(separator)
+    }
+
+    /// Sets the line ending.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.inner.set_line_ending(line_ending);
+    }
+
+    /// Finishes the encoding and flushes the underlying stream.
+    pub async fn finish(&mut self) -> Result<(), PropertiesError> {
+        self.inner.finish()?;
+        self.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncPropertiesIter;
+    use super::AsyncPropertiesWriter;
+    use crate::LineEnding;
+    use crate::PropertiesIter;
+    use crate::PropertiesWriter;
+    use encoding_rs::UTF_16LE;
+    use encoding_rs::UTF_8;
+    use std::pin::Pin;
+    use std::task::Context;
+    use std::task::Poll;
+    use tokio::io::AsyncRead;
+    use tokio::io::ReadBuf;
+
+    /// Returns one byte per read, to exercise lines split across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            if let Some((&first, rest)) = self.0.split_first() {
+                buf.put_slice(&[first]);
+                self.0 = rest;
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_sync_parser() {
+        let inputs: &[&[u8]] = &[
+            b"",
+            b"a=b",
+            b"a=b\r\nc=d\\\r\n   e\r# comment\\\nf g\n\n! x\ny=\\\n",
+            b"a=b\rc\\\\\rd=\\u00e9\xe9\r",
+            b"x=\\u00zz\n",
+        ];
+        for &input in inputs {
+            let expected: Vec<_> = PropertiesIter::new(input)
+                .map(|line| line.map_err(|e| e.to_string()))
+                .collect();
+            for chunked in [false, true] {
+                let mut actual = Vec::new();
+                if chunked {
+                    let mut iter = AsyncPropertiesIter::new(Trickle(input));
+                    while let Some(line) = iter.next().await {
+                        actual.push(line.map_err(|e| e.to_string()));
+                    }
+                } else {
+                    let mut iter = AsyncPropertiesIter::new(input);
+                    while let Some(line) = iter.next().await {
+                        actual.push(line.map_err(|e| e.to_string()));
+                    }
+                }
+                assert_eq!(actual, expected, "{:?}", input);
+            }
+        }
+
+        let input: Vec<u8> = "a=\u{1F41E}\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let mut pairs = Vec::new();
+        AsyncPropertiesIter::new_with_encoding(Trickle(&input), UTF_16LE)
+            .read_into(|k, v| pairs.push((k, v)))
+            .await
+            .unwrap();
+        assert_eq!(pairs, [("a".to_string(), "\u{1F41E}".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn matches_sync_writer() {
+        let mut expected = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new_with_encoding(&mut expected, UTF_8);
+            writer.set_line_ending(LineEnding::CRLF);
+            writer.set_kv_separator(": ").unwrap();
+            writer.write_comment("header").unwrap();
+            writer.write("a b", "\u{e9}").unwrap();
+            writer.write_key_only("c").unwrap();
+            writer.finish().unwrap();
+        }
+        let mut actual = Vec::new();
+        let mut writer = AsyncPropertiesWriter::new_with_encoding(&mut actual, UTF_8);
+        writer.set_line_ending(LineEnding::CRLF);
+        writer.set_kv_separator(": ").unwrap();
+        assert!(writer.set_comment_prefix("x").is_err());
+        writer.write_comment("header").await.unwrap();
+        writer.write("a b", "\u{e9}").await.unwrap();
+        writer.write_key_only("c").await.unwrap();
+        writer.finish().await.unwrap();
+        assert_eq!(actual, expected);
+    }
+}
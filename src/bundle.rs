@@ -0,0 +1,450 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Checks for families of localized resource bundles.
+//!
+//! A bundle family consists of a default file (e.g. `messages.properties`) plus one file per locale
+//! (e.g. `messages_fr.properties`), all sharing the same keys.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A localized value which is identical to the default locale's value.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
+pub struct UntranslatedEntry {
+    key: String,
+    value: String,
+}
+
+impl UntranslatedEntry {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the value shared by the default and the localized bundle.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// Untranslated-value statistics for a single locale.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LocaleReport {
+    locale: String,
+    compared: usize,
+    untranslated: Vec<UntranslatedEntry>,
+}
+
+impl LocaleReport {
+    /// Returns the name of the locale.
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// Returns the number of keys which were compared against the default bundle.
+    ///
+    /// Keys missing from either bundle and values without any alphabetic characters are not compared.
+    pub fn compared_count(&self) -> usize {
+        self.compared
+    }
+
+    /// Returns the number of values identical to the default bundle.
+    pub fn untranslated_count(&self) -> usize {
+        self.untranslated.len()
+    }
+
+    /// Returns the entries identical to the default bundle, sorted by key.
+    pub fn untranslated(&self) -> &[UntranslatedEntry] {
+        &self.untranslated
+    }
+
+    /// Returns the fraction of compared values which differ from the default bundle.
+    ///
+    /// Returns 1.0 if no values were compared.
+    pub fn translated_ratio(&self) -> f64 {
+        if self.compared == 0 {
+            1.0
+        } else {
+            1.0 - self.untranslated.len() as f64 / self.compared as f64
+        }
+    }
+}
+
+/// The result of [`find_untranslated`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct UntranslatedReport {
+    locales: Vec<LocaleReport>,
+}
+
+impl UntranslatedReport {
+    /// Returns the per-locale reports, in the order the locales were given.
+    pub fn locales(&self) -> &[LocaleReport] {
+        &self.locales
+    }
+
+    /// Returns the report for the given locale, if it was checked.
+    pub fn locale(&self, locale: &str) -> Option<&LocaleReport> {
+        self.locales.iter().find(|r| r.locale == locale)
+    }
+
+    /// Returns the total number of untranslated values across all locales.
+    pub fn total_untranslated(&self) -> usize {
+        self.locales
+            .iter()
+            .map(LocaleReport::untranslated_count)
+            .sum()
+    }
+
+    /// Returns true if no locale contains untranslated values.
+    pub fn is_clean(&self) -> bool {
+        self.total_untranslated() == 0
+    }
+}
+
+// Values such as "42", "{0}", or "--" are legitimately identical in every locale.
+fn is_translatable(value: &str) -> bool {
+    value.chars().any(char::is_alphabetic)
+}
+
+/// Flags localized values which are identical to the default bundle's value, and are therefore
+/// likely to be untranslated copies.
+///
+/// `locales` yields pairs of locale names and their bundles.
+pub fn find_untranslated<'a, I>(default: &HashMap<String, String>, locales: I) -> UntranslatedReport
+where
+    I: IntoIterator<Item = (&'a str, &'a HashMap<String, String>)>,
+{
+    let mut report = UntranslatedReport::default();
+    for (locale, bundle) in locales {
+        let mut compared = 0;
+        let mut untranslated = Vec::new();
+        for (key, value) in bundle {
+            match default.get(key) {
+                Some(default_value) if is_translatable(default_value) => {
+                    compared += 1;
+                    if value == default_value {
+                        untranslated.push(UntranslatedEntry {
+                            key: key.clone(),
+                            value: value.clone(),
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+        untranslated.sort();
+        report.locales.push(LocaleReport {
+            locale: locale.to_string(),
+            compared,
+            untranslated,
+        });
+    }
+    report
+}
+
+/// The kind of a placeholder found in a message value.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
+pub enum PlaceholderKind {
+    /// A `java.text.MessageFormat` argument such as `{0}` or `{0,number,#.##}`.
+    ///
+    /// The format contains everything after the first comma, if present.
+    Indexed {
+        /// The argument index.
+        index: usize,
+        /// The format type and style, e.g. `number,#.##`.
+        format: Option<String>,
+    },
+
+    /// A named argument such as `{name}`.
+    Named(String),
+
+    /// A `java.util.Formatter` (printf-style) specifier such as `%s` or `%1$d`.
+    Printf {
+        /// The explicit 1-based argument index, e.g. 1 for `%1$d`.
+        position: Option<usize>,
+        /// The conversion, e.g. `d` or `tY`.
+        conversion: String,
+    },
+}
+
+/// A placeholder found in a message value.
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct Placeholder {
+    span: Range<usize>,
+    text: String,
+    kind: PlaceholderKind,
+}
+
+impl Placeholder {
+    /// Returns the byte range of the placeholder within the value.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// Returns the placeholder text exactly as it appears in the value.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the parsed placeholder.
+    pub fn kind(&self) -> &PlaceholderKind {
+        &self.kind
+    }
+}
+
+fn parse_brace_placeholder(body: &str) -> Option<PlaceholderKind> {
+    let (head, format) = match body.find(',') {
+        Some(i) => (&body[..i], Some(body[i + 1..].to_string())),
+        None => (body, None),
+    };
+    let head = head.trim();
+    if !head.is_empty() && head.chars().all(|c| c.is_ascii_digit()) {
+        return head
+            .parse()
+            .ok()
+            .map(|index| PlaceholderKind::Indexed { index, format });
+    }
+    let mut chars = head.chars();
+    match chars.next() {
+        Some(c) if format.is_none() && (c.is_alphabetic() || c == '_') => {
+            if chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '-') {
+                Some(PlaceholderKind::Named(head.to_string()))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// Parses a printf specifier starting just after the '%'.  Returns the specifier and its length.
+fn parse_printf_placeholder(s: &str) -> Option<(PlaceholderKind, usize)> {
+    // A '%' followed by a space is almost always prose, as in "100% sure", rather than the space
+    // flag.
+    if s.starts_with(' ') {
+        return None;
+    }
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    let digits = |i: usize| bytes[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let mut position = None;
+    let n = digits(i);
+    if n > 0 && bytes.get(i + n) == Some(&b'$') {
+        position = s[i..i + n].parse().ok();
+        i += n + 1;
+    } else if bytes.get(i) == Some(&b'<') {
+        i += 1;
+    }
+    while i < bytes.len() && b"-#+ 0,(".contains(&bytes[i]) {
+        i += 1;
+    }
+    i += digits(i);
+    if bytes.get(i) == Some(&b'.') {
+        let n = digits(i + 1);
+        if n == 0 {
+            return None;
+        }
+        i += n + 1;
+    }
+    let start = i;
+    match bytes.get(i) {
+        Some(b't') | Some(b'T') => match bytes.get(i + 1) {
+            Some(c) if c.is_ascii_alphabetic() => i += 2,
+            _ => return None,
+        },
+        Some(c) if c.is_ascii_alphabetic() => i += 1,
+        _ => return None,
+    }
+    let conversion = &s[start..i];
+    if conversion == "n" {
+        return None;
+    }
+    Some((
+        PlaceholderKind::Printf {
+            position,
+            conversion: conversion.to_string(),
+        },
+        i,
+    ))
+}
+
+/// Extracts the MessageFormat (`{0}`, `{name}`) and printf-style (`%s`, `%1$d`) placeholders
+/// from a value, in the order they appear.
+///
+/// MessageFormat quoting is honored for braces, so `'{0}'` is not a placeholder, while `%%` and
+/// `%n` are not reported since they do not consume arguments.  A `%` followed by a space is taken
+/// as literal text, so `100% sure` has no placeholders; this means specifiers starting with the
+/// space flag, such as `% d`, are not reported either.
+pub fn extract_placeholders(value: &str) -> Vec<Placeholder> {
+    let mut placeholders = Vec::new();
+    let mut quoted = false;
+    let mut iter = value.char_indices().peekable();
+    while let Some((i, c)) = iter.next() {
+        match c {
+            '\'' => {
+                if let Some(&(_, '\'')) = iter.peek() {
+                    iter.next();
+                } else {
+                    quoted = !quoted;
+                }
+            }
+            '{' if !quoted => {
+                let mut depth = 1;
+                let mut end = None;
+                for (j, c) in value[i + 1..].char_indices() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                end = Some(i + 1 + j);
+                                break;
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                if let Some(end) = end {
+                    if let Some(kind) = parse_brace_placeholder(&value[i + 1..end]) {
+                        placeholders.push(Placeholder {
+                            span: i..end + 1,
+                            text: value[i..end + 1].to_string(),
+                            kind,
+                        });
+                        while let Some(&(j, _)) = iter.peek() {
+                            if j > end {
+                                break;
+                            }
+                            iter.next();
+                        }
+                    }
+                }
+            }
+            '%' => {
+                if let Some(&(_, '%')) = iter.peek() {
+                    iter.next();
+                } else if let Some((kind, len)) = parse_printf_placeholder(&value[i + 1..]) {
+                    let end = i + 1 + len;
+                    placeholders.push(Placeholder {
+                        span: i..end,
+                        text: value[i..end].to_string(),
+                        kind,
+                    });
+                    while let Some(&(j, _)) = iter.peek() {
+                        if j >= end {
+                            break;
+                        }
+                        iter.next();
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    placeholders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::extract_placeholders;
+    use super::find_untranslated;
+    use super::PlaceholderKind;
+    use std::collections::HashMap;
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn untranslated() {
+        let default = map(&[
+            ("greeting", "Hello"),
+            ("farewell", "Goodbye"),
+            ("count", "{0}"),
+            ("ok", "OK"),
+        ]);
+        let fr = map(&[
+            ("greeting", "Bonjour"),
+            ("farewell", "Goodbye"),
+            ("count", "{0}"),
+            ("ok", "OK"),
+        ]);
+        let de = map(&[("greeting", "Hallo"), ("extra", "Extra")]);
+        let report = find_untranslated(&default, vec![("fr", &fr), ("de", &de)]);
+        assert_eq!(report.total_untranslated(), 2);
+        assert!(!report.is_clean());
+
+        let fr_report = report.locale("fr").unwrap();
+        assert_eq!(fr_report.compared_count(), 3);
+        let keys: Vec<&str> = fr_report.untranslated().iter().map(|e| e.key()).collect();
+        assert_eq!(keys, vec!["farewell", "ok"]);
+        assert!((fr_report.translated_ratio() - 1.0 / 3.0).abs() < 1e-9);
+
+        let de_report = report.locale("de").unwrap();
+        assert_eq!(de_report.compared_count(), 1);
+        assert_eq!(de_report.untranslated_count(), 0);
+        assert_eq!(de_report.translated_ratio(), 1.0);
+    }
+
+    #[test]
+    fn placeholders() {
+        fn kinds(value: &str) -> Vec<PlaceholderKind> {
+            extract_placeholders(value)
+                .into_iter()
+                .map(|p| p.kind().clone())
+                .collect()
+        }
+        fn printf(position: Option<usize>, conversion: &str) -> PlaceholderKind {
+            PlaceholderKind::Printf {
+                position,
+                conversion: conversion.to_string(),
+            }
+        }
+        assert_eq!(kinds(""), vec![]);
+        assert_eq!(kinds("plain text"), vec![]);
+        assert_eq!(
+            kinds("{0} and {1,number,#.##}"),
+            vec![
+                PlaceholderKind::Indexed {
+                    index: 0,
+                    format: None
+                },
+                PlaceholderKind::Indexed {
+                    index: 1,
+                    format: Some("number,#.##".to_string())
+                },
+            ]
+        );
+        assert_eq!(
+            kinds("Hi {name}!"),
+            vec![PlaceholderKind::Named("name".to_string())]
+        );
+        assert_eq!(kinds("'{0}' is quoted, '' is not"), vec![]);
+        assert_eq!(
+            kinds("it''s {0}"),
+            vec![PlaceholderKind::Indexed {
+                index: 0,
+                format: None
+            }]
+        );
+        assert_eq!(
+            kinds("%s has %1$d items, %-10.2f%% %n %tY"),
+            vec![
+                printf(None, "s"),
+                printf(Some(1), "d"),
+                printf(None, "f"),
+                printf(None, "tY"),
+            ]
+        );
+        assert_eq!(kinds("100% sure { not a placeholder }"), vec![]);
+        assert_eq!(kinds("% d, %+ d"), vec![printf(None, "d")]);
+
+        let placeholders = extract_placeholders("a {0} b %2$s");
+        assert_eq!(placeholders[0].span(), 2..5);
+        assert_eq!(placeholders[0].text(), "{0}");
+        assert_eq!(placeholders[1].span(), 8..12);
+        assert_eq!(placeholders[1].text(), "%2$s");
+    }
+}
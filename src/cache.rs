@@ -0,0 +1,195 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! A cache of parsed properties files.
+//!
+//! Build tooling often loads the same files many times.  A [`PropertiesCache`] remembers the parsed
+//! contents of each file along with its modification time and size, and only parses it again once
+//! either of those changes.
+
+use crate::PropertiesError;
+use crate::PropertiesIter;
+use crate::PropertiesWriter;
+use encoding_rs::Encoding;
+use encoding_rs::WINDOWS_1252;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Result<Self, PropertiesError> {
+        let metadata = fs::metadata(path)?;
+        Ok(Stamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    stamp: Stamp,
+    values: Arc<HashMap<String, String>>,
+}
+
+/// Memoizes parsed properties files by path.
+///
+/// A cached file is considered unchanged as long as its modification time and size are the same.
+/// File systems with coarse timestamps can miss a rewrite of the same size within the timestamp
+/// granularity; call [`invalidate`](Self::invalidate) after such writes, or write through the cache with
+/// [`store`](Self::store).
+///
+/// The cache can be shared between threads.
+#[derive(Debug)]
+pub struct PropertiesCache {
+    encoding: &'static Encoding,
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+}
+
+impl PropertiesCache {
+    /// Creates an empty cache which reads and writes files as ISO-8859-1.
+    pub fn new() -> Self {
+        Self::new_with_encoding(WINDOWS_1252)
+    }
+
+    /// Creates an empty cache which reads and writes files with the given encoding.
+    pub fn new_with_encoding(encoding: &'static Encoding) -> Self {
+        PropertiesCache {
+            encoding,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the contents of the file at `path`, parsing it only if it is not cached or has changed since it was cached.
+    pub fn load<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Arc<HashMap<String, String>>, PropertiesError> {
+        let path = path.as_ref();
+        let stamp = Stamp::of(path)?;
+        if let Some(entry) = self.entries.lock().unwrap().get(path) {
+            if entry.stamp == stamp {
+                return Ok(entry.values.clone());
+            }
+        }
+        let mut map = HashMap::new();
+        PropertiesIter::new_with_encoding(BufReader::new(File::open(path)?), self.encoding)
+            .read_into(|k, v| {
+                map.insert(k, v);
+            })?;
+        let values = Arc::new(map);
+        self.entries.lock().unwrap().insert(
+            path.to_path_buf(),
+            CacheEntry {
+                stamp,
+                values: values.clone(),
+            },
+        );
+        Ok(values)
+    }
+
+    /// Writes `map` to the file at `path` and caches it, so that the next [`load`](Self::load) doesn't read the file back.
+    pub fn store<P: AsRef<Path>>(
+        &self,
+        path: P,
+        map: &HashMap<String, String>,
+    ) -> Result<(), PropertiesError> {
+        let path = path.as_ref();
+        // If writing fails part way, the file no longer matches any cached contents.
+        self.invalidate(path);
+        let mut writer =
+            PropertiesWriter::new_with_encoding(BufWriter::new(File::create(path)?), self.encoding);
+        for (k, v) in map {
+            writer.write(k, v)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        let stamp = Stamp::of(path)?;
+        self.entries.lock().unwrap().insert(
+            path.to_path_buf(),
+            CacheEntry {
+                stamp,
+                values: Arc::new(map.clone()),
+            },
+        );
+        Ok(())
+    }
+
+    /// Forgets the cached contents of the file at `path`, if any.
+    pub fn invalidate<P: AsRef<Path>>(&self, path: P) {
+        self.entries.lock().unwrap().remove(path.as_ref());
+    }
+
+    /// Forgets all cached contents.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Returns the number of cached files.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns true if no files are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for PropertiesCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PropertiesCache;
+    use crate::test_dir;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn reuses_unchanged_files() {
+        let dir = test_dir();
+        let path = dir.path().join("a.properties");
+        fs::write(&path, "a=1\nb=2\n").unwrap();
+
+        let cache = PropertiesCache::new();
+        let first = cache.load(&path).unwrap();
+        assert_eq!(first.get("a").map(String::as_str), Some("1"));
+        let second = cache.load(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+
+        // A different size is noticed even if the timestamp is unchanged.
+        fs::write(&path, "a=10\nb=2\n").unwrap();
+        let third = cache.load(&path).unwrap();
+        assert_eq!(third.get("a").map(String::as_str), Some("10"));
+
+        cache.invalidate(&path);
+        assert!(cache.is_empty());
+        assert!(!Arc::ptr_eq(&third, &cache.load(&path).unwrap()));
+
+        let mut map = HashMap::new();
+        map.insert("c".to_string(), "3".to_string());
+        cache.store(&path, &map).unwrap();
+        assert_eq!(*cache.load(&path).unwrap(), map);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "c=3\n");
+
+        drop(dir);
+        assert!(cache.load(&path).is_err());
+    }
+}
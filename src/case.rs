@@ -0,0 +1,158 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Conversions between key naming styles.
+//!
+//! Keys are split into words at `.`, `_`, `-`, and whitespace, and at lower-to-upper case
+//! transitions (`poolSize`) and acronym boundaries (`HTTPServer`).  The words are then rejoined in
+//! the requested style.
+//!
+//! ```
+//! use java_properties::case::to_screaming_snake_case;
+//! assert_eq!(to_screaming_snake_case("db.poolSize"), "DB_POOL_SIZE");
+//! ```
+
+use std::collections::HashMap;
+
+/// A key naming style.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
+pub enum KeyCase {
+    /// Lowercase words separated by dots, e.g. `db.pool.size`.
+    Dot,
+    /// Uppercase words separated by underscores, e.g. `DB_POOL_SIZE`.
+    ScreamingSnake,
+    /// Lowercase words separated by hyphens, e.g. `db-pool-size`.
+    Kebab,
+    /// Words joined with the first letter of each word after the first capitalized, e.g. `dbPoolSize`.
+    Camel,
+}
+
+impl KeyCase {
+    /// Converts a key to this style.
+    pub fn convert(self, key: &str) -> String {
+        match self {
+            KeyCase::Dot => to_dot_case(key),
+            KeyCase::ScreamingSnake => to_screaming_snake_case(key),
+            KeyCase::Kebab => to_kebab_case(key),
+            KeyCase::Camel => to_camel_case(key),
+        }
+    }
+}
+
+/// Splits a key into lowercase words.
+pub(crate) fn split_words(key: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let chars: Vec<char> = key.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '.' || c == '_' || c == '-' || c.is_whitespace() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        if c.is_uppercase() && !word.is_empty() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_uppercase() && next_is_lower)
+            {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        word.extend(c.to_lowercase());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Converts a key to `dot.case`.
+pub fn to_dot_case(key: &str) -> String {
+    split_words(key).join(".")
+}
+
+/// Converts a key to `SCREAMING_SNAKE_CASE`, the usual style for environment variables.
+pub fn to_screaming_snake_case(key: &str) -> String {
+    split_words(key).join("_").to_uppercase()
+}
+
+/// Converts a key to `kebab-case`.
+pub fn to_kebab_case(key: &str) -> String {
+    split_words(key).join("-")
+}
+
+/// Converts a key to `camelCase`, the usual style for struct fields in Java.
+pub fn to_camel_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    for (i, word) in split_words(key).iter().enumerate() {
+        if i == 0 {
+            out.push_str(word);
+        } else {
+            let mut chars = word.chars();
+            if let Some(first) = chars.next() {
+                out.extend(first.to_uppercase());
+                out.push_str(chars.as_str());
+            }
+        }
+    }
+    out
+}
+
+/// Converts every key in a map to the given style.
+///
+/// If several keys convert to the same key, the value of the lexicographically greatest original key wins.
+pub fn convert_keys(map: &HashMap<String, String>, case: KeyCase) -> HashMap<String, String> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    keys.into_iter()
+        .map(|k| (case.convert(k), map[k].clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::convert_keys;
+    use super::split_words;
+    use super::to_camel_case;
+    use super::to_dot_case;
+    use super::to_kebab_case;
+    use super::to_screaming_snake_case;
+    use super::KeyCase;
+    use std::collections::HashMap;
+
+    #[test]
+    fn words() {
+        let data: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("a", &["a"]),
+            ("db.pool-size", &["db", "pool", "size"]),
+            ("DB_POOL_SIZE", &["db", "pool", "size"]),
+            ("db.poolSize", &["db", "pool", "size"]),
+            ("HTTPServer.port", &["http", "server", "port"]),
+            ("v2Api", &["v2", "api"]),
+            ("a..b__c", &["a", "b", "c"]),
+        ];
+        for &(key, expected) in data {
+            assert_eq!(split_words(key), expected, "splitting {:?}", key);
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(to_dot_case("DB_POOL_SIZE"), "db.pool.size");
+        assert_eq!(to_screaming_snake_case("db.poolSize"), "DB_POOL_SIZE");
+        assert_eq!(to_kebab_case("db.poolSize"), "db-pool-size");
+        assert_eq!(to_camel_case("db.pool-size"), "dbPoolSize");
+        assert_eq!(KeyCase::Camel.convert("SERVER_PORT"), "serverPort");
+
+        let mut map = HashMap::new();
+        map.insert("server.port".to_string(), "80".to_string());
+        map.insert("SERVER_PORT".to_string(), "81".to_string());
+        map.insert("log.level".to_string(), "info".to_string());
+        let converted = convert_keys(&map, KeyCase::ScreamingSnake);
+        assert_eq!(converted.len(), 2);
+        assert_eq!(converted["SERVER_PORT"], "80");
+        assert_eq!(converted["LOG_LEVEL"], "info");
+    }
+}
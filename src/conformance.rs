@@ -0,0 +1,295 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Reference vectors for `java.util.Properties` compatibility.
+//!
+//! Each [`Case`] pairs the bytes of a properties file with the key/value pairs that
+//! `java.util.Properties.load(InputStream)` produces for it, or with an error where Java throws.
+//! Forks and alternative readers can implement [`ConformanceReader`] and call
+//! [`run_conformance`] to check that they still follow the Java semantics.
+//!
+//! Inputs are ISO-8859-1, as with `load(InputStream)`.  Java stores properties in a hash table, so
+//! readers return pairs in file order and later assignments replace earlier ones before comparison.
+//!
+//! This module requires the `conformance` feature.
+//!
+//! ```
+//! use java_properties::conformance::run_conformance;
+//! use java_properties::conformance::PropertiesIterReader;
+//!
+//! let report = run_conformance::<PropertiesIterReader>();
+//! assert!(report.is_success(), "{}", report);
+//! ```
+
+use crate::PropertiesIter;
+use encoding_rs::WINDOWS_1252;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+/// A reader which can be checked against the reference vectors.
+pub trait ConformanceReader {
+    /// Reads the key/value pairs in `input`, in file order.
+    fn read(input: &[u8]) -> Result<Vec<(String, String)>, Box<dyn Error + Send + Sync>>;
+}
+
+/// This crate's [`PropertiesIter`], reading ISO-8859-1.
+#[derive(Debug, Clone, Copy)]
+pub struct PropertiesIterReader;
+
+impl ConformanceReader for PropertiesIterReader {
+    fn read(input: &[u8]) -> Result<Vec<(String, String)>, Box<dyn Error + Send + Sync>> {
+        let mut pairs = Vec::new();
+        PropertiesIter::new_with_encoding(input, WINDOWS_1252).read_into(|k, v| {
+            pairs.push((k, v));
+        })?;
+        Ok(pairs)
+    }
+}
+
+/// A reference vector.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub struct Case {
+    name: &'static str,
+    input: &'static [u8],
+    expected: Option<&'static [(&'static str, &'static str)]>,
+}
+
+impl Case {
+    /// Returns a short description of what the case checks.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the bytes of the properties file.
+    pub fn input(&self) -> &'static [u8] {
+        self.input
+    }
+
+    /// Returns the properties Java loads from the input, sorted by key, or `None` if Java rejects it.
+    pub fn expected(&self) -> Option<&'static [(&'static str, &'static str)]> {
+        self.expected
+    }
+}
+
+const fn case(
+    name: &'static str,
+    input: &'static [u8],
+    expected: &'static [(&'static str, &'static str)],
+) -> Case {
+    Case {
+        name,
+        input,
+        expected: Some(expected),
+    }
+}
+
+const fn error_case(name: &'static str, input: &'static [u8]) -> Case {
+    Case {
+        name,
+        input,
+        expected: None,
+    }
+}
+
+/// The reference vectors.
+pub const CASES: &[Case] = &[
+    case("empty file", b"", &[]),
+    case("equals separator", b"a=b", &[("a", "b")]),
+    case("colon separator", b"a:b", &[("a", "b")]),
+    case("whitespace separator", b"a b", &[("a", "b")]),
+    case(
+        "whitespace around separator",
+        b"a = b\nc\t:\td\ne  f",
+        &[("a", "b"), ("c", "d"), ("e", "f")],
+    ),
+    case("trailing whitespace kept", b"a=b  ", &[("a", "b  ")]),
+    case("leading whitespace ignored", b"  \ta=b", &[("a", "b")]),
+    case("key without value", b"a\nb=", &[("a", ""), ("b", "")]),
+    case("separator in value", b"a=b=c:d", &[("a", "b=c:d")]),
+    case("comments", b"#a=1\n!b=2\n  # c=3\nd=4", &[("d", "4")]),
+    case("blank lines", b"\n \t \na=1\n\n", &[("a", "1")]),
+    case(
+        "line endings",
+        b"a=1\r\nb=2\rc=3\nd=4",
+        &[("a", "1"), ("b", "2"), ("c", "3"), ("d", "4")],
+    ),
+    case("continuation", b"a=b\\\n   c\\\n\td", &[("a", "bcd")]),
+    case("continuation in key", b"a\\\n  b=c", &[("ab", "c")]),
+    case("continuation with CRLF", b"a=b\\\r\n  c", &[("a", "bc")]),
+    case("comments are not continued", b"#a\\\nb=1", &[("b", "1")]),
+    case(
+        "escaped backslash does not continue",
+        b"a=b\\\\\nc=d",
+        &[("a", "b\\"), ("c", "d")],
+    ),
+    case(
+        "continuation joins before comment detection",
+        b"a=b\\\n#c",
+        &[("a", "b#c")],
+    ),
+    case(
+        "escaped separators in key",
+        b"a\\=b=1\nc\\:d=2\ne\\ f=3",
+        &[("a=b", "1"), ("c:d", "2"), ("e f", "3")],
+    ),
+    case("escaped leading space", b"a=\\ b", &[("a", " b")]),
+    case(
+        "character escapes",
+        b"a=\\t\\n\\r\\f",
+        &[("a", "\t\n\r\x0c")],
+    ),
+    case("unknown escape", b"a=\\q\\\"", &[("a", "q\"")]),
+    case("unicode escape", b"a=\\u0041\\u00e9", &[("a", "A\u{e9}")]),
+    case("unicode escape in key", b"\\u0041=1", &[("A", "1")]),
+    case("ISO-8859-1 input", b"a=\xe9\xff", &[("a", "\u{e9}\u{ff}")]),
+    case("later assignment wins", b"a=1\na=2", &[("a", "2")]),
+    error_case("truncated unicode escape", b"a=\\u12"),
+    error_case("invalid unicode escape", b"a=\\u12g4"),
+];
+
+/// A case for which the reader's result didn't match Java's.
+#[derive(Debug)]
+pub struct Failure {
+    case: Case,
+    actual: Result<Vec<(String, String)>, Box<dyn Error + Send + Sync>>,
+}
+
+impl Failure {
+    /// Returns the case which failed.
+    pub fn case(&self) -> &Case {
+        &self.case
+    }
+
+    /// Returns what the reader produced, sorted by key with later assignments applied, or the error it returned.
+    pub fn actual(&self) -> Result<&[(String, String)], &(dyn Error + Send + Sync)> {
+        match &self.actual {
+            Ok(pairs) => Ok(pairs),
+            Err(e) => Err(&**e),
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: expected ", self.case.name)?;
+        match self.case.expected {
+            Some(expected) => write!(f, "{:?}", expected)?,
+            None => f.write_str("an error")?,
+        }
+        match &self.actual {
+            Ok(actual) => write!(f, ", got {:?}", actual),
+            Err(e) => write!(f, ", got error: {}", e),
+        }
+    }
+}
+
+/// The result of [`run_conformance`].
+#[derive(Debug, Default)]
+pub struct Report {
+    passed: usize,
+    failures: Vec<Failure>,
+}
+
+impl Report {
+    /// Returns the number of cases which passed.
+    pub fn passed(&self) -> usize {
+        self.passed
+    }
+
+    /// Returns the cases which failed, in the order of [`CASES`].
+    pub fn failures(&self) -> &[Failure] {
+        &self.failures
+    }
+
+    /// Returns true if every case passed.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} conformance cases passed",
+            self.passed,
+            self.passed + self.failures.len()
+        )?;
+        for failure in &self.failures {
+            write!(f, "\n{}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs every case in [`CASES`] against `R`.
+pub fn run_conformance<R: ConformanceReader>() -> Report {
+    let mut report = Report::default();
+    for case in CASES {
+        let actual = R::read(case.input).map(|pairs| {
+            pairs
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        });
+        let passed = match (&actual, case.expected) {
+            (Ok(actual), Some(expected)) => {
+                actual.len() == expected.len()
+                    && actual
+                        .iter()
+                        .zip(expected)
+                        .all(|((ak, av), (ek, ev))| ak == ek && av == ev)
+            }
+            (Err(_), None) => true,
+            _ => false,
+        };
+        if passed {
+            report.passed += 1;
+        } else {
+            report.failures.push(Failure {
+                case: *case,
+                actual,
+            });
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_conformance;
+    use super::ConformanceReader;
+    use super::PropertiesIterReader;
+    use super::CASES;
+    use std::error::Error;
+
+    #[test]
+    fn properties_iter_conforms() {
+        let report = run_conformance::<PropertiesIterReader>();
+        assert!(report.is_success(), "{}", report);
+        assert_eq!(report.passed(), CASES.len());
+    }
+
+    #[test]
+    fn reports_failures() {
+        struct IgnoresContinuations;
+        impl ConformanceReader for IgnoresContinuations {
+            fn read(input: &[u8]) -> Result<Vec<(String, String)>, Box<dyn Error + Send + Sync>> {
+                let mut input = input.to_vec();
+                input.retain(|&b| b != b'\\');
+                PropertiesIterReader::read(&input)
+            }
+        }
+        let report = run_conformance::<IgnoresContinuations>();
+        assert!(!report.is_success());
+        let failure = report
+            .failures()
+            .iter()
+            .find(|f| f.case().name() == "continuation")
+            .unwrap();
+        assert!(failure.actual().unwrap().len() > 1);
+        assert!(report.to_string().contains("continuation: expected"));
+    }
+}
@@ -0,0 +1,492 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Semantic differences between two properties files.
+//!
+//! Unlike a text diff, [`diff`] compares what the files define: reformatting, escaping, and line
+//! continuations don't count as changes, and a key which is assigned more than once is compared by
+//! the value which takes effect.  By default, the comment directly above a key and the order of the
+//! keys are compared too; see [`DiffOptions`].
+//!
+//! When only whether anything changed matters, [`properties_eq`] compares two files, and a
+//! [`Fingerprint`] can be stored and compared later.
+//!
+//! ```
+//! use java_properties::diff::ChangeKind;
+//!
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let old = "host=localhost\nport=8080\ndebug=true\n";
+//! let new = "host = localhost\nport=9090\nworkers=4\n";
+//! let diff = java_properties::diff::diff(old.as_bytes(), new.as_bytes())?;
+//! let changes: Vec<_> = diff.changes().iter().map(|c| (c.kind(), c.key())).collect();
+//! assert_eq!(changes, [
+//!     (ChangeKind::Removed, "debug"),
+//!     (ChangeKind::Changed, "port"),
+//!     (ChangeKind::Added, "workers"),
+//! ]);
+//! assert_eq!(diff.to_string(), "- debug=true\n~ port=8080 -> 9090\n+ workers=4\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::CommentedEntry;
+use crate::PropertiesError;
+use crate::PropertiesIterBuilder;
+use encoding_rs::Encoding;
+use encoding_rs::WINDOWS_1252;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io::Read;
+
+/// Options for [`diff_with_options`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DiffOptions {
+    encoding: &'static Encoding,
+    ignore_comments: bool,
+    ignore_order: bool,
+}
+
+impl DiffOptions {
+    /// Creates the default options, which read ISO-8859-1 and compare comments and order.
+    pub fn new() -> Self {
+        DiffOptions {
+            encoding: WINDOWS_1252,
+            ignore_comments: false,
+            ignore_order: false,
+        }
+    }
+
+    /// Sets the encoding both files are read in.  Defaults to ISO-8859-1.
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets whether changes to the comments directly above keys are ignored.  Defaults to false,
+    /// which reports them as [`ChangeKind::CommentChanged`].
+    pub fn ignore_comments(mut self, ignore_comments: bool) -> Self {
+        self.ignore_comments = ignore_comments;
+        self
+    }
+
+    /// Sets whether changes to the order of keys are ignored.  Defaults to false, which reports
+    /// them as [`ChangeKind::Moved`].
+    pub fn ignore_order(mut self, ignore_order: bool) -> Self {
+        self.ignore_order = ignore_order;
+        self
+    }
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How a key differs between the two files.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub enum ChangeKind {
+    /// The key is only in the new file.
+    Added,
+    /// The key is only in the old file.
+    Removed,
+    /// The key has a different value.
+    Changed,
+    /// The key has the same value, but the comment directly above it is different.
+    CommentChanged,
+    /// The key has the same value and comment, but has moved relative to the other keys.
+    Moved,
+}
+
+/// A key which differs between the two files.
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct Change {
+    kind: ChangeKind,
+    old: Option<CommentedEntry>,
+    new: Option<CommentedEntry>,
+}
+
+impl Change {
+    /// Returns how the key differs.
+    pub fn kind(&self) -> ChangeKind {
+        self.kind
+    }
+
+    /// Returns the key.
+    pub fn key(&self) -> &str {
+        match (&self.old, &self.new) {
+            (Some(entry), _) | (None, Some(entry)) => entry.key(),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    /// Returns the key's entry in the old file, with its value, comment, and line number, or
+    /// `None` if it was added.
+    pub fn old_entry(&self) -> Option<&CommentedEntry> {
+        self.old.as_ref()
+    }
+
+    /// Returns the key's entry in the new file, or `None` if it was removed.
+    pub fn new_entry(&self) -> Option<&CommentedEntry> {
+        self.new.as_ref()
+    }
+
+    /// Returns the value in the old file, if any.
+    pub fn old_value(&self) -> Option<&str> {
+        self.old.as_ref().map(CommentedEntry::value)
+    }
+
+    /// Returns the value in the new file, if any.
+    pub fn new_value(&self) -> Option<&str> {
+        self.new.as_ref().map(CommentedEntry::value)
+    }
+}
+
+impl Display for Change {
+    /// Formats the change as one line: `+ key=value`, `- key=value`, `~ key=old -> new`,
+    /// `# key` for a changed comment, or `> key` for a moved key.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.kind, &self.old, &self.new) {
+            (ChangeKind::Added, _, Some(new)) => write!(f, "+ {}={}", new.key(), new.value()),
+            (ChangeKind::Removed, Some(old), _) => write!(f, "- {}={}", old.key(), old.value()),
+            (ChangeKind::Changed, Some(old), Some(new)) => {
+                write!(f, "~ {}={} -> {}", old.key(), old.value(), new.value())
+            }
+            (ChangeKind::CommentChanged, _, _) => write!(f, "# {}", self.key()),
+            _ => write!(f, "> {}", self.key()),
+        }
+    }
+}
+
+/// The result of [`diff`]: every key which differs, sorted by key.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct PropertiesDiff {
+    changes: Vec<Change>,
+}
+
+impl PropertiesDiff {
+    /// Returns the changes, sorted by key.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Returns true if the files define the same properties.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Returns the changes of the given kind, sorted by key.
+    pub fn of_kind(&self, kind: ChangeKind) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(move |c| c.kind == kind)
+    }
+}
+
+impl Display for PropertiesDiff {
+    /// Formats the changes one per line.  See [`Change`]'s `Display` implementation.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// A digest of a set of properties which only depends on the key/value pairs.  See [`fingerprint`].
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct Fingerprint(u128);
+
+impl Fingerprint {
+    /// Returns the digest as a number.
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+}
+
+impl Display for Fingerprint {
+    /// Formats the digest as 32 lowercase hexadecimal digits.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+/// Returns a digest of the key/value pairs, independent of their order.
+///
+/// The digest is stable across platforms and versions of this crate, so it can be stored, e.g. to
+/// redeploy only when configuration changes.  It isn't cryptographic: it detects accidental changes,
+/// not tampering.
+///
+/// ```
+/// use java_properties::diff::fingerprint;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let a = java_properties::read("# Server\nhost=example.com\nport=80\n".as_bytes())?;
+/// let b = java_properties::read_str("port : 80\nhost = example\\\n  .com\nport=80")?;
+/// assert_eq!(fingerprint(&a), fingerprint(&b));
+/// let c = java_properties::read("host=example.com\nport=8080\n".as_bytes())?;
+/// assert_ne!(fingerprint(&a), fingerprint(&c));
+/// # Ok(())
+/// # }
+/// ```
+pub fn fingerprint(map: &HashMap<String, String>) -> Fingerprint {
+    // 128-bit FNV-1a over the pairs sorted by key, each string prefixed with its length so that
+    // different pairs can't produce the same bytes.
+    const OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+    let mut pairs = map.iter().collect::<Vec<_>>();
+    pairs.sort_unstable();
+    let mut hash = OFFSET;
+    let mut update = |bytes: &[u8]| {
+        for &b in bytes {
+            hash = (hash ^ u128::from(b)).wrapping_mul(PRIME);
+        }
+    };
+    for (key, value) in pairs {
+        for s in [key, value] {
+            update(&(s.len() as u64).to_le_bytes());
+            update(s.as_bytes());
+        }
+    }
+    Fingerprint(hash)
+}
+
+/// Returns true if two properties files in ISO-8859-1 define the same key/value pairs, regardless
+/// of comments, order, and formatting.  As with [`read`](crate::read), the last value of a key
+/// which is assigned more than once takes effect.
+///
+/// Files in other encodings can be read with [`read_bytes`](crate::read_bytes) and compared as maps
+/// or by [`fingerprint`].
+///
+/// ```
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let a = "# Generated\na=1\nb=x y\n";
+/// let b = "b = x\\ y\na : 0\na : 1\n";
+/// assert!(java_properties::diff::properties_eq(a.as_bytes(), b.as_bytes())?);
+/// # Ok(())
+/// # }
+/// ```
+pub fn properties_eq<A: Read, B: Read>(a: A, b: B) -> Result<bool, PropertiesError> {
+    Ok(crate::read(a)? == crate::read(b)?)
+}
+
+/// Compares two properties files in ISO-8859-1 with the default [`DiffOptions`].
+pub fn diff<A: Read, B: Read>(old: A, new: B) -> Result<PropertiesDiff, PropertiesError> {
+    diff_with_options(old, new, &DiffOptions::new())
+}
+
+/// Compares two properties files with the given options.
+///
+/// ```
+/// use java_properties::diff::ChangeKind;
+/// use java_properties::diff::DiffOptions;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let old = "# Port\nport=80\nhost=a\n";
+/// let new = "host=a\n# The port\nport=80\n";
+/// let diff = java_properties::diff::diff(old.as_bytes(), new.as_bytes())?;
+/// let kinds: Vec<_> = diff.changes().iter().map(|c| c.kind()).collect();
+/// assert_eq!(kinds, [ChangeKind::CommentChanged]);
+/// assert_eq!(diff.changes()[0].new_entry().unwrap().line_number(), Some(3));
+///
+/// let options = DiffOptions::new().ignore_comments(true);
+/// let diff = java_properties::diff::diff_with_options(old.as_bytes(), new.as_bytes(), &options)?;
+/// assert_eq!(diff.changes()[0].kind(), ChangeKind::Moved);
+///
+/// let options = options.ignore_order(true);
+/// let diff = java_properties::diff::diff_with_options(old.as_bytes(), new.as_bytes(), &options)?;
+/// assert!(diff.is_empty());
+/// # Ok(())
+/// # }
+/// ```
+pub fn diff_with_options<A: Read, B: Read>(
+    old: A,
+    new: B,
+    options: &DiffOptions,
+) -> Result<PropertiesDiff, PropertiesError> {
+    let old = read_entries(old, options.encoding)?;
+    let new = read_entries(new, options.encoding)?;
+    let mut changes = BTreeMap::new();
+    for (key, (_, old_entry)) in &old {
+        let kind = match new.get(key) {
+            None => ChangeKind::Removed,
+            Some((_, new_entry)) if new_entry.value() != old_entry.value() => ChangeKind::Changed,
+            Some((_, new_entry))
+                if !options.ignore_comments && new_entry.comment() != old_entry.comment() =>
+            {
+                ChangeKind::CommentChanged
+            }
+            Some(_) => continue,
+        };
+        changes.insert(key.as_str(), kind);
+    }
+    for key in new.keys() {
+        if !old.contains_key(key) {
+            changes.insert(key.as_str(), ChangeKind::Added);
+        }
+    }
+    if !options.ignore_order {
+        for key in moved_keys(&old, &new) {
+            changes.entry(key).or_insert(ChangeKind::Moved);
+        }
+    }
+    Ok(PropertiesDiff {
+        changes: changes
+            .into_iter()
+            .map(|(key, kind)| Change {
+                kind,
+                old: old.get(key).map(|(_, entry)| entry.clone()),
+                new: new.get(key).map(|(_, entry)| entry.clone()),
+            })
+            .collect(),
+    })
+}
+
+// Maps each key to the position of its first definition and the entry which takes effect.
+type Entries = HashMap<String, (usize, CommentedEntry)>;
+
+fn read_entries<R: Read>(
+    input: R,
+    encoding: &'static Encoding,
+) -> Result<Entries, PropertiesError> {
+    let mut entries = Entries::new();
+    let iter = PropertiesIterBuilder::new().encoding(encoding).build(input);
+    for (position, entry) in iter.commented_entries().enumerate() {
+        let entry = entry?;
+        let position = entries.get(entry.key()).map_or(position, |&(p, _)| p);
+        entries.insert(entry.key().to_string(), (position, entry));
+    }
+    Ok(entries)
+}
+
+// Returns the keys in both files which aren't in a longest sequence of keys in the same order in
+// both, i.e. the fewest keys which have to move to turn one order into the other.
+fn moved_keys<'a>(old: &'a Entries, new: &Entries) -> Vec<&'a str> {
+    let mut common = old
+        .iter()
+        .filter_map(|(key, (old_position, _))| {
+            new.get(key)
+                .map(|(new_position, _)| (*old_position, *new_position, key.as_str()))
+        })
+        .collect::<Vec<_>>();
+    common.sort_unstable();
+    // Longest increasing subsequence of the new positions, in O(n log n).  `tails[i]` is the index
+    // in `common` of the smallest tail of an increasing subsequence of length i + 1.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; common.len()];
+    for i in 0..common.len() {
+        let len = tails.partition_point(|&j| common[j].1 < common[i].1);
+        if len > 0 {
+            previous[i] = Some(tails[len - 1]);
+        }
+        if len == tails.len() {
+            tails.push(i);
+        } else {
+            tails[len] = i;
+        }
+    }
+    let mut in_order = vec![false; common.len()];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        in_order[i] = true;
+        next = previous[i];
+    }
+    common
+        .iter()
+        .zip(in_order)
+        .filter(|&(_, in_order)| !in_order)
+        .map(|(&(_, _, key), _)| key)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+    use super::diff_with_options;
+    use super::fingerprint;
+    use super::properties_eq;
+    use super::ChangeKind;
+    use super::DiffOptions;
+
+    fn changes(old: &str, new: &str, options: &DiffOptions) -> Vec<(ChangeKind, String)> {
+        diff_with_options(old.as_bytes(), new.as_bytes(), options)
+            .unwrap()
+            .changes()
+            .iter()
+            .map(|c| (c.kind(), c.key().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn kinds() {
+        let old = "a=1\nb=2\n# c\nc=3\nd=4\ne=5\ndup=1\n";
+        let new = "a=1\n# b\nb=2\nd=4\n# c\nc:3\ne=6\nf=7\ndup=0\ndup\\\n  =1\n";
+        let options = DiffOptions::new();
+        assert_eq!(
+            changes(old, new, &options),
+            [
+                (ChangeKind::CommentChanged, "b".to_string()),
+                (ChangeKind::Moved, "c".to_string()),
+                (ChangeKind::Changed, "e".to_string()),
+                (ChangeKind::Added, "f".to_string()),
+            ]
+        );
+        let result = diff(old.as_bytes(), new.as_bytes()).unwrap();
+        let e = &result.changes()[2];
+        assert_eq!((e.old_value(), e.new_value()), (Some("5"), Some("6")));
+        assert_eq!(e.old_entry().unwrap().line_number(), Some(6));
+        assert_eq!(e.new_entry().unwrap().line_number(), Some(7));
+        assert_eq!(result.of_kind(ChangeKind::Added).count(), 1);
+        assert_eq!(result.to_string(), "# b\n> c\n~ e=5 -> 6\n+ f=7\n");
+
+        let options = DiffOptions::new().ignore_comments(true).ignore_order(true);
+        assert_eq!(
+            changes(old, new, &options),
+            [
+                (ChangeKind::Changed, "e".to_string()),
+                (ChangeKind::Added, "f".to_string()),
+            ]
+        );
+        assert!(diff("".as_bytes(), "".as_bytes()).unwrap().is_empty());
+        assert!(diff("a=\\u12".as_bytes(), "".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn moves() {
+        let options = DiffOptions::new();
+        let moved = |old: &str, new: &str| -> Vec<String> {
+            changes(old, new, &options)
+                .into_iter()
+                .map(|(kind, key)| {
+                    assert_eq!(kind, ChangeKind::Moved);
+                    key
+                })
+                .collect()
+        };
+        assert_eq!(moved("a=\nb=\nc=\nd=\n", "d=\na=\nb=\nc=\n"), ["d"]);
+        assert_eq!(moved("a=\nb=\nc=\nd=\n", "b=\nc=\nd=\na=\n"), ["a"]);
+        assert_eq!(moved("a=\nb=\nc=\n", "c=\nb=\na=\n").len(), 2);
+        assert!(moved("a=\nb=\na=\n", "a=\nb=\n").is_empty());
+    }
+
+    #[test]
+    fn equality() {
+        let read = |s: &str| crate::read(s.as_bytes()).unwrap();
+        let empty = fingerprint(&read(""));
+        assert_eq!(empty.to_string(), "6c62272e07bb014262b821756295c58d");
+        assert_eq!(empty.as_u128(), 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d);
+        assert_eq!(
+            fingerprint(&read("a=1\nb=2")),
+            fingerprint(&read("b=2\na=1"))
+        );
+        assert_ne!(fingerprint(&read("ab=c")), fingerprint(&read("a=bc")));
+        assert_ne!(fingerprint(&read("a=1")), fingerprint(&read("a=1\nb=")));
+        assert_ne!(
+            fingerprint(&read("a=1\nb=2")),
+            fingerprint(&read("a=2\nb=1"))
+        );
+
+        assert!(properties_eq("a=\\u00e9".as_bytes(), &b"a=\xe9"[..]).unwrap());
+        assert!(!properties_eq("a=1".as_bytes(), "a=1\na=2".as_bytes()).unwrap());
+        assert!(properties_eq("a=\\u12".as_bytes(), "".as_bytes()).is_err());
+    }
+}
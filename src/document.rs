@@ -0,0 +1,827 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! An ordered, formatting-preserving model of a properties file.
+//!
+//! [`PropertiesDocument`] keeps the original text of every line, so a file can be edited
+//! programmatically and written back without disturbing comments, blank lines, separators,
+//! escaping, or line endings that a human chose.  [`edit_file`] and [`set_in_file`] apply such edits
+//! to a file on disk.
+//!
+//! ```
+//! use java_properties::PropertiesDocument;
+//!
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let input = "# Server settings\nserver.port : 8080\n\nserver.host=example.com\n";
+//! let mut doc = PropertiesDocument::load(input.as_bytes())?;
+//! doc.set("server.port", "9090");
+//! doc.insert_after("server.port", "server.timeout", "30")?;
+//! let mut output = Vec::new();
+//! doc.store(&mut output)?;
+//! assert_eq!(
+//!     String::from_utf8(output).unwrap(),
+//!     "# Server settings\nserver.port : 9090\nserver.timeout=30\n\nserver.host=example.com\n"
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::count_ending_backslashes;
+use crate::escape;
+use crate::is_comment_line;
+use crate::parse_line;
+use crate::unescape_at;
+use crate::write_file_atomically;
+use crate::EncodingWriter;
+use crate::KVSeparator;
+use crate::ParsedLine;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use crate::SaveOptions;
+use encoding_rs::Encoding;
+use encoding_rs::WINDOWS_1252;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum EntryKind {
+    /// A blank line.
+    Blank,
+    Comment(String),
+    Pair {
+        key: String,
+        value: String,
+        /// The text of the logical line before the value, i.e. leading whitespace, the key as written, and the separator.
+        prefix: String,
+        separator: KVSeparator,
+    },
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct Entry {
+    /// The exact text of the entry, including continuation lines and the final line terminator.
+    raw: String,
+    kind: EntryKind,
+}
+
+impl Entry {
+    fn key(&self) -> Option<&str> {
+        match self.kind {
+            EntryKind::Pair { ref key, .. } => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Changes the key of a pair, keeping the indentation, separator, and value as written where possible.
+    fn rename(&mut self, new_key: &str) {
+        let Entry { raw, kind } = self;
+        if let EntryKind::Pair {
+            key, value, prefix, ..
+        } = kind
+        {
+            let new_prefix = match parse_line(prefix) {
+                Some(ParsedLine::KVPair(k, _, _)) => {
+                    let start = k.as_ptr() as usize - prefix.as_ptr() as usize;
+                    format!(
+                        "{}{}{}",
+                        &prefix[..start],
+                        escape(new_key),
+                        &prefix[start + k.len()..]
+                    )
+                }
+                _ => format!("{}=", escape(new_key)),
+            };
+            *raw = match raw.strip_prefix(prefix.as_str()) {
+                Some(rest) => format!("{}{}", new_prefix, rest),
+                // The key continues onto another line.
+                None => format!("{}{}{}", new_prefix, escape(value), terminator(raw)),
+            };
+            *prefix = new_prefix;
+            *key = new_key.to_string();
+        }
+    }
+
+    fn new_pair(key: &str, value: &str, line_ending: &str) -> Entry {
+        let prefix = format!("{}=", escape(key));
+        Entry {
+            raw: format!("{}{}{}", prefix, escape(value), line_ending),
+            kind: EntryKind::Pair {
+                key: key.to_string(),
+                value: value.to_string(),
+                prefix,
+                separator: KVSeparator::Equals,
+            },
+        }
+    }
+}
+
+fn terminator(raw: &str) -> &str {
+    if raw.ends_with("\r\n") {
+        "\r\n"
+    } else if raw.ends_with('\r') {
+        "\r"
+    } else if raw.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+/// Splits text into natural lines and their terminators.  Like `PropertiesIter`, the text after the
+/// last terminator is always a line, even if it is empty.
+fn split_lines(text: &str) -> Vec<(&str, &str)> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(['\r', '\n']) {
+        let len = if rest[i..].starts_with("\r\n") { 2 } else { 1 };
+        lines.push((&rest[..i], &rest[i..i + len]));
+        rest = &rest[i + len..];
+    }
+    lines.push((rest, ""));
+    lines
+}
+
+/// Unescapes `s`.  If it is malformed and `diagnostics` is given, the error is pushed to it and `s`
+/// is returned as it is.
+fn unescape_or_report(
+    s: &str,
+    line_number: usize,
+    diagnostics: &mut Option<&mut Vec<PropertiesError>>,
+) -> Result<String, PropertiesError> {
+    match (unescape_at(s, line_number), diagnostics) {
+        (Err(e), Some(diagnostics)) => {
+            diagnostics.push(e);
+            Ok(s.to_string())
+        }
+        (result, _) => result,
+    }
+}
+
+/// A properties file which preserves ordering and formatting.
+///
+/// Lookups follow the same rules as [`read`](crate::read): if a key appears more than once, the
+/// last occurrence wins.  Edits only change the lines they touch; [`store`](Self::store) writes
+/// everything else back byte for byte, provided the input was valid in its encoding.  New and edited
+/// values are escaped the same way as by [`PropertiesWriter`](crate::PropertiesWriter).
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PropertiesDocument {
+    entries: Vec<Entry>,
+    encoding: &'static Encoding,
+    bom: Vec<u8>,
+    line_ending: &'static str,
+}
+
+impl Default for PropertiesDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PropertiesDocument {
+    /// Creates an empty document which is stored as ISO-8859-1 with `\n` line endings.
+    pub fn new() -> Self {
+        PropertiesDocument {
+            entries: Vec::new(),
+            encoding: WINDOWS_1252,
+            bom: Vec::new(),
+            line_ending: "\n",
+        }
+    }
+
+    /// Loads a document in ISO-8859-1, like [`PropertiesIter::new`](crate::PropertiesIter::new).
+    pub fn load<R: Read>(input: R) -> Result<Self, PropertiesError> {
+        Self::load_with_encoding(input, WINDOWS_1252)
+    }
+
+    /// Loads a document in the given encoding.  A byte order mark overrides the encoding, and is
+    /// preserved when the document is stored.
+    pub fn load_with_encoding<R: Read>(
+        mut input: R,
+        encoding: &'static Encoding,
+    ) -> Result<Self, PropertiesError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        Self::parse(&bytes, encoding, None)
+    }
+
+    /// Parses a document from bytes, recovering from anything wrong with them.  This never fails or
+    /// panics, so it suits editors and recovery tools which must open any file.
+    ///
+    /// Problems are returned as diagnostics instead of errors:
+    ///
+    /// - input which is malformed in the encoding, which is replaced with U+FFFD REPLACEMENT
+    ///   CHARACTER, so [`store`](Self::store) no longer reproduces those bytes;
+    /// - malformed `\uxxxx` escapes, where the key, value, or comment is kept as written, escapes
+    ///   and all.
+    ///
+    /// As in [`PropertiesIter`](crate::PropertiesIter), a line continuation at the end of the input
+    /// ends the logical line.
+    ///
+    /// ```
+    /// use encoding_rs::UTF_8;
+    /// use java_properties::PropertiesDocument;
+    /// use java_properties::PropertiesErrorKind;
+    ///
+    /// let input = b"a=caf\xe9\nb=\\u12\nc=3\n";
+    /// let (doc, diagnostics) = PropertiesDocument::parse_lossless(input, UTF_8);
+    /// assert_eq!(doc.get("a"), Some("caf\u{fffd}"));
+    /// assert_eq!(doc.get("b"), Some("\\u12"));
+    /// assert_eq!(doc.get("c"), Some("3"));
+    /// assert_eq!(diagnostics.len(), 2);
+    /// assert_eq!(diagnostics[0].kind(), PropertiesErrorKind::Decode);
+    /// assert_eq!(diagnostics[1].line_number(), Some(2));
+    /// ```
+    pub fn parse_lossless(
+        bytes: &[u8],
+        encoding: &'static Encoding,
+    ) -> (Self, Vec<PropertiesError>) {
+        let mut diagnostics = Vec::new();
+        // Every error is pushed to `diagnostics`, so this can't fail, but an empty document is the
+        // best effort if it does.
+        let doc = Self::parse(bytes, encoding, Some(&mut diagnostics)).unwrap_or_else(|e| {
+            diagnostics.push(e);
+            Self::new()
+        });
+        (doc, diagnostics)
+    }
+
+    // Errors other than I/O errors are pushed to `diagnostics` instead of being returned, if given.
+    fn parse(
+        bytes: &[u8],
+        encoding: &'static Encoding,
+        mut diagnostics: Option<&mut Vec<PropertiesError>>,
+    ) -> Result<Self, PropertiesError> {
+        let (encoding, bom_len) = Encoding::for_bom(bytes).unwrap_or((encoding, 0));
+        let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        if had_errors {
+            if let Some(diagnostics) = diagnostics.as_deref_mut() {
+                diagnostics.push(PropertiesError::new(
+                    PropertiesErrorKind::Decode,
+                    format!("Malformed {} input", encoding.name()),
+                    None,
+                    None,
+                ));
+            }
+        }
+
+        let mut entries = Vec::new();
+        let mut line_ending = None;
+        let mut line_number = 0;
+        let mut lines = split_lines(&text).into_iter();
+        while let Some((first, first_terminator)) = lines.next() {
+            line_number += 1;
+            let start_line = line_number;
+            let mut raw = format!("{}{}", first, first_terminator);
+            let mut logical = first.to_string();
+            if !first_terminator.is_empty() && line_ending.is_none() {
+                line_ending = Some(first_terminator);
+            }
+            if !is_comment_line(first) {
+                let mut last = first;
+                while count_ending_backslashes(last) % 2 == 1 {
+                    logical.pop();
+                    match lines.next() {
+                        Some((line, line_terminator)) => {
+                            line_number += 1;
+                            raw.push_str(line);
+                            raw.push_str(line_terminator);
+                            logical.push_str(line.trim_start());
+                            last = line;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            if raw.is_empty() {
+                // The empty line after the final line terminator.
+                continue;
+            }
+            let kind = match parse_line(&logical) {
+                None => EntryKind::Blank,
+                Some(ParsedLine::Comment(c)) => {
+                    EntryKind::Comment(unescape_or_report(c, start_line, &mut diagnostics)?)
+                }
+                Some(ParsedLine::KVPair(k, v, separator)) => EntryKind::Pair {
+                    key: unescape_or_report(k, start_line, &mut diagnostics)?,
+                    value: unescape_or_report(v, start_line, &mut diagnostics)?,
+                    prefix: logical[..logical.len() - v.len()].to_string(),
+                    separator,
+                },
+            };
+            entries.push(Entry { raw, kind });
+        }
+        let line_ending = match line_ending {
+            Some("\r\n") => "\r\n",
+            Some("\r") => "\r",
+            _ => "\n",
+        };
+        Ok(PropertiesDocument {
+            entries,
+            encoding,
+            bom: bytes[..bom_len].to_vec(),
+            line_ending,
+        })
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.entries.iter().rposition(|e| e.key() == Some(key))
+    }
+
+    /// Returns the value of a key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match self.entries[self.position(key)?].kind {
+            EntryKind::Pair { ref value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the document contains the key.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    /// Iterates over the key/value pairs in file order.  Keys which appear more than once are
+    /// returned once, at the position of their last occurrence.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        let mut last = HashMap::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            if let Some(key) = entry.key() {
+                last.insert(key, i);
+            }
+        }
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, entry)| match entry.kind {
+                EntryKind::Pair {
+                    ref key, ref value, ..
+                } if last[key.as_str()] == i => Some((key.as_str(), value.as_str())),
+                _ => None,
+            })
+    }
+
+    /// Returns the number of distinct keys.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns true if the document contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|e| e.key().is_none())
+    }
+
+    /// Sets the value of a key.
+    ///
+    /// If the key exists, the line is rewritten in place, keeping its indentation, the key as
+    /// written, its separator, and its line terminator.  A key written without a separator stays
+    /// that way if the new value is empty, and gains `=` otherwise.  If the key does not exist, a
+    /// `key=value` line is appended to the end of the document.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let i = match self.position(key) {
+            Some(i) => i,
+            None => {
+                self.push(Entry::new_pair(key, value, self.line_ending));
+                return;
+            }
+        };
+        let entry = &mut self.entries[i];
+        let terminator = terminator(&entry.raw).to_string();
+        if let EntryKind::Pair {
+            value: ref mut old_value,
+            ref mut prefix,
+            ref mut separator,
+            ..
+        } = entry.kind
+        {
+            if old_value == value {
+                return;
+            }
+            if *separator == KVSeparator::Absent && !value.is_empty() {
+                prefix.push('=');
+                *separator = KVSeparator::Equals;
+            }
+            entry.raw = format!("{}{}{}", prefix, escape(value), terminator);
+            *old_value = value.to_string();
+        }
+    }
+
+    /// Removes every occurrence of a key, returning the value it had.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let value = self.get(key)?.to_string();
+        self.entries.retain(|e| e.key() != Some(key));
+        Some(value)
+    }
+
+    /// Renames every occurrence of a key, keeping each line's indentation, separator, and value as
+    /// written.  Returns true if the key was found.
+    ///
+    /// If `new` already exists, both definitions are kept, and the later one takes effect.
+    pub fn rename_key(&mut self, old: &str, new: &str) -> bool {
+        let mut found = false;
+        for entry in self.entries.iter_mut() {
+            if entry.key() == Some(old) {
+                entry.rename(new);
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Applies a rename mapping from old keys to new keys.  All keys are renamed at once, so keys
+    /// can be swapped.  Returns the old keys which were found, sorted.
+    pub fn rename_keys(&mut self, renames: &HashMap<String, String>) -> Vec<String> {
+        let mut found = Vec::new();
+        for entry in self.entries.iter_mut() {
+            let new = match entry.key().and_then(|k| renames.get_key_value(k)) {
+                Some((old, new)) => {
+                    found.push(old.clone());
+                    new.clone()
+                }
+                None => continue,
+            };
+            entry.rename(&new);
+        }
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    /// Inserts a `key=value` line directly after the line defining `after`.
+    ///
+    /// If `key` already exists, it is moved.  Returns an error if `after` does not exist.
+    pub fn insert_after(
+        &mut self,
+        after: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), PropertiesError> {
+        if key != after {
+            self.entries.retain(|e| e.key() != Some(key));
+        }
+        let i = self.position(after).ok_or_else(|| {
+            PropertiesError::new(
+                PropertiesErrorKind::KeyNotFound,
+                format!("Key not found: {:?}", after),
+                None,
+                None,
+            )
+        })?;
+        if key == after {
+            self.set(key, value);
+            return Ok(());
+        }
+        self.terminate(i);
+        self.entries
+            .insert(i + 1, Entry::new_pair(key, value, self.line_ending));
+        Ok(())
+    }
+
+    /// Makes sure the entry at `i` ends with a line terminator, so another line can follow it.  A
+    /// continuation at the end of the input is removed first, so it doesn't continue onto that line.
+    fn terminate(&mut self, i: usize) {
+        let entry = &mut self.entries[i];
+        if terminator(&entry.raw).is_empty() {
+            if !matches!(entry.kind, EntryKind::Comment(_))
+                && count_ending_backslashes(&entry.raw) % 2 == 1
+            {
+                entry.raw.pop();
+            }
+            entry.raw.push_str(self.line_ending);
+        }
+    }
+
+    fn push(&mut self, entry: Entry) {
+        if !self.entries.is_empty() {
+            self.terminate(self.entries.len() - 1);
+        }
+        self.entries.push(entry);
+    }
+
+    /// Writes the document in the encoding it was loaded with.
+    pub fn store<W: Write>(&self, mut writer: W) -> Result<(), PropertiesError> {
+        writer.write_all(&self.bom)?;
+        let mut writer = EncodingWriter::new(writer, self.encoding);
+        for entry in &self.entries {
+            writer.write(&entry.raw)?;
+        }
+        writer.finish()?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the document to the file at `path`, replacing it atomically.  See
+    /// [`write_file_atomically`].
+    pub fn store_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), PropertiesError> {
+        write_file_atomically(path, &SaveOptions::new(), |writer| self.store(writer))
+    }
+}
+
+/// Loads the file at `path` as a [`PropertiesDocument`] in ISO-8859-1, lets `edit` change it, and
+/// saves it if anything changed.
+///
+/// Only the lines `edit` touches are rewritten; every other byte of the file is kept.  The file is
+/// replaced atomically, by writing a temporary file in the same directory and renaming it over the
+/// original, so a crash leaves either the old or the new contents.  The file's permissions are kept.
+///
+/// ```
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// # let dir = tempfile::tempdir()?;
+/// let path = dir.path().join("app.properties");
+/// std::fs::write(&path, "# Features\r\ndebug = false\r\nname=app\r\n")?;
+/// let old = java_properties::document::edit_file(&path, |doc| {
+///     let old = doc.get("debug").map(str::to_string);
+///     doc.set("debug", "true");
+///     old
+/// })?;
+/// assert_eq!(old.as_deref(), Some("false"));
+/// assert_eq!(
+///     std::fs::read_to_string(&path)?,
+///     "# Features\r\ndebug = true\r\nname=app\r\n"
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub fn edit_file<P, F, T>(path: P, edit: F) -> Result<T, PropertiesError>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut PropertiesDocument) -> T,
+{
+    edit_file_with_encoding(path, WINDOWS_1252, edit)
+}
+
+/// Like [`edit_file`], but loads the file in the given encoding.  A byte order mark overrides the encoding.
+pub fn edit_file_with_encoding<P, F, T>(
+    path: P,
+    encoding: &'static Encoding,
+    edit: F,
+) -> Result<T, PropertiesError>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut PropertiesDocument) -> T,
+{
+    let path = path.as_ref();
+    let original =
+        PropertiesDocument::load_with_encoding(BufReader::new(File::open(path)?), encoding)?;
+    let mut doc = original.clone();
+    let result = edit(&mut doc);
+    if doc != original {
+        doc.store_to_path(path)?;
+    }
+    Ok(result)
+}
+
+/// Sets the value of a key in the file at `path`, rewriting only the line which defines it.
+///
+/// See [`edit_file`] and [`PropertiesDocument::set`].
+pub fn set_in_file<P: AsRef<Path>>(path: P, key: &str, value: &str) -> Result<(), PropertiesError> {
+    edit_file(path, |doc| doc.set(key, value))
+}
+
+/// The result of [`rename_keys_in_files`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct RenameReport {
+    changed_files: Vec<PathBuf>,
+    not_found: Vec<String>,
+}
+
+impl RenameReport {
+    /// Returns the files which contained at least one of the old keys and were rewritten, in the
+    /// order they were given.
+    pub fn changed_files(&self) -> &[PathBuf] {
+        &self.changed_files
+    }
+
+    /// Returns the old keys which weren't found in any of the files, sorted.
+    pub fn not_found(&self) -> &[String] {
+        &self.not_found
+    }
+}
+
+/// Applies a rename mapping from old keys to new keys to every file in `paths`, for migrating a
+/// set of configuration files at once.
+///
+/// Each file is edited with [`edit_file`], so only the renamed lines change.  Files which contain
+/// none of the old keys are left untouched.  If a file can't be read or written, the error is
+/// returned, and files earlier in `paths` keep their changes.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// # let dir = tempfile::tempdir()?;
+/// let a = dir.path().join("a.properties");
+/// let b = dir.path().join("b.properties");
+/// std::fs::write(&a, "# db\ndb.url = jdbc:x\n")?;
+/// std::fs::write(&b, "other=1\n")?;
+/// let mut renames = HashMap::new();
+/// renames.insert("db.url".to_string(), "datasource.url".to_string());
+/// renames.insert("db.user".to_string(), "datasource.user".to_string());
+/// let report = java_properties::document::rename_keys_in_files(&[&a, &b], &renames)?;
+/// assert_eq!(report.changed_files(), [a.clone()]);
+/// assert_eq!(report.not_found(), ["db.user"]);
+/// assert_eq!(std::fs::read_to_string(&a)?, "# db\ndatasource.url = jdbc:x\n");
+/// # Ok(())
+/// # }
+/// ```
+pub fn rename_keys_in_files<I, P>(
+    paths: I,
+    renames: &HashMap<String, String>,
+) -> Result<RenameReport, PropertiesError>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let mut report = RenameReport::default();
+    let mut found = HashSet::new();
+    for path in paths {
+        let path = path.as_ref();
+        let renamed = edit_file(path, |doc| doc.rename_keys(renames))?;
+        if !renamed.is_empty() {
+            report.changed_files.push(path.to_path_buf());
+        }
+        found.extend(renamed);
+    }
+    report.not_found = renames
+        .keys()
+        .filter(|k| !found.contains(*k))
+        .cloned()
+        .collect();
+    report.not_found.sort();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::edit_file;
+    use super::set_in_file;
+    use super::PropertiesDocument;
+    use crate::test_dir;
+    use crate::PropertiesErrorKind;
+    use encoding_rs::UTF_16LE;
+    use encoding_rs::UTF_8;
+    use encoding_rs::WINDOWS_1252;
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn store(doc: &PropertiesDocument) -> String {
+        let mut buf = Vec::new();
+        doc.store(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    const INPUT: &str = "# Leading comment\r\n\r\n  a = 1\r\nb:2\r\nlong = x\\\r\n    y\r\nbare\r\n! bang\r\nb : 3\r\ntrailing=\\u0041";
+
+    #[test]
+    fn round_trip() {
+        let doc = PropertiesDocument::load(INPUT.as_bytes()).unwrap();
+        assert_eq!(store(&doc), INPUT);
+        assert_eq!(
+            doc.iter().collect::<Vec<_>>(),
+            vec![
+                ("a", "1"),
+                ("long", "xy"),
+                ("bare", ""),
+                ("b", "3"),
+                ("trailing", "A")
+            ]
+        );
+        assert_eq!(doc.len(), 5);
+        assert_eq!(doc.get("b"), Some("3"));
+        assert_eq!(doc.get("missing"), None);
+
+        let unterminated = "a=b\nc=d\\";
+        let doc = PropertiesDocument::load(unterminated.as_bytes()).unwrap();
+        assert_eq!(store(&doc), unterminated);
+        assert_eq!(doc.get("c"), Some("d"));
+        let mut doc = doc;
+        doc.set("e", "f");
+        assert_eq!(store(&doc), "a=b\nc=d\ne=f\n");
+
+        let bom = b"\xef\xbb\xbfa=\xc3\xa9\n";
+        let doc = PropertiesDocument::load(&bom[..]).unwrap();
+        assert_eq!(doc.get("a"), Some("\u{e9}"));
+        let mut buf = Vec::new();
+        doc.store(&mut buf).unwrap();
+        assert_eq!(buf, bom);
+    }
+
+    #[test]
+    fn lossless() {
+        let (doc, diagnostics) = PropertiesDocument::parse_lossless(INPUT.as_bytes(), WINDOWS_1252);
+        assert!(diagnostics.is_empty());
+        assert_eq!(doc, PropertiesDocument::load(INPUT.as_bytes()).unwrap());
+
+        let input = "# \\uzz\na\\u0=\\u0041\nb=1\\";
+        assert!(PropertiesDocument::load(input.as_bytes()).is_err());
+        let (doc, diagnostics) = PropertiesDocument::parse_lossless(input.as_bytes(), WINDOWS_1252);
+        assert_eq!(store(&doc), input);
+        assert_eq!(doc.get("a\\u0"), Some("A"));
+        assert_eq!(doc.get("b"), Some("1"));
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|e| (e.kind(), e.line_number()))
+                .collect::<Vec<_>>(),
+            vec![
+                (PropertiesErrorKind::MalformedUnicodeEscape, Some(1)),
+                (PropertiesErrorKind::MalformedUnicodeEscape, Some(2)),
+            ]
+        );
+
+        // Arbitrary input never panics, and parses the same as `load` when there's nothing to report.
+        let alphabet = b"a=: \\u0Ff#!\r\n\t\xc3\xa9\xff\xd8";
+        let mut state = 1u32;
+        for _ in 0..2000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let len = (state >> 16) as usize % 24;
+            let mut input = Vec::with_capacity(len);
+            for _ in 0..len {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                input.push(alphabet[(state >> 16) as usize % alphabet.len()]);
+            }
+            for &encoding in &[WINDOWS_1252, UTF_8, UTF_16LE] {
+                let (doc, diagnostics) = PropertiesDocument::parse_lossless(&input, encoding);
+                if diagnostics.is_empty() {
+                    let loaded = PropertiesDocument::load_with_encoding(&input[..], encoding);
+                    assert_eq!(loaded.unwrap(), doc, "{:?}", input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edits() {
+        let mut doc = PropertiesDocument::load(INPUT.as_bytes()).unwrap();
+        doc.set("a", "one two");
+        doc.set("long", "z");
+        doc.set("b", "4");
+        doc.set("bare", "");
+        doc.set("new", "n");
+        assert_eq!(
+            store(&doc),
+            "# Leading comment\r\n\r\n  a = one\\ two\r\nb:2\r\nlong = z\r\nbare\r\n! bang\r\nb : 4\r\ntrailing=\\u0041\r\nnew=n\r\n"
+        );
+
+        doc.set("bare", "v");
+        assert_eq!(doc.remove("b"), Some("4".to_string()));
+        assert_eq!(doc.remove("b"), None);
+        doc.insert_after("a", "inserted", "i").unwrap();
+        doc.insert_after("inserted", "new", "moved").unwrap();
+        assert!(doc.insert_after("missing", "x", "y").is_err());
+        assert_eq!(
+            store(&doc),
+            "# Leading comment\r\n\r\n  a = one\\ two\r\ninserted=i\r\nnew=moved\r\nlong = z\r\nbare=v\r\n! bang\r\ntrailing=\\u0041\r\n"
+        );
+
+        let mut doc = PropertiesDocument::new();
+        assert!(doc.is_empty());
+        doc.set("k", "v");
+        assert_eq!(store(&doc), "k=v\n");
+    }
+
+    #[test]
+    fn rename() {
+        let mut doc = PropertiesDocument::load(INPUT.as_bytes()).unwrap();
+        assert!(doc.rename_key("a", "x y"));
+        assert!(!doc.rename_key("missing", "z"));
+        let mut renames = HashMap::new();
+        renames.insert("b".to_string(), "bare".to_string());
+        renames.insert("bare".to_string(), "b".to_string());
+        renames.insert("long".to_string(), "l".to_string());
+        renames.insert("missing".to_string(), "m".to_string());
+        assert_eq!(doc.rename_keys(&renames), ["b", "bare", "long"]);
+        assert_eq!(
+            store(&doc),
+            "# Leading comment\r\n\r\n  x\\ y = 1\r\nbare:2\r\nl = x\\\r\n    y\r\nb\r\n! bang\r\nbare : 3\r\ntrailing=\\u0041"
+        );
+        assert_eq!(doc.get("bare"), Some("3"));
+        assert_eq!(doc.get("b"), Some(""));
+
+        let mut doc = PropertiesDocument::load("lo\\\n  ng=v\n".as_bytes()).unwrap();
+        assert!(doc.rename_key("long", "short"));
+        assert_eq!(store(&doc), "short=v\n");
+    }
+
+    #[test]
+    fn files() {
+        let dir = test_dir();
+        let path = dir.path().join("a.properties");
+        fs::write(&path, INPUT).unwrap();
+
+        set_in_file(&path, "long", "z").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            INPUT.replace("long = x\\\r\n    y", "long = z")
+        );
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(edit_file(&path, |doc| doc.len()).unwrap(), 5);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        assert!(set_in_file(dir.path().join("missing.properties"), "a", "b").is_err());
+    }
+}
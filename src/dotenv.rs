@@ -0,0 +1,240 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Support for `.env` files.
+//!
+//! A `.env` file holds one `KEY=value` assignment per line, optionally preceded by `export`, with
+//! `#` comments.  Values may be unquoted, single-quoted (taken literally), or double-quoted (with
+//! `\n`, `\r`, `\t`, `\"`, `\\`, `\$`, and ``\` `` escapes).  Unquoted values end at the first
+//! ` #`, which starts a comment.  There are no line continuations.
+//!
+//! [`PropertiesIter`](crate::PropertiesIter) and [`PropertiesWriter`](crate::PropertiesWriter)
+//! handle these files when set to [`Dialect::DotEnv`]; [`read_dotenv`] and [`write_dotenv`] cover
+//! the common cases.
+
+use crate::sanitize_unescaped;
+use crate::Dialect;
+use crate::KVSeparator;
+use crate::Line;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Write;
+
+/// Parses a line of a `.env` file.  Returns `None` for blank lines.
+pub(crate) fn parse_line(line: &str, line_number: usize) -> Result<Option<Line>, PropertiesError> {
+    let error = |description: &str| {
+        Err(PropertiesError::new(
+            PropertiesErrorKind::Syntax,
+            description.to_string(),
+            None,
+            Some(line_number),
+        ))
+    };
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if let Some(comment) = line.strip_prefix('#') {
+        return Ok(Some(Line::mk_comment(
+            line_number,
+            comment.trim_start().to_string(),
+        )));
+    }
+    let line = match line.strip_prefix("export") {
+        Some(rest) if rest.starts_with([' ', '\t']) => rest.trim_start(),
+        _ => line,
+    };
+    let (key, value) = match line.find('=') {
+        Some(i) => (line[..i].trim_end(), line[i + 1..].trim_start()),
+        None => return error("Missing '=' in .env assignment"),
+    };
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return error("Invalid key in .env assignment");
+    }
+    let (value, rest) = match value.chars().next() {
+        Some(quote @ '\'') | Some(quote @ '"') => {
+            let mut unquoted = String::new();
+            let mut chars = value[1..].char_indices();
+            let mut end = None;
+            while let Some((i, c)) = chars.next() {
+                if c == quote {
+                    end = Some(i + 2);
+                    break;
+                }
+                if c == '\\' && quote == '"' {
+                    match chars.next() {
+                        Some((_, 'n')) => unquoted.push('\n'),
+                        Some((_, 'r')) => unquoted.push('\r'),
+                        Some((_, 't')) => unquoted.push('\t'),
+                        Some((_, c @ ('"' | '\\' | '$' | '`'))) => unquoted.push(c),
+                        Some((_, c)) => {
+                            unquoted.push('\\');
+                            unquoted.push(c);
+                        }
+                        None => unquoted.push('\\'),
+                    }
+                } else {
+                    unquoted.push(c);
+                }
+            }
+            match end {
+                Some(end) => (unquoted, value[end..].trim_start()),
+                None => return error("Unterminated quoted value in .env assignment"),
+            }
+        }
+        _ => {
+            let end = value
+                .find(" #")
+                .or_else(|| value.find("\t#"))
+                .unwrap_or(value.len());
+            (value[..end].trim_end().to_string(), &value[end..])
+        }
+    };
+    if !rest.trim_start().is_empty() && !rest.trim_start().starts_with('#') {
+        return error("Unexpected text after quoted value in .env assignment");
+    }
+    Ok(Some(Line::mk_pair(
+        line_number,
+        key.to_string(),
+        value,
+        KVSeparator::Equals,
+    )))
+}
+
+/// Checks that a key can be written to a `.env` file.
+pub(crate) fn check_key(key: &str) -> Result<(), PropertiesError> {
+    if key.is_empty()
+        || key.starts_with('#')
+        || key.contains(|c: char| c == '=' || c.is_whitespace() || c.is_control())
+    {
+        return Err(PropertiesError::new(
+            PropertiesErrorKind::InvalidKey,
+            format!("Key {:?} can't be written to a .env file", key),
+            None,
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// Replaces the characters of a key which [`check_key`] rejects with `_`.
+pub(crate) fn sanitize_key(key: &str) -> String {
+    sanitize_unescaped(
+        key,
+        |c| c == '=' || c.is_whitespace() || c.is_control(),
+        &['#'],
+    )
+}
+
+/// Formats a value for a `.env` file, quoting it if necessary.  Single quotes are used where
+/// possible, since shells expand `$` and backticks inside double quotes; otherwise those are escaped.
+pub(crate) fn format_value(value: &str) -> String {
+    let needs_quotes = value != value.trim()
+        || value
+            .contains(|c: char| matches!(c, '#' | '"' | '\'' | '\\' | '$' | '`') || c.is_control());
+    if !needs_quotes {
+        return value.to_string();
+    }
+    if !value.contains(|c: char| c == '\'' || c.is_control()) {
+        return format!("'{}'", value);
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '$' => quoted.push_str("\\$"),
+            '`' => quoted.push_str("\\`"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads a UTF-8 `.env` file into a hash map.
+///
+/// ```
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let input = "# settings\nexport HOST=example.com\nGREETING=\"hello\\nworld\" # comment\n";
+/// let map = java_properties::dotenv::read_dotenv(input.as_bytes())?;
+/// assert_eq!(map["HOST"], "example.com");
+/// assert_eq!(map["GREETING"], "hello\nworld");
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_dotenv<R: Read>(input: R) -> Result<HashMap<String, String>, PropertiesError> {
+    Dialect::DotEnv.read_utf8(input)
+}
+
+/// Writes key/value pairs to a UTF-8 `.env` file, in the order given.
+pub fn write_dotenv<W, I, K, V>(writer: W, pairs: I) -> Result<(), PropertiesError>
+where
+    W: Write,
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    Dialect::DotEnv.write_utf8(writer, pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_line;
+    use super::read_dotenv;
+    use super::write_dotenv;
+    use crate::check_parsed_lines;
+    use crate::parsed_comment;
+    use crate::parsed_pair;
+
+    #[test]
+    fn parse() {
+        let data = [
+            ("", None),
+            ("  # c ", parsed_comment("c")),
+            ("A=1", parsed_pair("A", "1")),
+            ("export A = 1 2 ", parsed_pair("A", "1 2")),
+            ("exporter=1", parsed_pair("exporter", "1")),
+            ("A=", parsed_pair("A", "")),
+            ("A=x#y # z", parsed_pair("A", "x#y")),
+            ("A='a\\n $b' # z", parsed_pair("A", "a\\n $b")),
+            ("A=\"a\\n\\\"\\\\\\$\\`\"", parsed_pair("A", "a\n\"\\$`")),
+            ("A=\"x", Some(Err(()))),
+            ("A='x' y", Some(Err(()))),
+            ("A", Some(Err(()))),
+            ("A B=1", Some(Err(()))),
+            ("=1", Some(Err(()))),
+        ];
+        check_parsed_lines(&data, |input| parse_line(input, 1));
+    }
+
+    #[test]
+    fn round_trip() {
+        let pairs = [
+            ("A", "plain"),
+            ("B", ""),
+            ("C", " padded "),
+            ("D", "quote\" back\\slash\nnewline # not a comment"),
+            ("E", "$HOME"),
+            ("F", "it's `$HOME`"),
+        ];
+        let mut buf = Vec::new();
+        write_dotenv(&mut buf, pairs.iter().cloned()).unwrap();
+        assert_eq!(
+            String::from_utf8(buf.clone()).unwrap(),
+            "A=plain\nB=\nC=' padded '\nD=\"quote\\\" back\\\\slash\\nnewline # not a comment\"\n\
+             E='$HOME'\nF=\"it's \\`\\$HOME\\`\"\n"
+        );
+        let map = read_dotenv(&buf[..]).unwrap();
+        for (k, v) in pairs.iter() {
+            assert_eq!(map[*k], *v);
+        }
+
+        assert!(write_dotenv(Vec::new(), vec![("A B", "1")]).is_err());
+        assert!(write_dotenv(Vec::new(), vec![("A=", "1")]).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Encrypting properties files at rest.
+//!
+//! An encrypted file starts with a plaintext comment header identifying the format, followed by the
+//! serialized properties encrypted with AES-256-GCM and encoded as base64:
+//!
+//! ```text
+//! # java-properties encrypted file
+//! # cipher=AES-256-GCM
+//! 3q2+7wAAAAAAAAAA...
+//! ```
+//!
+//! The whole serialized file is encrypted, so comments and the order of entries are kept.  Each
+//! file uses a new random nonce, and modified files fail to decrypt rather than producing altered
+//! properties.
+//!
+//! This module is only available with the `encryption` feature.
+//!
+//! ```
+//! use java_properties::encryption;
+//! use std::collections::HashMap;
+//!
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let key = [7u8; encryption::KEY_LEN];
+//! let mut map = HashMap::new();
+//! map.insert("db.password".to_string(), "hunter2".to_string());
+//! let mut file = Vec::new();
+//! encryption::write_encrypted(&mut file, &map, &key)?;
+//! assert!(file.starts_with(b"# java-properties encrypted file\n"));
+//! assert_eq!(encryption::read_encrypted(&file[..], &key)?, map);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use aes_gcm::aead::Aead;
+use aes_gcm::aead::AeadCore;
+use aes_gcm::aead::KeyInit;
+use aes_gcm::aead::OsRng;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::Nonce;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Write;
+
+/// The length of a key in bytes.
+pub const KEY_LEN: usize = 32;
+
+const HEADER: &str = "# java-properties encrypted file\n# cipher=AES-256-GCM\n";
+const NONCE_LEN: usize = 12;
+// Length of the base64 lines, as in MIME.
+const LINE_LEN: usize = 76;
+
+fn syntax_error(description: &str) -> PropertiesError {
+    PropertiesError::new(PropertiesErrorKind::Syntax, description, None, None)
+}
+
+/// Encrypts `plaintext`, usually a serialized properties file, and writes it with the header.
+pub fn encrypt<W: Write>(
+    mut writer: W,
+    plaintext: &[u8],
+    key: &[u8; KEY_LEN],
+) -> Result<(), PropertiesError> {
+    let cipher = Aes256Gcm::new(key.into());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(|_| {
+        PropertiesError::new(
+            PropertiesErrorKind::Encryption,
+            "Unable to encrypt the properties",
+            None,
+            None,
+        )
+    })?;
+    let mut data = nonce.to_vec();
+    data.extend_from_slice(&ciphertext);
+    let encoded = STANDARD.encode(&data);
+    writer.write_all(HEADER.as_bytes())?;
+    for line in encoded.as_bytes().chunks(LINE_LEN) {
+        writer.write_all(line)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a file written by [`encrypt`] and returns the plaintext.
+///
+/// Returns an error of kind [`PropertiesErrorKind::Encryption`] if the key is wrong or the file was
+/// modified.
+pub fn decrypt<R: Read>(mut reader: R, key: &[u8; KEY_LEN]) -> Result<Vec<u8>, PropertiesError> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input).map_err(|e| {
+        PropertiesError::new(
+            PropertiesErrorKind::Io,
+            "Unable to read the encrypted properties",
+            Some(Box::new(e)),
+            None,
+        )
+    })?;
+    let mut lines = input.lines().map(str::trim);
+    if !HEADER.lines().all(|header| lines.next() == Some(header)) {
+        return Err(syntax_error("Missing encrypted properties header"));
+    }
+    let encoded = lines.collect::<String>();
+    let data = STANDARD
+        .decode(encoded)
+        .map_err(|_| syntax_error("Malformed base64 in encrypted properties"))?;
+    if data.len() < NONCE_LEN {
+        return Err(syntax_error("Encrypted properties are truncated"));
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| {
+            PropertiesError::new(
+                PropertiesErrorKind::Encryption,
+                "Unable to decrypt the properties: wrong key or modified file",
+                None,
+                None,
+            )
+        })
+}
+
+/// Writes a hash map as an encrypted properties file.  See [`crate::write`].
+pub fn write_encrypted<W: Write>(
+    writer: W,
+    map: &HashMap<String, String>,
+    key: &[u8; KEY_LEN],
+) -> Result<(), PropertiesError> {
+    let mut plaintext = Vec::new();
+    crate::write(&mut plaintext, map)?;
+    encrypt(writer, &plaintext, key)
+}
+
+/// Reads an encrypted properties file into a hash map.  See [`crate::read`].
+pub fn read_encrypted<R: Read>(
+    reader: R,
+    key: &[u8; KEY_LEN],
+) -> Result<HashMap<String, String>, PropertiesError> {
+    crate::read(&decrypt(reader, key)?[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decrypt;
+    use super::encrypt;
+    use super::KEY_LEN;
+    use crate::PropertiesErrorKind;
+
+    #[test]
+    fn round_trip() {
+        let key = [1; KEY_LEN];
+        let plaintext = "# comment\nb=2\na=1\n".repeat(10);
+        let mut file = Vec::new();
+        encrypt(&mut file, plaintext.as_bytes(), &key).unwrap();
+        let text = String::from_utf8(file.clone()).unwrap();
+        assert!(!text.contains("b=2"));
+        assert!(text.lines().skip(2).all(|line| line.len() <= 76));
+        assert_eq!(decrypt(&file[..], &key).unwrap(), plaintext.as_bytes());
+
+        let mut other = Vec::new();
+        encrypt(&mut other, plaintext.as_bytes(), &key).unwrap();
+        assert_ne!(file, other);
+    }
+
+    #[test]
+    fn errors() {
+        let key = [1; KEY_LEN];
+        let mut file = Vec::new();
+        encrypt(&mut file, b"a=1\n", &key).unwrap();
+
+        let err = decrypt(&file[..], &[2; KEY_LEN]).unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::Encryption);
+
+        let mut modified = file.clone();
+        let i = super::HEADER.len() + 20;
+        modified[i] = if modified[i] == b'A' { b'B' } else { b'A' };
+        let err = decrypt(&modified[..], &key).unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::Encryption);
+
+        let err = decrypt(&b"a=1\n"[..], &key).unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::Syntax);
+        let err = decrypt(&file[..file.len() - 2], &key).unwrap_err();
+        assert_ne!(err.kind(), PropertiesErrorKind::Io);
+    }
+}
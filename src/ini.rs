@@ -0,0 +1,272 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Support for INI files.
+//!
+//! INI files hold `key=value` or `key: value` assignments grouped under `[section]` headers, with
+//! `#` and `;` comments.  A section is a key prefix: `host=example.com` under `[db]` is read as the
+//! key `db.host`, and keys before the first header have no prefix.  On write, the part of a key
+//! before its first `.` is the section.  There are no escapes or line continuations, and whitespace
+//! around keys, values, and section names is ignored.
+//!
+//! [`PropertiesIter`](crate::PropertiesIter) and [`PropertiesWriter`](crate::PropertiesWriter)
+//! handle these files when set to [`Dialect::Ini`]; [`read_ini`] and [`write_ini`] cover the common
+//! cases.
+
+use crate::sanitize_unescaped;
+use crate::Dialect;
+use crate::KVSeparator;
+use crate::Line;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Write;
+
+/// Returns true if the line is a section header, which [`parse_line`] consumes without returning a
+/// line.
+pub(crate) fn is_section_header(line: &str) -> bool {
+    line.trim_start().starts_with('[')
+}
+
+/// Parses a line of an INI file, prefixing keys with the current section.  Returns `None` for blank
+/// lines and section headers, which change `section`.
+pub(crate) fn parse_line(
+    line: &str,
+    line_number: usize,
+    section: &mut Option<String>,
+) -> Result<Option<Line>, PropertiesError> {
+    let error = |description: &str| {
+        Err(PropertiesError::new(
+            PropertiesErrorKind::Syntax,
+            description.to_string(),
+            None,
+            Some(line_number),
+        ))
+    };
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if let Some(comment) = line.strip_prefix(['#', ';']) {
+        return Ok(Some(Line::mk_comment(
+            line_number,
+            comment.trim_start().to_string(),
+        )));
+    }
+    if let Some(header) = line.strip_prefix('[') {
+        return match header.strip_suffix(']').map(str::trim) {
+            Some("") => error("Empty INI section name"),
+            Some(name) => {
+                *section = Some(name.to_string());
+                Ok(None)
+            }
+            None => error("Missing ']' in INI section header"),
+        };
+    }
+    let (i, separator) = match line.find(['=', ':']) {
+        Some(i) if line[i..].starts_with('=') => (i, KVSeparator::Equals),
+        Some(i) => (i, KVSeparator::Colon),
+        None => return error("Missing '=' in INI assignment"),
+    };
+    let key = line[..i].trim_end();
+    if key.is_empty() {
+        return error("Missing key in INI assignment");
+    }
+    let key = match section {
+        Some(section) => format!("{}.{}", section, key),
+        None => key.to_string(),
+    };
+    Ok(Some(Line::mk_pair(
+        line_number,
+        key,
+        line[i + 1..].trim_start().to_string(),
+        separator,
+    )))
+}
+
+/// Splits a key into its section, the part before the first `.`, and the name within the section.
+pub(crate) fn split_key(key: &str) -> (Option<&str>, &str) {
+    match key.split_once('.') {
+        Some((section, name)) => (Some(section), name),
+        None => (None, key),
+    }
+}
+
+/// Checks that a key can be written to an INI file and read back unchanged.
+pub(crate) fn check_key(key: &str) -> Result<(), PropertiesError> {
+    let (section, name) = split_key(key);
+    let bad_section = section.is_some_and(|section| {
+        section.is_empty() || section != section.trim() || section.contains([']', '\r', '\n'])
+    });
+    let bad_name = name.is_empty()
+        || name != name.trim()
+        || name.starts_with(['#', ';', '['])
+        || name.contains(['=', ':', '\r', '\n']);
+    if bad_section || bad_name {
+        return Err(PropertiesError::new(
+            PropertiesErrorKind::InvalidKey,
+            format!("Key {:?} can't be written to an INI file", key),
+            None,
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// Replaces the characters of a key which [`check_key`] rejects with `_`.
+pub(crate) fn sanitize_key(key: &str) -> String {
+    let (section, name) = split_key(key);
+    let name = sanitize_unescaped(
+        name,
+        |c| matches!(c, '=' | ':' | '\r' | '\n'),
+        &['#', ';', '['],
+    );
+    match section {
+        Some(section) => {
+            let section = sanitize_unescaped(section, |c| matches!(c, ']' | '\r' | '\n'), &[]);
+            format!("{}.{}", section, name)
+        }
+        None => name,
+    }
+}
+
+/// Checks that a value can be written to an INI file and read back unchanged.
+pub(crate) fn check_value(key: &str, value: &str) -> Result<(), PropertiesError> {
+    if value != value.trim() || value.contains(['\r', '\n']) {
+        return Err(PropertiesError::new(
+            PropertiesErrorKind::InvalidValue,
+            format!("Value for key {:?} can't be written to an INI file", key),
+            None,
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a UTF-8 INI file into a hash map, with section names as key prefixes.
+///
+/// ```
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let input = "; settings\nname=app\n\n[db]\nhost = example.com\nport: 5432\n";
+/// let map = java_properties::ini::read_ini(input.as_bytes())?;
+/// assert_eq!(map["name"], "app");
+/// assert_eq!(map["db.host"], "example.com");
+/// assert_eq!(map["db.port"], "5432");
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_ini<R: Read>(input: R) -> Result<HashMap<String, String>, PropertiesError> {
+    Dialect::Ini.read_utf8(input)
+}
+
+/// Writes key/value pairs to a UTF-8 INI file, with key prefixes as section names.
+///
+/// Keys without a section come first, then the keys of each section, with sections in the order
+/// they first appear and keys in the order given.
+pub fn write_ini<W, I, K, V>(writer: W, pairs: I) -> Result<(), PropertiesError>
+where
+    W: Write,
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut pairs = pairs.into_iter().collect::<Vec<_>>();
+    let mut sections: Vec<Option<String>> = vec![None];
+    for (key, _) in &pairs {
+        let section = split_key(key.as_ref()).0.map(str::to_string);
+        if !sections.contains(&section) {
+            sections.push(section);
+        }
+    }
+    pairs.sort_by_key(|(key, _)| {
+        let section = split_key(key.as_ref()).0;
+        sections.iter().position(|s| s.as_deref() == section)
+    });
+    Dialect::Ini.write_utf8(writer, pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_line;
+    use super::read_ini;
+    use super::write_ini;
+    use crate::check_parsed_lines;
+    use crate::parsed_comment;
+    use crate::parsed_pair;
+    use crate::LineContent;
+    use crate::PropertiesErrorKind;
+    use crate::PropertiesWriter;
+
+    #[test]
+    fn parse() {
+        let data = [
+            ("", None),
+            ("  # c ", parsed_comment("c")),
+            ("; c", parsed_comment("c")),
+            ("a=1", parsed_pair("a", "1")),
+            (" a b : x = y ", parsed_pair("a b", "x = y")),
+            ("a=b:c", parsed_pair("a", "b:c")),
+            ("a=", parsed_pair("a", "")),
+            ("a=x ; y", parsed_pair("a", "x ; y")),
+            ("a=\\u0041\\", parsed_pair("a", "\\u0041\\")),
+            ("a", Some(Err(()))),
+            ("=1", Some(Err(()))),
+            ("[", Some(Err(()))),
+            ("[ ]", Some(Err(()))),
+        ];
+        check_parsed_lines(&data, |input| parse_line(input, 1, &mut None));
+
+        let mut section = None;
+        assert!(parse_line(" [ db ] ", 1, &mut section).unwrap().is_none());
+        assert_eq!(section.as_deref(), Some("db"));
+        let line = parse_line("host=h", 2, &mut section).unwrap().unwrap();
+        assert_eq!(
+            line.consume_content(),
+            LineContent::KVPair("db.host".to_string(), "h".to_string())
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let pairs = [
+            ("db.host", "example.com"),
+            ("name", "app"),
+            ("log.level", "debug"),
+            ("db.url", "jdbc:h2:mem: ; x"),
+            ("log.file.path", ""),
+        ];
+        let mut buf = Vec::new();
+        write_ini(&mut buf, pairs.iter().cloned()).unwrap();
+        assert_eq!(
+            String::from_utf8(buf.clone()).unwrap(),
+            "name=app\n\n[db]\nhost=example.com\nurl=jdbc:h2:mem: ; x\n\n[log]\nlevel=debug\nfile.path=\n"
+        );
+        let map = read_ini(&buf[..]).unwrap();
+        assert_eq!(map.len(), pairs.len());
+        for (k, v) in pairs.iter() {
+            assert_eq!(map[*k], *v);
+        }
+
+        assert!(write_ini(Vec::new(), vec![("a.b=c", "1")]).is_err());
+        assert!(write_ini(Vec::new(), vec![(".a", "1")]).is_err());
+        assert!(write_ini(Vec::new(), vec![("a", " 1")]).is_err());
+    }
+
+    #[test]
+    fn writer() {
+        let mut buf = Vec::new();
+        let mut writer = PropertiesWriter::new(&mut buf);
+        writer.set_dialect(crate::Dialect::Ini);
+        writer.write("a.x", "1").unwrap();
+        writer.write("b.x", "2").unwrap();
+        writer.write("a.y", "3").unwrap();
+        let err = writer.write("z", "4").unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::InvalidKey);
+        writer.set_key_policy(crate::KeyPolicy::Sanitize);
+        writer.write("a.y=", "5").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "[a]\nx=1\n\n[b]\nx=2\n\n[a]\ny=3\ny_=5\n"
+        );
+    }
+}
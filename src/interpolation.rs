@@ -0,0 +1,810 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Expansion of `${key}` placeholders in values.
+//!
+//! This follows the convention used by Spring and Ant: `${other.key}` is replaced with the value
+//! of `other.key`, and `${other.key:fallback}` uses `fallback` if `other.key` is not defined.
+//! Replacement values and fallbacks may contain placeholders themselves.
+//!
+//! Windows-style `%KEY%` placeholders can be enabled as well, with
+//! [`set_percent_placeholders`](Resolver::set_percent_placeholders).
+//!
+//! [`Resolver::dependencies`] finds the references between values without expanding them, to check
+//! for missing keys and cycles ahead of time, and [`Dependencies::to_dot`] renders them for
+//! Graphviz.
+//!
+//! ```
+//! use java_properties::interpolation::Resolver;
+//!
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let input = "host=example.com\nurl=https://${host}:${port:443}/\n";
+//! let values = java_properties::read(input.as_bytes())?;
+//! let resolved = Resolver::new().resolve(&values)?;
+//! assert_eq!(resolved["url"], "https://example.com:443/");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Write as _;
+
+type Lookup = Box<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+// The expanded values of keys, with how deeply their placeholders are nested, so each key is only
+// expanded once.
+type Cache<'a> = HashMap<&'a str, (String, usize)>;
+
+/// The default limit on how deeply placeholders may be nested.
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/// The default limit on the length of an expanded value, in bytes.
+pub const DEFAULT_MAX_LENGTH: usize = 1 << 20;
+
+/// Expands `${key}` and `${key:default}` placeholders.
+///
+/// Keys are looked up in the properties being resolved first, then in the external lookup, if one is set.
+/// A placeholder whose key can't be found and which has no default is an error, as is a key which
+/// refers back to itself.  So that a few short values can't expand to gigabytes, as in
+/// `b=${a}${a}`, `c=${b}${b}`, and so on, expanded values are limited in length as well.
+pub struct Resolver {
+    max_depth: usize,
+    max_length: usize,
+    lookup: Option<Lookup>,
+    percent_placeholders: bool,
+}
+
+impl Resolver {
+    /// Creates a resolver with no external lookup.
+    pub fn new() -> Self {
+        Resolver {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_length: DEFAULT_MAX_LENGTH,
+            lookup: None,
+            percent_placeholders: false,
+        }
+    }
+
+    /// Sets how deeply placeholders may be nested, counting placeholders in replacement values and
+    /// defaults.  Defaults to [`DEFAULT_MAX_DEPTH`].
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Sets the maximum length of an expanded value in bytes, beyond which expansion fails with
+    /// [`PropertiesErrorKind::ExpansionTooLong`].  Defaults to [`DEFAULT_MAX_LENGTH`].
+    ///
+    /// ```
+    /// use java_properties::interpolation::Resolver;
+    /// use java_properties::PropertiesErrorKind;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let values = java_properties::read("a=xx\nb=${a}${a}\nc=${b}${b}\n".as_bytes())?;
+    /// let mut resolver = Resolver::new();
+    /// resolver.set_max_length(4);
+    /// assert_eq!(resolver.resolve_value("${b}", &values)?, "xxxx");
+    /// let err = resolver.resolve_value("${c}", &values).unwrap_err();
+    /// assert_eq!(err.kind(), PropertiesErrorKind::ExpansionTooLong);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_max_length(&mut self, max_length: usize) {
+        self.max_length = max_length;
+    }
+
+    /// Sets a lookup for keys which are not defined in the properties being resolved, such as
+    /// environment variables.
+    ///
+    /// ```
+    /// use java_properties::interpolation::Resolver;
+    /// use std::collections::HashMap;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut resolver = Resolver::new();
+    /// resolver.set_lookup(|key| std::env::var(key).ok());
+    /// let values = HashMap::new();
+    /// let home = resolver.resolve_value("${NO_SUCH_VARIABLE:/home}", &values)?;
+    /// assert_eq!(home, "/home");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_lookup<F: Fn(&str) -> Option<String> + Send + Sync + 'static>(&mut self, lookup: F) {
+        self.lookup = Some(Box::new(lookup));
+    }
+
+    /// Sets whether `%KEY%` placeholders are expanded as well as `${key}` placeholders.  Defaults
+    /// to false.
+    ///
+    /// `%KEY%` placeholders have no default value, and their keys are made of ASCII letters, digits,
+    /// `_`, `.`, and `-`, not starting with a digit.  When they are enabled, `%%` stands for a
+    /// literal `%`, and a `%` which doesn't start a placeholder, such as those in `50% off` and
+    /// `p%40ss%21`, is left as it is.
+    ///
+    /// ```
+    /// use java_properties::interpolation::Resolver;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "root=C:/app\nlogs=%root%/logs\nlimit=${quota:90}%%\n";
+    /// let values = java_properties::read(input.as_bytes())?;
+    /// let mut resolver = Resolver::new();
+    /// resolver.set_percent_placeholders(true);
+    /// let resolved = resolver.resolve(&values)?;
+    /// assert_eq!(resolved["logs"], "C:/app/logs");
+    /// assert_eq!(resolved["limit"], "90%");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_percent_placeholders(&mut self, enabled: bool) {
+        self.percent_placeholders = enabled;
+    }
+
+    /// Returns a copy of `values` with the placeholders in every value expanded.
+    pub fn resolve(
+        &self,
+        values: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, PropertiesError> {
+        let mut cache = Cache::new();
+        let mut resolved = HashMap::with_capacity(values.len());
+        for (key, value) in values {
+            if !cache.contains_key(key.as_str()) {
+                let mut stack = vec![key.as_str()];
+                let expanded = self.expand(value, values, &mut stack, 0, &mut cache)?;
+                cache.insert(key, expanded);
+            }
+            resolved.insert(key.clone(), cache[key.as_str()].0.clone());
+        }
+        Ok(resolved)
+    }
+
+    /// Expands the placeholders in a single value, looking up keys in `values`.
+    pub fn resolve_value(
+        &self,
+        value: &str,
+        values: &HashMap<String, String>,
+    ) -> Result<String, PropertiesError> {
+        let (expanded, _) = self.expand(value, values, &mut Vec::new(), 0, &mut Cache::new())?;
+        Ok(expanded)
+    }
+
+    /// Finds the placeholders in every value without expanding them.
+    ///
+    /// Keys in defaults count as references, since whether they are used depends on the
+    /// external lookup.
+    ///
+    /// ```
+    /// use java_properties::interpolation::Resolver;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "url=${scheme}://${host}:${port:443}/\nhost=${env}.example.com\nscheme=https\n";
+    /// let values = java_properties::read(input.as_bytes())?;
+    /// let dependencies = Resolver::new().dependencies(&values);
+    /// assert_eq!(dependencies.references("url").unwrap().len(), 3);
+    /// assert!(dependencies.unresolvable()["host"].contains("env"));
+    /// assert_eq!(dependencies.resolution_order()?, ["host", "scheme", "url"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn dependencies(&self, values: &HashMap<String, String>) -> Dependencies {
+        let mut dependencies = Dependencies::default();
+        for (key, value) in values {
+            let mut references = BTreeSet::new();
+            let mut unresolvable = BTreeSet::new();
+            self.scan(value, values, &mut references, &mut unresolvable);
+            if !unresolvable.is_empty() {
+                dependencies.unresolvable.insert(key.clone(), unresolvable);
+            }
+            dependencies.references.insert(key.clone(), references);
+        }
+        dependencies
+    }
+
+    fn scan(
+        &self,
+        value: &str,
+        values: &HashMap<String, String>,
+        references: &mut BTreeSet<String>,
+        unresolvable: &mut BTreeSet<String>,
+    ) {
+        let mut rest = value;
+        while let Some(placeholder) = self.find_placeholder(rest) {
+            rest = &rest[placeholder.end..];
+            let (key, default) = match placeholder.kind {
+                PlaceholderKind::Dollar { key, default } => (key, default),
+                PlaceholderKind::Percent { key } => (key, None),
+                PlaceholderKind::PercentEscape => continue,
+            };
+            references.insert(key.to_string());
+            match default {
+                Some(default) => self.scan(default, values, references, unresolvable),
+                None if !values.contains_key(key) => {
+                    unresolvable.insert(key.to_string());
+                }
+                None => (),
+            }
+        }
+    }
+
+    // Returns the expanded value and how deeply placeholders are nested in it.  `stack` holds the
+    // keys whose values are being expanded, to detect cycles.
+    fn expand<'a>(
+        &'a self,
+        value: &str,
+        values: &'a HashMap<String, String>,
+        stack: &mut Vec<&'a str>,
+        depth: usize,
+        cache: &mut Cache<'a>,
+    ) -> Result<(String, usize), PropertiesError> {
+        let mut out = String::with_capacity(value.len().min(self.max_length));
+        let mut nesting = 0;
+        let mut rest = value;
+        while let Some(placeholder) = self.find_placeholder(rest) {
+            self.push_limited(&mut out, &rest[..placeholder.start])?;
+            let text = &rest[placeholder.start..placeholder.end];
+            rest = &rest[placeholder.end..];
+            let (key, default) = match placeholder.kind {
+                PlaceholderKind::Dollar { key, default } => (key, default),
+                PlaceholderKind::Percent { key } => (key, None),
+                PlaceholderKind::PercentEscape => {
+                    self.push_limited(&mut out, "%")?;
+                    continue;
+                }
+            };
+            if depth >= self.max_depth {
+                return Err(PropertiesError::new(
+                    PropertiesErrorKind::PlaceholderRecursion,
+                    format!(
+                        "Placeholders nested more than {} deep in {}",
+                        self.max_depth, text
+                    ),
+                    None,
+                    None,
+                ));
+            }
+            if stack.contains(&key) {
+                let mut cycle = stack.join(" -> ");
+                cycle.push_str(" -> ");
+                cycle.push_str(key);
+                return Err(PropertiesError::new(
+                    PropertiesErrorKind::PlaceholderRecursion,
+                    format!("Circular placeholder reference: {}", cycle),
+                    None,
+                    None,
+                ));
+            }
+            let inner_nesting = match values.get_key_value(key) {
+                // A cached value is reused unless it would now be nested too deeply, in which case
+                // expanding it again reports the error.
+                Some((key, _))
+                    if cache
+                        .get(key.as_str())
+                        .is_some_and(|(_, n)| depth + n < self.max_depth) =>
+                {
+                    let (expanded, n) = &cache[key.as_str()];
+                    self.push_limited(&mut out, expanded)?;
+                    *n
+                }
+                Some((key, replacement)) => {
+                    stack.push(key);
+                    let expanded = self.expand(replacement, values, stack, depth + 1, cache);
+                    stack.pop();
+                    let (expanded, n) = expanded?;
+                    self.push_limited(&mut out, &expanded)?;
+                    cache.insert(key, (expanded, n));
+                    n
+                }
+                None => match (self.lookup.as_ref().and_then(|l| l(key)), default) {
+                    (Some(replacement), _) => {
+                        self.push_limited(&mut out, &replacement)?;
+                        0
+                    }
+                    (None, Some(default)) => {
+                        let (expanded, n) =
+                            self.expand(default, values, stack, depth + 1, cache)?;
+                        self.push_limited(&mut out, &expanded)?;
+                        n
+                    }
+                    (None, None) => {
+                        return Err(PropertiesError::new(
+                            PropertiesErrorKind::UnresolvedPlaceholder,
+                            match stack.last() {
+                                Some(referrer) => format!(
+                                    "Unresolved placeholder {} in the value of {:?}",
+                                    text, referrer
+                                ),
+                                None => format!("Unresolved placeholder {}", text),
+                            },
+                            None,
+                            None,
+                        ))
+                    }
+                },
+            };
+            nesting = nesting.max(inner_nesting + 1);
+        }
+        self.push_limited(&mut out, rest)?;
+        Ok((out, nesting))
+    }
+
+    // Appends `s` to an expanded value, unless that makes it too long.
+    fn push_limited(&self, out: &mut String, s: &str) -> Result<(), PropertiesError> {
+        if out.len() + s.len() > self.max_length {
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::ExpansionTooLong,
+                format!("Expanded value is longer than {} bytes", self.max_length),
+                None,
+                None,
+            ));
+        }
+        out.push_str(s);
+        Ok(())
+    }
+
+    // Returns the first placeholder in `s`, if any.
+    fn find_placeholder<'s>(&self, s: &'s str) -> Option<Placeholder<'s>> {
+        let dollar = s.find("${").and_then(|start| {
+            let body_start = start + 2;
+            // An unterminated placeholder is left as it is.
+            let body_len = find_closing_brace(&s[body_start..])?;
+            let body = &s[body_start..body_start + body_len];
+            let (key, default) = match body.find(':') {
+                Some(i) => (&body[..i], Some(&body[i + 1..])),
+                None => (body, None),
+            };
+            Some(Placeholder {
+                start,
+                end: body_start + body_len + 1,
+                kind: PlaceholderKind::Dollar { key, default },
+            })
+        });
+        let percent = if self.percent_placeholders {
+            find_percent_placeholder(&s[..dollar.as_ref().map_or(s.len(), |p| p.start)])
+        } else {
+            None
+        };
+        percent.or(dollar)
+    }
+}
+
+struct Placeholder<'s> {
+    // Byte offsets of the whole placeholder, including delimiters.
+    start: usize,
+    end: usize,
+    kind: PlaceholderKind<'s>,
+}
+
+enum PlaceholderKind<'s> {
+    Dollar {
+        key: &'s str,
+        default: Option<&'s str>,
+    },
+    Percent {
+        key: &'s str,
+    },
+    // `%%`
+    PercentEscape,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Resolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resolver")
+            .field("max_depth", &self.max_depth)
+            .field("max_length", &self.max_length)
+            .field("lookup", &self.lookup.is_some())
+            .field("percent_placeholders", &self.percent_placeholders)
+            .finish()
+    }
+}
+
+/// The placeholder references between values, as returned by [`Resolver::dependencies`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Dependencies {
+    references: BTreeMap<String, BTreeSet<String>>,
+    unresolvable: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Dependencies {
+    /// Returns the keys referenced by the value of `key`, or `None` if `key` is not defined.
+    pub fn references(&self, key: &str) -> Option<&BTreeSet<String>> {
+        self.references.get(key)
+    }
+
+    /// Returns the graph, mapping every key to the keys referenced by its value.
+    pub fn graph(&self) -> &BTreeMap<String, BTreeSet<String>> {
+        &self.references
+    }
+
+    /// Returns the keys whose values contain placeholders that can't be resolved from the
+    /// properties alone, mapped to the undefined keys they refer to.  Placeholders with defaults
+    /// are not included.  An external lookup may still supply these keys.
+    pub fn unresolvable(&self) -> &BTreeMap<String, BTreeSet<String>> {
+        &self.unresolvable
+    }
+
+    /// Returns every key, ordered so that each key comes after the keys its value references.
+    /// Otherwise, keys are sorted.
+    ///
+    /// Returns an error if the references form a cycle.
+    pub fn resolution_order(&self) -> Result<Vec<&str>, PropertiesError> {
+        let mut order = Vec::with_capacity(self.references.len());
+        let mut done = HashSet::new();
+        let mut stack = Vec::new();
+        for key in self.references.keys() {
+            self.visit(key, &mut stack, &mut done, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// Renders the graph in the Graphviz DOT language, e.g. for `dot -Tsvg`.
+    ///
+    /// Each reference is an edge from the key whose value contains the placeholder to the key it
+    /// refers to.  Keys which neither reference nor are referenced by other keys are omitted, to
+    /// keep large graphs readable.  Undefined keys are drawn dashed, and references which can't be
+    /// resolved, having no default, are drawn in red.
+    ///
+    /// ```
+    /// use java_properties::interpolation::Resolver;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "url=${host}:${port:80}\nhost=${env}.example.com\nname=app\n";
+    /// let values = java_properties::read(input.as_bytes())?;
+    /// let dot = Resolver::new().dependencies(&values).to_dot();
+    /// assert_eq!(
+    ///     dot,
+    ///     r#"digraph properties {
+    ///     "host" -> "env" [color=red];
+    ///     "url" -> "host";
+    ///     "url" -> "port";
+    ///     "env" [style=dashed];
+    ///     "port" [style=dashed];
+    /// }
+    /// "#
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph properties {\n");
+        let mut undefined = BTreeSet::new();
+        for (key, references) in &self.references {
+            for reference in references {
+                let _ = write!(dot, "    {} -> {}", dot_id(key), dot_id(reference));
+                if self
+                    .unresolvable
+                    .get(key)
+                    .is_some_and(|keys| keys.contains(reference))
+                {
+                    dot.push_str(" [color=red]");
+                }
+                dot.push_str(";\n");
+                if !self.references.contains_key(reference) {
+                    undefined.insert(reference);
+                }
+            }
+        }
+        for key in undefined {
+            let _ = writeln!(dot, "    {} [style=dashed];", dot_id(key));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Appends `key` to `order` after the keys it depends on.  `stack` holds the keys being
+    // visited, to detect cycles.
+    fn visit<'a>(
+        &'a self,
+        key: &'a str,
+        stack: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) -> Result<(), PropertiesError> {
+        if done.contains(key) {
+            return Ok(());
+        }
+        if let Some(i) = stack.iter().position(|k| *k == key) {
+            let mut cycle = stack[i..].join(" -> ");
+            cycle.push_str(" -> ");
+            cycle.push_str(key);
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::PlaceholderRecursion,
+                format!("Circular placeholder reference: {}", cycle),
+                None,
+                None,
+            ));
+        }
+        // Undefined keys have nothing to resolve.
+        let (key, references) = match self.references.get_key_value(key) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        stack.push(key);
+        for reference in references {
+            self.visit(reference, stack, done, order)?;
+        }
+        stack.pop();
+        done.insert(key);
+        order.push(key);
+        Ok(())
+    }
+}
+
+// Quotes a key as a DOT identifier.
+fn dot_id(key: &str) -> String {
+    let mut id = String::with_capacity(key.len() + 2);
+    id.push('"');
+    for c in key.chars() {
+        match c {
+            '"' => id.push_str("\\\""),
+            '\\' => id.push_str("\\\\"),
+            '\n' => id.push_str("\\n"),
+            '\r' => id.push_str("\\r"),
+            _ => id.push(c),
+        }
+    }
+    id.push('"');
+    id
+}
+
+// Returns the length of the placeholder body starting at `s`, i.e. the index of the `}` which
+// closes it, taking nested placeholders into account.
+fn find_closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                depth += 1;
+                i += 1;
+            }
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+// Returns the first `%KEY%` placeholder or `%%` in `s`.  A key is made of ASCII letters, digits,
+// `_`, `.`, and `-`, and doesn't start with a digit, so that percentages and URL-encoded bytes in
+// text, such as `100%;` or `%40`, aren't mistaken for placeholders.
+fn find_percent_placeholder(s: &str) -> Option<Placeholder<'_>> {
+    let mut from = 0;
+    while let Some(i) = s[from..].find('%') {
+        let start = from + i;
+        let body_start = start + 1;
+        let body_len = s[body_start..].find('%')?;
+        let key = &s[body_start..body_start + body_len];
+        let end = body_start + body_len + 1;
+        if key.is_empty() {
+            return Some(Placeholder {
+                start,
+                end,
+                kind: PlaceholderKind::PercentEscape,
+            });
+        }
+        let is_key = !key.starts_with(|c: char| c.is_ascii_digit())
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if is_key {
+            return Some(Placeholder {
+                start,
+                end,
+                kind: PlaceholderKind::Percent { key },
+            });
+        }
+        // The closing `%` may start the next placeholder.
+        from = body_start + body_len;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolver;
+    use crate::PropertiesErrorKind;
+    use std::collections::BTreeSet;
+    use std::collections::HashMap;
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn resolve() {
+        let values = map(&[
+            ("a", "1"),
+            ("b", "${a}${a}"),
+            ("c", "[${b}|${missing:${a}x}|${missing:}]"),
+            ("d", "${unterminated"),
+            ("e", "$a {a} $${a}"),
+        ]);
+        let resolved = Resolver::new().resolve(&values).unwrap();
+        assert_eq!(resolved["b"], "11");
+        assert_eq!(resolved["c"], "[11|1x|]");
+        assert_eq!(resolved["d"], "${unterminated");
+        assert_eq!(resolved["e"], "$a {a} $1");
+    }
+
+    #[test]
+    fn errors() {
+        let resolver = Resolver::new();
+        let err = resolver
+            .resolve(&map(&[("a", "${b}"), ("b", "x${c}"), ("c", "${a}")]))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Circular placeholder reference: "));
+        let err = resolver.resolve(&map(&[("a", "${a}")])).unwrap_err();
+        assert!(err.to_string().contains("a -> a"));
+
+        let err = resolver.resolve(&map(&[("a", "${nope}")])).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Unresolved placeholder ${nope} in the value of \"a\""));
+
+        let mut resolver = Resolver::new();
+        resolver.set_max_depth(3);
+        let values = map(&[("a", "${b}"), ("b", "${c}"), ("c", "${d}"), ("d", "x")]);
+        assert_eq!(resolver.resolve_value("${b}", &values).unwrap(), "x");
+        assert!(resolver.resolve_value("${a}", &values).is_err());
+        // The value of c is cached from the first placeholder, but is nested too deeply in b.
+        resolver.set_max_depth(2);
+        assert!(resolver.resolve_value("${c}", &values).is_ok());
+        assert!(resolver.resolve_value("${c}${b}", &values).is_err());
+    }
+
+    #[test]
+    fn max_length() {
+        // Each value is twice as long as the one before, so the last is a gigabyte.
+        let mut values = map(&[("k0", "x")]);
+        for i in 1..=30 {
+            values.insert(format!("k{}", i), format!("${{k{0}}}${{k{0}}}", i - 1));
+        }
+        let err = Resolver::new().resolve(&values).unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::ExpansionTooLong);
+        let resolver = Resolver::new();
+        let expanded = resolver.resolve_value("${k20}", &values).unwrap();
+        assert_eq!(expanded.len(), super::DEFAULT_MAX_LENGTH);
+        assert!(resolver.resolve_value("${k20}!", &values).is_err());
+
+        let mut resolver = Resolver::new();
+        resolver.set_max_length(3);
+        assert_eq!(resolver.resolve_value("abc", &values).unwrap(), "abc");
+        assert!(resolver.resolve_value("abcd", &values).is_err());
+        assert!(resolver.resolve_value("%%${k2}", &values).is_err());
+    }
+
+    #[test]
+    fn dependencies() {
+        let values = map(&[
+            ("a", "1"),
+            ("b", "${a}${a}"),
+            ("c", "[${b}|${missing:${a}x}|${other}|%d%]"),
+            ("d", "${unterminated"),
+        ]);
+        let dependencies = Resolver::new().dependencies(&values);
+        let keys = |ks: &[&str]| ks.iter().map(|k| k.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(dependencies.references("a"), Some(&keys(&[])));
+        assert_eq!(dependencies.references("b"), Some(&keys(&["a"])));
+        assert_eq!(
+            dependencies.references("c"),
+            Some(&keys(&["a", "b", "missing", "other"]))
+        );
+        assert_eq!(dependencies.references("d"), Some(&keys(&[])));
+        assert_eq!(dependencies.references("missing"), None);
+        assert_eq!(dependencies.graph().len(), 4);
+        assert_eq!(dependencies.unresolvable().len(), 1);
+        assert_eq!(dependencies.unresolvable()["c"], keys(&["other"]));
+        assert_eq!(
+            dependencies.resolution_order().unwrap(),
+            ["a", "b", "c", "d"]
+        );
+
+        let mut resolver = Resolver::new();
+        resolver.set_percent_placeholders(true);
+        let dependencies = resolver.dependencies(&values);
+        assert_eq!(
+            dependencies.references("c"),
+            Some(&keys(&["a", "b", "d", "missing", "other"]))
+        );
+        assert_eq!(
+            dependencies.resolution_order().unwrap(),
+            ["a", "b", "d", "c"]
+        );
+
+        let values = map(&[("a", "${b}"), ("b", "x${c}"), ("c", "${a:1}"), ("d", "")]);
+        let err = Resolver::new()
+            .dependencies(&values)
+            .resolution_order()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Circular placeholder reference: a -> b -> c -> a (line_number = unknown)"
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let values = map(&[
+            ("a", "${b}"),
+            ("b", "${a}"),
+            ("say \"hi\"", "${a\\b}"),
+            ("isolated", "1"),
+        ]);
+        assert_eq!(
+            Resolver::new().dependencies(&values).to_dot(),
+            "digraph properties {\n    \"a\" -> \"b\";\n    \"b\" -> \"a\";\n    \
+             \"say \\\"hi\\\"\" -> \"a\\\\b\" [color=red];\n    \"a\\\\b\" [style=dashed];\n}\n"
+        );
+        assert_eq!(
+            Resolver::new().dependencies(&HashMap::new()).to_dot(),
+            "digraph properties {\n}\n"
+        );
+    }
+
+    #[test]
+    fn lookup() {
+        let mut resolver = Resolver::new();
+        resolver.set_lookup(|key| match key {
+            "HOME" => Some("/home/me".to_string()),
+            "a" => Some("external".to_string()),
+            _ => None,
+        });
+        let values = map(&[("a", "local"), ("dir", "${HOME}/${a}")]);
+        let resolved = resolver.resolve(&values).unwrap();
+        assert_eq!(resolved["dir"], "/home/me/local");
+    }
+
+    #[test]
+    fn percent() {
+        let values = map(&[
+            ("a", "1"),
+            ("b", "%a%${a}%a%"),
+            ("c", "50% off, 100%% sure, 20% more %a%"),
+            ("d", "${missing:%a%}"),
+            ("e", "%unterminated"),
+            ("password", "p%40ss%21"),
+            ("style", "width=100%;height=50%"),
+        ]);
+        let resolved = Resolver::new().resolve(&values).unwrap();
+        assert_eq!(resolved["b"], "%a%1%a%");
+
+        let mut resolver = Resolver::new();
+        resolver.set_percent_placeholders(true);
+        let resolved = resolver.resolve(&values).unwrap();
+        assert_eq!(resolved["b"], "111");
+        assert_eq!(resolved["c"], "50% off, 100% sure, 20% more 1");
+        assert_eq!(resolved["d"], "1");
+        assert_eq!(resolved["e"], "%unterminated");
+        assert_eq!(resolved["password"], "p%40ss%21");
+        assert_eq!(resolved["style"], "width=100%;height=50%");
+
+        let err = resolver
+            .resolve_value("%a%", &map(&[("a", "%b%"), ("b", "${a}")]))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Circular placeholder reference: a -> b -> a"));
+        let err = resolver.resolve(&map(&[("a", "%nope%")])).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Unresolved placeholder %nope% in the value of \"a\""));
+    }
+}
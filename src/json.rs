@@ -0,0 +1,151 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Converting properties to and from JSON.
+//!
+//! Dotted keys become nested objects, as in [`tree`], and keys continuing with the indexes `0`,
+//! `1`, ... become arrays, so `servers.0.host` is the `host` of the first item of the `servers`
+//! array.  Values are always JSON strings, since properties are untyped.
+//!
+//! Going the other way, numbers and booleans are written as their JSON text, `null` values are
+//! omitted, and empty arrays and objects produce no keys.
+//!
+//! This module is only available with the `json` feature.
+//!
+//! ```
+//! use serde_json::json;
+//!
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let map = java_properties::read("db.url=jdbc:h2:mem:\nhosts.0=a\nhosts.1=b\n".as_bytes())?;
+//! let value = java_properties::json::to_json_value(&map)?;
+//! assert_eq!(value, json!({"db": {"url": "jdbc:h2:mem:"}, "hosts": ["a", "b"]}));
+//! assert_eq!(java_properties::json::from_json_value(&value)?, map);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::tree;
+use crate::tree::Nested;
+use crate::tree::View;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Converts properties to a JSON value, which is an object unless the keys are list indexes.
+///
+/// Returns an error of kind [`PropertiesErrorKind::KeyConflict`] if a key has a value and also has
+/// keys under it, since JSON can't represent both.  See [`tree::to_tree`].
+pub fn to_json_value(map: &HashMap<String, String>) -> Result<Value, PropertiesError> {
+    Ok(tree::to_nested(&tree::to_tree(map)?))
+}
+
+/// Converts a JSON object or array to properties, flattening nested values into dotted keys.
+///
+/// Returns an error of kind [`PropertiesErrorKind::InvalidValue`] if `value` is a string, number,
+/// boolean, or `null`, since it has no key.
+pub fn from_json_value(value: &Value) -> Result<HashMap<String, String>, PropertiesError> {
+    if !value.is_object() && !value.is_array() {
+        return Err(PropertiesError::new(
+            PropertiesErrorKind::InvalidValue,
+            "Only a JSON object or array can be converted to properties",
+            None,
+            None,
+        ));
+    }
+    tree::flatten(value)
+}
+
+impl Nested for Value {
+    fn string(value: String) -> Self {
+        Value::String(value)
+    }
+
+    fn list(items: Vec<Self>) -> Self {
+        Value::Array(items)
+    }
+
+    fn map(entries: Vec<(String, Self)>) -> Self {
+        Value::Object(entries.into_iter().collect())
+    }
+
+    fn view(&self) -> Result<View<'_, Self>, PropertiesError> {
+        Ok(match self {
+            Value::Null => View::Absent,
+            Value::Bool(b) => View::Scalar(b.to_string()),
+            Value::Number(n) => View::Scalar(n.to_string()),
+            Value::String(s) => View::Scalar(s.clone()),
+            Value::Array(items) => View::List(items),
+            Value::Object(fields) => {
+                View::Map(fields.iter().map(|(k, v)| (k.clone(), v)).collect())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_json_value;
+    use super::to_json_value;
+    use crate::tree::map;
+    use crate::PropertiesErrorKind;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn round_trip() {
+        let flat = map(&[
+            ("name", "app"),
+            ("servers.0.host", "a"),
+            ("servers.0.port", "80"),
+            ("servers.1.host", "b"),
+            ("sparse.0", "x"),
+            ("sparse.2", "z"),
+            ("padded.01", "y"),
+        ]);
+        let value = to_json_value(&flat).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "name": "app",
+                "servers": [{"host": "a", "port": "80"}, {"host": "b"}],
+                "sparse": {"0": "x", "2": "z"},
+                "padded": {"01": "y"},
+            })
+        );
+        assert_eq!(from_json_value(&value).unwrap(), flat);
+
+        let list = map(&[("0", "a"), ("1", "b")]);
+        assert_eq!(to_json_value(&list).unwrap(), json!(["a", "b"]));
+        assert_eq!(from_json_value(&json!(["a", "b"])).unwrap(), list);
+        assert_eq!(to_json_value(&HashMap::new()).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn from_json() {
+        let value = json!({
+            "n": 1.5,
+            "b": true,
+            "null": null,
+            "empty": [],
+            "nested": {"list": [1, {"x": "y"}]},
+        });
+        assert_eq!(
+            from_json_value(&value).unwrap(),
+            map(&[
+                ("n", "1.5"),
+                ("b", "true"),
+                ("nested.list.0", "1"),
+                ("nested.list.1.x", "y"),
+            ])
+        );
+        assert_eq!(
+            from_json_value(&json!("x")).unwrap_err().kind(),
+            PropertiesErrorKind::InvalidValue
+        );
+        assert_eq!(
+            to_json_value(&map(&[("a", "1"), ("a.b", "2")]))
+                .unwrap_err()
+                .kind(),
+            PropertiesErrorKind::KeyConflict
+        );
+    }
+}
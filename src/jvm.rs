@@ -0,0 +1,431 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Conversions between properties and JVM command-line arguments.
+
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Write;
+
+fn check_key(key: &str) -> Result<(), PropertiesError> {
+    if key.is_empty() || key.contains('=') {
+        return Err(PropertiesError::new(
+            PropertiesErrorKind::InvalidKey,
+            format!("Key cannot be passed as a -D argument: {:?}", key),
+            None,
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// Renders key/value pairs as `-Dkey=value` JVM arguments, in the order given.
+///
+/// The arguments are suitable for passing directly to a process (e.g. with `std::process::Command::args`),
+/// so no quoting is applied.  Keys which are empty or contain `=` cannot be represented and result in an error.
+pub fn to_jvm_args<I, K, V>(pairs: I) -> Result<Vec<String>, PropertiesError>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    pairs
+        .into_iter()
+        .map(|(k, v)| {
+            let (k, v) = (k.as_ref(), v.as_ref());
+            check_key(k)?;
+            Ok(format!("-D{}={}", k, v))
+        })
+        .collect()
+}
+
+/// Quotes a string for a POSIX shell, if necessary.
+pub(crate) fn shell_quote(s: &str) -> String {
+    let safe = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.,:/=@%+".contains(c));
+    if safe {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// Renders key/value pairs as a single string of `-Dkey=value` arguments, quoted for a POSIX shell.
+///
+/// Arguments are separated by single spaces and appear in the order given.
+pub fn to_command_line<I, K, V>(pairs: I) -> Result<String, PropertiesError>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let args = to_jvm_args(pairs)?;
+    Ok(args
+        .iter()
+        .map(|a| shell_quote(a))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// Extracts `-Dkey=value` definitions from a list of command-line arguments.
+///
+/// Other arguments are ignored.  A definition without `=` (`-Dflag`) has an empty value, matching the JVM,
+/// and later definitions of the same key replace earlier ones.  `-D` and `-D=value` are ignored since they
+/// have no key.
+pub fn parse_jvm_args<I, S>(args: I) -> HashMap<String, String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut map = HashMap::new();
+    for arg in args {
+        if let Some(definition) = arg.as_ref().strip_prefix("-D") {
+            let (key, value) = match definition.find('=') {
+                Some(i) => (&definition[..i], &definition[i + 1..]),
+                None => (definition, ""),
+            };
+            if !key.is_empty() {
+                map.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    map
+}
+
+/// Splits a command-line string into arguments, following POSIX shell quoting rules.
+///
+/// Single quotes, double quotes, and backslash escapes are supported.  Variable expansion and other shell
+/// features are not.  An unterminated quote results in an error.
+pub fn split_command_line(s: &str) -> Result<Vec<String>, PropertiesError> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => {
+                            return Err(PropertiesError::new(
+                                PropertiesErrorKind::Syntax,
+                                "Unterminated single quote in command line",
+                                None,
+                                None,
+                            ))
+                        }
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') | Some(c @ '$') | Some(c @ '`') => {
+                                arg.push(c)
+                            }
+                            Some('\n') => (),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => {
+                                return Err(PropertiesError::new(
+                                    PropertiesErrorKind::Syntax,
+                                    "Unterminated double quote in command line",
+                                    None,
+                                    None,
+                                ))
+                            }
+                        },
+                        Some(c) => arg.push(c),
+                        None => {
+                            return Err(PropertiesError::new(
+                                PropertiesErrorKind::Syntax,
+                                "Unterminated double quote in command line",
+                                None,
+                                None,
+                            ))
+                        }
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => (),
+                Some(c) => {
+                    in_arg = true;
+                    arg.push(c);
+                }
+                None => {
+                    in_arg = true;
+                    arg.push('\\');
+                }
+            },
+            _ if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            _ => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// Extracts `-Dkey=value` definitions from a `JAVA_OPTS`-style string.
+///
+/// The string is split with [`split_command_line`] and the definitions are extracted with [`parse_jvm_args`].
+pub fn parse_java_opts(opts: &str) -> Result<HashMap<String, String>, PropertiesError> {
+    Ok(parse_jvm_args(split_command_line(opts)?))
+}
+
+/// A line of a `.vmoptions` file.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
+pub enum VmOptionsLine {
+    /// A blank line.
+    Blank,
+    /// A comment, without the leading `#`.
+    Comment(String),
+    /// A `-Dkey=value` system property definition.  The value is `None` for `-Dkey` without `=`.
+    Property(String, Option<String>),
+    /// Any other JVM option, such as `-Xmx2g`, stored verbatim.
+    Flag(String),
+}
+
+/// The contents of a JVM `.vmoptions` file, as used by JetBrains IDEs and install4j launchers.
+///
+/// The format has one option per line, `#` comments, and no quoting or escaping.
+/// Lines other than `-D` definitions are kept verbatim as flags.
+/// Lines are preserved in order, so the file can be edited and written back.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Hash)]
+pub struct VmOptions {
+    lines: Vec<VmOptionsLine>,
+}
+
+impl VmOptions {
+    /// Creates an empty set of options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the contents of a `.vmoptions` file.
+    pub fn parse(s: &str) -> Self {
+        let lines = s
+            .lines()
+            .map(|line| {
+                let line = line.trim();
+                if line.is_empty() {
+                    VmOptionsLine::Blank
+                } else if let Some(comment) = line.strip_prefix('#') {
+                    VmOptionsLine::Comment(comment.to_string())
+                } else if let Some(definition) = line.strip_prefix("-D").filter(|d| !d.is_empty()) {
+                    match definition.find('=') {
+                        Some(i) => VmOptionsLine::Property(
+                            definition[..i].to_string(),
+                            Some(definition[i + 1..].to_string()),
+                        ),
+                        None => VmOptionsLine::Property(definition.to_string(), None),
+                    }
+                } else {
+                    VmOptionsLine::Flag(line.to_string())
+                }
+            })
+            .collect();
+        VmOptions { lines }
+    }
+
+    /// Reads a UTF-8 encoded `.vmoptions` file.
+    pub fn read<R: Read>(mut input: R) -> Result<Self, PropertiesError> {
+        let mut s = String::new();
+        input.read_to_string(&mut s)?;
+        Ok(Self::parse(&s))
+    }
+
+    /// Returns all lines in order.
+    pub fn lines(&self) -> &[VmOptionsLine] {
+        &self.lines
+    }
+
+    /// Returns the `-D` system properties.
+    ///
+    /// Later definitions of a key replace earlier ones, and `-Dkey` without `=` has an empty value.
+    pub fn properties(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        for line in &self.lines {
+            if let VmOptionsLine::Property(k, v) = line {
+                map.insert(k.clone(), v.clone().unwrap_or_default());
+            }
+        }
+        map
+    }
+
+    /// Returns the options other than `-D` definitions, in order.
+    pub fn flags(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                VmOptionsLine::Flag(flag) => Some(flag.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sets a system property, replacing the last existing definition of the key in place or
+    /// appending a new one.  Earlier duplicate definitions are removed.
+    pub fn set_property(&mut self, key: &str, value: &str) -> Result<(), PropertiesError> {
+        check_key(key)?;
+        let is_key =
+            |line: &VmOptionsLine| matches!(line, VmOptionsLine::Property(k, _) if k == key);
+        let property = VmOptionsLine::Property(key.to_string(), Some(value.to_string()));
+        match self.lines.iter().rposition(is_key) {
+            Some(last) => {
+                self.lines[last] = property;
+                let mut index = 0;
+                self.lines.retain(|line| {
+                    let keep = index >= last || !is_key(line);
+                    index += 1;
+                    keep
+                });
+            }
+            None => self.lines.push(property),
+        }
+        Ok(())
+    }
+
+    /// Removes all definitions of a system property, returning true if any were present.
+    pub fn remove_property(&mut self, key: &str) -> bool {
+        let before = self.lines.len();
+        self.lines
+            .retain(|line| !matches!(line, VmOptionsLine::Property(k, _) if k == key));
+        self.lines.len() != before
+    }
+
+    /// Appends a JVM option such as `-Xmx2g`.
+    pub fn push_flag<S: Into<String>>(&mut self, flag: S) {
+        self.lines.push(VmOptionsLine::Flag(flag.into()));
+    }
+
+    /// Writes the options as a UTF-8 encoded `.vmoptions` file with `\n` line endings.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), PropertiesError> {
+        for line in &self.lines {
+            match line {
+                VmOptionsLine::Blank => writeln!(writer)?,
+                VmOptionsLine::Comment(c) => writeln!(writer, "#{}", c)?,
+                VmOptionsLine::Property(k, Some(v)) => writeln!(writer, "-D{}={}", k, v)?,
+                VmOptionsLine::Property(k, None) => writeln!(writer, "-D{}", k)?,
+                VmOptionsLine::Flag(f) => writeln!(writer, "{}", f)?,
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_java_opts;
+    use super::parse_jvm_args;
+    use super::split_command_line;
+    use super::to_command_line;
+    use super::to_jvm_args;
+    use super::VmOptions;
+
+    #[test]
+    fn jvm_args() {
+        let pairs = vec![
+            ("a", "b"),
+            ("path", "/opt/app dir"),
+            ("quote", "it's"),
+            ("empty", ""),
+        ];
+        assert_eq!(
+            to_jvm_args(pairs.clone()).unwrap(),
+            vec!["-Da=b", "-Dpath=/opt/app dir", "-Dquote=it's", "-Dempty="]
+        );
+        assert_eq!(
+            to_command_line(pairs).unwrap(),
+            r#"-Da=b '-Dpath=/opt/app dir' '-Dquote=it'\''s' -Dempty="#
+        );
+        assert!(to_jvm_args(vec![("a=b", "c")]).is_err());
+        assert!(to_command_line(vec![("", "c")]).is_err());
+    }
+
+    #[test]
+    fn parse_args() {
+        let map = parse_jvm_args(vec![
+            "-Xmx1g", "-Da=b", "-Dflag", "-D", "-D=x", "-Da=c", "-Deq=x=y", "-jar",
+        ]);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["a"], "c");
+        assert_eq!(map["flag"], "");
+        assert_eq!(map["eq"], "x=y");
+    }
+
+    #[test]
+    fn split() {
+        let data: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("  a  b ", &["a", "b"]),
+            ("'a b' c", &["a b", "c"]),
+            (r#""a \"b\" \$c \x""#, &[r#"a "b" $c \x"#]),
+            (r"a\ b", &["a b"]),
+            ("''", &[""]),
+            ("-D'x y'=z", &["-Dx y=z"]),
+        ];
+        for &(input, expected) in data {
+            assert_eq!(split_command_line(input).unwrap(), expected, "{:?}", input);
+        }
+        assert!(split_command_line("'abc").is_err());
+        assert!(split_command_line("\"abc").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let pairs = vec![("path", "/opt/app dir"), ("quote", "it's \"x\"")];
+        let map = parse_java_opts(&to_command_line(pairs.clone()).unwrap()).unwrap();
+        for (k, v) in pairs {
+            assert_eq!(map[k], v);
+        }
+    }
+
+    #[test]
+    fn vmoptions() {
+        let input = "# Custom options\n-Xmx2g\n\n-Dfile.encoding=UTF-8\n-XX:+UseG1GC\n-Dflag\n-Dpath=C:\\Program Files\\x\n";
+        let mut options = VmOptions::read(input.as_bytes()).unwrap();
+        assert_eq!(options.flags(), vec!["-Xmx2g", "-XX:+UseG1GC"]);
+        let props = options.properties();
+        assert_eq!(props.len(), 3);
+        assert_eq!(props["file.encoding"], "UTF-8");
+        assert_eq!(props["flag"], "");
+        assert_eq!(props["path"], "C:\\Program Files\\x");
+
+        let mut out = Vec::new();
+        options.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), input);
+
+        options.set_property("flag", "on").unwrap();
+        assert!(options.remove_property("path"));
+        assert!(!options.remove_property("path"));
+        options.set_property("new", "1").unwrap();
+        options.push_flag("-ea");
+        let mut out = Vec::new();
+        options.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# Custom options\n-Xmx2g\n\n-Dfile.encoding=UTF-8\n-XX:+UseG1GC\n-Dflag=on\n-Dnew=1\n-ea\n"
+        );
+    }
+}
@@ -0,0 +1,538 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Layered configuration, where later layers override earlier ones.
+//!
+//! A typical stack has a defaults file at the bottom, an environment-specific file above it, and
+//! overrides from environment variables on top.  [`PropertiesStack`] keeps every layer separate, so
+//! the source of each effective value can be reported.
+//!
+//! ```no_run
+//! use java_properties::case::KeyCase;
+//! use java_properties::layers::PropertiesStack;
+//!
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let mut stack = PropertiesStack::new();
+//! stack.push_file("config/defaults.properties")?;
+//! stack.push_optional_file("config/prod.properties")?;
+//! stack.push_env("env", "MYAPP_", Some(KeyCase::Dot));
+//! println!("db.url = {:?} (from {:?})", stack.get("db.url"), stack.source_of("db.url"));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::case::KeyCase;
+use crate::properties::Properties;
+use crate::LineContent;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use crate::PropertiesIter;
+use crate::PropertiesWriter;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct LayerValue {
+    value: String,
+    line_number: Option<usize>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct Layer {
+    name: String,
+    values: HashMap<String, LayerValue>,
+}
+
+/// A layer's definition of a key, as returned by [`PropertiesStack::explain`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub struct Definition<'a> {
+    layer: &'a str,
+    value: &'a str,
+    line_number: Option<usize>,
+}
+
+impl<'a> Definition<'a> {
+    /// Returns the name of the layer.
+    pub fn layer(&self) -> &'a str {
+        self.layer
+    }
+
+    /// Returns the value the layer assigns.
+    pub fn value(&self) -> &'a str {
+        self.value
+    }
+
+    /// Returns the 1-based line number of the definition, if the layer was read from a file.
+    pub fn line_number(&self) -> Option<usize> {
+        self.line_number
+    }
+}
+
+impl<'a> Display for Definition<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}", self.layer, self.value)?;
+        if let Some(line_number) = self.line_number {
+            write!(f, " (line_number = {})", line_number)?;
+        }
+        Ok(())
+    }
+}
+
+/// A key defined by more than one file, as returned by [`PropertiesStack::duplicates`].
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct Duplicate<'a> {
+    key: &'a str,
+    definitions: Vec<Definition<'a>>,
+}
+
+impl<'a> Duplicate<'a> {
+    /// Returns the key.
+    pub fn key(&self) -> &'a str {
+        self.key
+    }
+
+    /// Returns each file's definition of the key, in decreasing order of precedence.
+    pub fn definitions(&self) -> &[Definition<'a>] {
+        &self.definitions
+    }
+}
+
+impl<'a> Display for Duplicate<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} is defined in ", self.key)?;
+        for (i, definition) in self.definitions.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", definition)?;
+        }
+        Ok(())
+    }
+}
+
+/// A stack of named layers of properties.
+///
+/// Lookups return the value from the last pushed layer which defines the key.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct PropertiesStack {
+    // In increasing order of precedence.
+    layers: Vec<Layer>,
+}
+
+impl PropertiesStack {
+    /// Creates an empty stack.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer which takes precedence over all existing layers.
+    pub fn push_layer<S: Into<String>>(&mut self, name: S, values: HashMap<String, String>) {
+        self.layers.push(Layer {
+            name: name.into(),
+            values: values
+                .into_iter()
+                .map(|(key, value)| {
+                    (
+                        key,
+                        LayerValue {
+                            value,
+                            line_number: None,
+                        },
+                    )
+                })
+                .collect(),
+        });
+    }
+
+    /// Reads a properties file and adds it as a layer which takes precedence over all existing layers.
+    ///
+    /// The line number of each value is recorded.  If a key appears more than once, the last
+    /// occurrence is used, as with [`read`](crate::read).
+    pub fn push_reader<S: Into<String>, R: Read>(
+        &mut self,
+        name: S,
+        input: R,
+    ) -> Result<(), PropertiesError> {
+        let mut values = HashMap::new();
+        for line in PropertiesIter::new(input) {
+            let line = line?;
+            let line_number = line.line_number();
+            if let LineContent::KVPair(key, value) = line.consume_content() {
+                values.insert(
+                    key,
+                    LayerValue {
+                        value,
+                        line_number: Some(line_number),
+                    },
+                );
+            }
+        }
+        self.layers.push(Layer {
+            name: name.into(),
+            values,
+        });
+        Ok(())
+    }
+
+    /// Reads a properties file and adds it as a layer named after its path.  See [`push_reader`](Self::push_reader).
+    pub fn push_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PropertiesError> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        self.push_reader(path.display().to_string(), BufReader::new(file))
+    }
+
+    /// Like [`push_file`](Self::push_file), but does nothing if the file does not exist.
+    ///
+    /// Returns true if the file was found.
+    pub fn push_optional_file<P: AsRef<Path>>(&mut self, path: P) -> Result<bool, PropertiesError> {
+        let path = path.as_ref();
+        match File::open(path) {
+            Ok(file) => {
+                self.push_reader(path.display().to_string(), BufReader::new(file))?;
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Adds a layer of the process environment variables starting with `prefix`, which takes
+    /// precedence over all existing layers.  See [`Properties::from_env`] for how variable names
+    /// become keys.
+    pub fn push_env<S: Into<String>>(&mut self, name: S, prefix: &str, case: Option<KeyCase>) {
+        self.push_layer(name, Properties::from_env(prefix, case).into_map());
+    }
+
+    /// Returns the names of the layers, in increasing order of precedence.
+    pub fn layer_names(&self) -> Vec<&str> {
+        self.layers.iter().map(|l| l.name.as_str()).collect()
+    }
+
+    /// Returns the number of layers.
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Returns true if there are no layers.
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    fn resolve(&self, key: &str) -> Option<(&Layer, &LayerValue)> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.values.get(key).map(|v| (layer, v)))
+    }
+
+    /// Returns the effective value for the key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.resolve(key).map(|(_, v)| v.value.as_str())
+    }
+
+    /// Returns the name of the layer which supplies the effective value for the key.
+    pub fn source_of(&self, key: &str) -> Option<&str> {
+        self.resolve(key).map(|(layer, _)| layer.name.as_str())
+    }
+
+    /// Returns every layer's definition of the key, in decreasing order of precedence.
+    ///
+    /// The first definition is the effective one, and the rest are overridden.  The result is empty
+    /// if no layer defines the key.
+    ///
+    /// ```
+    /// use java_properties::layers::PropertiesStack;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut stack = PropertiesStack::new();
+    /// stack.push_reader("defaults", "port=80\nhost=localhost\n".as_bytes())?;
+    /// stack.push_reader("prod", "host=example.com\n".as_bytes())?;
+    /// let trace: Vec<String> = stack.explain("host").iter().map(|d| d.to_string()).collect();
+    /// assert_eq!(
+    ///     trace,
+    ///     [
+    ///         "prod: \"example.com\" (line_number = 1)",
+    ///         "defaults: \"localhost\" (line_number = 2)",
+    ///     ]
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn explain(&self, key: &str) -> Vec<Definition<'_>> {
+        self.layers
+            .iter()
+            .rev()
+            .filter_map(|layer| {
+                layer.values.get(key).map(|v| Definition {
+                    layer: &layer.name,
+                    value: &v.value,
+                    line_number: v.line_number,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the keys defined in more than one file, sorted by key.
+    ///
+    /// Only layers read with [`push_reader`](Self::push_reader) or the file methods are compared,
+    /// since layers such as environment variables exist to override files.  Duplicates across files
+    /// are usually configuration mistakes, which precedence would otherwise hide.
+    ///
+    /// ```
+    /// use java_properties::layers::PropertiesStack;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut stack = PropertiesStack::new();
+    /// stack.push_reader("db.properties", "db.url=jdbc:h2:mem:\n".as_bytes())?;
+    /// stack.push_reader("app.properties", "name=app\ndb.url=jdbc:h2:file:\n".as_bytes())?;
+    /// let duplicates = stack.duplicates();
+    /// assert_eq!(duplicates.len(), 1);
+    /// assert_eq!(
+    ///     duplicates[0].to_string(),
+    ///     "\"db.url\" is defined in app.properties: \"jdbc:h2:file:\" (line_number = 2), \
+    ///      db.properties: \"jdbc:h2:mem:\" (line_number = 1)"
+    /// );
+    /// assert!(stack.merged_checked().is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn duplicates(&self) -> Vec<Duplicate<'_>> {
+        let mut counts = HashMap::new();
+        for layer in &self.layers {
+            for (key, v) in &layer.values {
+                if v.line_number.is_some() {
+                    *counts.entry(key.as_str()).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut duplicates: Vec<_> = counts
+            .into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(key, _)| Duplicate {
+                key,
+                definitions: self
+                    .explain(key)
+                    .into_iter()
+                    .filter(|d| d.line_number.is_some())
+                    .collect(),
+            })
+            .collect();
+        duplicates.sort_by_key(|d| d.key);
+        duplicates
+    }
+
+    /// Like [`merged`](Self::merged), but returns an error of kind
+    /// [`PropertiesErrorKind::DuplicateKey`] listing every location if any key is defined in more
+    /// than one file.  See [`duplicates`](Self::duplicates).
+    pub fn merged_checked(&self) -> Result<HashMap<String, String>, PropertiesError> {
+        let duplicates = self.duplicates();
+        if !duplicates.is_empty() {
+            let list: Vec<String> = duplicates.iter().map(|d| d.to_string()).collect();
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::DuplicateKey,
+                format!("Keys defined in more than one file: {}", list.join("; ")),
+                None,
+                None,
+            ));
+        }
+        Ok(self.merged())
+    }
+
+    /// Returns the effective values of all keys.
+    pub fn merged(&self) -> HashMap<String, String> {
+        let mut merged = HashMap::new();
+        for layer in &self.layers {
+            for (key, v) in &layer.values {
+                merged.insert(key.clone(), v.value.clone());
+            }
+        }
+        merged
+    }
+
+    /// Writes the effective values of all keys, sorted by key.
+    pub fn write<W: Write>(&self, writer: &mut PropertiesWriter<W>) -> Result<(), PropertiesError> {
+        let merged = self.merged();
+        let mut keys: Vec<_> = merged.keys().collect();
+        keys.sort();
+        for key in keys {
+            writer.write(key, &merged[key])?;
+        }
+        Ok(())
+    }
+
+    /// Writes the effective values of all keys, grouped by the layer which supplies them.
+    ///
+    /// Groups are written in increasing order of precedence, each preceded by a comment naming the
+    /// layer, and keys are sorted within each group.  Layers which don't supply any effective values are omitted.
+    ///
+    /// ```
+    /// use java_properties::layers::PropertiesStack;
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut stack = PropertiesStack::new();
+    /// stack.push_reader("defaults", "port=80\nhost=localhost\n".as_bytes())?;
+    /// stack.push_reader("prod", "host=example.com\n".as_bytes())?;
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// stack.write_grouped_by_layer(&mut writer)?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(
+    ///     String::from_utf8(buf).unwrap(),
+    ///     "# layer: defaults\nport=80\n# layer: prod\nhost=example.com\n"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_grouped_by_layer<W: Write>(
+        &self,
+        writer: &mut PropertiesWriter<W>,
+    ) -> Result<(), PropertiesError> {
+        for (i, layer) in self.layers.iter().enumerate() {
+            let mut keys: Vec<_> = layer
+                .values
+                .keys()
+                .filter(|key| {
+                    !self.layers[i + 1..]
+                        .iter()
+                        .any(|l| l.values.contains_key(*key))
+                })
+                .collect();
+            if keys.is_empty() {
+                continue;
+            }
+            keys.sort();
+            writer.write_comment(&format!("layer: {}", layer.name))?;
+            for key in keys {
+                writer.write(key, &layer.values[key].value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PropertiesStack;
+    use crate::test_dir;
+    use crate::PropertiesErrorKind;
+    use crate::PropertiesWriter;
+    use std::collections::HashMap;
+
+    fn write_grouped(stack: &PropertiesStack) -> String {
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            stack.write_grouped_by_layer(&mut writer).unwrap();
+            writer.finish().unwrap();
+        }
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn lookups() {
+        let mut stack = PropertiesStack::new();
+        stack
+            .push_reader("defaults", "a=1\nb=2\nc=3\n".as_bytes())
+            .unwrap();
+        let mut env = HashMap::new();
+        env.insert("b".to_string(), "20".to_string());
+        stack.push_layer("env", env);
+        assert_eq!(stack.layer_names(), vec!["defaults", "env"]);
+        assert_eq!(stack.get("a"), Some("1"));
+        assert_eq!(stack.get("b"), Some("20"));
+        assert_eq!(stack.get("d"), None);
+        assert_eq!(stack.source_of("a"), Some("defaults"));
+        assert_eq!(stack.source_of("b"), Some("env"));
+        assert_eq!(stack.merged().len(), 3);
+
+        let trace = stack.explain("b");
+        assert_eq!(trace.len(), 2);
+        assert_eq!(
+            (trace[0].layer(), trace[0].value(), trace[0].line_number()),
+            ("env", "20", None)
+        );
+        assert_eq!(
+            (trace[1].layer(), trace[1].value(), trace[1].line_number()),
+            ("defaults", "2", Some(2))
+        );
+        assert_eq!(trace[1].to_string(), "defaults: \"2\" (line_number = 2)");
+        assert!(stack.explain("d").is_empty());
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut stack = PropertiesStack::new();
+        stack.push_reader("a", "x=1\ny=1\n".as_bytes()).unwrap();
+        let mut env = HashMap::new();
+        env.insert("y".to_string(), "env".to_string());
+        stack.push_layer("env", env);
+        assert!(stack.duplicates().is_empty());
+        assert_eq!(stack.merged_checked().unwrap()["y"], "env");
+
+        stack
+            .push_reader("b", "z=2\ny=2\nx=2\n".as_bytes())
+            .unwrap();
+        stack.push_reader("c", "y=3\n".as_bytes()).unwrap();
+        let duplicates = stack.duplicates();
+        let keys: Vec<_> = duplicates.iter().map(|d| d.key()).collect();
+        assert_eq!(keys, ["x", "y"]);
+        let locations: Vec<_> = duplicates[1]
+            .definitions()
+            .iter()
+            .map(|d| (d.layer(), d.line_number().unwrap()))
+            .collect();
+        assert_eq!(locations, [("c", 1), ("b", 2), ("a", 2)]);
+
+        let err = stack.merged_checked().unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::DuplicateKey);
+        assert!(err.to_string().starts_with(
+            "Keys defined in more than one file: \"x\" is defined in b: \"2\" (line_number = 3), \
+             a: \"1\" (line_number = 1); \"y\" is defined in c:"
+        ));
+    }
+
+    #[test]
+    fn files() {
+        let dir = test_dir();
+        let defaults = dir.path().join("defaults.properties");
+        std::fs::write(&defaults, "a=1\nb=2\n").unwrap();
+
+        let mut stack = PropertiesStack::new();
+        stack.push_file(&defaults).unwrap();
+        assert!(!stack
+            .push_optional_file(dir.path().join("missing.properties"))
+            .unwrap());
+        assert!(stack
+            .push_file(dir.path().join("missing.properties"))
+            .is_err());
+
+        assert_eq!(stack.len(), 1);
+        let name = defaults.display().to_string();
+        assert_eq!(stack.source_of("b"), Some(name.as_str()));
+    }
+
+    #[test]
+    fn grouped_output() {
+        let mut stack = PropertiesStack::new();
+        stack
+            .push_reader("defaults", "b=2\na=1\n".as_bytes())
+            .unwrap();
+        stack.push_reader("shadowed", "a=x\n".as_bytes()).unwrap();
+        stack
+            .push_reader("local", "a=10\nz=26\n".as_bytes())
+            .unwrap();
+        assert_eq!(
+            write_grouped(&stack),
+            "# layer: defaults\nb=2\n# layer: local\na=10\nz=26\n"
+        );
+        assert_eq!(write_grouped(&PropertiesStack::new()), "");
+    }
+}
@@ -66,55 +66,266 @@
 
 use encoding_rs::CoderResult;
 use encoding_rs::Decoder;
+use encoding_rs::DecoderResult;
 use encoding_rs::Encoder;
 use encoding_rs::EncoderResult;
 use encoding_rs::Encoding;
+use encoding_rs::UTF_16BE;
+use encoding_rs::UTF_16LE;
+use encoding_rs::UTF_8;
 use encoding_rs::WINDOWS_1252;
 use lazy_static::lazy_static;
 #[cfg(feature = "unicode")]
 use regex::Regex;
 #[cfg(not(feature = "unicode"))]
 use regex_lite::Regex;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::convert::From;
 use std::error::Error;
+use std::ffi::OsString;
 use std::fmt;
+use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::fmt::Write as _;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::io;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
-use std::iter::Peekable;
+use std::mem;
 use std::ops::Deref;
+use std::ops::DerefMut;
+use std::ops::Range;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+pub mod android;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod bundle;
+pub mod cache;
+pub mod case;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+pub mod diff;
+pub mod document;
+pub mod dotenv;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod ini;
+pub mod interpolation;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod jvm;
+pub mod layers;
+pub mod lint;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+pub mod metadata;
+pub mod properties;
+pub mod schema;
+#[cfg(feature = "serde")]
+pub mod ser;
+pub mod shell;
+#[cfg(feature = "toml")]
+pub mod toml;
+pub mod tree;
+pub mod typed;
+#[cfg(feature = "yaml")]
+pub mod yaml;
+
+#[cfg(feature = "tokio")]
+pub use crate::async_io::AsyncPropertiesIter;
+#[cfg(feature = "tokio")]
+pub use crate::async_io::AsyncPropertiesWriter;
+pub use crate::document::PropertiesDocument;
+pub use crate::properties::Properties;
+pub use crate::typed::TypedProperties;
+
+/// Parses a properties file at compile time and embeds its contents as a
+/// `&'static [(&'static str, &'static str)]`.
+///
+/// The path is relative to the directory containing the crate's `Cargo.toml`.
+/// Pairs are in the order their keys first appear in the file, and if a key appears more than once,
+/// the last value wins, as with [`read`].  The file is decoded as ISO-8859-1 unless another
+/// encoding is given with `encoding = "..."`.  Malformed files are reported as compile errors, and
+/// the crate is rebuilt when the file changes.
+///
+/// This is only available with the `macros` feature.
+///
+/// ```
+/// use java_properties::include_properties;
+///
+/// static BUILD_INFO: &[(&str, &str)] = include_properties!("testdata/build-info.properties");
+/// static BUILD_INFO_UTF8: &[(&str, &str)] =
+///     include_properties!("testdata/build-info.properties", encoding = "UTF-8");
+///
+/// assert_eq!(BUILD_INFO[0], ("version", "1.2.4"));
+/// assert_eq!(BUILD_INFO.len(), BUILD_INFO_UTF8.len());
+/// ```
+#[cfg(feature = "macros")]
+pub use java_properties_macros::include_properties;
 
 /////////////////////
 
+/// The category of a [`PropertiesError`].
+///
+/// More kinds may be added in the future, so matches need a wildcard arm.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+#[non_exhaustive]
+pub enum PropertiesErrorKind {
+    /// Reading or writing failed.  The underlying [`io::Error`] is the error's source.
+    Io,
+    /// The input contains bytes which are not valid in its encoding.
+    Decode,
+    /// A character can't be represented in the output encoding.
+    Encode,
+    /// A `\uxxxx` escape is truncated, not hexadecimal, or not a valid character.
+    MalformedUnicodeEscape,
+    /// A comment prefix passed to [`PropertiesWriter::set_comment_prefix`] is not valid.
+    InvalidCommentPrefix,
+    /// A separator passed to [`PropertiesWriter::set_kv_separator`] is not valid.
+    InvalidSeparator,
+    /// A key appears more than once; see [`DuplicateKeyPolicy::Error`].
+    DuplicateKey,
+    /// A key can't be written in the requested format.
+    InvalidKey,
+    /// A value can't be written in the requested format.
+    InvalidValue,
+    /// The input doesn't follow the syntax of its format.
+    Syntax,
+    /// A key the operation refers to doesn't exist.
+    KeyNotFound,
+    /// A `${...}` placeholder refers to a key with no value.
+    UnresolvedPlaceholder,
+    /// Placeholders refer to each other in a cycle, or are nested too deeply.
+    PlaceholderRecursion,
+    /// Parsing was stopped by a cancellation check.
+    Cancelled,
+    /// A value's [`Display`] implementation returned an error.
+    Format,
+    /// A value can't be serialized as properties.
+    Serialize,
+    /// A path doesn't name a file.
+    InvalidPath,
+    /// Encrypted input can't be decrypted, because the key is wrong or the input was modified.
+    Encryption,
+    /// A line is continued over more lines than allowed; see
+    /// [`PropertiesIter::set_max_continuation_lines`].
+    TooManyContinuationLines,
+    /// A key has a value and is also the prefix of other keys, such as `a.b` and `a.b.c`, where a
+    /// format only allows one; see [`tree::to_tree`].
+    KeyConflict,
+    /// Expanding placeholders produces a value longer than allowed; see
+    /// [`Resolver::set_max_length`](interpolation::Resolver::set_max_length).
+    ExpansionTooLong,
+}
+
 /// The error type for reading and writing properties files.
 #[derive(Debug)]
 pub struct PropertiesError {
+    kind: PropertiesErrorKind,
     description: String,
     cause: Option<Box<dyn Error + 'static + Send + Sync>>,
     line_number: Option<usize>,
+    column: Option<usize>,
+    byte_range: Option<Range<u64>>,
+    source_name: Option<Arc<str>>,
 }
 
 impl PropertiesError {
     fn new<S: Into<String>>(
+        kind: PropertiesErrorKind,
         description: S,
         cause: Option<Box<dyn Error + 'static + Send + Sync>>,
         line_number: Option<usize>,
     ) -> Self {
         PropertiesError {
+            kind,
             description: description.into(),
             cause,
             line_number,
+            column: None,
+            byte_range: None,
+            source_name: None,
         }
     }
 
+    /// Returns the category of the error.
+    pub fn kind(&self) -> PropertiesErrorKind {
+        self.kind
+    }
+
+    /// Creates an error reporting a duplicate key, for use in a handler passed to
+    /// [`PropertiesIter::on_duplicate_key`].
+    pub fn duplicate_key(duplicate: &DuplicateKey<'_>) -> Self {
+        PropertiesError::new(
+            PropertiesErrorKind::DuplicateKey,
+            format!(
+                "Duplicate key {:?} (previously defined on line {})",
+                duplicate.key(),
+                duplicate.previous_line_number()
+            ),
+            None,
+            Some(duplicate.line_number()),
+        )
+    }
+
     /// Returns the 1-based line number associated with the error, if available.
     pub fn line_number(&self) -> Option<usize> {
         self.line_number
     }
+
+    /// Returns the 1-based column, counted in characters, associated with the error, if available.
+    ///
+    /// This is known for malformed `\uxxxx` escapes, where it is the column of the backslash, if
+    /// [`PropertiesIterBuilder::track_positions`] is set, and for malformed input with
+    /// [`PropertiesIterBuilder::strict_decoding`].
+    pub fn column(&self) -> Option<usize> {
+        self.column
+    }
+
+    /// Returns the range of bytes in the input which caused the error, if available, such as a
+    /// malformed `\uxxxx` escape.  See [`column`](Self::column).
+    ///
+    /// ```
+    /// use java_properties::PropertiesIterBuilder;
+    ///
+    /// let input = "a=1\nb=x\\u12g4\n";
+    /// let mut iter = PropertiesIterBuilder::new()
+    ///     .track_positions(true)
+    ///     .build(input.as_bytes());
+    /// let err = iter.nth(1).unwrap().unwrap_err();
+    /// assert_eq!(err.line_number(), Some(2));
+    /// assert_eq!(err.column(), Some(4));
+    /// assert_eq!(err.byte_range(), Some(7..13));
+    /// assert_eq!(&input[7..13], "\\u12g4");
+    /// ```
+    pub fn byte_range(&self) -> Option<Range<u64>> {
+        self.byte_range.clone()
+    }
+
+    /// Returns the name of the input which caused the error, if one was given with
+    /// [`PropertiesIter::with_source_name`].
+    pub fn source_name(&self) -> Option<&str> {
+        self.source_name.as_deref()
+    }
 }
 
 impl Error for PropertiesError {
@@ -134,16 +345,25 @@ impl Error for PropertiesError {
 
 impl From<io::Error> for PropertiesError {
     fn from(e: io::Error) -> Self {
-        PropertiesError::new("I/O error", Some(Box::new(e)), None)
+        PropertiesError::new(
+            PropertiesErrorKind::Io,
+            "I/O error",
+            Some(Box::new(e)),
+            None,
+        )
     }
 }
 
 impl Display for PropertiesError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", &self.description)?;
+        write!(f, " (")?;
+        if let Some(source_name) = &self.source_name {
+            write!(f, "source = {}, ", source_name)?;
+        }
         match self.line_number {
-            Some(n) => write!(f, " (line_number = {})", n),
-            None => write!(f, " (line_number = unknown)"),
+            Some(n) => write!(f, "line_number = {})", n),
+            None => write!(f, "line_number = unknown)"),
         }
     }
 }
@@ -151,34 +371,137 @@ impl Display for PropertiesError {
 /////////////////////
 
 struct DecodeIter<R: Read> {
+    encoding: &'static Encoding,
     decoder: Decoder,
     reader: R,
     input_buffer: Vec<u8>,
     output_buffer: String,
     chars: VecDeque<char>,
+    bytes_read: u64,
+    detect_bom: bool,
+    // If true, malformed input is an error instead of being replaced with U+FFFD.
+    strict: bool,
+    // An error to return once the characters decoded before it have been returned.
+    pending_error: Option<PropertiesError>,
+    // Whether to count `offset`, which is only needed for positions.
+    track_offsets: bool,
+    // The byte offset in the input of the next character returned.
+    offset: u64,
+    // The first few bytes of the input, to find the length of a byte order mark.
+    head: Vec<u8>,
+    bom_checked: bool,
 }
 
+// The default size of the buffer input is read into.
+const DEFAULT_BUFFER_SIZE: usize = 64;
+
 impl<R: Read> DecodeIter<R> {
-    fn new(reader: R, encoding: &'static Encoding) -> Self {
+    fn new(reader: R, encoding: &'static Encoding, buffer_size: usize) -> Self {
         Self {
+            encoding,
             decoder: encoding.new_decoder(),
             reader,
             // must have a non-zero capacity since we double it as needed
-            input_buffer: Vec::with_capacity(64),
+            input_buffer: Vec::with_capacity(buffer_size.max(1)),
             // must have a non-zero capacity since we double it as needed
             output_buffer: String::with_capacity(64),
             chars: VecDeque::new(),
+            bytes_read: 0,
+            detect_bom: true,
+            strict: false,
+            pending_error: None,
+            track_offsets: false,
+            offset: 0,
+            head: Vec::with_capacity(3),
+            bom_checked: false,
+        }
+    }
+
+    // Decodes the input strictly as `encoding`, even if it starts with a byte order mark.
+    fn disable_bom_detection(&mut self) {
+        self.detect_bom = false;
+        self.decoder = self.encoding.new_decoder_without_bom_handling();
+    }
+
+    fn new_decoder(&self) -> Decoder {
+        if self.detect_bom {
+            self.encoding.new_decoder()
+        } else {
+            self.encoding.new_decoder_without_bom_handling()
+        }
+    }
+
+    // Skips over the byte order mark in `offset`, if the decoder removed one.  This must be called
+    // before the first character is returned, by which point the decoder has seen the whole mark.
+    fn check_bom(&mut self) {
+        if !self.bom_checked {
+            self.bom_checked = true;
+            if self.detect_bom {
+                if let Some((_, len)) = Encoding::for_bom(&self.head) {
+                    self.offset = len as u64;
+                }
+            }
         }
     }
 }
 
+// Returns the number of bytes `s` takes up in `encoding`, to map positions in decoded text back to
+// the input.  This is inexact for input which was malformed and replaced with U+FFFD.
+fn encoded_len(encoding: &'static Encoding, s: &str) -> u64 {
+    if encoding == UTF_8 {
+        s.len() as u64
+    } else if encoding == UTF_16LE || encoding == UTF_16BE {
+        2 * s.encode_utf16().count() as u64
+    } else if encoding.is_single_byte() {
+        s.chars().count() as u64
+    } else {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii() {
+                    1
+                } else {
+                    let mut buf = [0; 4];
+                    encoding.encode(c.encode_utf8(&mut buf)).0.len() as u64
+                }
+            })
+            .sum()
+    }
+}
+
+impl<R: Read + Seek> DecodeIter<R> {
+    fn rewind(&mut self) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.decoder = self.new_decoder();
+        self.input_buffer.clear();
+        self.output_buffer.clear();
+        self.chars.clear();
+        self.bytes_read = 0;
+        self.pending_error = None;
+        self.offset = 0;
+        self.head.clear();
+        self.bom_checked = false;
+        Ok(())
+    }
+}
+
 impl<R: Read> Iterator for DecodeIter<R> {
-    type Item = Result<char, io::Error>;
+    // Each character comes with its byte offset in the input, or 0 if offsets aren't tracked.
+    // Errors don't have line numbers; NaturalLines adds them.
+    type Item = Result<(char, u64), PropertiesError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             if let Some(c) = self.chars.pop_front() {
-                return Some(Ok(c));
+                if !self.track_offsets {
+                    return Some(Ok((c, 0)));
+                }
+                self.check_bom();
+                let offset = self.offset;
+                self.offset += encoded_len(self.decoder.encoding(), c.encode_utf8(&mut [0; 4]));
+                return Some(Ok((c, offset)));
+            }
+            if let Some(e) = self.pending_error.take() {
+                return Some(Err(e));
             }
             let reader_eof = if self.input_buffer.is_empty() {
                 self.input_buffer.resize(self.input_buffer.capacity(), 0);
@@ -186,28 +509,72 @@ impl<R: Read> Iterator for DecodeIter<R> {
                     Ok(x) => x,
                     Err(e) => {
                         self.input_buffer.clear();
-                        return Some(Err(e));
+                        return Some(Err(PropertiesError::new(
+                            PropertiesErrorKind::Io,
+                            "I/O error",
+                            Some(Box::new(e)),
+                            None,
+                        )));
                     }
                 };
                 self.input_buffer.truncate(bytes_read);
+                self.bytes_read += bytes_read as u64;
+                if self.head.len() < 3 {
+                    let n = (3 - self.head.len()).min(bytes_read);
+                    self.head.extend_from_slice(&self.input_buffer[..n]);
+                }
                 bytes_read == 0
             } else {
                 false
             };
-            let (result, bytes_read, _) = self.decoder.decode_to_string(
-                &self.input_buffer,
-                &mut self.output_buffer,
-                reader_eof,
-            );
-            self.input_buffer.drain(..bytes_read);
-            match result {
-                CoderResult::InputEmpty => (),
-                CoderResult::OutputFull => {
-                    self.output_buffer.reserve(self.output_buffer.capacity());
+            if self.strict {
+                let decoded_offset = self.bytes_read - self.input_buffer.len() as u64;
+                let (result, bytes_read) = self.decoder.decode_to_string_without_replacement(
+                    &self.input_buffer,
+                    &mut self.output_buffer,
+                    reader_eof,
+                );
+                self.input_buffer.drain(..bytes_read);
+                match result {
+                    DecoderResult::InputEmpty => (),
+                    DecoderResult::OutputFull => {
+                        self.output_buffer.reserve(self.output_buffer.capacity());
+                    }
+                    DecoderResult::Malformed(malformed_len, consumed_after) => {
+                        let offset = decoded_offset + bytes_read as u64
+                            - malformed_len as u64
+                            - consumed_after as u64;
+                        let mut error = PropertiesError::new(
+                            PropertiesErrorKind::Decode,
+                            format!(
+                                "Malformed {} input at byte offset {}",
+                                self.decoder.encoding().name(),
+                                offset
+                            ),
+                            None,
+                            None,
+                        );
+                        error.byte_range = Some(offset..offset + malformed_len as u64);
+                        self.pending_error = Some(error);
+                    }
                 }
-            };
+            } else {
+                let (result, bytes_read, _) = self.decoder.decode_to_string(
+                    &self.input_buffer,
+                    &mut self.output_buffer,
+                    reader_eof,
+                );
+                self.input_buffer.drain(..bytes_read);
+                match result {
+                    CoderResult::InputEmpty => (),
+                    CoderResult::OutputFull => {
+                        self.output_buffer.reserve(self.output_buffer.capacity());
+                    }
+                };
+            }
             self.chars.extend(self.output_buffer.drain(..));
-            if self.chars.is_empty() && reader_eof {
+            if self.chars.is_empty() && self.pending_error.is_none() && reader_eof {
+                self.check_bom();
                 return None;
             }
         }
@@ -216,24 +583,51 @@ impl<R: Read> Iterator for DecodeIter<R> {
 
 /////////////////////
 
+// The line number, the text, the range of bytes in the input holding the text, and the line
+// terminator which ends it, or "" at the end of the input.
 #[derive(PartialEq, Eq, Debug)]
-struct NaturalLine(usize, String);
+struct NaturalLine(usize, String, Range<u64>, &'static str);
 
 // We can't use BufRead.lines() because it doesn't use the proper line endings
 struct NaturalLines<R: Read> {
-    chars: Peekable<DecodeIter<R>>,
+    chars: DecodeIter<R>,
+    // Like Peekable, but we need access to the underlying reader to rewind it.
+    peeked: Option<Option<Result<(char, u64), PropertiesError>>>,
     eof: bool,
     line_count: usize,
 }
 
 impl<R: Read> NaturalLines<R> {
-    fn new(reader: R, encoding: &'static Encoding) -> Self {
+    fn new(reader: R, encoding: &'static Encoding, buffer_size: usize) -> Self {
         NaturalLines {
-            chars: DecodeIter::new(reader, encoding).peekable(),
+            chars: DecodeIter::new(reader, encoding, buffer_size),
+            peeked: None,
             eof: false,
             line_count: 0,
         }
     }
+
+    fn next_char(&mut self) -> Option<Result<(char, u64), PropertiesError>> {
+        match self.peeked.take() {
+            Some(c) => c,
+            None => self.chars.next(),
+        }
+    }
+
+    fn peek_char(&mut self) -> Option<&Result<(char, u64), PropertiesError>> {
+        let chars = &mut self.chars;
+        self.peeked.get_or_insert_with(|| chars.next()).as_ref()
+    }
+}
+
+impl<R: Read + Seek> NaturalLines<R> {
+    fn rewind(&mut self) -> io::Result<()> {
+        self.chars.rewind()?;
+        self.peeked = None;
+        self.eof = false;
+        self.line_count = 0;
+        Ok(())
+    }
 }
 
 const LF: char = '\n';
@@ -247,31 +641,46 @@ impl<R: Read> Iterator for NaturalLines<R> {
             return None;
         }
         let mut buf = String::new();
+        let mut start = None;
         loop {
-            match self.chars.next() {
-                Some(Ok(CR)) => {
-                    if let Some(&Ok(LF)) = self.chars.peek() {
-                        self.chars.next();
+            match self.next_char() {
+                Some(Ok((CR, offset))) => {
+                    let mut terminator = "\r";
+                    if let Some(&Ok((LF, _))) = self.peek_char() {
+                        self.next_char();
+                        terminator = "\r\n";
                     }
                     self.line_count += 1;
-                    return Some(Ok(NaturalLine(self.line_count, buf)));
+                    let start = start.unwrap_or(offset);
+                    return Some(Ok(NaturalLine(
+                        self.line_count,
+                        buf,
+                        start..offset,
+                        terminator,
+                    )));
                 }
-                Some(Ok(LF)) => {
+                Some(Ok((LF, offset))) => {
                     self.line_count += 1;
-                    return Some(Ok(NaturalLine(self.line_count, buf)));
+                    let start = start.unwrap_or(offset);
+                    return Some(Ok(NaturalLine(self.line_count, buf, start..offset, "\n")));
+                }
+                Some(Ok((c, offset))) => {
+                    start.get_or_insert(offset);
+                    buf.push(c);
                 }
-                Some(Ok(c)) => buf.push(c),
-                Some(Err(e)) => {
-                    return Some(Err(PropertiesError::new(
-                        "I/O error",
-                        Some(Box::new(e)),
-                        Some(self.line_count + 1),
-                    )))
+                Some(Err(mut e)) => {
+                    e.line_number = Some(self.line_count + 1);
+                    if e.byte_range.is_some() {
+                        e.column = Some(buf.chars().count() + 1);
+                    }
+                    return Some(Err(e));
                 }
                 None => {
                     self.eof = true;
                     self.line_count += 1;
-                    return Some(Ok(NaturalLine(self.line_count, buf)));
+                    let end = self.chars.offset;
+                    let start = start.unwrap_or(end);
+                    return Some(Ok(NaturalLine(self.line_count, buf, start..end, "")));
                 }
             }
         }
@@ -281,23 +690,105 @@ impl<R: Read> Iterator for NaturalLines<R> {
 /////////////////////
 
 #[derive(PartialEq, Eq, Debug)]
-struct LogicalLine(usize, String);
+struct LogicalLine(usize, String, LinePositions);
+
+// Maps byte indices in a logical line back to the natural lines it was joined from.
+#[derive(PartialEq, Eq, Debug, Default)]
+struct LinePositions {
+    segments: Vec<Segment>,
+    // The byte offset in the input just past the last natural line, excluding its terminator.
+    end: u64,
+    // The natural lines as they appear in the input, without the final terminator, if kept.
+    raw: Option<String>,
+}
+
+// The part of a logical line which came from one natural line.
+#[derive(PartialEq, Eq, Debug)]
+struct Segment {
+    // The byte index in the logical line where the segment starts.
+    start: usize,
+    line_number: usize,
+    // The byte offset in the input of the natural line.
+    offset: u64,
+    // Leading whitespace removed from the natural line.
+    indent: String,
+}
+
+impl LinePositions {
+    // Returns the line number, the 1-based column, and the byte offset in the input of byte `index`
+    // of the logical line `text`.
+    fn locate(
+        &self,
+        text: &str,
+        index: usize,
+        encoding: &'static Encoding,
+    ) -> Option<(usize, usize, u64)> {
+        let segment = self.segments.iter().rev().find(|s| s.start <= index)?;
+        let piece = &text[segment.start..index];
+        Some((
+            segment.line_number,
+            1 + segment.indent.chars().count() + piece.chars().count(),
+            segment.offset + encoded_len(encoding, &segment.indent) + encoded_len(encoding, piece),
+        ))
+    }
+
+    // Points `e` at `malformed`, a slice of the logical line `text`.
+    fn locate_error(
+        &self,
+        e: &mut PropertiesError,
+        text: &str,
+        malformed: &str,
+        encoding: &'static Encoding,
+    ) {
+        let start = malformed.as_ptr() as usize - text.as_ptr() as usize;
+        let end = start + malformed.len();
+        if let (Some((line_number, column, start)), Some((_, _, end))) = (
+            self.locate(text, start, encoding),
+            self.locate(text, end, encoding),
+        ) {
+            e.line_number = Some(line_number);
+            e.column = Some(column);
+            e.byte_range = Some(start..end);
+        }
+    }
+}
 
 struct LogicalLines<I: Iterator<Item = Result<NaturalLine, PropertiesError>>> {
     physical_lines: I,
     eof: bool,
+    // False for dialects without line continuations, where every natural line is a logical line.
+    continuations: bool,
+    blank_continuation_lines: BlankContinuationLine,
+    max_continuation_lines: Option<usize>,
+    // Whether to record where each logical line came from, for `Line` positions and error locations.
+    track_positions: bool,
+    keep_raw_text: bool,
 }
 
 impl<I: Iterator<Item = Result<NaturalLine, PropertiesError>>> LogicalLines<I> {
-    fn new(physical_lines: I) -> Self {
+    fn new(physical_lines: I, continuations: bool) -> Self {
         LogicalLines {
             physical_lines,
             eof: false,
+            continuations,
+            blank_continuation_lines: BlankContinuationLine::Terminate,
+            max_continuation_lines: None,
+            track_positions: false,
+            keep_raw_text: false,
         }
     }
 }
 
-fn count_ending_backslashes(s: &str) -> usize {
+lazy_static! {
+    static ref COMMENT_RE: Regex = Regex::new("^[ \t\r\n\x0c]*[#!]").unwrap();
+}
+
+/// Returns true if a natural line starts a comment.
+pub(crate) fn is_comment_line(line: &str) -> bool {
+    COMMENT_RE.is_match(line)
+}
+
+pub(crate) fn count_ending_backslashes(s: &str) -> usize {
     let mut n = 0;
     for c in s.chars() {
         if c == '\\' {
@@ -317,36 +808,91 @@ impl<I: Iterator<Item = Result<NaturalLine, PropertiesError>>> Iterator for Logi
             return None;
         }
         let mut buf = String::new();
+        let mut positions = LinePositions::default();
+        if self.keep_raw_text {
+            positions.raw = Some(String::new());
+        }
+        // The terminator of the previous natural line, which precedes this one in the raw text.
+        let mut previous_terminator = "";
         let mut first = true;
         let mut line_number = 0;
+        let mut continuation_lines = 0;
+        // Spaces from blank continuation lines, added once more of the logical line follows.
+        let mut pending_spaces = 0;
         loop {
             match self.physical_lines.next() {
                 Some(Err(e)) => return Some(Err(e)),
-                Some(Ok(NaturalLine(line_no, line))) => {
+                Some(Ok(NaturalLine(line_no, line, range, terminator))) => {
+                    if let Some(raw) = &mut positions.raw {
+                        raw.push_str(previous_terminator);
+                        raw.push_str(&line);
+                    }
+                    previous_terminator = terminator;
+                    if !first {
+                        continuation_lines += 1;
+                        match self.max_continuation_lines {
+                            Some(max) if continuation_lines > max => {
+                                return Some(Err(PropertiesError::new(
+                                    PropertiesErrorKind::TooManyContinuationLines,
+                                    format!("Line is continued over more than {} lines", max),
+                                    None,
+                                    Some(line_number),
+                                )));
+                            }
+                            _ => (),
+                        }
+                    }
                     if first {
                         line_number = line_no;
+                    } else if line.trim().is_empty() {
+                        match self.blank_continuation_lines {
+                            BlankContinuationLine::Terminate => (),
+                            BlankContinuationLine::Skip => continue,
+                            BlankContinuationLine::Space => {
+                                pending_spaces += 1;
+                                continue;
+                            }
+                        }
+                    } else {
+                        buf.push_str(&" ".repeat(pending_spaces));
+                        pending_spaces = 0;
+                    }
+                    let text = if first { &line } else { line.trim_start() };
+                    if self.track_positions {
+                        positions.segments.push(Segment {
+                            start: buf.len(),
+                            line_number: line_no,
+                            offset: range.start,
+                            indent: line[..line.len() - text.len()].to_string(),
+                        });
+                        positions.end = range.end;
                     }
-                    buf.push_str(if first { &line } else { line.trim_start() });
-                    lazy_static! {
-                        static ref COMMENT_RE: Regex = Regex::new("^[ \t\r\n\x0c]*[#!]").unwrap();
+                    buf.push_str(text);
+                    if !self.continuations {
+                        return Some(Ok(LogicalLine(line_number, buf, positions)));
                     }
-                    if first && COMMENT_RE.is_match(&line) {
+                    if first && is_comment_line(&line) {
                         // This format is terrible.  We can't throw out comment lines before joining natural lines, because "a\\\n#b" should be joined into "a#b".
                         // On the other hand, we can't join natural lines before processing comments, because "#a\\\nb" should stay as two lines, "#a\\" and "b".
                         // Processing line joins and comments are inextricably linked.
                         assert!(line_number != 0);
-                        return Some(Ok(LogicalLine(line_number, buf)));
+                        return Some(Ok(LogicalLine(line_number, buf, positions)));
                     }
                     if count_ending_backslashes(&line) % 2 == 1 {
                         buf.pop();
                     } else {
                         assert!(line_number != 0);
-                        return Some(Ok(LogicalLine(line_number, buf)));
+                        return Some(Ok(LogicalLine(line_number, buf, positions)));
                     }
                 }
                 None => {
                     self.eof = true;
-                    return None;
+                    // A continuation at the end of the file ends the logical line.
+                    return if first {
+                        None
+                    } else {
+                        Some(Ok(LogicalLine(line_number, buf, positions)))
+                    };
                 }
             }
             first = false;
@@ -357,16 +903,230 @@ impl<I: Iterator<Item = Result<NaturalLine, PropertiesError>>> Iterator for Logi
 /////////////////////
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
-enum ParsedLine<'a> {
+pub(crate) enum ParsedLine<'a> {
     Comment(&'a str),
-    KVPair(&'a str, &'a str),
+    KVPair(&'a str, &'a str, KVSeparator),
+}
+
+/// How the key and value of a key/value line are separated.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
+pub enum KVSeparator {
+    /// There is no separator and no value, as in `key`.
+    Absent,
+    /// An equals sign, possibly surrounded by whitespace, as in `key=value` or `key =`.
+    Equals,
+    /// A colon, possibly surrounded by whitespace, as in `key: value` or `key:`.
+    Colon,
+    /// Whitespace only, as in `key value`.
+    Whitespace,
+}
+
+/// A file format understood by [`PropertiesIter`] and [`PropertiesWriter`].
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash, Default)]
+pub enum Dialect {
+    /// Java properties files.  This is the default.
+    #[default]
+    Java,
+    /// `.env` files; see [`dotenv`].
+    DotEnv,
+    /// Android `build.prop` and system property files; see [`android`].
+    AndroidProp,
+    /// INI files, whose `[section]` headers are key prefixes; see [`ini`].
+    Ini,
+}
+
+impl Dialect {
+    fn has_continuations(self) -> bool {
+        match self {
+            Dialect::Java => true,
+            Dialect::DotEnv | Dialect::AndroidProp | Dialect::Ini => false,
+        }
+    }
+
+    /// Reads a UTF-8 file in this dialect into a hash map.
+    pub(crate) fn read_utf8<R: Read>(
+        self,
+        input: R,
+    ) -> Result<HashMap<String, String>, PropertiesError> {
+        let mut iter = PropertiesIterBuilder::new()
+            .encoding(UTF_8)
+            .dialect(self)
+            .build(input);
+        let mut map = HashMap::new();
+        iter.read_into(|k, v| {
+            map.insert(k, v);
+        })?;
+        Ok(map)
+    }
+
+    /// Writes key/value pairs to a UTF-8 file in this dialect, in the order given.
+    pub(crate) fn write_utf8<W, I, K, V>(self, writer: W, pairs: I) -> Result<(), PropertiesError>
+    where
+        W: Write,
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut writer = PropertiesWriter::new_with_encoding(writer, UTF_8);
+        writer.set_dialect(self);
+        for (key, value) in pairs {
+            writer.write(key.as_ref(), value.as_ref())?;
+        }
+        writer.finish()
+    }
+}
+
+/// Sanitizes a key, or part of one, for a dialect without escapes: whitespace at either end and
+/// characters for which `bad` returns true are replaced with `_`, and `_` is prepended if the
+/// result is empty or starts with one of `bad_start`.
+pub(crate) fn sanitize_unescaped(
+    part: &str,
+    bad: impl Fn(char) -> bool,
+    bad_start: &[char],
+) -> String {
+    let start = part.len() - part.trim_start().len();
+    let end = part.trim_end().len();
+    let mut sanitized = part
+        .char_indices()
+        .map(|(i, c)| {
+            if i < start || i >= end || bad(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+    if sanitized.is_empty() || sanitized.starts_with(bad_start) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+// Checks the lines parsed by a dialect's `parse_line` against a table of inputs and expected
+// results: `None` for no line, or an error to match any error.
+#[cfg(test)]
+pub(crate) fn check_parsed_lines<F>(data: &[(&str, Option<Result<LineContent, ()>>)], mut parse: F)
+where
+    F: FnMut(&str) -> Result<Option<Line>, PropertiesError>,
+{
+    for (input, expected) in data {
+        match (expected, parse(input)) {
+            (None, Ok(None)) => (),
+            (Some(Ok(e)), Ok(Some(a))) => assert_eq!(a.content(), e, "{:?}", input),
+            (Some(Err(())), Err(_)) => (),
+            (e, a) => panic!("{:?}: expected {:?}, got {:?}", input, e, a),
+        }
+    }
+}
+
+// Creates an empty directory for a test, which is removed when the returned guard is dropped, even
+// if the test fails.
+#[cfg(test)]
+pub(crate) fn test_dir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("java-properties-")
+        .tempdir()
+        .unwrap()
+}
+
+// An expected key/value line for `check_parsed_lines`.
+#[cfg(test)]
+pub(crate) fn parsed_pair(key: &str, value: &str) -> Option<Result<LineContent, ()>> {
+    Some(Ok(LineContent::KVPair(key.to_string(), value.to_string())))
+}
+
+// An expected comment line for `check_parsed_lines`.
+#[cfg(test)]
+pub(crate) fn parsed_comment(text: &str) -> Option<Result<LineContent, ()>> {
+    Some(Ok(LineContent::Comment(text.to_string())))
+}
+
+/// What a continuation line containing only whitespace does to the logical line it continues.
+///
+/// ```
+/// use java_properties::BlankContinuationLine;
+/// use java_properties::PropertiesIterBuilder;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let input = "a=one \\\n   \n  two\n";
+/// let mut iter = PropertiesIterBuilder::new()
+///     .blank_continuation_lines(BlankContinuationLine::Space)
+///     .build(input.as_bytes());
+/// assert_eq!(iter.blank_continuation_lines(), BlankContinuationLine::Space);
+/// let line = iter.next().unwrap()?;
+/// assert_eq!(line.content(), &java_properties::LineContent::KVPair("a".to_string(), "one  two".to_string()));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash, Default)]
+pub enum BlankContinuationLine {
+    /// The blank line ends the logical line, and the next line starts a new one.  This is what Java
+    /// does, and is the default.
+    #[default]
+    Terminate,
+    /// The blank line is ignored, and the logical line continues on the next line.
+    Skip,
+    /// The blank line contributes a single space if more of the logical line follows, and the logical
+    /// line continues on the next line.
+    Space,
 }
 
 /// A line read from a properties file.
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
+///
+/// Lines compare and hash by their line number, content, and separator.  Where a line was found in
+/// the input, as returned by [`column`](Self::column), [`byte_range`](Self::byte_range), and
+/// [`raw_text`](Self::raw_text), isn't compared, so a line read from a file equals the same line
+/// made with [`mk_pair`](Self::mk_pair).
+#[derive(Debug, Clone)]
 pub struct Line {
     line_number: usize,
     data: LineContent,
+    separator: Option<KVSeparator>,
+    position: Position,
+}
+
+// Where a line was read from.  This isn't part of a line's value, so `Line` doesn't compare it.
+#[derive(Debug, Clone, Default)]
+struct Position {
+    column: Option<usize>,
+    // Start and end byte offsets in the input.
+    byte_range: Option<(u64, u64)>,
+    // The text of the line as it appears in the input, if kept.
+    raw_text: Option<String>,
+}
+
+impl PartialEq for Line {
+    fn eq(&self, other: &Line) -> bool {
+        self.line_number == other.line_number
+            && self.data == other.data
+            && self.separator == other.separator
+    }
+}
+
+impl Eq for Line {}
+
+impl PartialOrd for Line {
+    fn partial_cmp(&self, other: &Line) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Line {
+    fn cmp(&self, other: &Line) -> std::cmp::Ordering {
+        (self.line_number, &self.data, self.separator).cmp(&(
+            other.line_number,
+            &other.data,
+            other.separator,
+        ))
+    }
+}
+
+impl Hash for Line {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.line_number.hash(state);
+        self.data.hash(state);
+        self.separator.hash(state);
+    }
 }
 
 impl Line {
@@ -385,17 +1145,107 @@ impl Line {
         self.data
     }
 
-    fn mk_pair(line_number: usize, key: String, value: String) -> Line {
+    /// Returns how the key and value are separated, or `None` for a comment.
+    ///
+    /// This distinguishes a key without a value (`key`) from a key with an empty value (`key=`).
+    pub fn separator(&self) -> Option<KVSeparator> {
+        self.separator
+    }
+
+    /// Returns the 1-based column, counted in characters, where the key or comment starts after any
+    /// indentation, if known.
+    ///
+    /// Positions are known for lines read by a [`PropertiesIter`] with
+    /// [`PropertiesIterBuilder::track_positions`] set.
+    pub fn column(&self) -> Option<usize> {
+        self.position.column
+    }
+
+    /// Returns the range of bytes in the input holding the line, if known.  This includes
+    /// indentation and continuation lines, but not the final line terminator.
+    ///
+    /// Positions are known for lines read by a [`PropertiesIter`] with
+    /// [`PropertiesIterBuilder::track_positions`] set.  They may be inexact after input which was
+    /// malformed in its encoding.
+    ///
+    /// ```
+    /// use java_properties::PropertiesIterBuilder;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "a=1\n  b=2\\\n    3\n";
+    /// let mut iter = PropertiesIterBuilder::new()
+    ///     .track_positions(true)
+    ///     .build(input.as_bytes());
+    /// let line = iter.nth(1).unwrap()?;
+    /// assert_eq!(line.column(), Some(3));
+    /// assert_eq!(line.byte_range(), Some(4..16));
+    /// assert_eq!(&input[4..16], "  b=2\\\n    3");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn byte_range(&self) -> Option<Range<u64>> {
+        self.position.byte_range.map(|(start, end)| start..end)
+    }
+
+    /// Returns the text of the line as it appears in the input, before escapes are processed and
+    /// continuation lines are joined.  This is the text of [`byte_range`](Self::byte_range),
+    /// decoded, and includes the line terminators of continued lines but not the final one.
+    ///
+    /// The text is only kept if [`PropertiesIterBuilder::keep_raw_text`] is set.
+    ///
+    /// ```
+    /// use java_properties::PropertiesIterBuilder;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "a=1\r\n  b = x\\\r\n    y\r\n";
+    /// let mut iter = PropertiesIterBuilder::new()
+    ///     .keep_raw_text(true)
+    ///     .build(input.as_bytes());
+    /// iter.next().unwrap()?;
+    /// let line = iter.next().unwrap()?;
+    /// assert_eq!(line.raw_text(), Some("  b = x\\\r\n    y"));
+    /// assert_eq!(line.byte_range(), Some(5..20));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn raw_text(&self) -> Option<&str> {
+        self.position.raw_text.as_deref()
+    }
+
+    /// Creates a key/value line.  The key and value are unescaped, as returned by
+    /// [`content`](Self::content).
+    ///
+    /// The line has no position, so [`column`](Self::column) and [`byte_range`](Self::byte_range)
+    /// return `None`.
+    pub fn mk_pair(line_number: usize, key: String, value: String, separator: KVSeparator) -> Line {
         Line {
             line_number,
             data: LineContent::KVPair(key, value),
+            separator: Some(separator),
+            position: Position::default(),
         }
     }
 
-    fn mk_comment(line_number: usize, text: String) -> Line {
+    /// Creates a comment line.  The text is unescaped and doesn't include the comment prefix.
+    ///
+    /// The line has no position, so [`column`](Self::column) and [`byte_range`](Self::byte_range)
+    /// return `None`.
+    pub fn mk_comment(line_number: usize, text: String) -> Line {
         Line {
             line_number,
             data: LineContent::Comment(text),
+            separator: None,
+            position: Position::default(),
+        }
+    }
+
+    /// Creates a blank line.
+    pub fn mk_blank(line_number: usize) -> Line {
+        Line {
+            line_number,
+            data: LineContent::Blank,
+            separator: None,
+            position: Position::default(),
         }
     }
 }
@@ -411,13 +1261,20 @@ impl Display for Line {
 }
 
 /// Parsed content of the line.
+///
+/// More kinds of lines may be added in future versions, so matches need a wildcard arm.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
+#[non_exhaustive]
 pub enum LineContent {
     /// Content of a comment line.
     Comment(String),
 
     /// Content of a key/value line.
     KVPair(String, String),
+
+    /// A line containing only whitespace.  These are only returned if
+    /// [`PropertiesIterBuilder::keep_blank_lines`] is set.
+    Blank,
 }
 
 impl Display for LineContent {
@@ -425,6 +1282,7 @@ impl Display for LineContent {
         match *self {
             LineContent::Comment(ref s) => write!(f, "Comment({:?})", s),
             LineContent::KVPair(ref k, ref v) => write!(f, "KVPair({:?}, {:?})", k, v),
+            LineContent::Blank => write!(f, "Blank"),
         }
     }
 }
@@ -435,12 +1293,123 @@ impl From<Line> for LineContent {
     }
 }
 
+/// A key/value pair with the comment directly above it, like a doc comment.
+///
+/// [`PropertiesIter::commented_entries`] reads entries with their comments, and
+/// [`PropertiesWriter::write_entry`] writes the comment and the pair together, so they can't get
+/// out of step.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
+pub struct CommentedEntry {
+    key: String,
+    value: String,
+    comment: Option<String>,
+    line_number: Option<usize>,
+}
+
+impl CommentedEntry {
+    /// Creates an entry without a comment.
+    pub fn new<K: Into<String>, V: Into<String>>(key: K, value: V) -> Self {
+        CommentedEntry {
+            key: key.into(),
+            value: value.into(),
+            comment: None,
+            line_number: None,
+        }
+    }
+
+    /// Sets the comment.  Lines are separated by line terminators.
+    pub fn with_comment<C: Into<String>>(mut self, comment: C) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Returns the key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns the comment, if any, with lines separated by `'\n'`.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// Returns the 1-based line number of the key/value pair, if the entry was read from a file.
+    pub fn line_number(&self) -> Option<usize> {
+        self.line_number
+    }
+}
+
 /////////////////////
 
-fn unescape(s: &str, line_number: usize) -> Result<String, PropertiesError> {
+/// Reads a `\uxxxx` escape holding a UTF-16 low surrogate, returning `None` if the input doesn't start with one.
+fn unescape_low_surrogate(iter: &mut std::str::Chars) -> Option<u16> {
+    if iter.next() != Some('\\') || iter.next() != Some('u') {
+        return None;
+    }
+    let digits: String = iter.take(4).collect();
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(&digits, 16)
+        .ok()
+        .filter(|x| (0xDC00..=0xDFFF).contains(x))
+}
+
+/// Replaces the escape sequences in a key, value, or comment read from a properties file with the
+/// characters they stand for.  This is the reverse of [`escape_key`], [`escape_value`], and
+/// [`escape_comment`].
+///
+/// Backslashes before other characters are dropped, and a trailing backslash becomes a NUL
+/// character, as in Java.  Line continuations are not handled, since they are joined before keys
+/// and values are split.
+///
+/// ```
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// assert_eq!(java_properties::unescape(r"a\ b\u00e9\tc\=")?, "a b\u{e9}\tc=");
+/// assert!(java_properties::unescape(r"\u12").is_err());
+/// # Ok(())
+/// # }
+/// ```
+pub fn unescape(s: &str) -> Result<String, PropertiesError> {
+    unescape_with_source(s, None).map_err(|(e, _)| e)
+}
+
+/// Like [`unescape`], but errors report `line_number`.
+pub(crate) fn unescape_at(s: &str, line_number: usize) -> Result<String, PropertiesError> {
+    unescape_with_source(s, Some(line_number)).map_err(|(e, _)| e)
+}
+
+// Like `unescape`, but errors come with the malformed escape, as a slice of `s`.  The errors are
+// rare and immediately unpacked, so their size doesn't matter.
+#[allow(clippy::result_large_err)]
+fn unescape_with_source(
+    s: &str,
+    line_number: Option<usize>,
+) -> Result<String, (PropertiesError, &str)> {
     let mut buf = String::new();
     let mut iter = s.chars();
     loop {
+        // The byte index of the next character.
+        let start = s.len() - iter.as_str().len();
+        let malformed = |description: &str,
+                         cause: Option<Box<dyn Error + 'static + Send + Sync>>,
+                         iter: &std::str::Chars| {
+            let end = s.len() - iter.as_str().len();
+            Err((
+                PropertiesError::new(
+                    PropertiesErrorKind::MalformedUnicodeEscape,
+                    description,
+                    cause,
+                    line_number,
+                ),
+                &s[start..end],
+            ))
+        };
         match iter.next() {
             None => break,
             Some(c) => {
@@ -458,31 +1427,61 @@ fn unescape(s: &str, line_number: usize) -> Result<String, PropertiesError> {
                                     for _ in 0..4 {
                                         match iter.next() {
                                             Some(c) => tmp.push(c),
-                                            None => return Err(PropertiesError::new(
+                                            None => return malformed(
                                                 "Malformed \\uxxxx encoding: not enough digits.",
                                                 None,
-                                                Some(line_number),
-                                            )),
+                                                &iter,
+                                            ),
                                         }
                                     }
                                     let val = match u16::from_str_radix(&tmp, 16) {
                                         Ok(x) => x,
                                         Err(e) => {
-                                            return Err(PropertiesError::new(
+                                            return malformed(
                                                 "Malformed \\uxxxx encoding: not hex.",
                                                 Some(Box::new(e)),
-                                                Some(line_number),
-                                            ))
+                                                &iter,
+                                            )
+                                        }
+                                    };
+                                    let code_point = match val {
+                                        0xD800..=0xDBFF => {
+                                            // Java writes characters outside the BMP as a pair of
+                                            // escaped UTF-16 surrogates, so the low half must follow.
+                                            let mut lookahead = iter.clone();
+                                            match unescape_low_surrogate(&mut lookahead) {
+                                                Some(low) => {
+                                                    iter = lookahead;
+                                                    0x10000
+                                                        + ((u32::from(val) - 0xD800) << 10)
+                                                        + (u32::from(low) - 0xDC00)
+                                                }
+                                                None => {
+                                                    return malformed(
+                                                        "Malformed \\uxxxx encoding: unpaired surrogate.",
+                                                        None,
+                                                        &iter,
+                                                    )
+                                                }
+                                            }
+                                        }
+                                        0xDC00..=0xDFFF => {
+                                            return malformed(
+                                                "Malformed \\uxxxx encoding: unpaired surrogate.",
+                                                None,
+                                                &iter,
+                                            )
                                         }
+                                        _ => u32::from(val),
                                     };
-                                    match std::char::from_u32(val as u32) {
+                                    match std::char::from_u32(code_point) {
                                         Some(c) => buf.push(c),
                                         None => {
-                                            return Err(PropertiesError::new(
+                                            return malformed(
                                                 "Malformed \\uxxxx encoding: invalid character.",
                                                 None,
-                                                Some(line_number),
-                                            ))
+                                                &iter,
+                                            )
                                         }
                                     }
                                 }
@@ -538,16 +1537,24 @@ lazy_static! {
     ").unwrap();
 }
 
-fn parse_line(line: &str) -> Option<ParsedLine> {
+pub(crate) fn parse_line(line: &str) -> Option<ParsedLine<'_>> {
     if let Some(c) = LINE_RE.captures(line) {
         if let Some(comment_match) = c.get(1) {
             Some(ParsedLine::Comment(comment_match.as_str()))
         } else if let Some(key_match) = c.get(2) {
             let key = key_match.as_str();
             if let Some(value_match) = c.get(3) {
-                Some(ParsedLine::KVPair(key, value_match.as_str()))
+                let separator = &line[key_match.end()..value_match.start()];
+                let separator = if separator.contains('=') {
+                    KVSeparator::Equals
+                } else if separator.contains(':') {
+                    KVSeparator::Colon
+                } else {
+                    KVSeparator::Whitespace
+                };
+                Some(ParsedLine::KVPair(key, value_match.as_str(), separator))
             } else if !key.is_empty() {
-                Some(ParsedLine::KVPair(key, ""))
+                Some(ParsedLine::KVPair(key, "", KVSeparator::Absent))
             } else {
                 None
             }
@@ -560,362 +1567,3631 @@ fn parse_line(line: &str) -> Option<ParsedLine> {
     }
 }
 
-/// Parses a properties file and iterates over its contents.
+/// A key which appears more than once in a properties file.
 ///
-/// For basic usage, see the crate-level documentation.
-/// Note that once `next` returns an error, the result of further calls is undefined.
-pub struct PropertiesIter<R: Read> {
-    lines: LogicalLines<NaturalLines<R>>,
+/// See [`PropertiesIter::on_duplicate_key`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct DuplicateKey<'a> {
+    key: &'a str,
+    previous_line_number: usize,
+    previous_value: &'a str,
+    line_number: usize,
+    value: &'a str,
 }
 
-impl<R: Read> PropertiesIter<R> {
-    /// Parses properties from the given `Read` stream.
-    pub fn new(input: R) -> Self {
-        Self::new_with_encoding(input, WINDOWS_1252)
+impl<'a> DuplicateKey<'a> {
+    /// Returns the duplicated key.
+    pub fn key(&self) -> &'a str {
+        self.key
     }
 
-    /// Parses properties from the given `Read` stream in the given encoding.
-    /// Note that the Java properties specification specifies ISO-8859-1 encoding
-    /// (a.k.a. windows-1252) for properties files; in most cases, `new` should be
-    /// called instead.
-    pub fn new_with_encoding(input: R, encoding: &'static Encoding) -> Self {
-        PropertiesIter {
-            lines: LogicalLines::new(NaturalLines::new(input, encoding)),
-        }
+    /// Returns the line number of the previous occurrence of the key.
+    pub fn previous_line_number(&self) -> usize {
+        self.previous_line_number
     }
 
-    /// Calls `f` for each key/value pair.
-    ///
-    /// Line numbers and comments are ignored.
-    /// On the first error, the error is returned.
-    /// Note that `f` may have already been called at this point.
-    pub fn read_into<F: FnMut(String, String)>(&mut self, mut f: F) -> Result<(), PropertiesError> {
-        for line in self {
-            if let LineContent::KVPair(key, value) = line?.data {
-                f(key, value);
-            }
-        }
-        Ok(())
+    /// Returns the value of the previous occurrence of the key.
+    pub fn previous_value(&self) -> &'a str {
+        self.previous_value
     }
 
-    fn parsed_line_to_line(
-        &self,
-        parsed_line: ParsedLine<'_>,
+    /// Returns the line number of this occurrence of the key.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Returns the value of this occurrence of the key.
+    pub fn value(&self) -> &'a str {
+        self.value
+    }
+}
+
+/// How far a [`PropertiesIter`] has read.
+///
+/// See [`PropertiesIter::on_progress`].
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash, Default)]
+pub struct Progress {
+    bytes_read: u64,
+    lines_read: usize,
+}
+
+impl Progress {
+    /// Returns the number of bytes read from the input.  Input is read in chunks, so this may be
+    /// slightly ahead of the last line parsed.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the number of natural lines read, i.e. the line number of the last line read.
+    pub fn lines_read(&self) -> usize {
+        self.lines_read
+    }
+}
+
+type ProgressHandler = Box<dyn FnMut(&Progress) + Send + Sync>;
+
+type CancellationCheck = Box<dyn Fn() -> bool + Send + Sync>;
+
+type DuplicateKeyHandler =
+    Box<dyn FnMut(&DuplicateKey<'_>) -> Result<(), PropertiesError> + Send + Sync>;
+
+/// What a [`PropertiesIter`] does when a key appears more than once.
+#[derive(Default)]
+pub enum DuplicateKeyPolicy {
+    /// Later occurrences of a key are skipped, so the first value is kept.
+    FirstWins,
+    /// Every occurrence is returned, so a map built from the pairs keeps the last value.  This is
+    /// what Java does, and is the default.
+    #[default]
+    LastWins,
+    /// A later occurrence of a key is an error.
+    Error,
+    /// The handler is called for each later occurrence of a key; see [`PropertiesIter::on_duplicate_key`].
+    Callback(DuplicateKeyHandler),
+}
+
+impl Debug for DuplicateKeyPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DuplicateKeyPolicy::FirstWins => "DuplicateKeyPolicy::FirstWins",
+            DuplicateKeyPolicy::LastWins => "DuplicateKeyPolicy::LastWins",
+            DuplicateKeyPolicy::Error => "DuplicateKeyPolicy::Error",
+            DuplicateKeyPolicy::Callback(_) => "DuplicateKeyPolicy::Callback(..)",
+        })
+    }
+}
+
+/// Parses a properties file and iterates over its contents.
+///
+/// For basic usage, see the crate-level documentation.
+/// Note that once `next` returns an error, the result of further calls is undefined.
+pub struct PropertiesIter<R: Read> {
+    lines: LogicalLines<NaturalLines<R>>,
+    duplicate_key_policy: DuplicateKeyPolicy,
+    // The line number and value of the occurrence of each key which is in effect, only kept while the
+    // duplicate key policy needs it.
+    seen_keys: HashMap<String, (usize, String)>,
+    progress_handler: Option<ProgressHandler>,
+    cancellation_check: Option<CancellationCheck>,
+    skip_comments: bool,
+    keep_blank_lines: bool,
+    // Whether Java comments are returned as written, without unescaping them, for `format`.
+    raw_comments: bool,
+    dialect: Dialect,
+    // The current section of an INI file.
+    section: Option<String>,
+    source_name: Option<Arc<str>>,
+}
+
+impl<R: Read> PropertiesIter<R> {
+    /// Parses properties from the given `Read` stream.
+    pub fn new(input: R) -> Self {
+        Self::new_with_encoding(input, WINDOWS_1252)
+    }
+
+    /// Parses properties from the given `Read` stream in the given encoding.
+    /// Note that the Java properties specification specifies ISO-8859-1 encoding
+    /// (a.k.a. windows-1252) for properties files; in most cases, `new` should be
+    /// called instead.
+    pub fn new_with_encoding(input: R, encoding: &'static Encoding) -> Self {
+        PropertiesIterBuilder::new().encoding(encoding).build(input)
+    }
+
+    /// Parses properties from the given `Read` stream, using the encoding given by a UTF-8 or UTF-16
+    /// byte order mark if the input starts with one, and `default_encoding` otherwise.  The byte
+    /// order mark is not part of the first line.
+    ///
+    /// `new` and `new_with_encoding` do the same, unless detection is turned off with
+    /// [`PropertiesIterBuilder::detect_bom`]; this constructor makes the intent explicit.
+    ///
+    /// ```
+    /// use encoding_rs::UTF_8;
+    /// use encoding_rs::WINDOWS_1252;
+    /// use java_properties::LineContent;
+    /// use java_properties::PropertiesIter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = b"\xef\xbb\xbfname=caf\xc3\xa9\n";
+    /// let mut iter = PropertiesIter::new_with_detection(&input[..], WINDOWS_1252);
+    /// let line = iter.next().unwrap()?;
+    /// assert_eq!(line.content(), &LineContent::KVPair("name".to_string(), "caf\u{e9}".to_string()));
+    /// assert_eq!(iter.encoding(), UTF_8);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_with_detection(input: R, default_encoding: &'static Encoding) -> Self {
+        PropertiesIterBuilder::new()
+            .encoding(default_encoding)
+            .detect_bom(true)
+            .build(input)
+    }
+
+    /// Returns the encoding the input is being decoded with.  If byte order mark detection is on,
+    /// this is only final once the first line has been read.
+    pub fn encoding(&self) -> &'static Encoding {
+        self.lines.physical_lines.chars.decoder.encoding()
+    }
+
+    /// Sets a name for the input, such as its path, which is attached to every error returned.
+    ///
+    /// ```
+    /// use java_properties::PropertiesIter;
+    ///
+    /// let err = PropertiesIter::new("a=\\u12".as_bytes())
+    ///     .with_source_name("config/app.properties")
+    ///     .read_into(|_, _| ())
+    ///     .unwrap_err();
+    /// assert_eq!(err.source_name(), Some("config/app.properties"));
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "Malformed \\uxxxx encoding: not enough digits. (source = config/app.properties, line_number = 1)"
+    /// );
+    /// ```
+    pub fn with_source_name<S: Into<String>>(mut self, source_name: S) -> Self {
+        self.source_name = Some(Arc::from(source_name.into()));
+        self
+    }
+
+    /// Returns the name of the input set with [`with_source_name`](Self::with_source_name).
+    pub fn source_name(&self) -> Option<&str> {
+        self.source_name.as_deref()
+    }
+
+    /// Returns what a continuation line containing only whitespace does.
+    pub fn blank_continuation_lines(&self) -> BlankContinuationLine {
+        self.lines.blank_continuation_lines
+    }
+
+    /// Sets what a continuation line containing only whitespace does.  Takes effect from the next
+    /// logical line.
+    pub fn set_blank_continuation_lines(&mut self, policy: BlankContinuationLine) {
+        self.lines.blank_continuation_lines = policy;
+    }
+
+    /// Returns how many continuation lines may follow the first line of a logical line, or `None`
+    /// if there is no limit.
+    pub fn max_continuation_lines(&self) -> Option<usize> {
+        self.lines.max_continuation_lines
+    }
+
+    /// Sets how many continuation lines may follow the first line of a logical line.  Defaults to
+    /// `None`, for no limit.
+    ///
+    /// A longer logical line is an error reporting the line it starts on.  This guards against
+    /// malicious input and against files where a stray trailing backslash joins many lines
+    /// together.  Blank continuation lines count towards the limit.
+    ///
+    /// ```
+    /// use java_properties::PropertiesErrorKind;
+    /// use java_properties::PropertiesIter;
+    ///
+    /// let input = "a=1\n\nb=1\\\n  2\\\n  3\n";
+    /// let mut iter = PropertiesIter::new(input.as_bytes());
+    /// iter.set_max_continuation_lines(Some(1));
+    /// assert!(iter.next().unwrap().is_ok());
+    /// let err = iter.next().unwrap().unwrap_err();
+    /// assert_eq!(err.kind(), PropertiesErrorKind::TooManyContinuationLines);
+    /// assert_eq!(err.line_number(), Some(3));
+    /// ```
+    pub fn set_max_continuation_lines(&mut self, max: Option<usize>) {
+        self.lines.max_continuation_lines = max;
+    }
+
+    /// Stops parsing once `flag` is set.
+    ///
+    /// The flag is checked before each logical line is read.  Once it is set, `next` returns an
+    /// error instead of reading further.  The iterator is left in a consistent state, so parsing
+    /// resumes where it stopped if the flag is cleared.
+    ///
+    /// ```
+    /// use java_properties::PropertiesIter;
+    /// use std::sync::atomic::AtomicBool;
+    /// use std::sync::atomic::Ordering;
+    /// use std::sync::Arc;
+    ///
+    /// let cancelled = Arc::new(AtomicBool::new(false));
+    /// let mut iter = PropertiesIter::new("a=1\nb=2\n".as_bytes());
+    /// iter.set_cancellation_flag(cancelled.clone());
+    /// assert!(iter.next().unwrap().is_ok());
+    /// cancelled.store(true, Ordering::Relaxed);
+    /// assert!(iter.next().unwrap().is_err());
+    /// ```
+    pub fn set_cancellation_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.set_cancellation_check(move || flag.load(Ordering::Relaxed));
+    }
+
+    /// Stops parsing once `check` returns true.  This is like
+    /// [`set_cancellation_flag`](Self::set_cancellation_flag), for cancellation signals other than a flag.
+    pub fn set_cancellation_check<F: Fn() -> bool + Send + Sync + 'static>(&mut self, check: F) {
+        self.cancellation_check = Some(Box::new(check));
+    }
+
+    fn is_end(&self, text: &str) -> bool {
+        text.is_empty() && self.lines.physical_lines.eof
+    }
+
+    fn is_section_header(&self, text: &str) -> bool {
+        self.dialect == Dialect::Ini && ini::is_section_header(text)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation_check
+            .as_ref()
+            .is_some_and(|check| check())
+    }
+
+    /// Returns how far the input has been read.
+    pub fn progress(&self) -> Progress {
+        let natural_lines = &self.lines.physical_lines;
+        Progress {
+            bytes_read: natural_lines.chars.bytes_read,
+            lines_read: natural_lines.line_count,
+        }
+    }
+
+    /// Calls `handler` with the current progress each time a line is returned and once the end of
+    /// the input is reached.
+    ///
+    /// The handler is called often, so it should be cheap; throttling updates to a progress bar
+    /// is up to the handler.
+    ///
+    /// ```
+    /// use java_properties::PropertiesIter;
+    /// use std::sync::atomic::AtomicU64;
+    /// use std::sync::atomic::Ordering;
+    /// use std::sync::Arc;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let bytes = Arc::new(AtomicU64::new(0));
+    /// let bytes2 = bytes.clone();
+    /// let mut iter = PropertiesIter::new("a=1\nb=2\n".as_bytes());
+    /// iter.on_progress(move |p| bytes2.store(p.bytes_read(), Ordering::Relaxed));
+    /// iter.read_into(|_, _| ())?;
+    /// assert_eq!(bytes.load(Ordering::Relaxed), 8);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_progress<F: FnMut(&Progress) + Send + Sync + 'static>(&mut self, handler: F) {
+        self.progress_handler = Some(Box::new(handler));
+    }
+
+    fn report_progress(&mut self) {
+        let progress = self.progress();
+        if let Some(handler) = &mut self.progress_handler {
+            handler(&progress);
+        }
+    }
+
+    /// Calls `handler` each time a key appears again after it has already been read.
+    ///
+    /// If `handler` returns an error, `next` returns that error instead of the line.
+    /// Only keys read after the handler is set are considered.
+    ///
+    /// ```
+    /// use java_properties::PropertiesError;
+    /// use java_properties::PropertiesIter;
+    ///
+    /// let input = "a=1\nsecurity.key=x\na=2\nsecurity.key=y\n";
+    /// let mut iter = PropertiesIter::new(input.as_bytes());
+    /// iter.on_duplicate_key(|dup| {
+    ///     if dup.key().starts_with("security.") {
+    ///         Err(PropertiesError::duplicate_key(dup))
+    ///     } else {
+    ///         Ok(())
+    ///     }
+    /// });
+    /// let err = iter.read_into(|_, _| ()).unwrap_err();
+    /// assert_eq!(err.line_number(), Some(4));
+    /// ```
+    pub fn on_duplicate_key<F>(&mut self, handler: F)
+    where
+        F: FnMut(&DuplicateKey<'_>) -> Result<(), PropertiesError> + Send + Sync + 'static,
+    {
+        self.duplicate_key_policy = DuplicateKeyPolicy::Callback(Box::new(handler));
+    }
+
+    /// Sets what happens when a key appears more than once.
+    ///
+    /// Only keys read after the policy is set are considered.
+    ///
+    /// ```
+    /// use java_properties::DuplicateKeyPolicy;
+    /// use java_properties::PropertiesIter;
+    /// use std::collections::HashMap;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "a=1\na=2\n";
+    /// let mut iter = PropertiesIter::new(input.as_bytes());
+    /// iter.set_duplicate_key_policy(DuplicateKeyPolicy::FirstWins);
+    /// let mut map = HashMap::new();
+    /// iter.read_into(|k, v| {
+    ///     map.insert(k, v);
+    /// })?;
+    /// assert_eq!(map["a"], "1");
+    ///
+    /// let mut iter = PropertiesIter::new(input.as_bytes());
+    /// iter.set_duplicate_key_policy(DuplicateKeyPolicy::Error);
+    /// assert_eq!(iter.read_into(|_, _| ()).unwrap_err().line_number(), Some(2));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_duplicate_key_policy(&mut self, policy: DuplicateKeyPolicy) {
+        self.duplicate_key_policy = policy;
+    }
+
+    // Returns false if the line should be skipped.
+    fn check_duplicate(&mut self, line: &Line) -> Result<bool, PropertiesError> {
+        if let DuplicateKeyPolicy::LastWins = self.duplicate_key_policy {
+            return Ok(true);
+        }
+        if let LineContent::KVPair(key, value) = &line.data {
+            if let Some((previous_line_number, previous_value)) = self.seen_keys.get(key) {
+                let duplicate = DuplicateKey {
+                    key,
+                    previous_line_number: *previous_line_number,
+                    previous_value,
+                    line_number: line.line_number,
+                    value,
+                };
+                match &mut self.duplicate_key_policy {
+                    DuplicateKeyPolicy::FirstWins => return Ok(false),
+                    DuplicateKeyPolicy::LastWins => (),
+                    DuplicateKeyPolicy::Error => {
+                        return Err(PropertiesError::duplicate_key(&duplicate))
+                    }
+                    DuplicateKeyPolicy::Callback(handler) => handler(&duplicate)?,
+                }
+            }
+            self.seen_keys
+                .insert(key.clone(), (line.line_number, value.clone()));
+        }
+        Ok(true)
+    }
+
+    // Returns None for lines which don't produce a `Line`, such as blank lines.  Errors come with
+    // the part of the line which caused them, if known.
+    #[allow(clippy::result_large_err)]
+    fn parse_logical_line<'a>(
+        &mut self,
+        line: &'a str,
         line_number: usize,
-    ) -> Result<Line, PropertiesError> {
-        Ok(match parsed_line {
-            ParsedLine::Comment(c) => {
-                let comment = unescape(c, line_number)?;
-                Line::mk_comment(line_number, comment)
+    ) -> Result<Option<Line>, (PropertiesError, Option<&'a str>)> {
+        match self.dialect {
+            Dialect::Java => match parse_line(line) {
+                Some(ParsedLine::Comment(comment)) if self.raw_comments => {
+                    Ok(Some(Line::mk_comment(line_number, comment.to_string())))
+                }
+                Some(parsed_line) => parsed_line_to_line_with_source(parsed_line, line_number)
+                    .map(Some)
+                    .map_err(|(e, malformed)| (e, Some(malformed))),
+                None => Ok(None),
+            },
+            Dialect::DotEnv => dotenv::parse_line(line, line_number).map_err(|e| (e, None)),
+            Dialect::AndroidProp => android::parse_line(line, line_number).map_err(|e| (e, None)),
+            Dialect::Ini => {
+                ini::parse_line(line, line_number, &mut self.section).map_err(|e| (e, None))
+            }
+        }
+    }
+
+    /// Returns an iterator over the key/value pairs, skipping comments and other lines.
+    ///
+    /// As with `next`, once an error is returned, the result of further calls is undefined, so
+    /// collecting into a `Result` is the usual way to use this.
+    ///
+    /// ```
+    /// use java_properties::PropertiesIter;
+    /// use std::collections::HashMap;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "# comment\na=1\nb=2\n";
+    /// let map = PropertiesIter::new(input.as_bytes())
+    ///     .pairs()
+    ///     .collect::<Result<HashMap<_, _>, _>>()?;
+    /// assert_eq!(map["b"], "2");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pairs(self) -> impl Iterator<Item = Result<(String, String), PropertiesError>> {
+        self.filter_map(|line| match line {
+            Ok(line) => match line.data {
+                LineContent::KVPair(key, value) => Some(Ok((key, value))),
+                _ => None,
+            },
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    /// Returns an iterator over the key/value pairs, each bundled with the block of comment lines
+    /// directly above it, like a doc comment.
+    ///
+    /// A blank line or any other gap ends a block, so a comment separated from the next key isn't
+    /// attached to it.  The lines of a block are joined with `'\n'`.  Comments aren't available if
+    /// the iterator was built with [`skip_comments`](PropertiesIterBuilder::skip_comments).
+    ///
+    /// ```
+    /// use java_properties::PropertiesIter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "# File header\n\n# Port to listen on.\n# Must be above 1024.\nport=8080\nhost=::\n";
+    /// let entries = PropertiesIter::new(input.as_bytes())
+    ///     .commented_entries()
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// assert_eq!(entries[0].key(), "port");
+    /// assert_eq!(entries[0].comment(), Some("Port to listen on.\nMust be above 1024."));
+    /// assert_eq!(entries[0].line_number(), Some(5));
+    /// assert_eq!(entries[1].comment(), None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn commented_entries(
+        self,
+    ) -> impl Iterator<Item = Result<CommentedEntry, PropertiesError>> {
+        let mut comment: Vec<String> = Vec::new();
+        let mut last_comment_line = 0;
+        self.filter_map(move |line| {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if line.line_number != last_comment_line + 1 {
+                comment.clear();
             }
-            ParsedLine::KVPair(k, v) => {
-                let key = unescape(k, line_number)?;
-                let value = unescape(v, line_number)?;
-                Line::mk_pair(line_number, key, value)
+            match line.data {
+                LineContent::Comment(text) => {
+                    comment.push(text);
+                    last_comment_line = line.line_number;
+                    None
+                }
+                LineContent::KVPair(key, value) => {
+                    let mut entry = CommentedEntry::new(key, value);
+                    if !comment.is_empty() {
+                        entry.comment = Some(comment.join("\n"));
+                        comment.clear();
+                    }
+                    entry.line_number = Some(line.line_number);
+                    Some(Ok(entry))
+                }
+                LineContent::Blank => {
+                    comment.clear();
+                    None
+                }
             }
         })
     }
+
+    /// Returns an iterator over the keys, in file order.  See [`pairs`](Self::pairs).
+    ///
+    /// Keys which appear more than once are returned each time.
+    pub fn keys(self) -> impl Iterator<Item = Result<String, PropertiesError>> {
+        self.pairs().map(|pair| pair.map(|(key, _)| key))
+    }
+
+    /// Returns an iterator over the values, in file order.  See [`pairs`](Self::pairs).
+    pub fn values(self) -> impl Iterator<Item = Result<String, PropertiesError>> {
+        self.pairs().map(|pair| pair.map(|(_, value)| value))
+    }
+
+    /// Calls `f` for each key/value pair.
+    ///
+    /// Line numbers and comments are ignored.
+    /// On the first error, the error is returned.
+    /// Note that `f` may have already been called at this point.
+    pub fn read_into<F: FnMut(String, String)>(&mut self, mut f: F) -> Result<(), PropertiesError> {
+        for line in self {
+            if let LineContent::KVPair(key, value) = line?.data {
+                f(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [`read_into`](Self::read_into), but `f` can fail.  Parsing stops at the first error
+    /// from either the input or `f`, and the error is returned.
+    ///
+    /// ```
+    /// use java_properties::PropertiesError;
+    /// use java_properties::PropertiesIter;
+    /// use std::collections::HashMap;
+    ///
+    /// #[derive(Debug)]
+    /// enum ConfigError {
+    ///     Parse(PropertiesError),
+    ///     BadPort(String),
+    /// }
+    ///
+    /// impl From<PropertiesError> for ConfigError {
+    ///     fn from(e: PropertiesError) -> Self {
+    ///         ConfigError::Parse(e)
+    ///     }
+    /// }
+    ///
+    /// let input = "host=example.com\nport=http\nuser=admin\n";
+    /// let mut map = HashMap::new();
+    /// let result = PropertiesIter::new(input.as_bytes()).try_read_into(|k, v| {
+    ///     if k == "port" && v.parse::<u16>().is_err() {
+    ///         return Err(ConfigError::BadPort(v));
+    ///     }
+    ///     map.insert(k, v);
+    ///     Ok(())
+    /// });
+    /// assert!(matches!(result, Err(ConfigError::BadPort(ref v)) if v == "http"));
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn try_read_into<E, F>(&mut self, mut f: F) -> Result<(), E>
+    where
+        E: From<PropertiesError>,
+        F: FnMut(String, String) -> Result<(), E>,
+    {
+        for line in self {
+            if let LineContent::KVPair(key, value) = line?.data {
+                f(key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Configures and creates a [`PropertiesIter`].
+///
+/// ```
+/// use encoding_rs::UTF_8;
+/// use java_properties::PropertiesError;
+/// use java_properties::PropertiesIterBuilder;
+///
+/// let input = "# comment\na=1\na=2\n";
+/// let lines: Vec<_> = PropertiesIterBuilder::new()
+///     .encoding(UTF_8)
+///     .skip_comments(true)
+///     .build(input.as_bytes())
+///     .collect::<Result<_, _>>()?;
+/// assert_eq!(lines.len(), 2);
+///
+/// let mut iter = PropertiesIterBuilder::new()
+///     .on_duplicate_key(|dup| Err(PropertiesError::duplicate_key(dup)))
+///     .build(input.as_bytes());
+/// assert!(iter.read_into(|_, _| ()).is_err());
+/// # Ok::<(), PropertiesError>(())
+/// ```
+pub struct PropertiesIterBuilder {
+    encoding: &'static Encoding,
+    buffer_size: usize,
+    detect_bom: bool,
+    strict_decoding: bool,
+    skip_comments: bool,
+    keep_blank_lines: bool,
+    track_positions: bool,
+    keep_raw_text: bool,
+    dialect: Dialect,
+    blank_continuation_lines: BlankContinuationLine,
+    max_continuation_lines: Option<usize>,
+    source_name: Option<Arc<str>>,
+    duplicate_key_policy: DuplicateKeyPolicy,
+    progress_handler: Option<ProgressHandler>,
+    cancellation_check: Option<CancellationCheck>,
+}
+
+impl PropertiesIterBuilder {
+    /// Creates a builder with the default options, which are the same as those of [`PropertiesIter::new`].
+    pub fn new() -> Self {
+        PropertiesIterBuilder {
+            encoding: WINDOWS_1252,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            detect_bom: true,
+            strict_decoding: false,
+            skip_comments: false,
+            keep_blank_lines: false,
+            track_positions: false,
+            keep_raw_text: false,
+            dialect: Dialect::Java,
+            blank_continuation_lines: BlankContinuationLine::Terminate,
+            max_continuation_lines: None,
+            source_name: None,
+            duplicate_key_policy: DuplicateKeyPolicy::LastWins,
+            progress_handler: None,
+            cancellation_check: None,
+        }
+    }
+
+    /// Sets the encoding of the input.  Defaults to ISO-8859-1 (a.k.a. windows-1252), as required by the
+    /// Java properties specification.
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets whether a UTF-8 or UTF-16 byte order mark at the start of the input overrides the
+    /// encoding.  Defaults to true.  The byte order mark itself is never part of the first line.
+    ///
+    /// If false, the input is decoded strictly in the configured encoding, as Java does, so a UTF-8
+    /// byte order mark read as ISO-8859-1 becomes part of the first key.
+    pub fn detect_bom(mut self, detect_bom: bool) -> Self {
+        self.detect_bom = detect_bom;
+        self
+    }
+
+    /// Sets whether bytes which are not valid in the encoding are an error.  Defaults to false,
+    /// which replaces them with U+FFFD REPLACEMENT CHARACTER.
+    ///
+    /// The error gives the line number and the byte offset of the malformed bytes.  Every byte is
+    /// valid in ISO-8859-1, so this only matters for encodings such as UTF-8.
+    ///
+    /// ```
+    /// use encoding_rs::UTF_8;
+    /// use java_properties::PropertiesIterBuilder;
+    ///
+    /// let input = b"a=1\nb=caf\xe9\n";
+    /// let mut iter = PropertiesIterBuilder::new()
+    ///     .encoding(UTF_8)
+    ///     .strict_decoding(true)
+    ///     .build(&input[..]);
+    /// assert!(iter.next().unwrap().is_ok());
+    /// let err = iter.next().unwrap().unwrap_err();
+    /// assert_eq!(err.line_number(), Some(2));
+    /// assert_eq!(err.to_string(), "Malformed UTF-8 input at byte offset 9 (line_number = 2)");
+    /// ```
+    pub fn strict_decoding(mut self, strict_decoding: bool) -> Self {
+        self.strict_decoding = strict_decoding;
+        self
+    }
+
+    /// Sets the number of bytes requested from the input at a time.  The buffer grows as needed, so
+    /// this is only a starting point.  Sizes below 1 are treated as 1.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Sets whether comments are skipped instead of being returned as lines.  Defaults to false.
+    pub fn skip_comments(mut self, skip_comments: bool) -> Self {
+        self.skip_comments = skip_comments;
+        self
+    }
+
+    /// Sets whether lines containing only whitespace are returned as [`LineContent::Blank`] instead
+    /// of being skipped.  Defaults to false.
+    ///
+    /// Tools which rewrite a file can use this to keep its grouping of entries, writing the blank
+    /// lines back with [`PropertiesWriter::write_line`].
+    ///
+    /// ```
+    /// use java_properties::LineContent;
+    /// use java_properties::PropertiesIterBuilder;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let lines = PropertiesIterBuilder::new()
+    ///     .keep_blank_lines(true)
+    ///     .build("a=1\n\n  \nb=2\n".as_bytes())
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// assert_eq!(lines.len(), 4);
+    /// assert_eq!(lines[1].content(), &LineContent::Blank);
+    /// assert_eq!(lines[2].line_number(), 3);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn keep_blank_lines(mut self, keep_blank_lines: bool) -> Self {
+        self.keep_blank_lines = keep_blank_lines;
+        self
+    }
+
+    /// Sets whether lines and errors record where they were found in the input, for
+    /// [`Line::column`], [`Line::byte_range`], and the column and byte range of errors such as
+    /// malformed `\uxxxx` escapes.  Defaults to false, since this slows down reading.
+    ///
+    /// ```
+    /// use java_properties::PropertiesIterBuilder;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "a=1\n  b=2\n";
+    /// let line = PropertiesIterBuilder::new().build(input.as_bytes()).nth(1).unwrap()?;
+    /// assert_eq!(line.column(), None);
+    /// let mut iter = PropertiesIterBuilder::new()
+    ///     .track_positions(true)
+    ///     .build(input.as_bytes());
+    /// let line = iter.nth(1).unwrap()?;
+    /// assert_eq!(line.column(), Some(3));
+    /// assert_eq!(line.byte_range(), Some(4..9));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn track_positions(mut self, track_positions: bool) -> Self {
+        self.track_positions = track_positions;
+        self
+    }
+
+    /// Sets whether each line keeps its text as it appears in the input, for [`Line::raw_text`].
+    /// Defaults to false, since this copies every line.  This also turns on
+    /// [`track_positions`](Self::track_positions).
+    ///
+    /// Tools which patch files can use the text with [`Line::byte_range`] to map entries back to
+    /// the exact regions of the file they came from.
+    pub fn keep_raw_text(mut self, keep_raw_text: bool) -> Self {
+        self.keep_raw_text = keep_raw_text;
+        self
+    }
+
+    /// Sets the file format.  Defaults to [`Dialect::Java`].
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets what a continuation line containing only whitespace does.  Defaults to
+    /// [`BlankContinuationLine::Terminate`].
+    pub fn blank_continuation_lines(mut self, policy: BlankContinuationLine) -> Self {
+        self.blank_continuation_lines = policy;
+        self
+    }
+
+    /// Sets how many continuation lines may follow the first line of a logical line.  See
+    /// [`PropertiesIter::set_max_continuation_lines`].
+    pub fn max_continuation_lines(mut self, max: usize) -> Self {
+        self.max_continuation_lines = Some(max);
+        self
+    }
+
+    /// Sets a name for the input.  See [`PropertiesIter::with_source_name`].
+    pub fn source_name<S: Into<String>>(mut self, source_name: S) -> Self {
+        self.source_name = Some(Arc::from(source_name.into()));
+        self
+    }
+
+    /// Sets a handler for duplicate keys.  See [`PropertiesIter::on_duplicate_key`].
+    pub fn on_duplicate_key<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(&DuplicateKey<'_>) -> Result<(), PropertiesError> + Send + Sync + 'static,
+    {
+        self.duplicate_key_policy = DuplicateKeyPolicy::Callback(Box::new(handler));
+        self
+    }
+
+    /// Sets the duplicate key policy.  See [`PropertiesIter::set_duplicate_key_policy`].
+    pub fn duplicate_key_policy(mut self, policy: DuplicateKeyPolicy) -> Self {
+        self.duplicate_key_policy = policy;
+        self
+    }
+
+    /// Sets a progress handler.  See [`PropertiesIter::on_progress`].
+    pub fn on_progress<F: FnMut(&Progress) + Send + Sync + 'static>(mut self, handler: F) -> Self {
+        self.progress_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets a cancellation flag.  See [`PropertiesIter::set_cancellation_flag`].
+    pub fn cancellation_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancellation_check = Some(Box::new(move || flag.load(Ordering::Relaxed)));
+        self
+    }
+
+    /// Creates a `PropertiesIter` reading from `input` with the configured options.
+    pub fn build<R: Read>(self, input: R) -> PropertiesIter<R> {
+        let mut natural_lines = NaturalLines::new(input, self.encoding, self.buffer_size);
+        if !self.detect_bom {
+            natural_lines.chars.disable_bom_detection();
+        }
+        natural_lines.chars.strict = self.strict_decoding;
+        let track_positions = self.track_positions || self.keep_raw_text;
+        natural_lines.chars.track_offsets = track_positions;
+        let mut lines = LogicalLines::new(natural_lines, self.dialect.has_continuations());
+        lines.blank_continuation_lines = self.blank_continuation_lines;
+        lines.max_continuation_lines = self.max_continuation_lines;
+        lines.track_positions = track_positions;
+        lines.keep_raw_text = self.keep_raw_text;
+        PropertiesIter {
+            lines,
+            duplicate_key_policy: self.duplicate_key_policy,
+            seen_keys: HashMap::new(),
+            progress_handler: self.progress_handler,
+            cancellation_check: self.cancellation_check,
+            skip_comments: self.skip_comments,
+            keep_blank_lines: self.keep_blank_lines,
+            raw_comments: false,
+            dialect: self.dialect,
+            section: None,
+            source_name: self.source_name,
+        }
+    }
+}
+
+impl Default for PropertiesIterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for PropertiesIterBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PropertiesIterBuilder")
+            .field("encoding", &self.encoding)
+            .field("buffer_size", &self.buffer_size)
+            .field("detect_bom", &self.detect_bom)
+            .field("strict_decoding", &self.strict_decoding)
+            .field("skip_comments", &self.skip_comments)
+            .field("keep_blank_lines", &self.keep_blank_lines)
+            .field("track_positions", &self.track_positions)
+            .field("keep_raw_text", &self.keep_raw_text)
+            .field("dialect", &self.dialect)
+            .field("blank_continuation_lines", &self.blank_continuation_lines)
+            .field("max_continuation_lines", &self.max_continuation_lines)
+            .field("source_name", &self.source_name)
+            .field("duplicate_key_policy", &self.duplicate_key_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) fn parsed_line_to_line(
+    parsed_line: ParsedLine<'_>,
+    line_number: usize,
+) -> Result<Line, PropertiesError> {
+    parsed_line_to_line_with_source(parsed_line, line_number).map_err(|(e, _)| e)
+}
+
+// Like `parsed_line_to_line`, but errors come with the malformed escape, as a slice of the line.
+#[allow(clippy::result_large_err)]
+fn parsed_line_to_line_with_source(
+    parsed_line: ParsedLine<'_>,
+    line_number: usize,
+) -> Result<Line, (PropertiesError, &str)> {
+    Ok(match parsed_line {
+        ParsedLine::Comment(c) => {
+            let comment = unescape_with_source(c, Some(line_number))?;
+            Line::mk_comment(line_number, comment)
+        }
+        ParsedLine::KVPair(k, v, separator) => {
+            let key = unescape_with_source(k, Some(line_number))?;
+            let value = unescape_with_source(v, Some(line_number))?;
+            Line::mk_pair(line_number, key, value, separator)
+        }
+    })
+}
+
+impl<R: Read + Seek> PropertiesIter<R> {
+    /// Seeks back to the beginning of the input and resets all parsing state, so the input can be
+    /// read again from the first line.
+    ///
+    /// Keys seen by a duplicate key handler are forgotten, but the handler itself is kept.
+    ///
+    /// ```
+    /// use java_properties::PropertiesIter;
+    /// use std::io::Cursor;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut iter = PropertiesIter::new(Cursor::new("a=1\nb=2\n"));
+    /// let count = iter.by_ref().count();
+    /// iter.rewind()?;
+    /// let mut keys = Vec::with_capacity(count);
+    /// iter.read_into(|k, _| keys.push(k))?;
+    /// assert_eq!(keys, ["a", "b"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rewind(&mut self) -> Result<(), PropertiesError> {
+        self.lines.physical_lines.rewind()?;
+        self.lines.eof = false;
+        self.seen_keys.clear();
+        Ok(())
+    }
+}
+
+impl PropertiesIter<io::Cursor<Vec<u8>>> {
+    /// Parses properties the way Java 9 and later read resource bundles: as UTF-8 if the input is
+    /// valid UTF-8, and as ISO-8859-1 otherwise.
+    ///
+    /// The whole input is read up front to decide, so a file is never decoded partly in one
+    /// encoding and partly in the other.  [`encoding`](Self::encoding) tells which one was chosen.
+    ///
+    /// ```
+    /// use encoding_rs::UTF_8;
+    /// use encoding_rs::WINDOWS_1252;
+    /// use java_properties::PropertiesIter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let iter = PropertiesIter::new_utf8_with_fallback(&b"name=caf\xc3\xa9\n"[..])?;
+    /// assert_eq!(iter.encoding(), UTF_8);
+    /// let iter = PropertiesIter::new_utf8_with_fallback(&b"name=caf\xe9\n"[..])?;
+    /// assert_eq!(iter.encoding(), WINDOWS_1252);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_utf8_with_fallback<R: Read>(mut input: R) -> Result<Self, PropertiesError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let encoding = if std::str::from_utf8(&bytes).is_ok() {
+            UTF_8
+        } else {
+            WINDOWS_1252
+        };
+        Ok(Self::new_with_encoding(io::Cursor::new(bytes), encoding))
+    }
+}
+
+/// Note that once `next` returns an error, the result of further calls is undefined.
+impl<R: Read> Iterator for PropertiesIter<R> {
+    type Item = Result<Line, PropertiesError>;
+
+    /// Returns the next line.
+    ///
+    /// Once this returns an error, the result of further calls is undefined.
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.next_line();
+        match (line, &self.source_name) {
+            (Some(Err(mut e)), Some(source_name)) => {
+                if e.source_name.is_none() {
+                    e.source_name = Some(source_name.clone());
+                }
+                Some(Err(e))
+            }
+            (line, _) => line,
+        }
+    }
+}
+
+impl<R: Read> PropertiesIter<R> {
+    fn next_line(&mut self) -> Option<Result<Line, PropertiesError>> {
+        loop {
+            if self.is_cancelled() {
+                return Some(Err(PropertiesError::new(
+                    PropertiesErrorKind::Cancelled,
+                    "Parsing was cancelled",
+                    None,
+                    Some(self.lines.physical_lines.line_count + 1),
+                )));
+            }
+            match self.lines.next() {
+                Some(Ok(LogicalLine(line_no, text, mut positions))) => {
+                    let encoding = self.encoding();
+                    let mut line = match self.parse_logical_line(&text, line_no) {
+                        Ok(Some(line)) => line,
+                        // The empty "line" after the final line terminator isn't a blank line, and
+                        // neither is an INI section header.
+                        Ok(None)
+                            if self.keep_blank_lines
+                                && !self.is_end(&text)
+                                && !self.is_section_header(&text) =>
+                        {
+                            Line::mk_blank(line_no)
+                        }
+                        Ok(None) => continue,
+                        Err((mut e, malformed)) => {
+                            if let Some(malformed) = malformed {
+                                positions.locate_error(&mut e, &text, malformed, encoding);
+                            }
+                            self.report_progress();
+                            return Some(Err(e));
+                        }
+                    };
+                    let indent = text.len() - text.trim_start().len();
+                    if let (Some((_, _, start)), Some((_, column, _))) = (
+                        positions.locate(&text, 0, encoding),
+                        positions.locate(&text, indent, encoding),
+                    ) {
+                        line.position = Position {
+                            column: Some(column),
+                            byte_range: Some((start, positions.end)),
+                            raw_text: positions.raw.take(),
+                        };
+                    }
+                    if self.skip_comments {
+                        if let LineContent::Comment(_) = line.data {
+                            continue;
+                        }
+                    }
+                    let line = match self.check_duplicate(&line) {
+                        Ok(true) => Ok(line),
+                        Ok(false) => continue,
+                        Err(e) => Err(e),
+                    };
+                    self.report_progress();
+                    return Some(line);
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.report_progress();
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/////////////////////
+
+/// A reader which copies every byte it reads to a secondary sink.
+///
+/// This allows the exact original input to be retained (e.g. in a cache file or a hasher)
+/// while it is parsed, without reading the source twice.
+///
+/// ```
+/// use java_properties::PropertiesIter;
+/// use java_properties::TeeReader;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let input = "a=b\n# comment\n";
+/// let mut tee = TeeReader::new(input.as_bytes(), Vec::new());
+/// let mut count = 0;
+/// PropertiesIter::new(&mut tee).read_into(|_, _| count += 1)?;
+/// let (_, raw) = tee.finish()?;
+/// assert_eq!(count, 1);
+/// assert_eq!(raw, input.as_bytes());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TeeReader<R: Read, W: Write> {
+    reader: R,
+    sink: W,
+}
+
+impl<R: Read, W: Write> TeeReader<R, W> {
+    /// Reads from `reader`, copying everything read to `sink`.
+    pub fn new(reader: R, sink: W) -> Self {
+        TeeReader { reader, sink }
+    }
+
+    /// Returns a reference to the sink.
+    pub fn sink(&self) -> &W {
+        &self.sink
+    }
+
+    /// Copies any input which has not been read yet to the sink, flushes the sink,
+    /// and returns the reader and the sink.
+    ///
+    /// This ensures the sink contains the complete input even if parsing stopped early.
+    pub fn finish(mut self) -> Result<(R, W), PropertiesError> {
+        io::copy(&mut self.reader, &mut self.sink)?;
+        self.sink.flush()?;
+        Ok((self.reader, self.sink))
+    }
+
+    /// Returns the reader and the sink without copying the rest of the input.
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.sink)
+    }
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.sink.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+/////////////////////
+
+/// A line ending style allowed in a Java properties file.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
+pub enum LineEnding {
+    /// Carriage return alone.
+    CR,
+    /// Line feed alone.
+    LF,
+    /// Carriage return followed by line feed.
+    // The name can't be changed without breaking backward compatibility.
+    #[allow(clippy::upper_case_acronyms)]
+    CRLF,
+}
+
+impl Display for LineEnding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            LineEnding::CR => "LineEnding::CR",
+            LineEnding::LF => "LineEnding::LF",
+            LineEnding::CRLF => "LineEnding::CRLF",
+        })
+    }
+}
+
+/// What a [`PropertiesWriter`] does with line terminators (`'\r'` or `'\n'`) in keys or values.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash, Default)]
+pub enum LineTerminatorPolicy {
+    /// Line terminators are escaped as `\r` and `\n`, so they are read back unchanged.  This is the default.
+    #[default]
+    Escape,
+    /// Writing a key or value containing a line terminator is an error.
+    Reject,
+    /// Each line terminator sequence (`"\r\n"`, `"\r"`, or `"\n"`) is replaced with a single space.
+    ReplaceWithSpace,
+}
+
+impl LineTerminatorPolicy {
+    // `what` describes the string for error messages.
+    fn apply<'a>(
+        self,
+        s: &'a str,
+        kind: PropertiesErrorKind,
+        what: impl FnOnce() -> String,
+    ) -> Result<Cow<'a, str>, PropertiesError> {
+        if !s.contains(['\r', '\n']) {
+            return Ok(Cow::Borrowed(s));
+        }
+        match self {
+            LineTerminatorPolicy::Escape => Ok(Cow::Borrowed(s)),
+            LineTerminatorPolicy::Reject => Err(PropertiesError::new(
+                kind,
+                format!("{} contains a line terminator", what()),
+                None,
+                None,
+            )),
+            LineTerminatorPolicy::ReplaceWithSpace => Ok(Cow::Owned(
+                s.replace("\r\n", " ").replace(['\r', '\n'], " "),
+            )),
+        }
+    }
+}
+
+/// What a [`PropertiesWriter`] does with keys which can't be written in its [`Dialect`], such as
+/// keys containing whitespace in a `.env` file.
+///
+/// Keys written in Java properties files can always be escaped, so this only affects the other
+/// dialects.  Changed keys are recorded in [`PropertiesWriter::key_mapping`], so they can be mapped
+/// back when the file is read.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash, Default)]
+pub enum KeyPolicy {
+    /// Writing the key is an error of kind [`PropertiesErrorKind::InvalidKey`].  This is the default.
+    #[default]
+    Reject,
+    /// Characters which aren't allowed are replaced with `_`.  Writing a key which is written the
+    /// same as an earlier, different key is an error, whether or not that key was changed too.
+    Sanitize,
+    /// Like `Sanitize`, but `_` and eight hex digits of a hash of the original key are appended, so
+    /// different keys are very unlikely to collide.  The hash is stable across versions of this crate.
+    HashSuffix,
+}
+
+// The error for two different keys which a key policy would write the same way.
+fn key_collision(first: &str, second: &str, written: &str) -> PropertiesError {
+    PropertiesError::new(
+        PropertiesErrorKind::InvalidKey,
+        format!(
+            "Keys {:?} and {:?} are both written as {:?}",
+            first, second, written
+        ),
+        None,
+        None,
+    )
+}
+
+impl KeyPolicy {
+    // Returns the replacement for a key rejected by the dialect, or None if it should be rejected.
+    fn replace(self, key: &str, dialect: Dialect) -> Option<String> {
+        let sanitized = match dialect {
+            Dialect::Java => return None,
+            Dialect::DotEnv => dotenv::sanitize_key(key),
+            Dialect::AndroidProp => android::sanitize_key(key),
+            Dialect::Ini => ini::sanitize_key(key),
+        };
+        match self {
+            KeyPolicy::Reject => None,
+            KeyPolicy::Sanitize => Some(sanitized),
+            KeyPolicy::HashSuffix => {
+                // 32-bit FNV-1a, which unlike `DefaultHasher` is fixed.
+                let hash = key.bytes().fold(0x811c_9dc5_u32, |hash, b| {
+                    (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+                });
+                Some(format!("{}_{:08x}", sanitized, hash))
+            }
+        }
+    }
+}
+
+/// Which spaces in values a [`PropertiesWriter`] escapes.  Spaces in keys are always escaped.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash, Default)]
+pub enum EscapeSpaces {
+    /// Every space is escaped, e.g. `greeting=hello\ world`.  This is the default.
+    #[default]
+    All,
+    /// Only a space at the start of the value is escaped, as `java.util.Properties.store` does, so
+    /// output can be compared byte for byte with Java's.  This is enough to keep leading spaces.
+    Leading,
+    /// No spaces are escaped.  Leading spaces are then dropped when the file is read, as is
+    /// whitespace after the separator.
+    None,
+}
+
+/// How an [`EscapePolicy`] writes a character.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash)]
+pub enum Escape {
+    /// The character is written as it is.  Characters the output encoding can't represent are
+    /// still written as `\uxxxx`.
+    Literal,
+    /// The character is written after a backslash, or as `\t`, `\n`, `\r`, or `\f`.
+    Backslash,
+    /// The character is written as `\uxxxx`, with a surrogate pair outside the Basic Multilingual
+    /// Plane.
+    Unicode,
+}
+
+/// Where a character passed to [`EscapePolicy::escape`] appears.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash)]
+pub struct EscapeContext {
+    in_key: bool,
+    at_start: bool,
+}
+
+impl EscapeContext {
+    /// Returns true if the character is part of a key, and false if it's part of a value.
+    pub fn is_key(&self) -> bool {
+        self.in_key
+    }
+
+    /// Returns true if the character is the first one of the key or value.
+    pub fn is_at_start(&self) -> bool {
+        self.at_start
+    }
+}
+
+/// Decides how a [`PropertiesWriter`] escapes each character of keys and values.
+///
+/// Consumers of properties files differ in which escapes they accept, so a policy can be installed
+/// with [`PropertiesWriter::set_escape_policy`].  Backslashes and line terminators are always
+/// escaped, whatever the policy returns, so that each entry stays on its own line.  Otherwise, the
+/// policy is responsible for escaping the characters the reader needs escaped, such as spaces and
+/// separators in keys.
+///
+/// ```
+/// use java_properties::Escape;
+/// use java_properties::EscapeContext;
+/// use java_properties::EscapePolicy;
+/// use java_properties::PropertiesWriter;
+/// use java_properties::StandardEscapes;
+///
+/// // Like the default, but writes every non-ASCII character as `\uxxxx`.
+/// struct AsciiOnly;
+///
+/// impl EscapePolicy for AsciiOnly {
+///     fn escape(&self, c: char, context: EscapeContext) -> Escape {
+///         if c.is_ascii() {
+///             StandardEscapes.escape(c, context)
+///         } else {
+///             Escape::Unicode
+///         }
+///     }
+/// }
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let mut buf = Vec::new();
+/// let mut writer = PropertiesWriter::new_with_encoding(&mut buf, encoding_rs::UTF_8);
+/// writer.set_escape_policy(AsciiOnly);
+/// writer.write("caf\u{e9}", "a b")?;
+/// writer.finish()?;
+/// drop(writer);
+/// assert_eq!(buf, b"caf\\u00e9=a\\ b\n");
+/// # Ok(())
+/// # }
+/// ```
+pub trait EscapePolicy: Send + Sync {
+    /// Returns how to write `c`.
+    fn escape(&self, c: char, context: EscapeContext) -> Escape;
+
+    /// Returns true if `\uxxxx` escapes use uppercase hex digits.  Defaults to false.
+    fn uppercase_hex(&self) -> bool {
+        false
+    }
+}
+
+/// The escaping [`PropertiesWriter`] does by default: whitespace, separators, comment characters,
+/// and backslashes get backslash escapes, and other control characters get `\uxxxx` escapes.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
+pub struct StandardEscapes;
+
+impl EscapePolicy for StandardEscapes {
+    fn escape(&self, c: char, _context: EscapeContext) -> Escape {
+        match c {
+            '\\' | ' ' | '\t' | '\r' | '\n' | '\x0c' | ':' | '=' | '!' | '#' => Escape::Backslash,
+            _ if c < ' ' => Escape::Unicode,
+            _ => Escape::Literal,
+        }
+    }
+}
+
+/// The escaping of `java.util.Properties.store(OutputStream, String)`, for output which can be
+/// compared byte for byte with Java's.
+///
+/// Spaces in values are only escaped at the start, and every character outside printable ASCII is
+/// written as `\uXXXX` with uppercase hex digits, even in encodings such as UTF-8.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash, Default)]
+pub struct JavaStoreEscapes;
+
+impl EscapePolicy for JavaStoreEscapes {
+    fn escape(&self, c: char, context: EscapeContext) -> Escape {
+        match c {
+            ' ' if context.is_key() || context.is_at_start() => Escape::Backslash,
+            '\\' | '\t' | '\r' | '\n' | '\x0c' | ':' | '=' | '!' | '#' => Escape::Backslash,
+            _ if !(' '..='~').contains(&c) => Escape::Unicode,
+            _ => Escape::Literal,
+        }
+    }
+
+    fn uppercase_hex(&self) -> bool {
+        true
+    }
+}
+
+/// Escapes a key or value according to `policy`.
+fn escape_with_policy(s: &str, in_key: bool, policy: &dyn EscapePolicy) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        let context = EscapeContext {
+            in_key,
+            at_start: i == 0,
+        };
+        match policy.escape(c, context) {
+            Escape::Literal if !matches!(c, '\\' | '\r' | '\n') => escaped.push(c),
+            Escape::Literal | Escape::Backslash => match c {
+                '\t' => escaped.push_str("\\t"),
+                '\r' => escaped.push_str("\\r"),
+                '\n' => escaped.push_str("\\n"),
+                '\x0c' => escaped.push_str("\\f"),
+                _ => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+            },
+            Escape::Unicode if policy.uppercase_hex() => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    let _ = write!(escaped, "\\u{:04X}", unit);
+                }
+            }
+            Escape::Unicode => push_unicode_escape(&mut escaped, c),
+        }
+    }
+    escaped
+}
+
+/// Formats a time as Java's `Date.toString` does in UTC, e.g. `Tue Mar 12 10:15:30 UTC 2024`.
+fn java_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+    };
+    let days = seconds.div_euclid(86400);
+    let seconds_of_day = seconds.rem_euclid(86400);
+    // Converts days since the epoch to a date in the proleptic Gregorian calendar.  See
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{} {} {:02} {:02}:{:02}:{:02} UTC {}",
+        DAYS[days.rem_euclid(7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        year
+    )
+}
+
+/// Appends a `\uxxxx` escape for `c`, using a surrogate pair for characters outside the BMP as Java does.
+fn push_unicode_escape(buf: &mut String, c: char) {
+    let mut units = [0; 2];
+    for unit in c.encode_utf16(&mut units) {
+        buf.push_str(&format!("\\u{:04x}", unit));
+    }
+}
+
+/// Escapes a key or value.  Characters which can't be represented in the output encoding are
+/// escaped by `EncodingWriter`.
+pub(crate) fn escape(s: &str) -> String {
+    escape_with_spaces(s, EscapeSpaces::All)
+}
+
+// Indentation of the continuation lines of a wrapped value.
+const FOLD_INDENT: &str = "    ";
+
+// Splits an escaped value into lines of at most `max` characters, counting the backslash which
+// continues each line but the last, and `column` characters before the value on the first line.
+// Lines are only broken between escape sequences, and before characters which the reader keeps
+// at the start of a continuation line.
+fn fold_value(escaped: &str, column: usize, max: usize) -> Vec<&str> {
+    // The byte offset, length in characters, and first character of each escape sequence or
+    // literal character.
+    let mut tokens = Vec::new();
+    let mut chars = escaped.char_indices();
+    while let Some((start, c)) = chars.next() {
+        let len = match c {
+            '\\' => match chars.next() {
+                Some((_, 'u')) => 2 + chars.by_ref().take(4).count(),
+                Some(_) => 2,
+                None => 1,
+            },
+            _ => 1,
+        };
+        tokens.push((start, len, c));
+    }
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+    let mut width = column;
+    // The last place the current line can be broken, and the width of the line before it.
+    let mut candidate: Option<(usize, usize)> = None;
+    for (i, &(start, len, first)) in tokens.iter().enumerate() {
+        if start > line_start && !matches!(first, ' ' | '\t' | '\x0c') {
+            candidate = Some((start, width));
+        }
+        width += len;
+        let limit = if i + 1 == tokens.len() { max } else { max - 1 };
+        if width > limit {
+            if let Some((at, before)) = candidate.take() {
+                lines.push(&escaped[line_start..at]);
+                line_start = at;
+                width = FOLD_INDENT.len() + width - before;
+            }
+        }
+    }
+    lines.push(&escaped[line_start..]);
+    lines
+}
+
+/// Like `escape`, but only escapes the spaces selected by `spaces`.
+fn escape_with_spaces(s: &str, spaces: EscapeSpaces) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        let unescaped_space = c == ' '
+            && match spaces {
+                EscapeSpaces::All => false,
+                EscapeSpaces::Leading => i > 0,
+                EscapeSpaces::None => true,
+            };
+        if unescaped_space {
+            escaped.push(c);
+        } else {
+            push_escaped(&mut escaped, c);
+        }
+    }
+    escaped
+}
+
+fn push_escaped(escaped: &mut String, c: char) {
+    match c {
+        '\\' => escaped.push_str("\\\\"),
+        ' ' => escaped.push_str("\\ "),
+        '\t' => escaped.push_str("\\t"),
+        '\r' => escaped.push_str("\\r"),
+        '\n' => escaped.push_str("\\n"),
+        '\x0c' => escaped.push_str("\\f"),
+        ':' => escaped.push_str("\\:"),
+        '=' => escaped.push_str("\\="),
+        '!' => escaped.push_str("\\!"),
+        '#' => escaped.push_str("\\#"),
+        _ if c < ' ' => push_unicode_escape(escaped, c),
+        _ => escaped.push(c), // We don't worry about other characters, since they're taken care of by EncodingWriter.
+    }
+}
+
+/// Escapes a key so it can be spliced into properties text, as [`PropertiesWriter`] does.
+///
+/// Whitespace, separators, comment characters, backslashes, and control characters are escaped.
+/// Other characters are left as they are, so characters which the file's encoding can't represent
+/// still need to be dealt with.
+///
+/// ```
+/// assert_eq!(java_properties::escape_key("a b=c"), r"a\ b\=c");
+/// ```
+pub fn escape_key(key: &str) -> String {
+    escape(key)
+}
+
+/// Escapes a value so it can be spliced into properties text after a separator.
+///
+/// This follows `java.util.Properties.store`, which only escapes a space at the start of a value.
+/// Other characters are escaped as by [`escape_key`].
+///
+/// ```
+/// assert_eq!(java_properties::escape_value("  a b\n"), r"\  a b\n");
+/// ```
+pub fn escape_value(value: &str) -> String {
+    escape_with_spaces(value, EscapeSpaces::Leading)
+}
+
+/// Escapes the text of a comment so it can be spliced into properties text after a comment
+/// prefix such as `# `.
+///
+/// Line terminators, backslashes, and other control characters are escaped, so the comment stays
+/// on one line and [`PropertiesIter`] reads back the original text.  Leading and trailing
+/// whitespace is not preserved.
+///
+/// ```
+/// assert_eq!(java_properties::escape_comment("two\nlines"), r"two\nlines");
+/// ```
+pub fn escape_comment(comment: &str) -> String {
+    let mut escaped = String::with_capacity(comment.len());
+    for c in comment.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            _ if c < ' ' && c != '\t' => push_unicode_escape(&mut escaped, c),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) struct EncodingWriter<W: Write> {
+    writer: W,
+    lines_written: usize,
+    encoder: Encoder,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    pub(crate) fn new(writer: W, encoding: &'static Encoding) -> Self {
+        EncodingWriter {
+            writer,
+            lines_written: 0,
+            encoder: encoding.new_encoder(),
+            // It's important that we start with a non-zero capacity, since we double it as needed.
+            buffer: Vec::with_capacity(256),
+        }
+    }
+
+    pub(crate) fn write(&mut self, mut data: &str) -> Result<(), PropertiesError> {
+        while !data.is_empty() {
+            let (result, bytes_read) = self.encoder.encode_from_utf8_to_vec_without_replacement(
+                data,
+                &mut self.buffer,
+                false,
+            );
+            data = &data[bytes_read..];
+            match result {
+                EncoderResult::InputEmpty => (),
+                EncoderResult::OutputFull => {
+                    // encoding_rs won't just append to vectors if it would require allocation.
+                    self.buffer.reserve(self.buffer.capacity() * 2);
+                }
+                EncoderResult::Unmappable(c) => {
+                    let mut escaped = String::new();
+                    push_unicode_escape(&mut escaped, c);
+                    let (result2, _) = self.encoder.encode_from_utf8_to_vec_without_replacement(
+                        &escaped,
+                        &mut self.buffer,
+                        false,
+                    );
+                    match result2 {
+                        EncoderResult::InputEmpty => (),
+                        EncoderResult::OutputFull => {
+                            // encoding_rs won't just append to vectors if it would require allocation.
+                            self.buffer.reserve(self.buffer.capacity() * 2);
+                        }
+                        EncoderResult::Unmappable(_) => {
+                            return Err(PropertiesError::new(
+                                PropertiesErrorKind::Encode,
+                                format!(
+                                    "Encoding error: unable to write UTF-8 escaping {:?} for {:?}",
+                                    escaped, c
+                                ),
+                                None,
+                                Some(self.lines_written),
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+        self.flush_buffer()?;
+        Ok(())
+    }
+
+    fn flush_buffer(&mut self) -> Result<(), PropertiesError> {
+        self.writer.write_all(&self.buffer).map_err(|e| {
+            PropertiesError::new(
+                PropertiesErrorKind::Io,
+                "I/O error",
+                Some(Box::new(e)),
+                Some(self.lines_written),
+            )
+        })?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), PropertiesError> {
+        self.flush_buffer()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn finish(&mut self) -> Result<(), PropertiesError> {
+        let (result, _) =
+            self.encoder
+                .encode_from_utf8_to_vec_without_replacement("", &mut self.buffer, true);
+        match result {
+            EncoderResult::InputEmpty => (),
+            EncoderResult::OutputFull => {
+                return Err(PropertiesError::new(
+                    PropertiesErrorKind::Encode,
+                    "Encoding error: output full",
+                    None,
+                    Some(self.lines_written),
+                ))
+            }
+            EncoderResult::Unmappable(c) => {
+                return Err(PropertiesError::new(
+                    PropertiesErrorKind::Encode,
+                    format!("Encoding error: unmappable character {:?}", c),
+                    None,
+                    Some(self.lines_written),
+                ))
+            }
+        }
+        self.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes to a properties file.
+///
+/// `finish()` *must* be called after writing all data.
+pub struct PropertiesWriter<W: Write> {
+    comment_prefix: String,
+    kv_separator: String,
+    line_ending: LineEnding,
+    escape_non_bmp: bool,
+    value_spaces: EscapeSpaces,
+    escape_policy: Option<Box<dyn EscapePolicy>>,
+    // Zero for no limit.
+    max_line_length: usize,
+    key_width: usize,
+    // The time written by write_header, if not the current time.
+    header_time: Option<SystemTime>,
+    dialect: Dialect,
+    // The section of an INI file which keys are currently written in.
+    section: Option<String>,
+    key_prefix: String,
+    key_line_terminators: LineTerminatorPolicy,
+    value_line_terminators: LineTerminatorPolicy,
+    key_policy: KeyPolicy,
+    // Maps keys changed by the key policy to the original keys.
+    key_mapping: HashMap<String, String>,
+    // Keys written unchanged in dialects other than Java, which changed keys mustn't collide with.
+    unchanged_keys: HashSet<String>,
+    // Reused for formatting values in write_display.
+    scratch: String,
+    // If true, this writes part of a file, and `finish` leaves the encoding unfinished.
+    fragment: bool,
+    writer: EncodingWriter<W>,
+}
+
+impl<W: Write> PropertiesWriter<W> {
+    /// Writes to the given `Write` stream.
+    pub fn new(writer: W) -> Self {
+        Self::new_with_encoding(writer, WINDOWS_1252)
+    }
+
+    /// Writes to the given `Write` stream in the given encoding.
+    /// Note that the Java properties specification specifies ISO-8859-1 encoding
+    /// for properties files; in most cases, `new` should be called instead.
+    pub fn new_with_encoding(writer: W, encoding: &'static Encoding) -> Self {
+        PropertiesWriter {
+            comment_prefix: "# ".to_string(),
+            kv_separator: "=".to_string(),
+            line_ending: LineEnding::LF,
+            escape_non_bmp: false,
+            value_spaces: EscapeSpaces::All,
+            escape_policy: None,
+            max_line_length: 0,
+            key_width: 0,
+            header_time: None,
+            dialect: Dialect::Java,
+            section: None,
+            key_prefix: String::new(),
+            key_line_terminators: LineTerminatorPolicy::Escape,
+            value_line_terminators: LineTerminatorPolicy::Escape,
+            key_policy: KeyPolicy::Reject,
+            key_mapping: HashMap::new(),
+            unchanged_keys: HashSet::new(),
+            scratch: String::new(),
+            fragment: false,
+            writer: EncodingWriter::new(writer, encoding),
+        }
+    }
+
+    /// Writes part of a properties file to the given `Write` stream in the given encoding.
+    ///
+    /// This is for composing one file from several producers, each writing its own keys and
+    /// comments.  A fragment's [`finish`](Self::finish) only flushes its output, without finishing
+    /// the encoding, so fragments can be appended to one stream in turn, or written to separate
+    /// buffers and joined with [`write_fragment`](Self::write_fragment).  The encoding is finished
+    /// once, by the writer for the whole file.
+    ///
+    /// ```
+    /// use encoding_rs::UTF_8;
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut db = Vec::new();
+    /// let mut writer = PropertiesWriter::new_fragment(&mut db, UTF_8);
+    /// writer.write_comment("Database")?;
+    /// writer.write("db.url", "jdbc:h2:mem:")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    ///
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new_with_encoding(&mut buf, UTF_8);
+    /// writer.write("name", "app")?;
+    /// writer.write_fragment(&db)?;
+    /// writer.write("debug", "false")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"name=app\n# Database\ndb.url=jdbc\\:h2\\:mem\\:\ndebug=false\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_fragment(writer: W, encoding: &'static Encoding) -> Self {
+        PropertiesWriter {
+            fragment: true,
+            ..Self::new_with_encoding(writer, encoding)
+        }
+    }
+
+    /// Returns true if this writer was created with [`new_fragment`](Self::new_fragment).
+    pub fn is_fragment(&self) -> bool {
+        self.fragment
+    }
+
+    /// Appends a fragment written by a [`new_fragment`](Self::new_fragment) writer in the same
+    /// encoding.  The fragment should end with a line terminator, as every fragment writer's output
+    /// does.
+    pub fn write_fragment(&mut self, fragment: &[u8]) -> Result<(), PropertiesError> {
+        self.write_raw(fragment)
+    }
+
+    /// Writes bytes directly to the underlying stream, bypassing escaping and the encoder.
+    ///
+    /// This is an escape hatch for output which is already encoded, such as a cached section of a
+    /// file.  The caller is responsible for the invariants the writer normally maintains:
+    ///
+    /// * The bytes must be in the writer's output encoding.  For stateful encodings such as
+    ///   ISO-2022-JP, they must also be valid in the encoder's current state, which they don't
+    ///   change; writing only ASCII between lines is always safe.
+    /// * Keys, values, and comments must already be escaped, or the file won't read back as intended.
+    /// * The bytes should end with a line terminator, or the next line will be joined to them.
+    ///
+    /// Line terminators in the bytes are counted towards the line numbers in later errors.
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.write("a", "1")?;
+    /// writer.write_raw(b"cached=caf\xe9\r\n")?;
+    /// writer.write("b", "2")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"a=1\ncached=caf\xe9\r\nb=2\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_raw(&mut self, bytes: &[u8]) -> Result<(), PropertiesError> {
+        self.writer.flush_buffer()?;
+        self.writer.writer.write_all(bytes)?;
+        // Every encoding_rs output encoding is ASCII-compatible, so line terminators can be counted
+        // bytewise.
+        let mut iter = bytes.iter().peekable();
+        while let Some(&b) = iter.next() {
+            if b == b'\n' || (b == b'\r' && iter.peek() != Some(&&b'\n')) {
+                self.writer.lines_written += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes to the given `Write` stream exactly as `java.util.Properties.store(OutputStream, String)`
+    /// does, so that files only differ from Java's in their content.
+    ///
+    /// The output is ISO-8859-1, every character outside printable ASCII is written as `\uXXXX`
+    /// (see [`JavaStoreEscapes`]), and keys and values are separated by `=`.  Like Java's, the file
+    /// should start with the comments and time written by [`write_header`](Self::write_header).
+    /// Lines end with `\n`; call [`set_line_ending`](Self::set_line_ending) to match a platform
+    /// where Java uses `\r\n`.
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    /// use std::time::UNIX_EPOCH;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::compat_java_store(&mut buf);
+    /// writer.set_header_time(UNIX_EPOCH);
+    /// writer.write_header(Some("Settings"), true)?;
+    /// writer.write("greeting", " h\u{e9}llo world")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(
+    ///     String::from_utf8(buf).unwrap(),
+    ///     "#Settings\n#Thu Jan 01 00:00:00 UTC 1970\ngreeting=\\ h\\u00E9llo world\n"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compat_java_store(writer: W) -> Self {
+        let mut writer = Self::new_with_encoding(writer, WINDOWS_1252);
+        writer.comment_prefix = "#".to_string();
+        writer.set_escape_policy(JavaStoreEscapes);
+        writer
+    }
+
+    /// Writes the header `java.util.Properties.store` starts a file with: `comment`, if any, as
+    /// `#comment`, and if `timestamp` is true, the time, as in `#Tue Mar 12 10:15:30 UTC 2024`.
+    ///
+    /// As in Java, each line of the comment becomes a comment line, unless it already starts with
+    /// `#` or `!`, and characters outside ISO-8859-1 are written as `\uXXXX`.  The time is the
+    /// current time in UTC, unless set with [`set_header_time`](Self::set_header_time) for
+    /// reproducible output.
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    /// use std::time::Duration;
+    /// use std::time::UNIX_EPOCH;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.set_header_time(UNIX_EPOCH + Duration::from_secs(1_710_238_530));
+    /// writer.write_header(Some("Generated\nDo not edit"), true)?;
+    /// writer.write("a", "1")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(
+    ///     String::from_utf8(buf).unwrap(),
+    ///     "#Generated\n#Do not edit\n#Tue Mar 12 10:15:30 UTC 2024\na=1\n"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_header(
+        &mut self,
+        comment: Option<&str>,
+        timestamp: bool,
+    ) -> Result<(), PropertiesError> {
+        if let Some(comment) = comment {
+            self.write_store_comments(comment)?;
+        }
+        if timestamp {
+            let time = self.header_time.unwrap_or_else(SystemTime::now);
+            self.writer.lines_written += 1;
+            self.writer.write("#")?;
+            self.writer.write(&java_date(time))?;
+            self.write_eol()?;
+        }
+        Ok(())
+    }
+
+    /// Sets the time [`write_header`](Self::write_header) writes, instead of the current time.
+    pub fn set_header_time(&mut self, time: SystemTime) {
+        self.header_time = Some(time);
+    }
+
+    // Writes comments as `Properties.store` does: each line of `comments` becomes a comment, unless
+    // it already starts with '#' or '!', and characters outside ISO-8859-1 are written as `\uXXXX`.
+    fn write_store_comments(&mut self, comments: &str) -> Result<(), PropertiesError> {
+        let mut text = String::from("#");
+        let mut chars = comments.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' | '\n' => {
+                    if c == '\r' && chars.peek() == Some(&'\n') {
+                        chars.next();
+                    }
+                    self.writer.lines_written += 1;
+                    self.writer.write(&text)?;
+                    self.write_eol()?;
+                    text.clear();
+                    if !matches!(chars.peek(), Some('#') | Some('!')) {
+                        text.push('#');
+                    }
+                }
+                _ if c > '\u{ff}' => {
+                    let mut units = [0; 2];
+                    for unit in c.encode_utf16(&mut units) {
+                        let _ = write!(text, "\\u{:04X}", unit);
+                    }
+                }
+                _ => text.push(c),
+            }
+        }
+        self.writer.lines_written += 1;
+        self.writer.write(&text)?;
+        self.write_eol()
+    }
+
+    /// Writes to the given `Write` stream, returning a guard which must be closed with
+    /// [`ScopedPropertiesWriter::close`] instead of calling `finish()`.
+    pub fn new_scoped(writer: W) -> ScopedPropertiesWriter<W> {
+        ScopedPropertiesWriter {
+            inner: Some(Self::new(writer)),
+        }
+    }
+
+    /// Like [`new_scoped`](Self::new_scoped), but in the given encoding.
+    pub fn new_scoped_with_encoding(
+        writer: W,
+        encoding: &'static Encoding,
+    ) -> ScopedPropertiesWriter<W> {
+        ScopedPropertiesWriter {
+            inner: Some(Self::new_with_encoding(writer, encoding)),
+        }
+    }
+
+    fn write_eol(&mut self) -> Result<(), PropertiesError> {
+        self.writer.write(match self.line_ending {
+            LineEnding::CR => "\r",
+            LineEnding::LF => "\n",
+            LineEnding::CRLF => "\r\n",
+        })?;
+        Ok(())
+    }
+
+    /// Writes a comment to the file.
+    pub fn write_comment(&mut self, comment: &str) -> Result<(), PropertiesError> {
+        self.writer.lines_written += 1;
+        self.writer.write(&self.comment_prefix)?;
+        self.writer.write(comment)?;
+        self.write_eol()?;
+        Ok(())
+    }
+
+    fn write_escaped(&mut self, s: &str, in_key: bool) -> Result<(), PropertiesError> {
+        self.writer.lines_written += 1;
+        let escaped = self.escape(s, in_key);
+        self.writer.write(&escaped)
+    }
+
+    fn escape(&self, s: &str, in_key: bool) -> String {
+        if let Some(policy) = &self.escape_policy {
+            return escape_with_policy(s, in_key, policy.as_ref());
+        }
+        let spaces = if in_key {
+            EscapeSpaces::All
+        } else {
+            self.value_spaces
+        };
+        let mut escaped = escape_with_spaces(s, spaces);
+        if self.escape_non_bmp && escaped.chars().any(|c| c > '\u{ffff}') {
+            let mut buf = String::with_capacity(escaped.len());
+            for c in escaped.chars() {
+                if c > '\u{ffff}' {
+                    push_unicode_escape(&mut buf, c);
+                } else {
+                    buf.push(c);
+                }
+            }
+            escaped = buf;
+        }
+        escaped
+    }
+
+    fn write_folded_value(&mut self, value: &str, column: usize) -> Result<(), PropertiesError> {
+        self.writer.lines_written += 1;
+        let escaped = self.escape(value, false);
+        let lines = fold_value(&escaped, column, self.max_line_length);
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                self.writer.write("\\")?;
+                self.write_eol()?;
+                self.writer.write(FOLD_INDENT)?;
+            }
+            self.writer.write(line)?;
+        }
+        Ok(())
+    }
+
+    fn prefix_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if self.key_prefix.is_empty() {
+            Cow::Borrowed(key)
+        } else {
+            Cow::Owned(format!("{}{}", self.key_prefix, key))
+        }
+    }
+
+    fn check_key<'a>(&mut self, key: &'a str) -> Result<Cow<'a, str>, PropertiesError> {
+        let key = self
+            .key_line_terminators
+            .apply(key, PropertiesErrorKind::InvalidKey, || {
+                format!("Key {:?}", key)
+            })?;
+        let result = match self.dialect {
+            Dialect::Java => Ok(()),
+            Dialect::DotEnv => dotenv::check_key(&key),
+            Dialect::AndroidProp => android::check_key(&key),
+            Dialect::Ini => ini::check_key(&key),
+        };
+        let replacement = match result {
+            Ok(()) if self.dialect == Dialect::Java => return Ok(key),
+            Ok(()) => {
+                if let Some(original) = self.key_mapping.get(key.as_ref()) {
+                    return Err(key_collision(original, &key, &key));
+                }
+                if !self.unchanged_keys.contains(key.as_ref()) {
+                    self.unchanged_keys.insert(key.to_string());
+                }
+                return Ok(key);
+            }
+            Err(e) => match self.key_policy.replace(&key, self.dialect) {
+                Some(replacement) => replacement,
+                None => return Err(e),
+            },
+        };
+        if self.unchanged_keys.contains(&replacement) {
+            return Err(key_collision(&replacement, &key, &replacement));
+        }
+        match self.key_mapping.get(&replacement) {
+            Some(original) if *original != *key => {
+                return Err(key_collision(original, &key, &replacement));
+            }
+            Some(_) => (),
+            None => {
+                self.key_mapping
+                    .insert(replacement.clone(), key.into_owned());
+            }
+        }
+        Ok(Cow::Owned(replacement))
+    }
+
+    /// Writes an empty line, e.g. to separate groups of entries.
+    pub fn write_blank_line(&mut self) -> Result<(), PropertiesError> {
+        self.writer.lines_written += 1;
+        self.write_eol()
+    }
+
+    /// Writes a key/value pair to the file.
+    pub fn write(&mut self, key: &str, value: &str) -> Result<(), PropertiesError> {
+        let value =
+            self.value_line_terminators
+                .apply(value, PropertiesErrorKind::InvalidValue, || {
+                    format!("Value for key {:?}", key)
+                })?;
+        let key = self.prefix_key(key);
+        let key = self.check_key(&key)?;
+        match self.dialect {
+            Dialect::Java => {
+                self.writer.lines_written += 1;
+                let escaped = self.escape(&key, true);
+                let width = escaped.chars().count();
+                self.writer.write(&escaped)?;
+                if width < self.key_width {
+                    self.writer.write(&" ".repeat(self.key_width - width))?;
+                }
+                self.writer.write(&self.kv_separator)?;
+                if self.max_line_length > 0 {
+                    let column = width.max(self.key_width) + self.kv_separator.chars().count();
+                    self.write_folded_value(&value, column)?;
+                } else {
+                    self.write_escaped(&value, false)?;
+                }
+            }
+            Dialect::DotEnv => {
+                self.writer.lines_written += 1;
+                self.writer.write(&key)?;
+                self.writer.write("=")?;
+                self.writer.write(&dotenv::format_value(&value))?;
+            }
+            Dialect::AndroidProp => {
+                android::check_value(&key, &value)?;
+                self.writer.lines_written += 1;
+                self.writer.write(&key)?;
+                self.writer.write("=")?;
+                self.writer.write(&value)?;
+            }
+            Dialect::Ini => {
+                ini::check_value(&key, &value)?;
+                let name = self.write_section(&key)?;
+                self.writer.lines_written += 1;
+                self.writer.write(name)?;
+                self.writer.write("=")?;
+                self.writer.write(&value)?;
+            }
+        }
+        self.write_eol()?;
+        Ok(())
+    }
+
+    // Writes a header if the key is in a different INI section than the previous key, and returns
+    // the name of the key within its section.
+    fn write_section<'a>(&mut self, key: &'a str) -> Result<&'a str, PropertiesError> {
+        let (section, name) = ini::split_key(key);
+        if section == self.section.as_deref() {
+            return Ok(name);
+        }
+        let section = section.ok_or_else(|| {
+            PropertiesError::new(
+                PropertiesErrorKind::InvalidKey,
+                format!(
+                    "Key {:?} has no section, so it can't be written after a section",
+                    key
+                ),
+                None,
+                None,
+            )
+        })?;
+        if self.writer.lines_written > 0 {
+            self.write_blank_line()?;
+        }
+        self.writer.lines_written += 1;
+        self.writer.write("[")?;
+        self.writer.write(section)?;
+        self.writer.write("]")?;
+        self.write_eol()?;
+        self.section = Some(section.to_string());
+        Ok(name)
+    }
+
+    /// Writes a key/value pair to the file, formatting the value with `Display`.
+    ///
+    /// The value is formatted into a buffer which is reused across calls, instead of a new `String`
+    /// from `to_string`.  It is then escaped like any other value, which may still allocate.
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.write_display("port", &8080)?;
+    /// writer.write_display_all(vec![("ratio", 0.5), ("scale", 2.0)])?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"port=8080\nratio=0.5\nscale=2\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_display<V: Display + ?Sized>(
+        &mut self,
+        key: &str,
+        value: &V,
+    ) -> Result<(), PropertiesError> {
+        let mut scratch = mem::take(&mut self.scratch);
+        scratch.clear();
+        let result = match write!(scratch, "{}", value) {
+            Ok(()) => self.write(key, &scratch),
+            Err(e) => Err(PropertiesError::new(
+                PropertiesErrorKind::Format,
+                format!("Unable to format the value for key {:?}", key),
+                Some(Box::new(e)),
+                None,
+            )),
+        };
+        self.scratch = scratch;
+        result
+    }
+
+    /// Writes each key/value pair with [`write_display`](Self::write_display), e.g. from a map.
+    pub fn write_display_all<I, K, V>(&mut self, pairs: I) -> Result<(), PropertiesError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Display,
+    {
+        for (key, value) in pairs {
+            self.write_display(key.as_ref(), &value)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a key/value pair preceded by a comment explaining it.  Each line of the comment is
+    /// written with [`write_comment`](Self::write_comment).
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.write_with_comment("port", "8080", "Port to listen on.\nMust be above 1024.")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(
+    ///     String::from_utf8(buf).unwrap(),
+    ///     "# Port to listen on.\n# Must be above 1024.\nport=8080\n"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_with_comment(
+        &mut self,
+        key: &str,
+        value: &str,
+        comment: &str,
+    ) -> Result<(), PropertiesError> {
+        write_comment_lines(self, comment)?;
+        self.write(key, value)
+    }
+
+    /// Writes an entry, preceded by its comment if it has one.  See
+    /// [`write_with_comment`](Self::write_with_comment).
+    pub fn write_entry(&mut self, entry: &CommentedEntry) -> Result<(), PropertiesError> {
+        match &entry.comment {
+            Some(comment) => self.write_with_comment(&entry.key, &entry.value, comment),
+            None => self.write(&entry.key, &entry.value),
+        }
+    }
+
+    /// Writes a line, such as one read by [`PropertiesIter`].
+    ///
+    /// Comments are written with [`write_comment`](Self::write_comment), and blank lines with
+    /// [`write_blank_line`](Self::write_blank_line).  Key/value pairs are
+    /// written with [`write_key_only`](Self::write_key_only) if they have no separator and an empty
+    /// value, and with [`write`](Self::write) otherwise.  The line number is ignored.
+    ///
+    /// [`PropertiesIter`] unescapes comments in Java properties files, so in [`Dialect::Java`] the
+    /// comment is escaped again with [`escape_comment`] before it is written.
+    ///
+    /// ```
+    /// use java_properties::LineContent;
+    /// use java_properties::PropertiesIter;
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let input = "# settings\nsecret.token=abc\nname = x\nflag\n";
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// for line in PropertiesIter::new(input.as_bytes()) {
+    ///     let line = line?;
+    ///     match line.content() {
+    ///         LineContent::KVPair(key, _) if key.starts_with("secret.") => (),
+    ///         _ => writer.write_line(&line)?,
+    ///     }
+    /// }
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"# settings\nname=x\nflag\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_line(&mut self, line: &Line) -> Result<(), PropertiesError> {
+        match &line.data {
+            LineContent::Comment(comment) if self.dialect == Dialect::Java => {
+                self.write_comment(&escape_comment(comment))
+            }
+            LineContent::Comment(comment) => self.write_comment(comment),
+            LineContent::KVPair(key, value)
+                if value.is_empty() && line.separator == Some(KVSeparator::Absent) =>
+            {
+                self.write_key_only(key)
+            }
+            LineContent::KVPair(key, value) => self.write(key, value),
+            LineContent::Blank => self.write_blank_line(),
+        }
+    }
+
+    /// Writes a key with an empty value and no separator.
+    ///
+    /// This is read back as the key with an empty value, the same as `write(key, "")`.
+    /// The key must not be empty, since an empty line would not be read back as a key.
+    pub fn write_key_only(&mut self, key: &str) -> Result<(), PropertiesError> {
+        let key = self.prefix_key(key);
+        if key.is_empty() {
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::InvalidKey,
+                "Cannot write an empty key without a separator",
+                None,
+                None,
+            ));
+        }
+        let key = self.check_key(&key)?;
+        match self.dialect {
+            Dialect::Java => self.write_escaped(&key, true)?,
+            Dialect::DotEnv | Dialect::AndroidProp => {
+                self.writer.lines_written += 1;
+                self.writer.write(&key)?;
+                self.writer.write("=")?;
+            }
+            Dialect::Ini => {
+                let name = self.write_section(&key)?;
+                self.writer.lines_written += 1;
+                self.writer.write(name)?;
+                self.writer.write("=")?;
+            }
+        }
+        self.write_eol()?;
+        Ok(())
+    }
+
+    /// Flushes the underlying stream.
+    pub fn flush(&mut self) -> Result<(), PropertiesError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Sets the comment prefix.
+    ///
+    /// The prefix must contain a '#' or a '!', may only contain spaces, tabs, or form feeds before the comment character,
+    /// and may not contain any carriage returns or line feeds ('\r' or '\n').
+    pub fn set_comment_prefix(&mut self, prefix: &str) -> Result<(), PropertiesError> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^[ \t\x0c]*[#!][^\r\n]*$").unwrap();
+        }
+        if !RE.is_match(prefix) {
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::InvalidCommentPrefix,
+                format!("Bad comment prefix: {:?}", prefix),
+                None,
+                None,
+            ));
+        }
+        self.comment_prefix = prefix.to_string();
+        Ok(())
+    }
+
+    /// Sets the key/value separator.
+    ///
+    /// The separator may be non-empty whitespace, or a colon with optional whitespace on either side,
+    /// or an equals sign with optional whitespace on either side.  (Whitespace here means ' ', '\t', or '\f'.)
+    pub fn set_kv_separator(&mut self, separator: &str) -> Result<(), PropertiesError> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^([ \t\x0c]*[:=][ \t\x0c]*|[ \t\x0c]+)$").unwrap();
+        }
+        if !RE.is_match(separator) {
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::InvalidSeparator,
+                format!("Bad key/value separator: {:?}", separator),
+                None,
+                None,
+            ));
+        }
+        self.kv_separator = separator.to_string();
+        Ok(())
+    }
+
+    /// Sets the line ending.
+    ///
+    /// [`write_with_line_ending`](Self::write_with_line_ending) and
+    /// [`write_comment_with_line_ending`](Self::write_comment_with_line_ending) override it for a single line.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    /// Writes a key/value pair to the file, ending the line with `line_ending` instead of the
+    /// writer's line ending.
+    ///
+    /// ```
+    /// use java_properties::LineEnding;
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.write("a", "1")?;
+    /// writer.write_comment_with_line_ending("Windows only", LineEnding::CRLF)?;
+    /// writer.write_with_line_ending("dir", "app", LineEnding::CRLF)?;
+    /// writer.write("b", "2")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"a=1\n# Windows only\r\ndir=app\r\nb=2\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_with_line_ending(
+        &mut self,
+        key: &str,
+        value: &str,
+        line_ending: LineEnding,
+    ) -> Result<(), PropertiesError> {
+        let default = mem::replace(&mut self.line_ending, line_ending);
+        let result = self.write(key, value);
+        self.line_ending = default;
+        result
+    }
+
+    /// Writes a comment to the file, ending the line with `line_ending` instead of the writer's
+    /// line ending.
+    pub fn write_comment_with_line_ending(
+        &mut self,
+        comment: &str,
+        line_ending: LineEnding,
+    ) -> Result<(), PropertiesError> {
+        let default = mem::replace(&mut self.line_ending, line_ending);
+        let result = self.write_comment(comment);
+        self.line_ending = default;
+        result
+    }
+
+    /// Sets a prefix which is prepended to every key written from now on, such as `"myapp."`.
+    /// Defaults to no prefix.  The prefix is part of the key, so it is escaped along with it.
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.set_key_prefix("myapp.");
+    /// writer.write("port", "8080")?;
+    /// writer.write_display("threads", &4)?;
+    /// writer.set_key_prefix("");
+    /// writer.write("other", "x")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"myapp.port=8080\nmyapp.threads=4\nother=x\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_key_prefix(&mut self, prefix: &str) {
+        self.key_prefix = prefix.to_string();
+    }
+
+    /// Returns the prefix prepended to every key.  See [`set_key_prefix`](Self::set_key_prefix).
+    pub fn key_prefix(&self) -> &str {
+        &self.key_prefix
+    }
+
+    /// Sets the file format.  Defaults to [`Dialect::Java`].
+    ///
+    /// Settings which don't apply to the dialect, such as the key/value separator for `.env` files, are ignored.
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    /// Sets what happens to line terminators in keys.  Defaults to [`LineTerminatorPolicy::Escape`].
+    ///
+    /// ```
+    /// use java_properties::LineTerminatorPolicy;
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.set_key_line_terminator_policy(LineTerminatorPolicy::Reject);
+    /// writer.set_value_line_terminator_policy(LineTerminatorPolicy::ReplaceWithSpace);
+    /// assert!(writer.write("a\nb", "1").is_err());
+    /// writer.write("a", "1\r\n2")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"a=1\\ 2\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_key_line_terminator_policy(&mut self, policy: LineTerminatorPolicy) {
+        self.key_line_terminators = policy;
+    }
+
+    /// Sets what happens to line terminators in values.  Defaults to [`LineTerminatorPolicy::Escape`].
+    pub fn set_value_line_terminator_policy(&mut self, policy: LineTerminatorPolicy) {
+        self.value_line_terminators = policy;
+    }
+
+    /// Sets what happens to keys which can't be written in the dialect.  Defaults to
+    /// [`KeyPolicy::Reject`].
+    ///
+    /// ```
+    /// use java_properties::Dialect;
+    /// use java_properties::KeyPolicy;
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.set_dialect(Dialect::DotEnv);
+    /// writer.set_key_policy(KeyPolicy::Sanitize);
+    /// writer.write("app name", "demo")?;
+    /// assert_eq!(writer.key_mapping()["app_name"], "app name");
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"app_name=demo\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_key_policy(&mut self, policy: KeyPolicy) {
+        self.key_policy = policy;
+    }
+
+    /// Returns the policy for keys which can't be written in the dialect.
+    pub fn key_policy(&self) -> KeyPolicy {
+        self.key_policy
+    }
+
+    /// Returns the keys changed by the [`KeyPolicy`] so far, mapping each key as written to the
+    /// original key.  Keys written unchanged aren't included.
+    pub fn key_mapping(&self) -> &HashMap<String, String> {
+        &self.key_mapping
+    }
+
+    /// Sets whether characters outside the Basic Multilingual Plane in keys and values are always
+    /// written as `\uxxxx` surrogate pair escapes.
+    ///
+    /// Characters the output encoding can't represent are escaped regardless, so this only matters for
+    /// encodings such as UTF-8, where it keeps files readable by tools which expect the BMP only.
+    /// Defaults to false.
+    pub fn set_escape_non_bmp(&mut self, escape_non_bmp: bool) {
+        self.escape_non_bmp = escape_non_bmp;
+    }
+
+    /// Sets which spaces in values are escaped.  Defaults to [`EscapeSpaces::All`].
+    ///
+    /// ```
+    /// use java_properties::EscapeSpaces;
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.set_escape_spaces(EscapeSpaces::Leading);
+    /// writer.write("greeting", " hello world")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"greeting=\\ hello world\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_escape_spaces(&mut self, spaces: EscapeSpaces) {
+        self.value_spaces = spaces;
+    }
+
+    /// Returns which spaces in values are escaped.
+    pub fn escape_spaces(&self) -> EscapeSpaces {
+        self.value_spaces
+    }
+
+    /// Sets how characters in keys and values are escaped in [`Dialect::Java`] files.
+    ///
+    /// The policy replaces the default escaping, so [`set_escape_spaces`](Self::set_escape_spaces)
+    /// and [`set_escape_non_bmp`](Self::set_escape_non_bmp) no longer apply.
+    ///
+    /// ```
+    /// use java_properties::JavaStoreEscapes;
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new_with_encoding(&mut buf, encoding_rs::UTF_8);
+    /// writer.set_escape_policy(JavaStoreEscapes);
+    /// writer.write("greeting", " h\u{e9}llo world")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(buf, b"greeting=\\ h\\u00E9llo world\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_escape_policy<P: EscapePolicy + 'static>(&mut self, policy: P) {
+        self.escape_policy = Some(Box::new(policy));
+    }
+
+    /// Sets the length in characters at which long values in [`Dialect::Java`] files are wrapped.
+    /// Zero, the default, means no limit.
+    ///
+    /// Values are wrapped with a trailing backslash and continued on indented lines, which readers
+    /// join back into the original value.  Lines are never broken inside an escape sequence or
+    /// before whitespace, which readers would strip, so a line may exceed the limit if there is
+    /// nowhere to break it.  Keys are never wrapped.  The length counts characters before encoding,
+    /// so characters which the output encoding can't represent and escapes count as one.
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.set_max_line_length(16);
+    /// writer.write("cp", "a.jar,b.jar,c.jar,d.jar")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(
+    ///     String::from_utf8(buf).unwrap(),
+    ///     "cp=a.jar,b.jar,\\\n    c.jar,d.jar\n"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_max_line_length(&mut self, max_line_length: usize) {
+        self.max_line_length = max_line_length;
+    }
+
+    /// Returns the length at which long values are wrapped, or zero for no limit.
+    pub fn max_line_length(&self) -> usize {
+        self.max_line_length
+    }
+
+    /// Sets the width in characters to which keys in [`Dialect::Java`] files are padded with
+    /// spaces, so that separators and values line up.  Longer keys aren't padded.  Defaults to zero,
+    /// which doesn't pad.
+    ///
+    /// [`WriteOptions::align_keys`] picks the width from the keys being written.
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut buf = Vec::new();
+    /// let mut writer = PropertiesWriter::new(&mut buf);
+    /// writer.set_key_width(6);
+    /// writer.set_kv_separator(" = ")?;
+    /// writer.write("host", "localhost")?;
+    /// writer.write("port", "8080")?;
+    /// writer.write("timeout", "30")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(
+    ///     String::from_utf8(buf).unwrap(),
+    ///     "host   = localhost\nport   = 8080\ntimeout = 30\n"
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_key_width(&mut self, width: usize) {
+        self.key_width = width;
+    }
+
+    /// Returns the width to which keys are padded, or zero if they aren't.
+    pub fn key_width(&self) -> usize {
+        self.key_width
+    }
+
+    /// Finishes the encoding.  For a writer created with [`new_fragment`](Self::new_fragment), this
+    /// only flushes the output.
+    pub fn finish(&mut self) -> Result<(), PropertiesError> {
+        if self.fragment {
+            self.writer.flush()?;
+        } else {
+            self.writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// A [`PropertiesWriter`] which has to be closed explicitly.
+///
+/// The guard dereferences to the writer.  [`close`](Self::close) consumes the guard and finishes
+/// the encoding.  Dropping the guard without closing it flushes what has been written so far, but
+/// the output may be incomplete, so this panics in debug builds.
+///
+/// ```
+/// use java_properties::PropertiesWriter;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let mut buf = Vec::new();
+/// let mut writer = PropertiesWriter::new_scoped(&mut buf);
+/// writer.write("a", "1")?;
+/// writer.close()?;
+/// assert_eq!(buf, b"a=1\n");
+/// # Ok(())
+/// # }
+/// ```
+pub struct ScopedPropertiesWriter<W: Write> {
+    // Only None once closed.
+    inner: Option<PropertiesWriter<W>>,
+}
+
+impl<W: Write> ScopedPropertiesWriter<W> {
+    /// Finishes the encoding and flushes the underlying stream.
+    pub fn close(mut self) -> Result<(), PropertiesError> {
+        let mut inner = self.inner.take().expect("writer is only taken when closed");
+        inner.finish()
+    }
+}
+
+impl<W: Write> Deref for ScopedPropertiesWriter<W> {
+    type Target = PropertiesWriter<W>;
+
+    fn deref(&self) -> &PropertiesWriter<W> {
+        self.inner
+            .as_ref()
+            .expect("writer is only taken when closed")
+    }
+}
+
+impl<W: Write> DerefMut for ScopedPropertiesWriter<W> {
+    fn deref_mut(&mut self) -> &mut PropertiesWriter<W> {
+        self.inner
+            .as_mut()
+            .expect("writer is only taken when closed")
+    }
+}
+
+impl<W: Write> Drop for ScopedPropertiesWriter<W> {
+    fn drop(&mut self) {
+        if let Some(inner) = &mut self.inner {
+            // Errors can't be reported from here.
+            let _ = inner.flush();
+            // Panicking while already panicking would abort.
+            debug_assert!(
+                thread::panicking(),
+                "ScopedPropertiesWriter dropped without calling close()"
+            );
+        }
+    }
+}
+
+/// Adapts a [`fmt::Write`] destination, such as a `String`, to [`io::Write`] for
+/// [`PropertiesWriter::new_fmt`].
+///
+/// Each write must be complete UTF-8, which holds for everything a UTF-8 [`PropertiesWriter`]
+/// writes, and for [`write_raw`](PropertiesWriter::write_raw) calls with complete UTF-8.
+#[derive(Debug)]
+pub struct FmtWriter<F: fmt::Write> {
+    inner: F,
+}
+
+impl<F: fmt::Write> FmtWriter<F> {
+    /// Wraps a `fmt::Write` destination.
+    pub fn new(inner: F) -> Self {
+        FmtWriter { inner }
+    }
+
+    /// Returns the destination.
+    pub fn get_ref(&self) -> &F {
+        &self.inner
+    }
+
+    /// Consumes the adapter, returning the destination.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F: fmt::Write> Write for FmtWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let s =
+            std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.inner.write_str(s).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: fmt::Write> PropertiesWriter<FmtWriter<F>> {
+    /// Writes to a [`fmt::Write`] destination, such as a `String`, in UTF-8.
+    ///
+    /// This builds text directly, e.g. a snippet to embed in another document, without writing to a
+    /// `Vec<u8>` and decoding it.
+    ///
+    /// ```
+    /// use java_properties::PropertiesWriter;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let mut text = String::new();
+    /// let mut writer = PropertiesWriter::new_fmt(&mut text);
+    /// writer.write("name", "caf\u{e9}")?;
+    /// writer.finish()?;
+    /// drop(writer);
+    /// assert_eq!(text, "name=caf\u{e9}\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_fmt(writer: F) -> Self {
+        Self::new_with_encoding(FmtWriter::new(writer), UTF_8)
+    }
+}
+
+/////////////////////
+
+/// Writes a hash map to a properties file.
+///
+/// For more advanced use cases, use `PropertiesWriter`.
+pub fn write<W: Write>(writer: W, map: &HashMap<String, String>) -> Result<(), PropertiesError> {
+    write_iter(writer, map)
+}
+
+/// Writes key/value pairs to a properties file, in the order given.
+///
+/// Unlike [`write()`], this takes any collection or iterator of pairs, such as a slice, a
+/// `BTreeMap`, or a chain of iterator adapters, without collecting them into a `HashMap` first.
+///
+/// ```
+/// use std::collections::BTreeMap;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let mut buf = Vec::new();
+/// let pairs = [("b", "1"), ("a", "2")];
+/// java_properties::write_iter(&mut buf, pairs.iter().copied())?;
+/// assert_eq!(buf, b"b=1\na=2\n");
+///
+/// let map: BTreeMap<String, String> = BTreeMap::new();
+/// java_properties::write_iter(Vec::new(), &map)?;
+/// let ports = (8000..8002).map(|port| (format!("port.{}", port), port.to_string()));
+/// java_properties::write_iter(Vec::new(), ports)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_iter<W, I, K, V>(writer: W, pairs: I) -> Result<(), PropertiesError>
+where
+    W: Write,
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut writer = PropertiesWriter::new(writer);
+    for (k, v) in pairs {
+        writer.write(k.as_ref(), v.as_ref())?;
+    }
+    writer.finish()?;
+    Ok(())
 }
 
-/// Note that once `next` returns an error, the result of further calls is undefined.
-impl<R: Read> Iterator for PropertiesIter<R> {
-    type Item = Result<Line, PropertiesError>;
+/// Writes a hash map to a `Vec` in the given encoding.  See [`write()`].
+pub fn write_to_vec(
+    map: &HashMap<String, String>,
+    encoding: &'static Encoding,
+) -> Result<Vec<u8>, PropertiesError> {
+    let mut buf = Vec::new();
+    let mut writer = PropertiesWriter::new_with_encoding(&mut buf, encoding);
+    for (k, v) in map {
+        writer.write(k, v)?;
+    }
+    writer.finish()?;
+    drop(writer);
+    Ok(buf)
+}
 
-    /// Returns the next line.
-    ///
-    /// Once this returns an error, the result of further calls is undefined.
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.lines.next() {
-                Some(Ok(LogicalLine(line_no, line))) => {
-                    if let Some(parsed_line) = parse_line(&line) {
-                        return Some(self.parsed_line_to_line(parsed_line, line_no));
-                    }
-                }
-                Some(Err(e)) => return Some(Err(e)),
-                None => return None,
-            }
+/// Writes a hash map to a `String`.  See [`write()`].
+///
+/// The text is the UTF-8 encoding of the file, so non-ASCII characters are written as they are
+/// rather than escaped.  [`read_str`] reads it back.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let mut map = HashMap::new();
+/// map.insert("greeting".to_string(), "gr\u{fc}\u{df}e".to_string());
+/// let text = java_properties::write_to_string(&map)?;
+/// assert_eq!(text, "greeting=gr\u{fc}\u{df}e\n");
+/// assert_eq!(java_properties::read_str(&text)?, map);
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_to_string(map: &HashMap<String, String>) -> Result<String, PropertiesError> {
+    let buf = write_to_vec(map, UTF_8)?;
+    Ok(String::from_utf8(buf).expect("UTF-8 encoder produced invalid UTF-8"))
+}
+
+/// Options for [`write_with_options`].
+///
+/// ```
+/// use java_properties::WriteOptions;
+/// use std::collections::HashMap;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let mut map = HashMap::new();
+/// map.insert("b".to_string(), "2".to_string());
+/// map.insert("a".to_string(), "1".to_string());
+/// let options = WriteOptions::new()
+///     .header("Generated file\nDo not edit")
+///     .footer("end")
+///     .sorted(true)
+///     .kv_separator(" = ");
+/// let mut buf = Vec::new();
+/// java_properties::write_with_options(&mut buf, &map, &options)?;
+/// assert_eq!(
+///     String::from_utf8(buf).unwrap(),
+///     "# Generated file\n# Do not edit\na = 1\nb = 2\n# end\n"
+/// );
+/// # Ok(())
+/// # }
+/// ```
+///
+/// For a layout meant for people to read, keys can be grouped by prefix and aligned:
+///
+/// ```
+/// use java_properties::WriteOptions;
+/// use std::collections::HashMap;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let mut map = HashMap::new();
+/// for (k, v) in [("name", "app"), ("db.url", "jdbc:h2:mem:"), ("db.user", "sa"), ("http.port", "80")] {
+///     map.insert(k.to_string(), v.to_string());
+/// }
+/// let options = WriteOptions::new()
+///     .group_by_prefix('.')
+///     .align_keys(true)
+///     .sorted(true)
+///     .kv_separator(" = ");
+/// let mut buf = Vec::new();
+/// java_properties::write_with_options(&mut buf, &map, &options)?;
+/// assert_eq!(
+///     String::from_utf8(buf).unwrap(),
+///     "name      = app\n\ndb.url    = jdbc\\:h2\\:mem\\:\ndb.user   = sa\n\nhttp.port = 80\n"
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct WriteOptions {
+    header: Option<String>,
+    footer: Option<String>,
+    sorted: bool,
+    kv_separator: String,
+    align_keys: bool,
+    group_delimiter: Option<char>,
+}
+
+impl WriteOptions {
+    /// Creates options which write the same output as [`write()`].
+    pub fn new() -> Self {
+        WriteOptions {
+            header: None,
+            footer: None,
+            sorted: false,
+            kv_separator: "=".to_string(),
+            align_keys: false,
+            group_delimiter: None,
         }
     }
+
+    /// Sets a comment written before the properties.  Each line of the text becomes a comment line.
+    pub fn header<S: Into<String>>(mut self, header: S) -> Self {
+        self.header = Some(header.into());
+        self
+    }
+
+    /// Sets a comment written after the properties.  Each line of the text becomes a comment line.
+    pub fn footer<S: Into<String>>(mut self, footer: S) -> Self {
+        self.footer = Some(footer.into());
+        self
+    }
+
+    /// Sets whether keys are sorted.  Defaults to false, which writes keys in arbitrary order.
+    ///
+    /// With [`group_by_prefix`](Self::group_by_prefix), keys are sorted within each group.
+    pub fn sorted(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Sets the key/value separator.  See [`PropertiesWriter::set_kv_separator`] for the allowed
+    /// separators; an invalid separator is reported when writing.
+    pub fn kv_separator<S: Into<String>>(mut self, kv_separator: S) -> Self {
+        self.kv_separator = kv_separator.into();
+        self
+    }
+
+    /// Sets whether keys are padded so that separators and values line up.  Defaults to false.
+    /// See [`PropertiesWriter::set_key_width`].
+    pub fn align_keys(mut self, align_keys: bool) -> Self {
+        self.align_keys = align_keys;
+        self
+    }
+
+    /// Groups keys by the part before the first `delimiter`, with a blank line between groups.
+    /// Keys without the delimiter come first, then the groups in order of their prefixes.
+    pub fn group_by_prefix(mut self, delimiter: char) -> Self {
+        self.group_delimiter = Some(delimiter);
+        self
+    }
 }
 
-/////////////////////
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-/// A line ending style allowed in a Java properties file.
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
-pub enum LineEnding {
-    /// Carriage return alone.
-    CR,
-    /// Line feed alone.
-    LF,
-    /// Carriage return followed by line feed.
-    // The name can't be changed without breaking backward compatibility.
-    #[allow(clippy::upper_case_acronyms)]
-    CRLF,
+fn write_comment_lines<W: Write>(
+    writer: &mut PropertiesWriter<W>,
+    text: &str,
+) -> Result<(), PropertiesError> {
+    for line in text.split("\r\n").flat_map(|l| l.split(['\r', '\n'])) {
+        writer.write_comment(line)?;
+    }
+    Ok(())
 }
 
-impl Display for LineEnding {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.write_str(match *self {
-            LineEnding::CR => "LineEnding::CR",
-            LineEnding::LF => "LineEnding::LF",
-            LineEnding::CRLF => "LineEnding::CRLF",
-        })
+// The group of a key for WriteOptions::group_by_prefix.
+fn key_group(key: &str, delimiter: char) -> &str {
+    key.split_once(delimiter).map_or("", |(group, _)| group)
+}
+
+/// Writes a hash map to a properties file with the given options.
+pub fn write_with_options<W: Write>(
+    writer: W,
+    map: &HashMap<String, String>,
+    options: &WriteOptions,
+) -> Result<(), PropertiesError> {
+    let mut writer = PropertiesWriter::new(writer);
+    writer.set_kv_separator(&options.kv_separator)?;
+    if let Some(header) = &options.header {
+        write_comment_lines(&mut writer, header)?;
+    }
+    let mut keys: Vec<&String> = map.keys().collect();
+    if options.sorted {
+        keys.sort();
+    }
+    if let Some(delimiter) = options.group_delimiter {
+        // The sort is stable, so sorted keys stay sorted within each group.
+        keys.sort_by_key(|k| key_group(k, delimiter));
+    }
+    if options.align_keys {
+        let width = keys
+            .iter()
+            .map(|k| writer.escape(k, true).chars().count())
+            .max()
+            .unwrap_or(0);
+        writer.set_key_width(width);
+    }
+    let mut group = None;
+    for k in keys {
+        if let Some(delimiter) = options.group_delimiter {
+            let key_group = key_group(k, delimiter);
+            if group.is_some_and(|group| group != key_group) {
+                writer.write_blank_line()?;
+            }
+            group = Some(key_group);
+        }
+        writer.write(k, &map[k])?;
+    }
+    if let Some(footer) = &options.footer {
+        write_comment_lines(&mut writer, footer)?;
     }
+    writer.finish()?;
+    Ok(())
 }
 
-struct EncodingWriter<W: Write> {
+/// Writes a hash map to a properties file, sorted by key.
+///
+/// Unlike [`write()`], the output is the same every time for the same map.
+pub fn write_sorted<W: Write>(
     writer: W,
-    lines_written: usize,
-    encoder: Encoder,
-    buffer: Vec<u8>,
+    map: &HashMap<String, String>,
+) -> Result<(), PropertiesError> {
+    write_sorted_by(writer, map, |a, b| a.cmp(b))
 }
 
-impl<W: Write> EncodingWriter<W> {
-    fn write(&mut self, mut data: &str) -> Result<(), PropertiesError> {
-        while !data.is_empty() {
-            let (result, bytes_read) = self.encoder.encode_from_utf8_to_vec_without_replacement(
-                data,
-                &mut self.buffer,
-                false,
-            );
-            data = &data[bytes_read..];
-            match result {
-                EncoderResult::InputEmpty => (),
-                EncoderResult::OutputFull => {
-                    // encoding_rs won't just append to vectors if it would require allocation.
-                    self.buffer.reserve(self.buffer.capacity() * 2);
-                }
-                EncoderResult::Unmappable(c) => {
-                    let escaped = format!("\\u{:x}", c as isize);
-                    let (result2, _) = self.encoder.encode_from_utf8_to_vec_without_replacement(
-                        &escaped,
-                        &mut self.buffer,
-                        false,
-                    );
-                    match result2 {
-                        EncoderResult::InputEmpty => (),
-                        EncoderResult::OutputFull => {
-                            // encoding_rs won't just append to vectors if it would require allocation.
-                            self.buffer.reserve(self.buffer.capacity() * 2);
-                        }
-                        EncoderResult::Unmappable(_) => {
-                            return Err(PropertiesError::new(
-                                format!(
-                                    "Encoding error: unable to write UTF-8 escaping {:?} for {:?}",
-                                    escaped, c
-                                ),
-                                None,
-                                Some(self.lines_written),
-                            ))
-                        }
-                    }
-                }
-            }
+/// Writes a hash map to a properties file, with keys in the order given by `compare`.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let mut map = HashMap::new();
+/// map.insert("b".to_string(), "1".to_string());
+/// map.insert("a".to_string(), "2".to_string());
+/// let mut buf = Vec::new();
+/// java_properties::write_sorted_by(&mut buf, &map, |a, b| b.cmp(a))?;
+/// assert_eq!(buf, b"b=1\na=2\n");
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_sorted_by<W, F>(
+    writer: W,
+    map: &HashMap<String, String>,
+    mut compare: F,
+) -> Result<(), PropertiesError>
+where
+    W: Write,
+    F: FnMut(&str, &str) -> std::cmp::Ordering,
+{
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort_by(|a, b| compare(a, b));
+    let mut writer = PropertiesWriter::new(writer);
+    for k in keys {
+        writer.write(k, &map[k])?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Options for [`write_file_atomically`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SaveOptions {
+    preserve_permissions: bool,
+}
+
+impl SaveOptions {
+    /// Creates the default options, which preserve permissions.
+    pub fn new() -> Self {
+        SaveOptions {
+            preserve_permissions: true,
         }
-        self.flush_buffer()?;
-        Ok(())
     }
 
-    fn flush_buffer(&mut self) -> Result<(), PropertiesError> {
-        self.writer.write_all(&self.buffer).map_err(|e| {
-            PropertiesError::new("I/O error", Some(Box::new(e)), Some(self.lines_written))
-        })?;
-        self.buffer.clear();
-        Ok(())
+    /// Sets whether a file which is replaced keeps its permissions.  Defaults to true.  If false, or
+    /// if the file doesn't exist yet, it gets the default permissions for new files.
+    pub fn preserve_permissions(mut self, preserve_permissions: bool) -> Self {
+        self.preserve_permissions = preserve_permissions;
+        self
     }
+}
 
-    fn flush(&mut self) -> Result<(), PropertiesError> {
-        self.flush_buffer()?;
-        self.writer.flush()?;
-        Ok(())
+impl Default for SaveOptions {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn finish(&mut self) -> Result<(), PropertiesError> {
-        let (result, _) =
-            self.encoder
-                .encode_from_utf8_to_vec_without_replacement("", &mut self.buffer, true);
-        match result {
-            EncoderResult::InputEmpty => (),
-            EncoderResult::OutputFull => {
-                return Err(PropertiesError::new(
-                    "Encoding error: output full",
-                    None,
-                    Some(self.lines_written),
-                ))
-            }
-            EncoderResult::Unmappable(c) => {
-                return Err(PropertiesError::new(
-                    format!("Encoding error: unmappable character {:?}", c),
-                    None,
-                    Some(self.lines_written),
-                ))
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a file atomically and durably.
+///
+/// `write` writes to a temporary file in the same directory as `path`.  Once it succeeds, the
+/// temporary file is synced to disk and renamed over `path`, so that other readers, and the file
+/// after a crash or power loss, have either the old contents or the new ones.  If anything fails,
+/// the temporary file is removed and `path` is left untouched.
+///
+/// ```
+/// use java_properties::PropertiesWriter;
+/// use java_properties::SaveOptions;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// # let dir = tempfile::tempdir()?;
+/// let path = dir.path().join("app.properties");
+/// java_properties::write_file_atomically(&path, &SaveOptions::new(), |out| {
+///     let mut writer = PropertiesWriter::new(out);
+///     writer.write_comment("Generated")?;
+///     writer.write("port", "8080")?;
+///     writer.finish()
+/// })?;
+/// assert_eq!(std::fs::read_to_string(&path)?, "# Generated\nport=8080\n");
+/// # Ok(())
+/// # }
+/// ```
+pub fn write_file_atomically<P, F>(
+    path: P,
+    options: &SaveOptions,
+    write: F,
+) -> Result<(), PropertiesError>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut dyn Write) -> Result<(), PropertiesError>,
+{
+    let path = path.as_ref();
+    let file_name = path.file_name().ok_or_else(|| {
+        PropertiesError::new(
+            PropertiesErrorKind::InvalidPath,
+            format!("Not a file path: {:?}", path),
+            None,
+            None,
+        )
+    })?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = dir.join(temp_name);
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)?;
+    let result = (|| {
+        let mut writer = io::BufWriter::new(&file);
+        write(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        if options.preserve_permissions {
+            if let Ok(metadata) = fs::metadata(path) {
+                file.set_permissions(metadata.permissions())?;
             }
         }
-        self.flush()?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        // Make the rename itself durable.
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
         Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
     }
+    result
 }
 
-/// Writes to a properties file.
+/// Writes a hash map to the file at `path`, replacing it atomically.
 ///
-/// `finish()` *must* be called after writing all data.
-pub struct PropertiesWriter<W: Write> {
-    comment_prefix: String,
-    kv_separator: String,
-    line_ending: LineEnding,
-    writer: EncodingWriter<W>,
+/// The output is the same as from [`write()`].  See [`write_file_atomically`].
+pub fn write_to_path<P: AsRef<Path>>(
+    path: P,
+    map: &HashMap<String, String>,
+) -> Result<(), PropertiesError> {
+    write_file_atomically(path, &SaveOptions::new(), |writer| write(writer, map))
 }
 
-impl<W: Write> PropertiesWriter<W> {
-    /// Writes to the given `Write` stream.
-    pub fn new(writer: W) -> Self {
-        Self::new_with_encoding(writer, WINDOWS_1252)
-    }
+/// Options for [`normalize_file`].
+#[derive(Debug)]
+pub struct NormalizeOptions {
+    duplicate_key_policy: DuplicateKeyPolicy,
+    write_options: WriteOptions,
+}
 
-    /// Writes to the given `Write` stream in the given encoding.
-    /// Note that the Java properties specification specifies ISO-8859-1 encoding
-    /// for properties files; in most cases, `new` should be called instead.
-    pub fn new_with_encoding(writer: W, encoding: &'static Encoding) -> Self {
-        PropertiesWriter {
-            comment_prefix: "# ".to_string(),
-            kv_separator: "=".to_string(),
-            line_ending: LineEnding::LF,
-            writer: EncodingWriter {
-                writer,
-                lines_written: 0,
-                encoder: encoding.new_encoder(),
-                // It's important that we start with a non-zero capacity, since we double it as needed.
-                buffer: Vec::with_capacity(256),
-            },
+impl NormalizeOptions {
+    /// Creates the default options, which keep the last value of a duplicate key, like Java, and
+    /// write the keys sorted with `=` separators.
+    pub fn new() -> Self {
+        NormalizeOptions {
+            duplicate_key_policy: DuplicateKeyPolicy::LastWins,
+            write_options: WriteOptions::new().sorted(true),
         }
     }
 
-    fn write_eol(&mut self) -> Result<(), PropertiesError> {
-        self.writer.write(match self.line_ending {
-            LineEnding::CR => "\r",
-            LineEnding::LF => "\n",
-            LineEnding::CRLF => "\r\n",
-        })?;
-        Ok(())
+    /// Sets which value of a duplicate key is kept, or whether duplicates are an error.
+    pub fn duplicate_key_policy(mut self, policy: DuplicateKeyPolicy) -> Self {
+        self.duplicate_key_policy = policy;
+        self
     }
 
-    /// Writes a comment to the file.
-    pub fn write_comment(&mut self, comment: &str) -> Result<(), PropertiesError> {
-        self.writer.lines_written += 1;
-        self.writer.write(&self.comment_prefix)?;
-        self.writer.write(comment)?;
-        self.write_eol()?;
-        Ok(())
+    /// Sets how the file is written.  Defaults to `WriteOptions::new().sorted(true)`.
+    pub fn write_options(mut self, write_options: WriteOptions) -> Self {
+        self.write_options = write_options;
+        self
     }
+}
 
-    fn write_escaped(&mut self, s: &str) -> Result<(), PropertiesError> {
-        self.writer.lines_written += 1;
-        let mut escaped = String::new();
-        for c in s.chars() {
-            match c {
-                '\\' => escaped.push_str("\\\\"),
-                ' ' => escaped.push_str("\\ "),
-                '\t' => escaped.push_str("\\t"),
-                '\r' => escaped.push_str("\\r"),
-                '\n' => escaped.push_str("\\n"),
-                '\x0c' => escaped.push_str("\\f"),
-                ':' => escaped.push_str("\\:"),
-                '=' => escaped.push_str("\\="),
-                '!' => escaped.push_str("\\!"),
-                '#' => escaped.push_str("\\#"),
-                _ if c < ' ' => escaped.push_str(&format!("\\u{:x}", c as u16)),
-                _ => escaped.push(c), // We don't worry about other characters, since they're taken care of below.
-            }
-        }
-        self.writer.write(&escaped)?;
-        Ok(())
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    /// Writes a key/value pair to the file.
-    pub fn write(&mut self, key: &str, value: &str) -> Result<(), PropertiesError> {
-        self.write_escaped(key)?;
-        self.writer.write(&self.kv_separator)?;
-        self.write_escaped(value)?;
-        self.write_eol()?;
-        Ok(())
+/// Cleans up the properties file at `path`: reads it, removes duplicate keys, and writes it back
+/// sorted and consistently formatted.
+///
+/// Comments and blank lines are dropped.  The file is replaced atomically, and only if its contents
+/// change; see [`write_file_atomically`].  Returns true if the file was rewritten.
+///
+/// ```
+/// use java_properties::NormalizeOptions;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// # let dir = tempfile::tempdir()?;
+/// let path = dir.path().join("app.properties");
+/// std::fs::write(&path, "# old\nb : 2\na=1\nb=3\n")?;
+/// assert!(java_properties::normalize_file(&path, NormalizeOptions::new())?);
+/// assert_eq!(std::fs::read_to_string(&path)?, "a=1\nb=3\n");
+/// assert!(!java_properties::normalize_file(&path, NormalizeOptions::new())?);
+/// # Ok(())
+/// # }
+/// ```
+pub fn normalize_file<P: AsRef<Path>>(
+    path: P,
+    options: NormalizeOptions,
+) -> Result<bool, PropertiesError> {
+    let path = path.as_ref();
+    let original = fs::read(path)?;
+    let map = read_with_duplicate_key_policy(&original[..], options.duplicate_key_policy)?;
+    let mut normalized = Vec::with_capacity(original.len());
+    write_with_options(&mut normalized, &map, &options.write_options)?;
+    if normalized == original {
+        return Ok(false);
     }
-
-    /// Flushes the underlying stream.
-    pub fn flush(&mut self) -> Result<(), PropertiesError> {
-        self.writer.flush()?;
+    write_file_atomically(path, &SaveOptions::new(), |writer| {
+        writer.write_all(&normalized)?;
         Ok(())
-    }
+    })?;
+    Ok(true)
+}
 
-    /// Sets the comment prefix.
-    ///
-    /// The prefix must contain a '#' or a '!', may only contain spaces, tabs, or form feeds before the comment character,
-    /// and may not contain any carriage returns or line feeds ('\r' or '\n').
-    pub fn set_comment_prefix(&mut self, prefix: &str) -> Result<(), PropertiesError> {
-        lazy_static! {
-            static ref RE: Regex = Regex::new(r"^[ \t\x0c]*[#!][^\r\n]*$").unwrap();
-        }
-        if !RE.is_match(prefix) {
-            return Err(PropertiesError::new(
-                &format!("Bad comment prefix: {:?}", prefix),
-                None,
-                None,
-            ));
+/// Options for [`format()`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FormatOptions {
+    encoding: &'static Encoding,
+    kv_separator: String,
+    comment_prefix: String,
+    line_ending: LineEnding,
+    value_spaces: EscapeSpaces,
+    sorted: bool,
+}
+
+impl FormatOptions {
+    /// Creates the default options, which read and write ISO-8859-1 with `=` separators, `# `
+    /// comments, `\n` line endings, and only leading spaces of values escaped, keeping the order of
+    /// the keys.
+    pub fn new() -> Self {
+        FormatOptions {
+            encoding: WINDOWS_1252,
+            kv_separator: "=".to_string(),
+            comment_prefix: "# ".to_string(),
+            line_ending: LineEnding::LF,
+            value_spaces: EscapeSpaces::Leading,
+            sorted: false,
         }
-        self.comment_prefix = prefix.to_string();
-        Ok(())
     }
 
-    /// Sets the key/value separator.
-    ///
-    /// The separator may be non-empty whitespace, or a colon with optional whitespace on either side,
-    /// or an equals sign with optional whitespace on either side.  (Whitespace here means ' ', '\t', or '\f'.)
-    pub fn set_kv_separator(&mut self, separator: &str) -> Result<(), PropertiesError> {
-        lazy_static! {
-            static ref RE: Regex = Regex::new(r"^([ \t\x0c]*[:=][ \t\x0c]*|[ \t\x0c]+)$").unwrap();
-        }
-        if !RE.is_match(separator) {
-            return Err(PropertiesError::new(
-                &format!("Bad key/value separator: {:?}", separator),
-                None,
-                None,
-            ));
-        }
-        self.kv_separator = separator.to_string();
-        Ok(())
+    /// Sets the encoding the file is read and written in.  Defaults to ISO-8859-1.
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets the key/value separator.  See [`PropertiesWriter::set_kv_separator`] for the allowed
+    /// separators; an invalid separator is reported when formatting.
+    pub fn kv_separator<S: Into<String>>(mut self, kv_separator: S) -> Self {
+        self.kv_separator = kv_separator.into();
+        self
+    }
+
+    /// Sets the comment prefix.  See [`PropertiesWriter::set_comment_prefix`].
+    pub fn comment_prefix<S: Into<String>>(mut self, comment_prefix: S) -> Self {
+        self.comment_prefix = comment_prefix.into();
+        self
     }
 
     /// Sets the line ending.
-    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
         self.line_ending = line_ending;
+        self
     }
 
-    /// Finishes the encoding.
-    pub fn finish(&mut self) -> Result<(), PropertiesError> {
-        self.writer.finish()?;
-        Ok(())
+    /// Sets which spaces in values are escaped.  Defaults to [`EscapeSpaces::Leading`], so values
+    /// read the same as in hand-written files.  See [`PropertiesWriter::set_escape_spaces`].
+    pub fn escape_spaces(mut self, spaces: EscapeSpaces) -> Self {
+        self.value_spaces = spaces;
+        self
+    }
+
+    /// Sets whether keys are sorted.  Defaults to false.
+    ///
+    /// Each key keeps the block of comment lines directly above it.  Comments at the start of the
+    /// file which are separated from the first key by a blank line stay at the start, and comments
+    /// after the last key stay at the end.  Keys which appear more than once keep their order, so
+    /// the same value wins.
+    pub fn sorted(mut self, sorted: bool) -> Self {
+        self.sorted = sorted;
+        self
     }
 }
 
-/////////////////////
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-/// Writes a hash map to a properties file.
+/// Rewrites a properties file in a canonical layout, like a code formatter.
 ///
-/// For more advanced use cases, use `PropertiesWriter`.
-pub fn write<W: Write>(writer: W, map: &HashMap<String, String>) -> Result<(), PropertiesError> {
-    let mut writer = PropertiesWriter::new(writer);
-    for (k, v) in map {
-        writer.write(k, v)?;
+/// Keys and values are escaped consistently and written with the same separator, and comments get
+/// the same prefix, without trailing whitespace.  The text of comments is otherwise kept as written,
+/// backslashes included, since comments aren't parsed.  Runs of blank lines become a single blank line,
+/// and blank lines at the start and end of the file are removed.  Comments and the order of the
+/// keys are kept, unless [`FormatOptions::sorted`] is set.  Formatting a formatted file doesn't
+/// change it.
+///
+/// ```
+/// use java_properties::FormatOptions;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let input = "! Settings\n\n\n# The port\nport : 8080\n  greeting   hello world\n";
+/// let mut output = Vec::new();
+/// java_properties::format(input.as_bytes(), &mut output, FormatOptions::new().sorted(true))?;
+/// assert_eq!(
+///     String::from_utf8(output).unwrap(),
+///     "# Settings\n\ngreeting=hello world\n# The port\nport=8080\n"
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub fn format<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    options: FormatOptions,
+) -> Result<(), PropertiesError> {
+    let mut iter = PropertiesIterBuilder::new()
+        .encoding(options.encoding)
+        .keep_blank_lines(true)
+        .build(reader);
+    iter.raw_comments = true;
+    let mut lines = Vec::new();
+    for line in iter {
+        let content = match line?.consume_content() {
+            LineContent::Comment(comment) => LineContent::Comment(comment.trim_end().to_string()),
+            content => content,
+        };
+        // Drop blank lines at the start and after another blank line.
+        if content == LineContent::Blank && matches!(lines.last(), None | Some(LineContent::Blank))
+        {
+            continue;
+        }
+        lines.push(content);
     }
-    writer.finish()?;
-    Ok(())
+    if lines.last() == Some(&LineContent::Blank) {
+        lines.pop();
+    }
+    if options.sorted {
+        lines = sort_lines(lines);
+    }
+    let mut writer = PropertiesWriter::new_with_encoding(writer, options.encoding);
+    writer.set_kv_separator(&options.kv_separator)?;
+    writer.set_comment_prefix(&options.comment_prefix)?;
+    writer.set_line_ending(options.line_ending);
+    writer.set_escape_spaces(options.value_spaces);
+    for line in &lines {
+        match line {
+            LineContent::Comment(comment) => writer.write_comment(comment)?,
+            LineContent::KVPair(key, value) => writer.write(key, value)?,
+            LineContent::Blank => writer.write_blank_line()?,
+        }
+    }
+    writer.finish()
+}
+
+// Sorts the key/value pairs for FormatOptions::sorted, moving the comments directly above each pair
+// with it.  Comments which aren't directly above a pair move with the next pair, except for those
+// before the first pair's comments and those after the last pair.
+fn sort_lines(mut lines: Vec<LineContent>) -> Vec<LineContent> {
+    let is_pair = |line: &LineContent| matches!(line, LineContent::KVPair(..));
+    let (first, last) = match (
+        lines.iter().position(is_pair),
+        lines.iter().rposition(is_pair),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return lines,
+    };
+    let mut start = first;
+    while start > 0 && matches!(lines[start - 1], LineContent::Comment(_)) {
+        start -= 1;
+    }
+    let footer = lines.split_off(last + 1);
+    let body = lines.split_off(start);
+    let mut entries = Vec::new();
+    let mut comments = Vec::new();
+    for line in body {
+        match line {
+            LineContent::Comment(_) => comments.push(line),
+            LineContent::KVPair(ref key, _) => {
+                let key = key.clone();
+                comments.push(line);
+                entries.push((key, mem::take(&mut comments)));
+            }
+            LineContent::Blank => (),
+        }
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    lines.extend(entries.into_iter().flat_map(|(_, lines)| lines));
+    lines.extend(footer);
+    lines
 }
 
 /// Reads a properties file into a hash map.
 ///
 /// For more advanced use cases, use `PropertiesIter`.
 pub fn read<R: Read>(input: R) -> Result<HashMap<String, String>, PropertiesError> {
-    let mut p = PropertiesIter::new(input);
+    read_with_duplicate_key_policy(input, DuplicateKeyPolicy::LastWins)
+}
+
+/// Reads a properties file into any collection of key/value pairs, such as a `BTreeMap` or a
+/// `Vec<(String, String)>`.
+///
+/// Pairs are added in file order, without an intermediate copy.  What happens to keys which appear
+/// more than once is up to the collection: maps keep the last value, while a `Vec` keeps every
+/// pair.
+///
+/// ```
+/// use std::collections::BTreeMap;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let input = "b=1\na=2\nb=3\n";
+/// let map: BTreeMap<String, String> = java_properties::read_into_map(input.as_bytes())?;
+/// assert_eq!(map.into_iter().collect::<Vec<_>>(), [
+///     ("a".to_string(), "2".to_string()),
+///     ("b".to_string(), "3".to_string()),
+/// ]);
+/// let pairs: Vec<(String, String)> = java_properties::read_into_map(input.as_bytes())?;
+/// assert_eq!(pairs.len(), 3);
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_into_map<R, M>(input: R) -> Result<M, PropertiesError>
+where
+    R: Read,
+    M: Default + Extend<(String, String)>,
+{
+    let mut map = M::default();
+    let mut error = None;
+    map.extend(
+        PropertiesIter::new(input)
+            .pairs()
+            .map_while(|pair| pair.map_err(|e| error = Some(e)).ok()),
+    );
+    match error {
+        Some(e) => Err(e),
+        None => Ok(map),
+    }
+}
+
+/// Reads a properties file into a [`TypedProperties`], which offers typed getters and placeholder
+/// expansion as well as the raw values.
+pub fn read_typed<R: Read>(input: R) -> Result<TypedProperties, PropertiesError> {
+    Ok(TypedProperties::new(read(input)?))
+}
+
+/// Reads properties from a string into a hash map.  See [`read`].
+///
+/// The string is read as it is, so non-ASCII characters don't need to be escaped.
+pub fn read_str(input: &str) -> Result<HashMap<String, String>, PropertiesError> {
+    read_bytes(input.as_bytes(), UTF_8)
+}
+
+/// Reads properties from bytes in the given encoding into a hash map.  See [`read`].
+///
+/// ```
+/// use encoding_rs::WINDOWS_1252;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let map = java_properties::read_bytes(b"name=caf\xe9\n", WINDOWS_1252)?;
+/// assert_eq!(map["name"], "caf\u{e9}");
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_bytes(
+    input: &[u8],
+    encoding: &'static Encoding,
+) -> Result<HashMap<String, String>, PropertiesError> {
+    let mut map = HashMap::new();
+    PropertiesIter::new_with_encoding(input, encoding).read_into(|k, v| {
+        map.insert(k, v);
+    })?;
+    Ok(map)
+}
+
+/// Reads a properties file into a list of key/value pairs in file order.
+///
+/// Each key appears once, at the position of its first occurrence, with the value of its last
+/// occurrence, so the result holds the same pairs as [`read`].
+///
+/// ```
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let pairs = java_properties::read_ordered("b=1\na=2\nb=3\n".as_bytes())?;
+/// assert_eq!(
+///     pairs,
+///     [("b".to_string(), "3".to_string()), ("a".to_string(), "2".to_string())]
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub fn read_ordered<R: Read>(input: R) -> Result<Vec<(String, String)>, PropertiesError> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    PropertiesIter::new(input).read_into(|k, v| match positions.get(&k) {
+        Some(&i) => pairs[i].1 = v,
+        None => {
+            positions.insert(k.clone(), pairs.len());
+            pairs.push((k, v));
+        }
+    })?;
+    Ok(pairs)
+}
+
+/// Reads a properties file into a hash map, handling duplicate keys according to `policy`.
+pub fn read_with_duplicate_key_policy<R: Read>(
+    input: R,
+    policy: DuplicateKeyPolicy,
+) -> Result<HashMap<String, String>, PropertiesError> {
+    let mut p = PropertiesIterBuilder::new()
+        .duplicate_key_policy(policy)
+        .build(input);
     let mut map = HashMap::new();
     p.read_into(|k, v| {
         map.insert(k, v);
@@ -925,18 +5201,185 @@ pub fn read<R: Read>(input: R) -> Result<HashMap<String, String>, PropertiesErro
 
 /////////////////////
 
+/// A summary of the beginning of a properties file, returned by [`preview`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Preview {
+    pairs: Vec<(String, String)>,
+    complete: bool,
+    line_ending: Option<LineEnding>,
+    mixed_line_endings: bool,
+    malformed: bool,
+    looks_like_utf8: bool,
+}
+
+impl Preview {
+    /// Returns the key/value pairs read, in file order.
+    pub fn pairs(&self) -> &[(String, String)] {
+        &self.pairs
+    }
+
+    /// Returns true if the whole input was read.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Returns the line ending of the first line, or `None` if no line ending was seen.
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        self.line_ending
+    }
+
+    /// Returns true if more than one line ending style was seen.
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    /// Returns true if the input read so far is not valid in the encoding it was read with.
+    /// Invalid sequences are replaced with U+FFFD when parsing.
+    pub fn has_malformed_input(&self) -> bool {
+        self.malformed
+    }
+
+    /// Returns true if the input was read as something other than UTF-8, but contains non-ASCII
+    /// text which is valid UTF-8.  This usually means the file is UTF-8 and should be read with
+    /// [`PropertiesIter::new_with_encoding`].
+    pub fn looks_like_utf8(&self) -> bool {
+        self.looks_like_utf8
+    }
+}
+
+/// Parses a properties file from bytes without ever failing, returning a best-effort document and
+/// diagnostics for anything wrong with the input.  See [`PropertiesDocument::parse_lossless`].
+pub fn parse_lossless(
+    bytes: &[u8],
+    encoding: &'static Encoding,
+) -> (PropertiesDocument, Vec<PropertiesError>) {
+    PropertiesDocument::parse_lossless(bytes, encoding)
+}
+
+/// Reads at most `max_entries` key/value pairs from the beginning of a properties file in
+/// ISO-8859-1, along with information about its line endings and encoding.
+///
+/// Reading stops after the requested number of pairs, so this is cheap even for large files.
+/// The line ending and encoding information only covers the part of the input which was read.
+pub fn preview<R: Read>(input: R, max_entries: usize) -> Result<Preview, PropertiesError> {
+    preview_with_encoding(input, WINDOWS_1252, max_entries)
+}
+
+/// Like [`preview`], but reads the file in the given encoding.
+pub fn preview_with_encoding<R: Read>(
+    input: R,
+    encoding: &'static Encoding,
+    max_entries: usize,
+) -> Result<Preview, PropertiesError> {
+    let mut sample = Vec::new();
+    let mut pairs = Vec::new();
+    let mut complete = false;
+    {
+        let mut iter =
+            PropertiesIter::new_with_encoding(TeeReader::new(input, &mut sample), encoding);
+        loop {
+            match iter.next() {
+                None => {
+                    complete = true;
+                    break;
+                }
+                Some(line) => {
+                    if pairs.len() == max_entries {
+                        line?;
+                        break;
+                    }
+                    if let LineContent::KVPair(key, value) = line?.data {
+                        pairs.push((key, value));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut line_endings = Vec::new();
+    let mut i = 0;
+    while i < sample.len() {
+        match sample[i] {
+            b'\r' if sample.get(i + 1) == Some(&b'\n') => {
+                line_endings.push(LineEnding::CRLF);
+                i += 1;
+            }
+            // A trailing carriage return may be the start of a CRLF which wasn't read.
+            b'\r' if i + 1 < sample.len() || complete => line_endings.push(LineEnding::CR),
+            b'\n' => line_endings.push(LineEnding::LF),
+            _ => (),
+        }
+        i += 1;
+    }
+    let line_ending = line_endings.first().copied();
+    let mixed_line_endings = line_endings.iter().any(|&e| Some(e) != line_ending);
+
+    let mut decoder = encoding.new_decoder();
+    let mut decoded = String::with_capacity(
+        decoder
+            .max_utf8_buffer_length(sample.len())
+            .unwrap_or(usize::MAX / 2),
+    );
+    let (result, _) = decoder.decode_to_string_without_replacement(&sample, &mut decoded, complete);
+    let malformed = matches!(result, DecoderResult::Malformed(..));
+    let looks_like_utf8 = encoding != UTF_8
+        && !sample.is_ascii()
+        && match std::str::from_utf8(&sample) {
+            Ok(_) => true,
+            // The sample may end in the middle of a character.
+            Err(e) => e.error_len().is_none() && !complete,
+        };
+
+    Ok(Preview {
+        pairs,
+        complete,
+        line_ending,
+        mixed_line_endings,
+        malformed,
+        looks_like_utf8,
+    })
+}
+
+/////////////////////
+
 #[cfg(test)]
 mod tests {
+    use super::test_dir;
+    use super::BlankContinuationLine;
+    use super::CommentedEntry;
+    use super::Dialect;
+    use super::DuplicateKeyPolicy;
+    use super::Escape;
+    use super::EscapeContext;
+    use super::EscapePolicy;
+    use super::EscapeSpaces;
+    use super::FormatOptions;
+    use super::JavaStoreEscapes;
+    use super::KVSeparator;
+    use super::KeyPolicy;
     use super::Line;
+    use super::LineContent;
     use super::LineEnding;
+    use super::LineTerminatorPolicy;
     use super::LogicalLine;
     use super::LogicalLines;
     use super::NaturalLine;
     use super::NaturalLines;
+    use super::NormalizeOptions;
     use super::ParsedLine;
     use super::PropertiesError;
+    use super::PropertiesErrorKind;
     use super::PropertiesIter;
+    use super::PropertiesIterBuilder;
     use super::PropertiesWriter;
+    use super::SaveOptions;
+    use super::StandardEscapes;
+    use super::TeeReader;
+    use super::WriteOptions;
+    use encoding_rs::Encoding;
+    use encoding_rs::ISO_2022_JP;
+    use encoding_rs::UTF_16BE;
+    use encoding_rs::UTF_16LE;
     use encoding_rs::UTF_8;
     use encoding_rs::WINDOWS_1252;
     use std::io;
@@ -965,13 +5408,12 @@ mod tests {
             (vec![LF, SP], vec!["", " "]),
             (vec![CR, LF, SP], vec!["", " "]),
         ];
-        for &(ref bytes, ref lines) in &data {
-            let reader = &bytes as &[u8];
-            let mut iter = NaturalLines::new(reader, WINDOWS_1252);
-            let mut count = 1;
-            for line in lines {
+        for (bytes, lines) in &data {
+            let reader = bytes as &[u8];
+            let mut iter = NaturalLines::new(reader, WINDOWS_1252, super::DEFAULT_BUFFER_SIZE);
+            for (count, line) in (1..).zip(lines.iter()) {
                 match (line.to_string(), iter.next()) {
-                    (ref e, Some(Ok(NaturalLine(a_ln, ref a)))) => {
+                    (ref e, Some(Ok(NaturalLine(a_ln, ref a, _, _)))) => {
                         if (count, e) != (a_ln, a) {
                             panic!("Failure while processing {:?}.  Expected Some(Ok({:?})), but was {:?}", bytes, (count, e), (a_ln, a));
                         }
@@ -983,7 +5425,6 @@ mod tests {
                         a
                     ),
                 }
-                count += 1;
             }
             match iter.next() {
                 None => (),
@@ -1007,23 +5448,27 @@ mod tests {
             (vec!["foo\\", " bar"], vec!["foobar"]),
             (vec!["#foo\\", " bar"], vec!["#foo\\", " bar"]),
             (vec!["foo\\", "# bar"], vec!["foo# bar"]),
+            (vec!["foo\\"], vec!["foo"]),
             (vec!["\u{1F41E}\\", "\u{1F41E}"], vec!["\u{1F41E}\u{1F41E}"]),
             (
                 vec!["\u{1F41E}\\", " \u{1F41E}"],
                 vec!["\u{1F41E}\u{1F41E}"],
             ),
         ];
-        for &(ref input_lines, ref lines) in &data {
+        for (input_lines, lines) in &data {
             let mut count = 0;
-            let mut iter = LogicalLines::new(input_lines.iter().map(|x| {
-                count += 1;
-                Ok(NaturalLine(count, x.to_string()))
-            }));
+            let mut iter = LogicalLines::new(
+                input_lines.iter().map(|x| {
+                    count += 1;
+                    Ok(NaturalLine(count, x.to_string(), 0..0, "\n"))
+                }),
+                true,
+            );
             let mut e_ln = 0;
             for line in lines {
                 e_ln += 1;
                 match (line.to_string(), iter.next()) {
-                    (ref e, Some(Ok(LogicalLine(a_ln, ref a)))) => {
+                    (ref e, Some(Ok(LogicalLine(a_ln, ref a, _)))) => {
                         if (e_ln, e) != (a_ln, a) {
                             panic!("Failure while processing {:?}.  Expected Some(Ok({:?})), but was {:?}", input_lines, (e_ln, e), (a_ln, a));
                         }
@@ -1046,6 +5491,417 @@ mod tests {
         }
     }
 
+    #[test]
+    fn write_to_path() {
+        let dir = test_dir();
+        let path = dir.path().join("a.properties");
+        let mut map = std::collections::HashMap::new();
+        map.insert("a".to_string(), "1".to_string());
+        super::write_to_path(&path, &map).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a=1\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+            super::write_to_path(&path, &map).unwrap();
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let failed = super::write_file_atomically(&path, &SaveOptions::new(), |writer| {
+            writer.write_all(b"partial")?;
+            Err(std::io::Error::other("failed").into())
+        });
+        assert!(failed.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a=1\n");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn bom_detection() {
+        let data: [(&[u8], bool, &str, &Encoding); 5] = [
+            (b"\xef\xbb\xbfa=\xc3\xa9", true, "a", UTF_8),
+            (b"\xff\xfea\x00=\x00\xe9\x00", true, "a", UTF_16LE),
+            (b"\xfe\xff\x00a\x00=\x00\xe9", true, "a", UTF_16BE),
+            (b"a=\xe9", true, "a", WINDOWS_1252),
+            (
+                b"\xef\xbb\xbfa=\xe9",
+                false,
+                "\u{ef}\u{bb}\u{bf}a",
+                WINDOWS_1252,
+            ),
+        ];
+        for &(input, detect, key, encoding) in data.iter() {
+            let mut iter = PropertiesIterBuilder::new().detect_bom(detect).build(input);
+            let line = iter.next().unwrap().unwrap();
+            assert_eq!(
+                line.content(),
+                &LineContent::KVPair(key.to_string(), "\u{e9}".to_string()),
+                "{:?}",
+                input
+            );
+            assert_eq!(iter.encoding(), encoding, "{:?}", input);
+        }
+
+        let mut iter = PropertiesIter::new_with_detection(
+            std::io::Cursor::new(&b"\xef\xbb\xbfa=1"[..]),
+            UTF_8,
+        );
+        iter.next().unwrap().unwrap();
+        iter.rewind().unwrap();
+        assert_eq!(
+            iter.next().unwrap().unwrap().content(),
+            &LineContent::KVPair("a".to_string(), "1".to_string())
+        );
+    }
+
+    #[test]
+    fn normalize_file() {
+        let dir = test_dir();
+        let path = dir.path().join("a.properties");
+        std::fs::write(&path, "c=1\r\n! note\r\na=2\r\nc=3\r\n").unwrap();
+
+        let options = NormalizeOptions::new()
+            .duplicate_key_policy(DuplicateKeyPolicy::FirstWins)
+            .write_options(WriteOptions::new().sorted(true).kv_separator(" = "));
+        assert!(super::normalize_file(&path, options).unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a = 2\nc = 1\n");
+
+        std::fs::write(&path, "a=1\na=2\n").unwrap();
+        let options = NormalizeOptions::new().duplicate_key_policy(DuplicateKeyPolicy::Error);
+        assert!(super::normalize_file(&path, options).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a=1\na=2\n");
+    }
+
+    #[test]
+    fn utf8_with_fallback() {
+        let data: [(&[u8], &str, &Encoding); 4] = [
+            (b"a=\xc3\xa9", "\u{e9}", UTF_8),
+            (b"\xef\xbb\xbfa=\xc3\xa9", "\u{e9}", UTF_8),
+            (b"a=\xc3\xa9\nb=\xe9", "\u{c3}\u{a9}", WINDOWS_1252),
+            (b"a=\\u00e9", "\u{e9}", UTF_8),
+        ];
+        for &(input, value, encoding) in data.iter() {
+            let mut iter = PropertiesIter::new_utf8_with_fallback(input).unwrap();
+            assert_eq!(iter.encoding(), encoding, "{:?}", input);
+            assert_eq!(
+                iter.next().unwrap().unwrap().content(),
+                &LineContent::KVPair("a".to_string(), value.to_string()),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn strict_decoding() {
+        let input = b"a=\xc3\xa9\nb=\xc3\xa9\xff\xc3\xa9\nc=1\n";
+        for &buffer_size in [1, 3, 8192].iter() {
+            let mut iter = PropertiesIterBuilder::new()
+                .encoding(UTF_8)
+                .buffer_size(buffer_size)
+                .strict_decoding(true)
+                .build(&input[..]);
+            assert_eq!(
+                iter.next().unwrap().unwrap().content(),
+                &LineContent::KVPair("a".to_string(), "\u{e9}".to_string())
+            );
+            let e = iter.next().unwrap().unwrap_err();
+            assert_eq!(e.line_number(), Some(2), "buffer size {}", buffer_size);
+            assert_eq!(
+                e.to_string(),
+                "Malformed UTF-8 input at byte offset 9 (line_number = 2)",
+                "buffer size {}",
+                buffer_size
+            );
+        }
+
+        let mut actual = Vec::new();
+        PropertiesIterBuilder::new()
+            .encoding(UTF_8)
+            .build(&input[..])
+            .read_into(|k, v| actual.push((k, v)))
+            .unwrap();
+        assert_eq!(
+            actual[1],
+            ("b".to_string(), "\u{e9}\u{fffd}\u{e9}".to_string())
+        );
+    }
+
+    #[test]
+    fn raw_text() {
+        let input = "# c\\\r\n a = 1\\\r  \\\n\n  \\u00e9\n\nb\\";
+        let raw = |builder: PropertiesIterBuilder| {
+            builder
+                .keep_raw_text(true)
+                .keep_blank_lines(true)
+                .build(input.as_bytes())
+                .map(|line| {
+                    let line = line.unwrap();
+                    let range = line.byte_range().unwrap();
+                    let raw_text = line.raw_text().unwrap().to_string();
+                    assert_eq!(&input[range.start as usize..range.end as usize], raw_text);
+                    raw_text
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            raw(PropertiesIterBuilder::new()),
+            ["# c\\", " a = 1\\\r  \\\n", "  \\u00e9", "", "b\\"]
+        );
+        assert_eq!(
+            raw(PropertiesIterBuilder::new().blank_continuation_lines(BlankContinuationLine::Skip)),
+            ["# c\\", " a = 1\\\r  \\\n\n  \\u00e9", "", "b\\"]
+        );
+        let line = PropertiesIterBuilder::new()
+            .dialect(Dialect::AndroidProp)
+            .keep_raw_text(true)
+            .build(" a = 1\\\r\nb=2".as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(line.raw_text(), Some(" a = 1\\"));
+        let line = PropertiesIter::new(input.as_bytes())
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(line.raw_text(), None);
+    }
+
+    #[test]
+    fn positions() {
+        let text = "# \u{e9}\r\n  a = 1\\\r\n   \u{e9}\n\nb\n";
+        let utf16 = |s: &str| {
+            s.encode_utf16()
+                .flat_map(|u| u.to_le_bytes())
+                .collect::<Vec<u8>>()
+        };
+        let data: [(&Encoding, &[u8]); 3] = [
+            (UTF_8, b"\xef\xbb\xbf"),
+            (UTF_16LE, b"\xff\xfe"),
+            (WINDOWS_1252, b""),
+        ];
+        for &(encoding, bom) in data.iter() {
+            let encode = |s: &str| match encoding {
+                e if e == UTF_16LE => utf16(s),
+                e => e.encode(s).0.into_owned(),
+            };
+            let mut bytes = bom.to_vec();
+            bytes.extend(encode(text));
+            let lines = PropertiesIterBuilder::new()
+                .encoding(encoding)
+                .track_positions(true)
+                .build(&bytes[..])
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let actual = lines
+                .iter()
+                .map(|l| {
+                    (
+                        l.line_number(),
+                        l.column().unwrap(),
+                        l.byte_range().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let at = |chars: usize| {
+                (bom.len() + encode(&text.chars().take(chars).collect::<String>()).len()) as u64
+            };
+            assert_eq!(
+                actual,
+                [
+                    (1, 1, at(0)..at(3)),
+                    (2, 3, at(5)..at(19)),
+                    (5, 1, at(21)..at(22)),
+                ],
+                "{}",
+                encoding.name()
+            );
+        }
+
+        let input = "a=1\\\n  x\\u00e9\\u12\n";
+        let e = PropertiesIterBuilder::new()
+            .track_positions(true)
+            .build(input.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(e.line_number(), Some(2));
+        assert_eq!(e.column(), Some(10));
+        assert_eq!(e.byte_range(), Some(14..18));
+        let e = PropertiesIter::new(input.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!((e.line_number(), e.column()), (Some(1), None));
+        let e = PropertiesIterBuilder::new()
+            .track_positions(true)
+            .build("a=\\u12\\\n  3x".as_bytes())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!((e.line_number(), e.column()), (Some(1), Some(3)));
+        assert_eq!(e.byte_range(), Some(2..12));
+
+        let e = PropertiesIterBuilder::new()
+            .encoding(UTF_8)
+            .strict_decoding(true)
+            .build(&b"a=1\nb=\xc3\xa9\xff"[..])
+            .nth(1)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(e.line_number(), Some(2));
+        assert_eq!(e.column(), Some(4));
+        assert_eq!(e.byte_range(), Some(8..9));
+    }
+
+    #[test]
+    fn blank_continuation_lines() {
+        let input = "a=1\\\n  \n  2\nb=3\\\n\t\n";
+        let data = [
+            (
+                BlankContinuationLine::Terminate,
+                vec![("a", "1"), ("2", ""), ("b", "3")],
+            ),
+            (BlankContinuationLine::Skip, vec![("a", "12"), ("b", "3")]),
+            (BlankContinuationLine::Space, vec![("a", "1 2"), ("b", "3")]),
+        ];
+        for (policy, expected) in data.iter() {
+            let mut iter = PropertiesIterBuilder::new()
+                .blank_continuation_lines(*policy)
+                .build(input.as_bytes());
+            assert_eq!(iter.blank_continuation_lines(), *policy);
+            let mut actual = Vec::new();
+            iter.read_into(|k, v| actual.push((k, v))).unwrap();
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            assert_eq!(actual, expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn max_continuation_lines() {
+        let input = "a=1\\\n2\\\n3\n#c\\\nb=4\\\n \\\n\n";
+        for &(max, ok) in [(0, false), (1, false), (2, true), (100, true)].iter() {
+            let mut iter = PropertiesIterBuilder::new()
+                .max_continuation_lines(max)
+                .build(input.as_bytes());
+            assert_eq!(iter.max_continuation_lines(), Some(max));
+            let result = iter.by_ref().collect::<Result<Vec<_>, _>>();
+            match (ok, result) {
+                (true, Ok(lines)) => assert_eq!(lines.len(), 3),
+                (false, Err(e)) => {
+                    assert_eq!(e.kind(), PropertiesErrorKind::TooManyContinuationLines);
+                    assert_eq!(e.line_number(), Some(1));
+                }
+                (_, result) => panic!("max {}: {:?}", max, result),
+            }
+        }
+
+        let input = "a=1\nb=2\\\n\n\n  3\n";
+        let mut iter = PropertiesIter::new(input.as_bytes());
+        assert_eq!(iter.max_continuation_lines(), None);
+        iter.set_blank_continuation_lines(BlankContinuationLine::Skip);
+        iter.set_max_continuation_lines(Some(3));
+        assert!(iter.by_ref().all(|line| line.is_ok()));
+        let mut iter = PropertiesIter::new(input.as_bytes());
+        iter.set_blank_continuation_lines(BlankContinuationLine::Skip);
+        iter.set_max_continuation_lines(Some(2));
+        let err = iter.nth(1).unwrap().unwrap_err();
+        assert_eq!(err.line_number(), Some(2));
+    }
+
+    #[test]
+    fn pairs() {
+        let input = "# c\na=1\n\nb=2\na=3\n";
+        assert_eq!(
+            PropertiesIter::new(input.as_bytes())
+                .pairs()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "2".to_string()),
+                ("a".to_string(), "3".to_string()),
+            ]
+        );
+        assert_eq!(
+            PropertiesIter::new(input.as_bytes())
+                .keys()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            ["a", "b", "a"]
+        );
+        assert_eq!(
+            PropertiesIterBuilder::new()
+                .keep_blank_lines(true)
+                .build(input.as_bytes())
+                .values()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            ["1", "2", "3"]
+        );
+        let result = PropertiesIter::new("a=1\nb=\\u12\n".as_bytes())
+            .pairs()
+            .collect::<Result<Vec<_>, _>>();
+        assert_eq!(result.unwrap_err().line_number(), Some(2));
+    }
+
+    #[test]
+    fn try_read_into() {
+        let mut keys = Vec::new();
+        let result = PropertiesIter::new("a=1\nb=x\nc=3\n".as_bytes()).try_read_into(|k, v| {
+            keys.push(k);
+            v.parse::<u32>().map(|_| ()).map_err(|e| {
+                PropertiesError::new(
+                    PropertiesErrorKind::InvalidValue,
+                    "",
+                    Some(Box::new(e)),
+                    None,
+                )
+            })
+        });
+        assert_eq!(
+            result.unwrap_err().kind(),
+            PropertiesErrorKind::InvalidValue
+        );
+        assert_eq!(keys, ["a", "b"]);
+
+        let result = PropertiesIter::new("a=1\nb=\\u12\n".as_bytes())
+            .try_read_into(|_, _| Ok::<(), PropertiesError>(()));
+        assert_eq!(result.unwrap_err().line_number(), Some(2));
+    }
+
+    #[test]
+    fn source_name() {
+        let input = "a=1\nb=\\u12\n";
+        let err = PropertiesIterBuilder::new()
+            .source_name("b.properties")
+            .build(input.as_bytes())
+            .read_into(|_, _| ())
+            .unwrap_err();
+        assert_eq!(err.source_name(), Some("b.properties"));
+        assert_eq!(err.line_number(), Some(2));
+        assert!(err
+            .to_string()
+            .ends_with("(source = b.properties, line_number = 2)"));
+
+        let iter = PropertiesIter::new(input.as_bytes()).with_source_name("a.properties");
+        assert_eq!(iter.source_name(), Some("a.properties"));
+        let mut iter = PropertiesIter::new("a=1\na=2\n".as_bytes()).with_source_name("c");
+        iter.set_duplicate_key_policy(DuplicateKeyPolicy::Error);
+        assert_eq!(iter.nth(1).unwrap().unwrap_err().source_name(), Some("c"));
+
+        let err = PropertiesIter::new(input.as_bytes())
+            .read_into(|_, _| ())
+            .unwrap_err();
+        assert_eq!(err.source_name(), None);
+        assert!(err.to_string().ends_with(" (line_number = 2)"));
+    }
+
     #[test]
     fn count_ending_backslashes() {
         assert_eq!(0, super::count_ending_backslashes(""));
@@ -1078,42 +5934,99 @@ mod tests {
         let data = [
             ("", None),
             (" ", None),
-            ("\\", Some(ParsedLine::KVPair("\\", ""))),
-            ("a=\\", Some(ParsedLine::KVPair("a", "\\"))),
-            ("\\ ", Some(ParsedLine::KVPair("\\ ", ""))),
+            (
+                "\\",
+                Some(ParsedLine::KVPair("\\", "", KVSeparator::Absent)),
+            ),
+            (
+                "a=\\",
+                Some(ParsedLine::KVPair("a", "\\", KVSeparator::Equals)),
+            ),
+            (
+                "\\ ",
+                Some(ParsedLine::KVPair("\\ ", "", KVSeparator::Absent)),
+            ),
             ("# foo", Some(ParsedLine::Comment("foo"))),
             (" # foo", Some(ParsedLine::Comment("foo"))),
-            ("a # foo", Some(ParsedLine::KVPair("a", "# foo"))),
-            ("a", Some(ParsedLine::KVPair("a", ""))),
-            ("a = b", Some(ParsedLine::KVPair("a", "b"))),
-            ("a : b", Some(ParsedLine::KVPair("a", "b"))),
-            ("a b", Some(ParsedLine::KVPair("a", "b"))),
-            (" a = b ", Some(ParsedLine::KVPair("a", "b "))),
-            (" a : b", Some(ParsedLine::KVPair("a", "b"))),
-            (" a b", Some(ParsedLine::KVPair("a", "b"))),
-            ("a:=b", Some(ParsedLine::KVPair("a", "=b"))),
-            ("a=:b", Some(ParsedLine::KVPair("a", ":b"))),
-            ("a b:c", Some(ParsedLine::KVPair("a", "b:c"))),
+            (
+                "a # foo",
+                Some(ParsedLine::KVPair("a", "# foo", KVSeparator::Whitespace)),
+            ),
+            ("a", Some(ParsedLine::KVPair("a", "", KVSeparator::Absent))),
+            (
+                "a = b",
+                Some(ParsedLine::KVPair("a", "b", KVSeparator::Equals)),
+            ),
+            (
+                "a : b",
+                Some(ParsedLine::KVPair("a", "b", KVSeparator::Colon)),
+            ),
+            (
+                "a b",
+                Some(ParsedLine::KVPair("a", "b", KVSeparator::Whitespace)),
+            ),
+            (
+                " a = b ",
+                Some(ParsedLine::KVPair("a", "b ", KVSeparator::Equals)),
+            ),
+            (
+                " a : b",
+                Some(ParsedLine::KVPair("a", "b", KVSeparator::Colon)),
+            ),
+            (
+                " a b",
+                Some(ParsedLine::KVPair("a", "b", KVSeparator::Whitespace)),
+            ),
+            (
+                "a:=b",
+                Some(ParsedLine::KVPair("a", "=b", KVSeparator::Colon)),
+            ),
+            (
+                "a=:b",
+                Some(ParsedLine::KVPair("a", ":b", KVSeparator::Equals)),
+            ),
+            (
+                "a b:c",
+                Some(ParsedLine::KVPair("a", "b:c", KVSeparator::Whitespace)),
+            ),
             (
                 "a\\ \\:\\=b c",
-                Some(ParsedLine::KVPair("a\\ \\:\\=b", "c")),
+                Some(ParsedLine::KVPair(
+                    "a\\ \\:\\=b",
+                    "c",
+                    KVSeparator::Whitespace,
+                )),
             ),
             (
                 "a\\ \\:\\=b=c",
-                Some(ParsedLine::KVPair("a\\ \\:\\=b", "c")),
+                Some(ParsedLine::KVPair("a\\ \\:\\=b", "c", KVSeparator::Equals)),
             ),
             (
                 "a\\\\ \\\\:\\\\=b c",
-                Some(ParsedLine::KVPair("a\\\\", "\\\\:\\\\=b c")),
+                Some(ParsedLine::KVPair(
+                    "a\\\\",
+                    "\\\\:\\\\=b c",
+                    KVSeparator::Whitespace,
+                )),
+            ),
+            (
+                "\\  b",
+                Some(ParsedLine::KVPair("\\ ", "b", KVSeparator::Whitespace)),
+            ),
+            ("=", Some(ParsedLine::KVPair("", "", KVSeparator::Equals))),
+            ("=x", Some(ParsedLine::KVPair("", "x", KVSeparator::Equals))),
+            ("x=", Some(ParsedLine::KVPair("x", "", KVSeparator::Equals))),
+            (
+                "\\=x",
+                Some(ParsedLine::KVPair("\\=x", "", KVSeparator::Absent)),
             ),
-            ("\\  b", Some(ParsedLine::KVPair("\\ ", "b"))),
-            ("=", Some(ParsedLine::KVPair("", ""))),
-            ("=x", Some(ParsedLine::KVPair("", "x"))),
-            ("x=", Some(ParsedLine::KVPair("x", ""))),
-            ("\\=x", Some(ParsedLine::KVPair("\\=x", ""))),
             (
                 "\u{1F41E}=\u{1F41E}",
-                Some(ParsedLine::KVPair("\u{1F41E}", "\u{1F41E}")),
+                Some(ParsedLine::KVPair(
+                    "\u{1F41E}",
+                    "\u{1F41E}",
+                    KVSeparator::Equals,
+                )),
             ),
         ];
         for &(line, ref expected) in &data {
@@ -1139,12 +6052,20 @@ mod tests {
             (r"\", Some("\x00")),
             (r"\u", None),
             (r"\uasfd", None),
+            (r"\ud83d\udc1e", Some("\u{1f41e}")),
+            (r"x\uD83D\uDC1Ey", Some("x\u{1f41e}y")),
+            (r"\ud83d", None),
+            (r"\ud83dx", None),
+            (r"\ud83d\u0041", None),
+            (r"\ud83d\ud83d", None),
+            (r"\udc1e", None),
+            (r"\udc1e\ud83d", None),
         ];
         for &(input, expected) in &data {
-            let actual = &super::unescape(input, 1);
+            let actual = &super::unescape(input);
             let is_match = match (expected, actual) {
-                (Some(e), &Ok(ref a)) => e == a,
-                (None, &Err(_)) => true,
+                (Some(e), Ok(a)) => e == a,
+                (None, Err(_)) => true,
                 _ => false,
             };
             if !is_match {
@@ -1156,13 +6077,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn public_escapes() {
+        assert_eq!(super::escape_key(" a:b "), r"\ a\:b\ ");
+        assert_eq!(super::escape_value(" a:b "), r"\ a\:b ");
+        assert_eq!(super::escape_comment("a\\b\r\n\x01c"), r"a\\b\r\n\u0001c");
+        let data = [
+            (
+                " key = x",
+                "  value\t# not: a comment\\",
+                "first line\nsecond\\line",
+            ),
+            ("#!", "\u{1f41e}\n", "\x7f\x00"),
+            ("", "", ""),
+        ];
+        for &(key, value, comment) in &data {
+            let text = format!(
+                "{}={}\n# {}\n",
+                super::escape_key(key),
+                super::escape_value(value),
+                super::escape_comment(comment)
+            );
+            let lines = PropertiesIter::new_with_encoding(text.as_bytes(), UTF_8)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(
+                lines.iter().map(Line::content).collect::<Vec<_>>(),
+                vec![
+                    &LineContent::KVPair(key.to_string(), value.to_string()),
+                    &LineContent::Comment(comment.to_string()),
+                ],
+                "{:?}",
+                text
+            );
+            assert_eq!(super::unescape(&super::escape_key(key)).unwrap(), key);
+            assert_eq!(super::unescape(&super::escape_value(value)).unwrap(), value);
+        }
+    }
+
     #[test]
     fn properties_iter() {
         fn mk_comment(line_no: usize, text: &str) -> Line {
             Line::mk_comment(line_no, text.to_string())
         }
         fn mk_pair(line_no: usize, key: &str, value: &str) -> Line {
-            Line::mk_pair(line_no, key.to_string(), value.to_string())
+            Line::mk_pair(
+                line_no,
+                key.to_string(),
+                value.to_string(),
+                KVSeparator::Equals,
+            )
         }
         let data = vec![
             (
@@ -1220,6 +6184,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn line_separator() {
+        let lines: Vec<Line> = PropertiesIter::new("a\nb=\nc :\nd \n# x\ne\\\n".as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let separators: Vec<_> = lines.iter().map(Line::separator).collect();
+        assert_eq!(
+            separators,
+            vec![
+                Some(KVSeparator::Absent),
+                Some(KVSeparator::Equals),
+                Some(KVSeparator::Colon),
+                Some(KVSeparator::Whitespace),
+                None,
+                Some(KVSeparator::Absent),
+            ]
+        );
+    }
+
     #[test]
     fn properties_writer_kv() {
         let data = [
@@ -1227,7 +6210,7 @@ mod tests {
             ("a", "b", "a=b\n"),
             (" :=", " :=", "\\ \\:\\==\\ \\:\\=\n"),
             ("!", "#", "\\!=\\#\n"),
-            ("\u{1F41E}", "\u{1F41E}", "\\u1f41e=\\u1f41e\n"),
+            ("\u{1F41E}", "\u{1F41E}", "\\ud83d\\udc1e=\\ud83d\\udc1e\n"),
         ];
         for &(key, value, expected) in &data {
             let mut buf = Vec::new();
@@ -1272,7 +6255,7 @@ mod tests {
             ("", "# \n"),
             ("a", "# a\n"),
             (" :=", "#  :=\n"),
-            ("\u{1F41E}", "# \\u1f41e\n"),
+            ("\u{1F41E}", "# \\ud83d\\udc1e\n"),
         ];
         for &(comment, expected) in &data {
             let mut buf = Vec::new();
@@ -1292,132 +6275,718 @@ mod tests {
     }
 
     #[test]
-    fn properties_writer_good_comment_prefix() {
-        let prefixes = ["#", "!", " #", " !", "#x", "!x", "\x0c#"];
+    fn properties_writer_good_comment_prefix() {
+        let prefixes = ["#", "!", " #", " !", "#x", "!x", "\x0c#"];
+        let mut buf = Vec::new();
+        for prefix in &prefixes {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            writer.set_comment_prefix(prefix).unwrap();
+        }
+    }
+
+    #[test]
+    fn properties_writer_bad_comment_prefix() {
+        let prefixes = ["", " ", "x", "\n#", "#\n", "#\r"];
+        let mut buf = Vec::new();
+        for prefix in &prefixes {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            if writer.set_comment_prefix(prefix).is_ok() {
+                panic!("Unexpectedly succeded with prefix {:?}", prefix);
+            }
+        }
+    }
+
+    #[test]
+    fn properties_writer_custom_comment_prefix() {
+        let data = [
+            ("", " !\n"),
+            ("a", " !a\n"),
+            (" :=", " ! :=\n"),
+            ("\u{1F41E}", " !\\ud83d\\udc1e\n"),
+        ];
+        for &(comment, expected) in &data {
+            let mut buf = Vec::new();
+            {
+                let mut writer = PropertiesWriter::new(&mut buf);
+                writer.set_comment_prefix(" !").unwrap();
+                writer.write_comment(comment).unwrap();
+                writer.finish().unwrap();
+            }
+            let actual = WINDOWS_1252.decode(&buf).0;
+            if expected != actual {
+                panic!(
+                    "Failure while processing {:?}.  Expected {:?}, but was {:?}",
+                    comment, expected, actual
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn properties_writer_good_kv_separator() {
+        let separators = [":", "=", " ", " :", ": ", " =", "= ", "\x0c", "\t"];
+        let mut buf = Vec::new();
+        for separator in &separators {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            writer.set_kv_separator(separator).unwrap();
+        }
+    }
+
+    #[test]
+    fn properties_writer_bad_kv_separator() {
+        let separators = ["", "x", ":=", "=:", "\n", "\r"];
+        let mut buf = Vec::new();
+        for separator in &separators {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            if writer.set_kv_separator(separator).is_ok() {
+                panic!("Unexpectedly succeded with separator {:?}", separator);
+            }
+        }
+    }
+
+    #[test]
+    fn properties_writer_custom_kv_separator() {
+        let data = [
+            (":", "x:y\n"),
+            ("=", "x=y\n"),
+            (" ", "x y\n"),
+            (" :", "x :y\n"),
+            (": ", "x: y\n"),
+            (" =", "x =y\n"),
+            ("= ", "x= y\n"),
+            ("\x0c", "x\x0cy\n"),
+            ("\t", "x\ty\n"),
+        ];
+        for &(separator, expected) in &data {
+            let mut buf = Vec::new();
+            {
+                let mut writer = PropertiesWriter::new(&mut buf);
+                writer.set_kv_separator(separator).unwrap();
+                writer.write("x", "y").unwrap();
+                writer.finish().unwrap();
+            }
+            let actual = WINDOWS_1252.decode(&buf).0;
+            if expected != actual {
+                panic!(
+                    "Failure while processing {:?}.  Expected {:?}, but was {:?}",
+                    separator, expected, actual
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn properties_writer_custom_line_ending() {
+        let data = [
+            (LineEnding::CR, "# foo\rx=y\r"),
+            (LineEnding::LF, "# foo\nx=y\n"),
+            (LineEnding::CRLF, "# foo\r\nx=y\r\n"),
+        ];
+        for &(line_ending, expected) in &data {
+            let mut buf = Vec::new();
+            {
+                let mut writer = PropertiesWriter::new(&mut buf);
+                writer.set_line_ending(line_ending);
+                writer.write_comment("foo").unwrap();
+                writer.write("x", "y").unwrap();
+                writer.finish().unwrap();
+            }
+            let actual = WINDOWS_1252.decode(&buf).0;
+            if expected != actual {
+                panic!(
+                    "Failure while processing {:?}.  Expected {:?}, but was {:?}",
+                    line_ending, expected, actual
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn properties_writer_per_line_line_ending() {
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            writer.set_line_ending(LineEnding::CRLF);
+            writer
+                .write_comment_with_line_ending("foo", LineEnding::LF)
+                .unwrap();
+            writer.write("a", "1").unwrap();
+            writer
+                .write_with_line_ending("b", "2", LineEnding::CR)
+                .unwrap();
+            writer.write_comment("bar").unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(WINDOWS_1252.decode(&buf).0, "# foo\na=1\r\nb=2\r# bar\r\n");
+    }
+
+    #[test]
+    fn properties_writer_key_prefix() {
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            writer.set_key_prefix("my app.");
+            assert_eq!(writer.key_prefix(), "my app.");
+            writer.write("a", "1").unwrap();
+            writer.write_key_only("b").unwrap();
+            writer.write_key_only("").unwrap();
+            writer.write_comment("c").unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(
+            WINDOWS_1252.decode(&buf).0,
+            "my\\ app.a=1\nmy\\ app.b\nmy\\ app.\n# c\n"
+        );
+    }
+
+    #[test]
+    fn properties_writer_escape_spaces() {
+        let data = [
+            (EscapeSpaces::All, "a\\ b=\\ \\ x\\ y\\ \n"),
+            (EscapeSpaces::Leading, "a\\ b=\\  x y \n"),
+            (EscapeSpaces::None, "a\\ b=  x y \n"),
+        ];
+        for &(spaces, expected) in &data {
+            let mut buf = Vec::new();
+            {
+                let mut writer = PropertiesWriter::new(&mut buf);
+                writer.set_escape_spaces(spaces);
+                assert_eq!(writer.escape_spaces(), spaces);
+                writer.write("a b", "  x y ").unwrap();
+                writer.finish().unwrap();
+            }
+            assert_eq!(String::from_utf8(buf.clone()).unwrap(), expected);
+            let expected_value = if spaces == EscapeSpaces::None {
+                "x y "
+            } else {
+                "  x y "
+            };
+            assert_eq!(super::read(&buf[..]).unwrap()["a b"], expected_value);
+        }
+    }
+
+    #[test]
+    fn properties_writer_escape_policy() {
+        struct NoEscapes;
+        impl EscapePolicy for NoEscapes {
+            fn escape(&self, _c: char, _context: EscapeContext) -> Escape {
+                Escape::Literal
+            }
+        }
+
+        fn write(policy: impl EscapePolicy + 'static) -> String {
+            let mut buf = Vec::new();
+            {
+                let mut writer = PropertiesWriter::new_with_encoding(&mut buf, UTF_8);
+                writer.set_escape_spaces(EscapeSpaces::None);
+                writer.set_escape_policy(policy);
+                writer.write(" k:\u{e9}", " v=\\\n\t\x01\u{1f600}").unwrap();
+                writer.finish().unwrap();
+            }
+            String::from_utf8(buf).unwrap()
+        }
+
+        let standard = write(StandardEscapes);
+        assert_eq!(
+            standard,
+            "\\ k\\:\u{e9}=\\ v\\=\\\\\\n\\t\\u0001\u{1f600}\n"
+        );
+        let java = write(JavaStoreEscapes);
+        assert_eq!(
+            java,
+            "\\ k\\:\\u00E9=\\ v\\=\\\\\\n\\t\\u0001\\uD83D\\uDE00\n"
+        );
+        for text in &[standard, java] {
+            let map = super::read_str(text).unwrap();
+            assert_eq!(map[" k:\u{e9}"], " v=\\\n\t\x01\u{1f600}");
+        }
+        assert_eq!(write(NoEscapes), " k:\u{e9}= v=\\\\\\n\t\x01\u{1f600}\n");
+    }
+
+    #[test]
+    fn java_date() {
+        let data = [
+            (0, "Thu Jan 01 00:00:00 UTC 1970"),
+            (1_710_238_530, "Tue Mar 12 10:15:30 UTC 2024"),
+            (951_782_400, "Tue Feb 29 00:00:00 UTC 2000"),
+            (4_102_444_799, "Thu Dec 31 23:59:59 UTC 2099"),
+        ];
+        for &(seconds, expected) in &data {
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            assert_eq!(super::java_date(time), expected);
+        }
+        let time = std::time::UNIX_EPOCH - std::time::Duration::from_millis(500);
+        assert_eq!(super::java_date(time), "Wed Dec 31 23:59:59 UTC 1969");
+    }
+
+    #[test]
+    fn properties_writer_compat_java_store() {
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::compat_java_store(&mut buf);
+            writer.write_header(None, true).unwrap();
+            writer.write("k y", "a:b=\u{e9}\u{1f600}  ").unwrap();
+            writer.finish().unwrap();
+        }
+        let text = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = text.split('\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with('#') && lines[0].contains(" UTC "));
+        assert_eq!(lines[1..], ["k\\ y=a\\:b\\=\\u00E9\\uD83D\\uDE00  ", ""]);
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            writer.set_line_ending(LineEnding::CRLF);
+            writer.set_header_time(std::time::UNIX_EPOCH);
+            writer
+                .write_header(Some("one\r\n#two\n!three\rfour \u{e9}\u{3042}"), true)
+                .unwrap();
+            writer.write_header(Some(""), false).unwrap();
+            writer.write_header(None, false).unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(
+            buf,
+            &b"#one\r\n#two\r\n!three\r\n#four \xe9\\u3042\r\n#Thu Jan 01 00:00:00 UTC 1970\r\n#\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn properties_writer_escape_non_bmp() {
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new_with_encoding(&mut buf, UTF_8);
+            writer.set_escape_non_bmp(true);
+            writer.write("\u{1F41E}\x01", "\u{e9}\u{1F41E}").unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(
+            UTF_8.decode(&buf).0,
+            "\\ud83d\\udc1e\\u0001=\u{e9}\\ud83d\\udc1e\n"
+        );
+        let map = super::read(&buf[..]).unwrap();
+        assert_eq!(
+            map.get("\u{1F41E}\x01").map(String::as_str),
+            Some("\u{c3}\u{a9}\u{1F41E}")
+        );
+    }
+
+    #[test]
+    fn properties_writer_display() {
+        struct Failing;
+        impl std::fmt::Display for Failing {
+            fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                Err(std::fmt::Error)
+            }
+        }
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            writer.write_display("a b", &-1).unwrap();
+            writer.write_display("c", "x=y").unwrap();
+            assert!(writer.write_display("d", &Failing).is_err());
+            writer.write_display("e", &'!').unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(buf, b"a\\ b=-1\nc=x\\=y\ne=\\!\n");
+    }
+
+    #[test]
+    fn scoped_properties_writer() {
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new_scoped_with_encoding(&mut buf, UTF_8);
+            writer.write_comment("\u{e9}").unwrap();
+            writer.close().unwrap();
+        }
+        assert_eq!(buf, "# \u{e9}\n".as_bytes());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "dropped without calling close()")]
+    fn scoped_properties_writer_not_closed() {
+        let mut writer = PropertiesWriter::new_scoped(Vec::new());
+        writer.write("a", "1").unwrap();
+    }
+
+    #[test]
+    fn properties_writer_line_terminators() {
+        let data = [
+            (LineTerminatorPolicy::Escape, Some("a\\nb=1\\r\\n2\n")),
+            (LineTerminatorPolicy::Reject, None),
+            (
+                LineTerminatorPolicy::ReplaceWithSpace,
+                Some("a\\ b=1\\ 2\n"),
+            ),
+        ];
+        for &(policy, expected) in &data {
+            let mut buf = Vec::new();
+            let result = {
+                let mut writer = PropertiesWriter::new(&mut buf);
+                writer.set_key_line_terminator_policy(policy);
+                writer.set_value_line_terminator_policy(policy);
+                writer.write("a\nb", "1\r\n2").and_then(|_| writer.finish())
+            };
+            match expected {
+                Some(expected) => {
+                    result.unwrap();
+                    assert_eq!(WINDOWS_1252.decode(&buf).0, expected);
+                }
+                None => assert_eq!(
+                    result.unwrap_err().to_string(),
+                    "Value for key \"a\\nb\" contains a line terminator (line_number = unknown)"
+                ),
+            }
+        }
+
+        let mut writer = PropertiesWriter::new(Vec::new());
+        writer.set_key_line_terminator_policy(LineTerminatorPolicy::Reject);
+        assert_eq!(
+            writer.write_key_only("\r").unwrap_err().to_string(),
+            "Key \"\\r\" contains a line terminator (line_number = unknown)"
+        );
+        writer.write("a", "\n").unwrap();
+    }
+
+    #[test]
+    fn properties_writer_key_policy() {
+        let mut writer = PropertiesWriter::new(Vec::new());
+        writer.set_dialect(Dialect::DotEnv);
+        assert!(writer.write("a b", "1").is_err());
+
+        writer.set_key_policy(KeyPolicy::Sanitize);
+        writer.write("a b", "1").unwrap();
+        writer.write("a b", "2").unwrap();
+        writer.write("=x", "3").unwrap();
+        writer.write("ok", "4").unwrap();
+        let err = writer.write("a\tb", "5").unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::InvalidKey);
+        // Changed and unchanged keys collide in either order.
+        let err = writer.write("a_b", "5").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Keys \"a b\" and \"a_b\" are both written as \"a_b\" (line_number = unknown)"
+        );
+        writer.write("c_d", "5").unwrap();
+        assert!(writer.write("c d", "5").is_err());
+
+        writer.set_key_policy(KeyPolicy::HashSuffix);
+        writer.write("a\tb", "5").unwrap();
+        writer.set_dialect(Dialect::AndroidProp);
+        writer.write(" #c ", "6").unwrap();
+        writer.finish().unwrap();
+
+        let mut mapping = writer
+            .key_mapping()
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        mapping.sort();
+        assert_eq!(
+            mapping,
+            [
+                ("_#c__894ba86f", " #c "),
+                ("_x", "=x"),
+                ("a_b", "a b"),
+                ("a_b_42e2b167", "a\tb"),
+            ]
+        );
+        let text = String::from_utf8(writer.writer.writer.clone()).unwrap();
+        let keys = text
+            .lines()
+            .map(|line| line.split('=').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "a_b",
+                "a_b",
+                "_x",
+                "ok",
+                "c_d",
+                "a_b_42e2b167",
+                "_#c__894ba86f"
+            ]
+        );
+    }
+
+    #[test]
+    fn properties_writer_fragments() {
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new_fragment(&mut buf, WINDOWS_1252);
+            assert!(writer.is_fragment());
+            writer.write("a", "1").unwrap();
+            writer.finish().unwrap();
+        }
+        {
+            let mut writer = PropertiesWriter::new_fragment(&mut buf, WINDOWS_1252);
+            writer.set_line_ending(LineEnding::CRLF);
+            writer.write_comment("second").unwrap();
+            writer.write("b", "\u{e9}").unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(buf, b"a=1\n# second\r\nb=\xe9\r\n");
+
+        let mut out = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut out);
+            assert!(!writer.is_fragment());
+            writer.write_fragment(&buf).unwrap();
+            writer.write_fragment(b"c=3\rd=4\n").unwrap();
+            assert_eq!(writer.writer.lines_written, 5);
+            writer.write("e", "5").unwrap();
+            writer.finish().unwrap();
+        }
+        let lines: Vec<Line> = PropertiesIter::new(&out[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[5].line_number(), 6);
+    }
+
+    #[test]
+    fn properties_writer_raw() {
         let mut buf = Vec::new();
-        for prefix in &prefixes {
-            let mut writer = PropertiesWriter::new(&mut buf);
-            writer.set_comment_prefix(prefix).unwrap();
+        {
+            let mut writer = PropertiesWriter::new_with_encoding(&mut buf, ISO_2022_JP);
+            writer.write("a", "\u{3042}").unwrap();
+            let lines_written = writer.writer.lines_written;
+            writer.write_raw(b"raw=1\n\n").unwrap();
+            assert_eq!(writer.writer.lines_written, lines_written + 2);
+            writer.write("b", "\u{3044}").unwrap();
+            writer.finish().unwrap();
         }
+        let map = super::read_bytes(&buf, ISO_2022_JP).unwrap();
+        assert_eq!(map["a"], "\u{3042}");
+        assert_eq!(map["raw"], "1");
+        assert_eq!(map["b"], "\u{3044}");
     }
 
     #[test]
-    fn properties_writer_bad_comment_prefix() {
-        let prefixes = ["", " ", "x", "\n#", "#\n", "#\r"];
-        let mut buf = Vec::new();
-        for prefix in &prefixes {
-            let mut writer = PropertiesWriter::new(&mut buf);
-            match writer.set_comment_prefix(prefix) {
-                Ok(_) => panic!("Unexpectedly succeded with prefix {:?}", prefix),
-                Err(_) => (),
-            }
-        }
+    fn properties_writer_fmt() {
+        let mut writer = PropertiesWriter::new_fmt(String::from("# snippet\n"));
+        writer.write("a b", "\u{1f600}").unwrap();
+        writer.write_raw("raw=\u{e9}\n".as_bytes()).unwrap();
+        assert_eq!(
+            writer.write_raw(b"\xe9").unwrap_err().kind(),
+            PropertiesErrorKind::Io
+        );
+        writer.finish().unwrap();
+        assert_eq!(
+            writer.writer.writer.get_ref(),
+            "# snippet\na\\ b=\u{1f600}\nraw=\u{e9}\n"
+        );
     }
 
     #[test]
-    fn properties_writer_custom_comment_prefix() {
-        let data = [
-            ("", " !\n"),
-            ("a", " !a\n"),
-            (" :=", " ! :=\n"),
-            ("\u{1F41E}", " !\\u1f41e\n"),
+    fn commented_entries() {
+        let input = "# a\na=1\n#x\n\nb=2\n! c1\n#c2\nc=3\\\n  4\n# d\nd=5\n# trailing\n";
+        let entries = PropertiesIter::new(input.as_bytes())
+            .commented_entries()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let actual = entries
+            .iter()
+            .map(|e| (e.key(), e.value(), e.comment(), e.line_number()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actual,
+            [
+                ("a", "1", Some("a"), Some(2)),
+                ("b", "2", None, Some(5)),
+                ("c", "34", Some("c1\nc2"), Some(8)),
+                ("d", "5", Some("d"), Some(11)),
+            ]
+        );
+
+        let entries = PropertiesIterBuilder::new()
+            .keep_blank_lines(true)
+            .build("# a\n\na=1\n".as_bytes())
+            .commented_entries()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries[0].comment(), None);
+    }
+
+    #[test]
+    fn properties_writer_max_line_length() {
+        let values = [
+            "",
+            "short",
+            "a b  c   d    e\tf\u{c}g h i j k l m n o p",
+            "\\\\\\\\==::##!!\r\n\u{e9}\u{20ac}\u{1f600} x",
+            "                              leading",
+            "x                                  ",
         ];
-        for &(comment, expected) in &data {
-            let mut buf = Vec::new();
-            {
-                let mut writer = PropertiesWriter::new(&mut buf);
-                writer.set_comment_prefix(" !").unwrap();
-                writer.write_comment(comment).unwrap();
-                writer.finish().unwrap();
-            }
-            let actual = WINDOWS_1252.decode(&buf).0;
-            if expected != actual {
-                panic!(
-                    "Failure while processing {:?}.  Expected {:?}, but was {:?}",
-                    comment, expected, actual
+        for (max, spaces, line_ending) in [
+            (1, EscapeSpaces::All, LineEnding::LF),
+            (8, EscapeSpaces::Leading, LineEnding::CRLF),
+            (12, EscapeSpaces::None, LineEnding::CR),
+        ] {
+            for value in values.iter() {
+                if spaces == EscapeSpaces::None && value.starts_with(' ') {
+                    // Leading spaces are lost regardless of wrapping.
+                    continue;
+                }
+                let mut buf = Vec::new();
+                {
+                    let mut writer = PropertiesWriter::new_with_encoding(&mut buf, UTF_8);
+                    writer.set_max_line_length(max);
+                    writer.set_escape_spaces(spaces);
+                    writer.set_line_ending(line_ending);
+                    writer.write("key", value).unwrap();
+                    writer.finish().unwrap();
+                }
+                let text = String::from_utf8(buf.clone()).unwrap();
+                if max > 1 && !value.contains(' ') {
+                    assert!(text.lines().all(|l| l.chars().count() <= max), "{:?}", text);
+                }
+                let mut iter = PropertiesIter::new_with_encoding(&buf[..], UTF_8);
+                let line = iter.next().unwrap().unwrap();
+                assert_eq!(
+                    line.content(),
+                    &LineContent::KVPair("key".to_string(), value.to_string()),
+                    "{:?}",
+                    text
                 );
+                assert!(iter.next().is_none());
             }
         }
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            writer.set_max_line_length(10);
+            assert_eq!(writer.max_line_length(), 10);
+            writer.write("k", "\u{4e2d}\u{4e2d}\u{4e2d}").unwrap();
+            writer.write("long_key_name", "abcdef").unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(
+            buf,
+            &b"k=\\u4e2d\\u4e2d\\u4e2d\nlong_key_name=a\\\n    bcdef\n"[..]
+        );
     }
 
     #[test]
-    fn properties_writer_good_kv_separator() {
-        let separators = [":", "=", " ", " :", ": ", " =", "= ", "\x0c", "\t"];
+    fn properties_writer_entries() {
         let mut buf = Vec::new();
-        for separator in &separators {
+        {
             let mut writer = PropertiesWriter::new(&mut buf);
-            writer.set_kv_separator(separator).unwrap();
+            writer.write_entry(&CommentedEntry::new("a", "1")).unwrap();
+            let entry = CommentedEntry::new("b", "2").with_comment("x\r\ny\rz");
+            assert_eq!(entry.comment(), Some("x\r\ny\rz"));
+            assert_eq!(entry.line_number(), None);
+            writer.write_entry(&entry).unwrap();
+            writer.write_with_comment("c", "3", "").unwrap();
+            writer.finish().unwrap();
         }
+        assert_eq!(buf, b"a=1\n# x\n# y\n# z\nb=2\n# \nc=3\n");
     }
 
     #[test]
-    fn properties_writer_bad_kv_separator() {
-        let separators = ["", "x", ":=", "=:", "\n", "\r"];
+    fn properties_writer_key_only() {
         let mut buf = Vec::new();
-        for separator in &separators {
+        {
             let mut writer = PropertiesWriter::new(&mut buf);
-            match writer.set_kv_separator(separator) {
-                Ok(_) => panic!("Unexpectedly succeded with separator {:?}", separator),
-                Err(_) => (),
-            }
+            writer.write_key_only("a").unwrap();
+            writer.write_key_only("b c").unwrap();
+            assert!(writer.write_key_only("").is_err());
+            writer.finish().unwrap();
         }
+        assert_eq!(WINDOWS_1252.decode(&buf).0, "a\nb\\ c\n");
+        let lines: Vec<Line> = PropertiesIter::new(&buf[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                Line::mk_pair(1, "a".to_string(), "".to_string(), KVSeparator::Absent),
+                Line::mk_pair(2, "b c".to_string(), "".to_string(), KVSeparator::Absent),
+            ]
+        );
     }
 
     #[test]
-    fn properties_writer_custom_kv_separator() {
-        let data = [
-            (":", "x:y\n"),
-            ("=", "x=y\n"),
-            (" ", "x y\n"),
-            (" :", "x :y\n"),
-            (": ", "x: y\n"),
-            (" =", "x =y\n"),
-            ("= ", "x= y\n"),
-            ("\x0c", "x\x0cy\n"),
-            ("\t", "x\ty\n"),
+    fn properties_writer_line() {
+        let lines = vec![
+            Line::mk_comment(1, "comment".to_string()),
+            Line::mk_pair(2, "a b".to_string(), "1".to_string(), KVSeparator::Colon),
+            Line::mk_pair(3, "c".to_string(), "".to_string(), KVSeparator::Absent),
+            Line::mk_pair(4, "d".to_string(), "".to_string(), KVSeparator::Equals),
+            Line::mk_comment(5, "C:\\dir\nnext".to_string()),
         ];
-        for &(separator, expected) in &data {
-            let mut buf = Vec::new();
-            {
-                let mut writer = PropertiesWriter::new(&mut buf);
-                writer.set_kv_separator(separator).unwrap();
-                writer.write("x", "y").unwrap();
-                writer.finish().unwrap();
-            }
-            let actual = WINDOWS_1252.decode(&buf).0;
-            if expected != actual {
-                panic!(
-                    "Failure while processing {:?}.  Expected {:?}, but was {:?}",
-                    separator, expected, actual
-                );
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            for line in &lines {
+                writer.write_line(line).unwrap();
             }
+            writer.finish().unwrap();
+        }
+        assert_eq!(
+            WINDOWS_1252.decode(&buf).0,
+            "# comment\na\\ b=1\nc\nd=\n# C:\\\\dir\\nnext\n"
+        );
+        let read: Vec<Line> = PropertiesIter::new(&buf[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read.len(), lines.len());
+        for (read, line) in read.iter().zip(&lines) {
+            assert_eq!(read.content(), line.content());
         }
+        assert_eq!(read[2].separator(), Some(KVSeparator::Absent));
+        assert_eq!(lines[0].column(), None);
     }
 
     #[test]
-    fn properties_writer_custom_line_ending() {
-        let data = [
-            (LineEnding::CR, "# foo\rx=y\r"),
-            (LineEnding::LF, "# foo\nx=y\n"),
-            (LineEnding::CRLF, "# foo\r\nx=y\r\n"),
-        ];
-        for &(line_ending, expected) in &data {
-            let mut buf = Vec::new();
-            {
-                let mut writer = PropertiesWriter::new(&mut buf);
-                writer.set_line_ending(line_ending);
-                writer.write_comment("foo").unwrap();
-                writer.write("x", "y").unwrap();
-                writer.finish().unwrap();
-            }
-            let actual = WINDOWS_1252.decode(&buf).0;
-            if expected != actual {
-                panic!(
-                    "Failure while processing {:?}.  Expected {:?}, but was {:?}",
-                    line_ending, expected, actual
-                );
+    fn blank_lines() {
+        let input = "# a\na=1\n\n \t\nb=2\\\n  3\n\n";
+        let lines: Vec<Line> = PropertiesIter::new(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lines.len(), 3);
+
+        let lines: Vec<Line> = PropertiesIterBuilder::new()
+            .keep_blank_lines(true)
+            .track_positions(true)
+            .build(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                Line::mk_comment(1, "a".to_string()),
+                Line::mk_pair(2, "a".to_string(), "1".to_string(), KVSeparator::Equals),
+                Line::mk_blank(3),
+                Line::mk_blank(4),
+                Line::mk_pair(5, "b".to_string(), "23".to_string(), KVSeparator::Equals),
+                Line::mk_blank(7),
+            ]
+        );
+        assert_eq!(lines[3].byte_range(), Some(9..11));
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = PropertiesWriter::new(&mut buf);
+            for line in &lines {
+                writer.write_line(line).unwrap();
             }
+            writer.finish().unwrap();
         }
+        assert_eq!(WINDOWS_1252.decode(&buf).0, "# a\na=1\n\n\nb=23\n\n");
+
+        let lines: Vec<Line> = PropertiesIterBuilder::new()
+            .keep_blank_lines(true)
+            .dialect(Dialect::DotEnv)
+            .build("A=1\n\nB=2\n".as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lines[1].content(), &LineContent::Blank);
     }
 
     struct ErrorReader;
@@ -1453,22 +7022,512 @@ mod tests {
         }
     }
 
+    #[test]
+    fn preview() {
+        let input = b"# c\r\na=1\r\nb=2\nc=\xc3\xa9\r\n";
+        let p = super::preview(&input[..], 2).unwrap();
+        assert_eq!(
+            p.pairs(),
+            &[
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "2".to_string())
+            ]
+        );
+        assert!(!p.is_complete());
+        assert_eq!(p.line_ending(), Some(LineEnding::CRLF));
+        assert!(p.has_mixed_line_endings());
+        assert!(!p.has_malformed_input());
+        assert!(p.looks_like_utf8());
+
+        let p = super::preview(&b"a=1\r\nb=\xe9"[..], 10).unwrap();
+        assert_eq!(p.pairs().len(), 2);
+        assert!(p.is_complete());
+        assert!(!p.has_mixed_line_endings());
+        assert!(!p.looks_like_utf8());
+
+        let p = super::preview_with_encoding(&b"a=\xe9\n"[..], UTF_8, 10).unwrap();
+        assert!(p.has_malformed_input());
+        assert!(!p.looks_like_utf8());
+        assert_eq!(p.pairs()[0].1, "\u{fffd}");
+
+        let p = super::preview(&b""[..], 0).unwrap();
+        assert!(p.is_complete());
+        assert_eq!(p.line_ending(), None);
+    }
+
+    #[test]
+    fn progress() {
+        let reports = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reports2 = reports.clone();
+        let input = "a=1\n\n# c\nb=2";
+        let mut iter = PropertiesIter::new(input.as_bytes());
+        iter.on_progress(move |p| {
+            reports2
+                .lock()
+                .unwrap()
+                .push((p.bytes_read(), p.lines_read()))
+        });
+        assert_eq!(iter.progress().lines_read(), 0);
+        assert_eq!(iter.by_ref().count(), 3);
+        assert!(iter.next().is_none());
+        assert_eq!(
+            *reports.lock().unwrap(),
+            vec![(12, 1), (12, 3), (12, 4), (12, 4), (12, 4)]
+        );
+    }
+
+    #[test]
+    fn builder() {
+        let input = "# c\na=1\n\u{e9}=\\\n  2\n";
+        let expected: Vec<Line> = PropertiesIter::new_with_encoding(input.as_bytes(), UTF_8)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        for buffer_size in 0..8 {
+            let actual: Vec<Line> = PropertiesIterBuilder::new()
+                .encoding(UTF_8)
+                .buffer_size(buffer_size)
+                .build(input.as_bytes())
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(actual, expected);
+        }
+        let actual: Vec<Line> = PropertiesIterBuilder::new()
+            .encoding(UTF_8)
+            .skip_comments(true)
+            .build(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(actual, &expected[1..]);
+    }
+
+    #[test]
+    fn cancellation() {
+        let cancelled = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let mut iter = PropertiesIter::new("a=1\n\n# c\nb=2\\\n  3\n".as_bytes());
+        iter.set_cancellation_flag(cancelled.clone());
+        assert!(iter.next().unwrap().is_ok());
+        cancelled.store(true, std::sync::atomic::Ordering::Relaxed);
+        let err = iter.next().unwrap().unwrap_err();
+        assert_eq!(err.line_number(), Some(2));
+        assert!(iter.next().unwrap().is_err());
+        cancelled.store(false, std::sync::atomic::Ordering::Relaxed);
+        let rest: Vec<Line> = iter.collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            rest,
+            vec![
+                Line::mk_comment(3, "c".to_string()),
+                Line::mk_pair(4, "b".to_string(), "23".to_string(), KVSeparator::Equals),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewind() {
+        let input = b"\xef\xbb\xbfa=\xc3\xa9\r\nb=2\rc=3";
+        let mut iter = PropertiesIter::new(io::Cursor::new(&input[..]));
+        let first: Vec<Line> = iter.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(first.len(), 3);
+        iter.rewind().unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), first[0]);
+        iter.rewind().unwrap();
+        let second: Vec<Line> = iter.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn write_sorted() {
+        let mut map = std::collections::HashMap::new();
+        for k in &["b", "c", "a", "B"] {
+            map.insert(k.to_string(), k.to_string());
+        }
+        let mut buf = Vec::new();
+        super::write_sorted(&mut buf, &map).unwrap();
+        assert_eq!(buf, b"B=B\na=a\nb=b\nc=c\n");
+        let mut buf = Vec::new();
+        super::write_sorted_by(&mut buf, &map, |a, b| {
+            a.to_lowercase().cmp(&b.to_lowercase()).then(b.cmp(a))
+        })
+        .unwrap();
+        assert_eq!(buf, b"a=a\nb=b\nB=B\nc=c\n");
+    }
+
+    #[test]
+    fn write_iter() {
+        let mut map = std::collections::BTreeMap::new();
+        map.insert("b", "2".to_string());
+        map.insert("a", "1".to_string());
+        let mut buf = Vec::new();
+        super::write_iter(&mut buf, &map).unwrap();
+        assert_eq!(buf, b"a=1\nb=2\n");
+
+        let mut buf = Vec::new();
+        super::write_iter(&mut buf, vec![("x y", String::from("\u{e9}"))]).unwrap();
+        assert_eq!(buf, b"x\\ y=\xe9\n");
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut map = std::collections::HashMap::new();
+        map.insert("a b".to_string(), "\u{e9}\u{1f600}".to_string());
+        let text = super::write_to_string(&map).unwrap();
+        assert_eq!(text, "a\\ b=\u{e9}\u{1f600}\n");
+        assert_eq!(super::read_str(&text).unwrap(), map);
+
+        let bytes = super::write_to_vec(&map, WINDOWS_1252).unwrap();
+        assert_eq!(bytes, b"a\\ b=\xe9\\ud83d\\ude00\n");
+        assert_eq!(super::read_bytes(&bytes, WINDOWS_1252).unwrap(), map);
+        assert!(super::read_str("a=\\u12").is_err());
+    }
+
+    #[test]
+    fn write_with_options() {
+        let mut map = std::collections::HashMap::new();
+        map.insert("k".to_string(), "v".to_string());
+        let mut buf = Vec::new();
+        super::write_with_options(&mut buf, &map, &WriteOptions::new()).unwrap();
+        assert_eq!(buf, b"k=v\n");
+
+        let options = WriteOptions::new().header("a\r\n\rb").kv_separator(":");
+        let mut buf = Vec::new();
+        super::write_with_options(&mut buf, &map, &options).unwrap();
+        assert_eq!(buf, b"# a\n# \n# b\nk:v\n");
+
+        let options = WriteOptions::new().kv_separator("-");
+        assert!(super::write_with_options(Vec::new(), &map, &options).is_err());
+
+        for (k, v) in [
+            ("a b", "1"),
+            ("x.b", "2"),
+            ("x.a", "3"),
+            ("x", "4"),
+            ("y.", "5"),
+        ] {
+            map.insert(k.to_string(), v.to_string());
+        }
+        let options = WriteOptions::new()
+            .group_by_prefix('.')
+            .align_keys(true)
+            .sorted(true);
+        let mut buf = Vec::new();
+        super::write_with_options(&mut buf, &map, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(buf.clone()).unwrap(),
+            "a\\ b=1\nk   =v\nx   =4\n\nx.a =3\nx.b =2\n\ny.  =5\n"
+        );
+        assert_eq!(super::read(&buf[..]).unwrap(), map);
+
+        let options = WriteOptions::new().group_by_prefix('.');
+        let mut buf = Vec::new();
+        super::write_with_options(&mut buf, &map, &options).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert_eq!(text.matches("\n\n").count(), 2);
+        assert!(text.ends_with("y.=5\n"));
+    }
+
+    #[test]
+    fn format() {
+        let format = |input: &str, options: FormatOptions| {
+            let mut output = Vec::new();
+            super::format(input.as_bytes(), &mut output, options.clone()).unwrap();
+            let mut again = Vec::new();
+            super::format(&output[..], &mut again, options).unwrap();
+            assert_eq!(output, again);
+            String::from_utf8(output).unwrap()
+        };
+        let input = "\n\n#  header  \n\n\n! b doc\nb = 2\n\n# detached\n\na\\\n  \\ x: 1\nc 3\nb=4\n# footer\n\n";
+        assert_eq!(
+            format(input, FormatOptions::new()),
+            "# header\n\n# b doc\nb=2\n\n# detached\n\na\\ x=1\nc=3\nb=4\n# footer\n"
+        );
+        assert_eq!(
+            format(
+                input,
+                FormatOptions::new()
+                    .sorted(true)
+                    .kv_separator(" = ")
+                    .comment_prefix("!")
+                    .line_ending(LineEnding::CRLF)
+            ),
+            "!header\r\n\r\n!detached\r\na\\ x = 1\r\n!b doc\r\nb = 2\r\nb = 4\r\nc = 3\r\n!footer\r\n"
+        );
+        assert_eq!(format("", FormatOptions::new().sorted(true)), "");
+        assert_eq!(
+            format("# a\n\n", FormatOptions::new().sorted(true)),
+            "# a\n"
+        );
+
+        let input = "# Logs go to C:\\users\\me\\logs\n!regex: \\d+\\.\\d+ \\\nk = hello world\n";
+        assert_eq!(
+            format(input, FormatOptions::new()),
+            "# Logs go to C:\\users\\me\\logs\n# regex: \\d+\\.\\d+ \\\nk=hello world\n"
+        );
+        assert_eq!(
+            format(
+                " k =  a b ",
+                FormatOptions::new().escape_spaces(EscapeSpaces::All)
+            ),
+            "k=a\\ b\\ \n"
+        );
+
+        let input = "k=\\u00e9\\u4e2d\n";
+        assert_eq!(
+            format(input, FormatOptions::new().encoding(UTF_8)),
+            "k=\u{e9}\u{4e2d}\n"
+        );
+        let mut output = Vec::new();
+        let options = FormatOptions::new().kv_separator("-");
+        assert!(super::format(input.as_bytes(), &mut output, options).is_err());
+        assert!(super::format("a=\\u12".as_bytes(), Vec::new(), FormatOptions::new()).is_err());
+    }
+
+    #[test]
+    fn read_into_map() {
+        let input = "# c\nz=1\ny=2\nz=3\n";
+        let map: std::collections::BTreeMap<String, String> =
+            super::read_into_map(input.as_bytes()).unwrap();
+        assert_eq!(map.keys().collect::<Vec<_>>(), ["y", "z"]);
+        assert_eq!(map["z"], "3");
+        let pairs: Vec<(String, String)> = super::read_into_map(input.as_bytes()).unwrap();
+        assert_eq!(pairs.len(), 3);
+
+        let err = super::read_into_map::<_, Vec<(String, String)>>("a=1\nb=\\u1\n".as_bytes())
+            .unwrap_err();
+        assert_eq!(err.line_number(), Some(2));
+    }
+
+    #[test]
+    fn read_ordered() {
+        let input = "# c\nz=1\ny=2\nx\\\n  =3\nz=4\nw\n";
+        let pairs = super::read_ordered(input.as_bytes()).unwrap();
+        let pairs: Vec<(&str, &str)> = pairs
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(pairs, [("z", "4"), ("y", "2"), ("x", "3"), ("w", "")]);
+    }
+
+    #[test]
+    fn duplicate_key_policy() {
+        let input = "a=1\nb=2\na=3\n";
+        let map =
+            super::read_with_duplicate_key_policy(input.as_bytes(), DuplicateKeyPolicy::FirstWins)
+                .unwrap();
+        assert_eq!(map.get("a").map(String::as_str), Some("1"));
+        assert_eq!(map.len(), 2);
+        let lines: Vec<Line> = PropertiesIterBuilder::new()
+            .duplicate_key_policy(DuplicateKeyPolicy::FirstWins)
+            .build(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(lines.len(), 2);
+
+        let map =
+            super::read_with_duplicate_key_policy(input.as_bytes(), DuplicateKeyPolicy::LastWins)
+                .unwrap();
+        assert_eq!(map.get("a").map(String::as_str), Some("3"));
+
+        let err =
+            super::read_with_duplicate_key_policy(input.as_bytes(), DuplicateKeyPolicy::Error)
+                .unwrap_err();
+        assert_eq!(err.line_number(), Some(3));
+
+        let err = super::read_with_duplicate_key_policy(
+            input.as_bytes(),
+            DuplicateKeyPolicy::Callback(Box::new(|dup| {
+                assert_eq!(dup.previous_value(), "1");
+                Err(PropertiesError::duplicate_key(dup))
+            })),
+        )
+        .unwrap_err();
+        assert_eq!(err.line_number(), Some(3));
+    }
+
+    #[test]
+    fn duplicate_key_handler() {
+        let input = "a=1\nb=2\na=3\n# a=4\na=5\n";
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut iter = PropertiesIter::new(input.as_bytes());
+        let seen2 = seen.clone();
+        iter.on_duplicate_key(move |dup| {
+            seen2.lock().unwrap().push((
+                dup.key().to_string(),
+                dup.previous_line_number(),
+                dup.previous_value().to_string(),
+                dup.line_number(),
+                dup.value().to_string(),
+            ));
+            Ok(())
+        });
+        let mut count = 0;
+        iter.read_into(|_, _| count += 1).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                ("a".to_string(), 1, "1".to_string(), 3, "3".to_string()),
+                ("a".to_string(), 3, "3".to_string(), 5, "5".to_string()),
+            ]
+        );
+
+        let mut iter = PropertiesIter::new(input.as_bytes());
+        iter.on_duplicate_key(|dup| Err(PropertiesError::duplicate_key(dup)));
+        let err = iter.read_into(|_, _| ()).unwrap_err();
+        assert_eq!(err.line_number(), Some(3));
+        assert_eq!(
+            err.to_string(),
+            "Duplicate key \"a\" (previously defined on line 1) (line_number = 3)"
+        );
+    }
+
+    #[test]
+    fn tee_reader() {
+        let input = "a=b\nc=d\n";
+        let mut tee = TeeReader::new(input.as_bytes(), Vec::new());
+        let first = PropertiesIter::new(&mut tee).next().unwrap().unwrap();
+        assert_eq!(
+            first,
+            Line::mk_pair(1, "a".to_string(), "b".to_string(), KVSeparator::Equals)
+        );
+        let (_, raw) = tee.finish().unwrap();
+        assert_eq!(raw, input.as_bytes());
+
+        let tee = TeeReader::new(ErrorReader, Vec::new());
+        assert!(PropertiesIter::new(tee).next().unwrap().is_err());
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn include_properties() {
+        let embedded: &[(&str, &str)] =
+            crate::include_properties!("testdata/build-info.properties");
+        assert_eq!(
+            embedded,
+            &[
+                ("version", "1.2.4"),
+                ("build.host", "ci-runner-01"),
+                ("empty", ""),
+                ("unicode", "caf\u{e9} \u{e9}"),
+            ]
+        );
+        let file = std::fs::File::open("testdata/build-info.properties").unwrap();
+        let expected = super::read(file).unwrap();
+        let actual: std::collections::HashMap<String, String> = embedded
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn continuation_at_end_of_input() {
+        // As in Java, every parser ends the logical line at the end of the input.
+        let data: [(&str, &[(&str, &str)]); 5] = [
+            ("a=b\\", &[("a", "b")]),
+            ("a=1\nb=\\", &[("a", "1"), ("b", "")]),
+            ("a=b\\\\", &[("a", "b\\")]),
+            ("# c\\", &[]),
+            (
+                include_str!("../testdata/continuation-at-end.properties"),
+                &[("a", "1"), ("b", "two")],
+            ),
+        ];
+        for &(input, expected) in &data {
+            let expected: Vec<(String, String)> = expected
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            let pairs = |doc: &crate::PropertiesDocument| {
+                doc.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(super::read_ordered(input.as_bytes()).unwrap(), expected);
+            let doc = crate::PropertiesDocument::load(input.as_bytes()).unwrap();
+            assert_eq!(pairs(&doc), expected, "{:?}", input);
+            let (doc, diagnostics) =
+                crate::PropertiesDocument::parse_lossless(input.as_bytes(), WINDOWS_1252);
+            assert!(diagnostics.is_empty(), "{:?}", input);
+            assert_eq!(pairs(&doc), expected, "{:?}", input);
+            #[cfg(feature = "tokio")]
+            {
+                let mut iter = crate::AsyncPropertiesIter::new(input.as_bytes());
+                let mut pairs = Vec::new();
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                runtime
+                    .block_on(iter.read_into(|k, v| pairs.push((k, v))))
+                    .unwrap();
+                assert_eq!(pairs, expected, "{:?}", input);
+            }
+        }
+
+        #[cfg(feature = "macros")]
+        assert_eq!(
+            crate::include_properties!("testdata/continuation-at-end.properties"),
+            &[("a", "1"), ("b", "two")]
+        );
+    }
+
     #[test]
     fn properties_error_display() {
         assert_eq!(
-            format!("{}", PropertiesError::new("foo", None, None)),
+            format!(
+                "{}",
+                PropertiesError::new(PropertiesErrorKind::Syntax, "foo", None, None)
+            ),
             "foo (line_number = unknown)"
         );
         assert_eq!(
-            format!("{}", PropertiesError::new("foo", None, Some(1))),
+            format!(
+                "{}",
+                PropertiesError::new(PropertiesErrorKind::Syntax, "foo", None, Some(1))
+            ),
             "foo (line_number = 1)"
         );
     }
 
+    #[test]
+    fn properties_error_kind() {
+        let read_error = |input: &[u8]| {
+            PropertiesIterBuilder::new()
+                .encoding(UTF_8)
+                .strict_decoding(true)
+                .duplicate_key_policy(DuplicateKeyPolicy::Error)
+                .build(input)
+                .read_into(|_, _| ())
+                .unwrap_err()
+                .kind()
+        };
+        assert_eq!(
+            read_error(b"a=\\u12"),
+            PropertiesErrorKind::MalformedUnicodeEscape
+        );
+        assert_eq!(read_error(b"a=\xff"), PropertiesErrorKind::Decode);
+        assert_eq!(read_error(b"a=1\na=2"), PropertiesErrorKind::DuplicateKey);
+        assert_eq!(
+            PropertiesError::from(io::Error::from(ErrorKind::UnexpectedEof)).kind(),
+            PropertiesErrorKind::Io
+        );
+
+        let mut writer = PropertiesWriter::new(Vec::new());
+        assert_eq!(
+            writer.set_comment_prefix("x").unwrap_err().kind(),
+            PropertiesErrorKind::InvalidCommentPrefix
+        );
+        assert_eq!(
+            writer.set_kv_separator("x").unwrap_err().kind(),
+            PropertiesErrorKind::InvalidSeparator
+        );
+    }
+
     #[test]
     fn line_display() {
         assert_eq!(
-            format!("{}", Line::mk_pair(1, "foo".to_string(), "bar".to_string())),
+            format!(
+                "{}",
+                Line::mk_pair(1, "foo".to_string(), "bar".to_string(), KVSeparator::Equals)
+            ),
             "Line {line_number: 1, content: KVPair(\"foo\", \"bar\")}"
         );
         assert_eq!(
@@ -0,0 +1,737 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Hygiene checks for properties files.
+//!
+//! Lint checks find things which parse fine but are probably mistakes, such as a key which is
+//! assigned twice.  [`validate_tree`] combines them with [`Schema`] checks for every file in a
+//! directory tree.
+
+use crate::schema::Schema;
+use crate::schema::Violation;
+use crate::LineContent;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use crate::PropertiesIterBuilder;
+use encoding_rs::Encoding;
+use encoding_rs::WINDOWS_1252;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+
+/// A lint check.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub enum Rule {
+    /// A key is assigned more than once; only the last value takes effect.
+    DuplicateKey,
+    /// Two keys differ only by case.  Keys are case-sensitive, so these are different keys, which
+    /// is rarely intended.
+    KeyCaseConflict,
+    /// A value ends with whitespace, which is kept as part of the value.
+    TrailingWhitespace,
+    /// The file ends with a backslash which continues the last line.  Some Java versions read it as
+    /// a NUL character at the end of the value.
+    DanglingBackslash,
+    /// An unescaped character which is invisible, changes the direction of text, or looks like
+    /// UTF-8 read as ISO-8859-1.
+    SuspiciousUnicode,
+    /// A malformed `\uxxxx` escape.  Java rejects the whole file; the line is skipped by the other
+    /// checks.
+    InvalidUnicodeEscape,
+}
+
+impl Rule {
+    /// Returns the name of the rule, as used in reports.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Rule::DuplicateKey => "duplicate-key",
+            Rule::KeyCaseConflict => "key-case-conflict",
+            Rule::TrailingWhitespace => "trailing-whitespace",
+            Rule::DanglingBackslash => "dangling-backslash",
+            Rule::SuspiciousUnicode => "suspicious-unicode",
+            Rule::InvalidUnicodeEscape => "invalid-unicode-escape",
+        }
+    }
+
+    /// Returns the severity of the rule's diagnostics unless configured otherwise with
+    /// [`LintConfig::set_severity`].
+    pub fn default_severity(&self) -> Severity {
+        match *self {
+            Rule::DanglingBackslash | Rule::InvalidUnicodeEscape => Severity::Error,
+            Rule::DuplicateKey
+            | Rule::KeyCaseConflict
+            | Rule::TrailingWhitespace
+            | Rule::SuspiciousUnicode => Severity::Warning,
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How serious a [`Diagnostic`] is.  Severities are ordered, so a CI check can fail on anything
+/// at or above a threshold.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub enum Severity {
+    /// Worth knowing about, but usually fine.
+    Info,
+    /// Probably a mistake.
+    Warning,
+    /// The file doesn't mean what it appears to, or Java can't read it.
+    Error,
+}
+
+impl Severity {
+    /// Returns the name of the severity, as used in reports.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Selects which lint checks run, and their severities.
+///
+/// All rules are enabled by default, with their [default severities](Rule::default_severity).
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct LintConfig {
+    disabled: HashSet<Rule>,
+    severities: HashMap<Rule, Severity>,
+    // ISO-8859-1 if not set.
+    encoding: Option<&'static Encoding>,
+}
+
+impl LintConfig {
+    /// Creates a configuration with all rules enabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables a rule.
+    pub fn enable(&mut self, rule: Rule) {
+        self.disabled.remove(&rule);
+    }
+
+    /// Disables a rule.
+    pub fn disable(&mut self, rule: Rule) {
+        self.disabled.insert(rule);
+    }
+
+    /// Returns true if the rule is enabled.
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        !self.disabled.contains(&rule)
+    }
+
+    /// Sets the severity of a rule's diagnostics.
+    pub fn set_severity(&mut self, rule: Rule, severity: Severity) {
+        self.severities.insert(rule, severity);
+    }
+
+    /// Returns the severity of a rule's diagnostics.
+    pub fn severity(&self, rule: Rule) -> Severity {
+        self.severities
+            .get(&rule)
+            .copied()
+            .unwrap_or_else(|| rule.default_severity())
+    }
+
+    /// Sets the encoding files are read in.  Defaults to ISO-8859-1, like
+    /// `java.util.Properties.load(InputStream)`.
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        self.encoding = Some(encoding);
+    }
+
+    /// Returns the encoding files are read in.
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding.unwrap_or(WINDOWS_1252)
+    }
+}
+
+/// A problem found by a lint check.
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct Diagnostic {
+    rule: Rule,
+    severity: Severity,
+    line_number: usize,
+    column: Option<usize>,
+    message: String,
+}
+
+impl Diagnostic {
+    /// Returns the rule which produced the diagnostic.
+    pub fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    /// Returns the severity of the diagnostic.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Returns the 1-based line number the problem was found on.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Returns the 1-based column, counted in characters, where the problem was found, if known.
+    pub fn column(&self) -> Option<usize> {
+        self.column
+    }
+
+    /// Returns a human-readable description of the problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the diagnostic as a JSON object, for tools which read lint results.
+    ///
+    /// ```
+    /// use java_properties::lint::lint;
+    /// use java_properties::lint::LintConfig;
+    ///
+    /// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+    /// let diagnostics = lint("a=1\nA=2\n".as_bytes(), &LintConfig::new())?;
+    /// assert_eq!(
+    ///     diagnostics[0].to_json(),
+    ///     r#"{"rule":"key-case-conflict","severity":"warning","line":2,"column":1,"message":"key \"A\" differs only by case from key \"a\" on line 1"}"#
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"rule\":\"{}\",\"severity\":\"{}\",\"line\":{},\"column\":",
+            self.rule, self.severity, self.line_number
+        );
+        match self.column {
+            Some(column) => json.push_str(&column.to_string()),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"message\":");
+        push_json_string(&mut json, &self.message);
+        json.push('}');
+        json
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} [{}] (line_number = {}",
+            self.severity, self.message, self.rule, self.line_number
+        )?;
+        if let Some(column) = self.column {
+            write!(f, ", column = {}", column)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Returns diagnostics as a JSON array of the objects described in [`Diagnostic::to_json`].
+pub fn to_json(diagnostics: &[Diagnostic]) -> String {
+    let objects = diagnostics
+        .iter()
+        .map(Diagnostic::to_json)
+        .collect::<Vec<_>>();
+    format!("[{}]", objects.join(","))
+}
+
+fn push_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+// Returns the number of line terminators in `raw` before byte `offset`, and the 1-based column of
+// the character at `offset` on its physical line.
+fn locate(raw: &str, offset: usize) -> (usize, usize) {
+    let before = &raw[..offset];
+    let lines = before.matches('\n').count()
+        + before
+            .match_indices('\r')
+            .filter(|&(i, _)| !raw[i + 1..].starts_with('\n'))
+            .count();
+    let line_start = before.rfind(['\r', '\n']).map_or(0, |i| i + 1);
+    (lines, before[line_start..].chars().count() + 1)
+}
+
+// Returns true if `text` starts with a multibyte UTF-8 sequence whose bytes were each read as an
+// ISO-8859-1 character.
+fn is_misread_utf8(text: &str) -> bool {
+    let byte = |c: char| -> Option<u8> {
+        let mut buf = [0; 4];
+        match WINDOWS_1252.encode(c.encode_utf8(&mut buf)).0.as_ref() {
+            &[b] if b >= 0x80 => Some(b),
+            _ => None,
+        }
+    };
+    let mut bytes = Vec::new();
+    for c in text.chars().take(4) {
+        match byte(c) {
+            Some(b) => bytes.push(b),
+            None => break,
+        }
+    }
+    let len = match bytes.first() {
+        Some(0xc2..=0xdf) => 2,
+        Some(0xe0..=0xef) => 3,
+        Some(0xf0..=0xf4) => 4,
+        _ => return false,
+    };
+    bytes.len() >= len && std::str::from_utf8(&bytes[..len]).is_ok()
+}
+
+// Returns the byte offset of the whitespace at the end of `raw` which isn't escaped, if any.
+fn unescaped_trailing_whitespace(raw: &str) -> Option<usize> {
+    let mut start = raw.trim_end_matches([' ', '\t', '\x0c']).len();
+    let before = &raw[..start];
+    if (before.len() - before.trim_end_matches('\\').len()) % 2 == 1 {
+        // The first whitespace character is escaped.
+        start += 1;
+    }
+    if start < raw.len() {
+        Some(start)
+    } else {
+        None
+    }
+}
+
+// Describes why an unescaped character is suspicious, or returns `None` if it isn't.  `rest` is
+// the text starting with the character.
+fn suspicious(c: char, rest: &str) -> Option<&'static str> {
+    match c {
+        '\u{ad}' | '\u{200b}'..='\u{200d}' | '\u{2060}'..='\u{2064}' | '\u{feff}' => {
+            Some("an invisible character")
+        }
+        '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' => {
+            Some("a character which changes the direction of text")
+        }
+        '\u{fffd}' => Some("a replacement character, from input which was malformed"),
+        '\t' | '\x0c' | '\r' | '\n' => None,
+        c if c.is_control() => Some("a control character"),
+        '\u{c2}'..='\u{f4}' if is_misread_utf8(rest) => {
+            Some("the start of text which looks like UTF-8 read as ISO-8859-1")
+        }
+        _ => None,
+    }
+}
+
+/// Runs the enabled lint checks over a properties file, returning the diagnostics in file order.
+///
+/// The file is read in the [configured encoding](LintConfig::set_encoding).  Malformed
+/// `\uxxxx` escapes are reported as [`Rule::InvalidUnicodeEscape`] and checking continues with
+/// the next line.  Otherwise, an error is only returned if the file cannot be read.
+///
+/// ```
+/// use java_properties::lint::lint;
+/// use java_properties::lint::LintConfig;
+/// use java_properties::lint::Rule;
+/// use java_properties::lint::Severity;
+///
+/// # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+/// let input = b"url=http://example.com \nname=caf\xc3\xa9\nbad=\\u12\nend=\\";
+/// let diagnostics = lint(&input[..], &LintConfig::new())?;
+/// let found: Vec<_> = diagnostics
+///     .iter()
+///     .map(|d| (d.rule(), d.line_number(), d.column()))
+///     .collect();
+/// assert_eq!(found, [
+///     (Rule::TrailingWhitespace, 1, Some(23)),
+///     (Rule::SuspiciousUnicode, 2, Some(9)),
+///     (Rule::InvalidUnicodeEscape, 3, Some(5)),
+///     (Rule::DanglingBackslash, 4, Some(5)),
+/// ]);
+/// assert!(diagnostics.iter().any(|d| d.severity() >= Severity::Error));
+/// # Ok(())
+/// # }
+/// ```
+pub fn lint<R: Read>(
+    mut input: R,
+    config: &LintConfig,
+) -> Result<Vec<Diagnostic>, PropertiesError> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let mut diagnostics = Vec::new();
+    let mut push = |rule: Rule, line_number: usize, column: Option<usize>, message: String| {
+        if config.is_enabled(rule) {
+            diagnostics.push(Diagnostic {
+                rule,
+                severity: config.severity(rule),
+                line_number,
+                column,
+                message,
+            });
+        }
+    };
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut seen_lowercase: HashMap<String, (String, usize)> = HashMap::new();
+    let iter = PropertiesIterBuilder::new()
+        .encoding(config.encoding())
+        .keep_raw_text(true)
+        .build(&bytes[..]);
+    for line in iter {
+        let line = match line {
+            Ok(line) => line,
+            // Parsing continues with the next line after a malformed escape.
+            Err(e) if e.kind() == PropertiesErrorKind::MalformedUnicodeEscape => {
+                push(
+                    Rule::InvalidUnicodeEscape,
+                    e.line_number().unwrap_or(0),
+                    e.column(),
+                    e.description.clone(),
+                );
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let line_number = line.line_number();
+        let column = line.column();
+        let raw = line.raw_text().unwrap_or_default().to_string();
+        let end = line.byte_range().map_or(0, |range| range.end as usize);
+        let is_comment = matches!(line.content(), LineContent::Comment(_));
+
+        for (i, c) in raw.char_indices() {
+            if let Some(reason) = suspicious(c, &raw[i..]) {
+                let (lines, column) = locate(&raw, i);
+                push(
+                    Rule::SuspiciousUnicode,
+                    line_number + lines,
+                    Some(column),
+                    format!("unescaped U+{:04X} is {}", c as u32, reason),
+                );
+            }
+        }
+        if !is_comment && end == bytes.len() && raw.ends_with('\\') {
+            let backslashes = raw.len() - raw.trim_end_matches('\\').len();
+            if backslashes % 2 == 1 {
+                let (lines, column) = locate(&raw, raw.len() - 1);
+                push(
+                    Rule::DanglingBackslash,
+                    line_number + lines,
+                    Some(column),
+                    "the file ends with a line continuation".to_string(),
+                );
+            }
+        }
+
+        if let LineContent::KVPair(key, value) = line.consume_content() {
+            if value.ends_with(|c: char| c.is_whitespace()) {
+                let (lines, column) = if let Some(start) = unescaped_trailing_whitespace(&raw) {
+                    let (lines, column) = locate(&raw, start);
+                    (lines, Some(column))
+                } else {
+                    // The whitespace is escaped.
+                    (locate(&raw, raw.len()).0, None)
+                };
+                push(
+                    Rule::TrailingWhitespace,
+                    line_number + lines,
+                    column,
+                    format!("value for key {:?} ends with whitespace", key),
+                );
+            }
+            match seen_lowercase.get(&key.to_lowercase()) {
+                Some((other, previous)) if *other != key => push(
+                    Rule::KeyCaseConflict,
+                    line_number,
+                    column,
+                    format!(
+                        "key {:?} differs only by case from key {:?} on line {}",
+                        key, other, previous
+                    ),
+                ),
+                Some(_) => (),
+                None => {
+                    seen_lowercase.insert(key.to_lowercase(), (key.clone(), line_number));
+                }
+            }
+            if let Some(previous) = seen.insert(key.clone(), line_number) {
+                push(
+                    Rule::DuplicateKey,
+                    line_number,
+                    column,
+                    format!("key {:?} was already assigned on line {}", key, previous),
+                );
+            }
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// The results of checking a single file with [`validate_tree`].
+#[derive(Debug)]
+pub struct FileReport {
+    path: PathBuf,
+    violations: Vec<Violation>,
+    diagnostics: Vec<Diagnostic>,
+    error: Option<PropertiesError>,
+}
+
+impl FileReport {
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the schema violations in file order.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Returns the lint diagnostics in file order.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns the error which prevented the file from being checked, if any.
+    pub fn error(&self) -> Option<&PropertiesError> {
+        self.error.as_ref()
+    }
+
+    /// Returns true if the file was checked and no problems were found.
+    pub fn is_clean(&self) -> bool {
+        self.error.is_none() && self.violations.is_empty() && self.diagnostics.is_empty()
+    }
+}
+
+/// The result of [`validate_tree`].
+#[derive(Debug, Default)]
+pub struct TreeReport {
+    files: Vec<FileReport>,
+}
+
+impl TreeReport {
+    /// Returns the reports for all checked files, sorted by path.
+    pub fn files(&self) -> &[FileReport] {
+        &self.files
+    }
+
+    /// Returns the report for the given file, if it was checked.
+    pub fn file<P: AsRef<Path>>(&self, path: P) -> Option<&FileReport> {
+        self.files.iter().find(|f| f.path == path.as_ref())
+    }
+
+    /// Returns the total number of schema violations, lint diagnostics, and unreadable files.
+    pub fn problem_count(&self) -> usize {
+        self.files
+            .iter()
+            .map(|f| f.violations.len() + f.diagnostics.len() + f.error.is_some() as usize)
+            .sum()
+    }
+
+    /// Returns true if no file has any problems.
+    pub fn is_clean(&self) -> bool {
+        self.files.iter().all(FileReport::is_clean)
+    }
+}
+
+/// Checks every `.properties` file under `dir` against `schema` and the lint checks enabled in `lint_config`.
+///
+/// Files are checked in parallel.  A file which cannot be read or parsed is recorded in its
+/// [`FileReport`] rather than stopping the walk; an error is only returned if a directory cannot be listed.
+pub fn validate_tree<P: AsRef<Path>>(
+    dir: P,
+    schema: &Schema,
+    lint_config: &LintConfig,
+) -> Result<TreeReport, PropertiesError> {
+    let mut paths = Vec::new();
+    collect_properties_files(dir.as_ref(), &mut paths)?;
+    paths.sort();
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(paths.len()));
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(paths.len());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                match paths.get(i) {
+                    Some(path) => {
+                        let report = check_file(path, schema, lint_config);
+                        results.lock().unwrap().push((i, report));
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|&(i, _)| i);
+    Ok(TreeReport {
+        files: results.into_iter().map(|(_, report)| report).collect(),
+    })
+}
+
+fn collect_properties_files(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), PropertiesError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_properties_files(&path, paths)?;
+        } else if path.extension().is_some_and(|e| e == "properties") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn check_file(path: &Path, schema: &Schema, lint_config: &LintConfig) -> FileReport {
+    let mut report = FileReport {
+        path: path.to_path_buf(),
+        violations: Vec::new(),
+        diagnostics: Vec::new(),
+        error: None,
+    };
+    let result = fs::read(path)
+        .map_err(PropertiesError::from)
+        .and_then(|bytes| {
+            let checked = schema.read_checked(&bytes[..])?;
+            let diagnostics = lint(&bytes[..], lint_config)?;
+            Ok((checked, diagnostics))
+        });
+    match result {
+        Ok((checked, diagnostics)) => {
+            report.violations = checked.violations().to_vec();
+            report.diagnostics = diagnostics;
+        }
+        Err(e) => report.error = Some(e),
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lint;
+    use super::validate_tree;
+    use super::LintConfig;
+    use super::Rule;
+    use super::Severity;
+    use crate::metadata::ValueType;
+    use crate::schema::Schema;
+    use crate::schema::SchemaEntry;
+    use crate::test_dir;
+    use std::fs;
+
+    #[test]
+    fn lint_rules() {
+        let input = "a=1 \nb=2\na=3\n";
+        let diagnostics = lint(input.as_bytes(), &LintConfig::new()).unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.rule(), d.line_number()))
+            .collect();
+        assert_eq!(
+            found,
+            [(Rule::TrailingWhitespace, 1), (Rule::DuplicateKey, 3)]
+        );
+
+        let mut config = LintConfig::new();
+        config.disable(Rule::TrailingWhitespace);
+        let diagnostics = lint(input.as_bytes(), &config).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].rule(), Rule::DuplicateKey);
+        assert_eq!(diagnostics[0].severity(), Severity::Warning);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "warning: key \"a\" was already assigned on line 1 [duplicate-key] \
+             (line_number = 3, column = 1)"
+        );
+    }
+
+    #[test]
+    fn lint_positions() {
+        let input = "# \u{200b}\r\nkey=a\\\r\n  b\u{202e} \t\r\nKey=\\ \n x=\\u00\ny=\\uxyz1\nz\\=\\\n   \\";
+        let mut config = LintConfig::new();
+        config.set_encoding(encoding_rs::UTF_8);
+        config.set_severity(Rule::SuspiciousUnicode, Severity::Info);
+        let diagnostics = lint(input.as_bytes(), &config).unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.rule(), d.severity(), d.line_number(), d.column()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Rule::SuspiciousUnicode, Severity::Info, 1, Some(3)),
+                (Rule::SuspiciousUnicode, Severity::Info, 3, Some(4)),
+                (Rule::TrailingWhitespace, Severity::Warning, 3, Some(5)),
+                (Rule::TrailingWhitespace, Severity::Warning, 4, None),
+                (Rule::KeyCaseConflict, Severity::Warning, 4, Some(1)),
+                (Rule::InvalidUnicodeEscape, Severity::Error, 5, Some(4)),
+                (Rule::InvalidUnicodeEscape, Severity::Error, 6, Some(3)),
+                (Rule::DanglingBackslash, Severity::Error, 8, Some(4)),
+            ]
+        );
+        assert!(Severity::Info < Severity::Warning && Severity::Warning < Severity::Error);
+
+        let json = super::to_json(&diagnostics[..2]);
+        assert!(
+            json.starts_with("[{\"rule\":\"suspicious-unicode\",\"severity\":\"info\",\"line\":1,")
+        );
+        assert!(json.ends_with("}]"));
+        assert_eq!(super::to_json(&[]), "[]");
+        let mut escaped = String::new();
+        super::push_json_string(&mut escaped, "\"\\\n\u{1}");
+        assert_eq!(escaped, "\"\\\"\\\\\\n\\u0001\"");
+
+        let diagnostics = lint("a=1\\\n".as_bytes(), &LintConfig::new()).unwrap();
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn tree() {
+        let temp = test_dir();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("good.properties"), "port=80\n").unwrap();
+        fs::write(dir.join("sub/bad.properties"), "port=eighty\nport=80\n").unwrap();
+        fs::write(dir.join("sub/broken.properties"), "x=\\u12\n").unwrap();
+        fs::write(dir.join("notes.txt"), "port=eighty\n").unwrap();
+
+        let mut schema = Schema::new();
+        schema.insert(SchemaEntry::new("port", ValueType::Integer));
+        let report = validate_tree(dir, &schema, &LintConfig::new()).unwrap();
+
+        assert_eq!(report.files().len(), 3);
+        assert!(report.file(dir.join("good.properties")).unwrap().is_clean());
+        let bad = report.file(dir.join("sub/bad.properties")).unwrap();
+        assert_eq!(bad.violations().len(), 1);
+        assert_eq!(bad.diagnostics().len(), 1);
+        let broken = report.file(dir.join("sub/broken.properties")).unwrap();
+        assert!(broken.error().is_some());
+        assert_eq!(report.problem_count(), 3);
+        assert!(!report.is_clean());
+    }
+}
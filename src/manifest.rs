@@ -0,0 +1,370 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Reading and writing JAR manifests (`META-INF/MANIFEST.MF`).
+//!
+//! The format is specified at <https://docs.oracle.com/en/java/javase/17/docs/specs/jar/jar.html#jar-manifest>.
+//! A manifest consists of a main section followed by zero or more named sections, separated by blank lines.
+//! Each section is a list of `Name: value` attributes.  Lines are limited to 72 bytes; longer values are
+//! continued on following lines starting with a single space.  Manifests are always UTF-8.
+//!
+//! This module is only available with the `manifest` feature.
+
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+const MAX_LINE_BYTES: usize = 72;
+
+/// An ordered list of manifest attributes.
+///
+/// Attribute names are case-insensitive.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Hash)]
+pub struct Attributes {
+    entries: Vec<(String, String)>,
+}
+
+impl Attributes {
+    /// Creates an empty list of attributes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the value of an attribute.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets an attribute, replacing its value in place if it already exists.
+    ///
+    /// Names must be non-empty and consist of ASCII letters, digits, `-`, and `_`.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), PropertiesError> {
+        check_name(name, None)?;
+        if value.contains(['\r', '\n', '\0']) {
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::InvalidValue,
+                format!(
+                    "Manifest attribute value contains a line break or NUL: {:?}",
+                    value
+                ),
+                None,
+                None,
+            ));
+        }
+        match self
+            .entries
+            .iter_mut()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.entries.push((name.to_string(), value.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Removes an attribute, returning its value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let i = self
+            .entries
+            .iter()
+            .position(|(n, _)| n.eq_ignore_ascii_case(name))?;
+        Some(self.entries.remove(i).1)
+    }
+
+    /// Iterates over the attributes in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// Returns the number of attributes.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there are no attributes.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn check_name(name: &str, line_number: Option<usize>) -> Result<(), PropertiesError> {
+    if name.is_empty()
+        || !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return Err(PropertiesError::new(
+            PropertiesErrorKind::InvalidKey,
+            format!("Invalid manifest attribute name: {:?}", name),
+            None,
+            line_number,
+        ));
+    }
+    Ok(())
+}
+
+/// A JAR manifest.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Hash)]
+pub struct Manifest {
+    main: Attributes,
+    sections: Vec<Attributes>,
+}
+
+impl Manifest {
+    /// Creates an empty manifest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the attributes of the main section.
+    pub fn main_attributes(&self) -> &Attributes {
+        &self.main
+    }
+
+    /// Returns the attributes of the main section for modification.
+    pub fn main_attributes_mut(&mut self) -> &mut Attributes {
+        &mut self.main
+    }
+
+    /// Returns the named sections in order.  Each section starts with a `Name` attribute.
+    pub fn sections(&self) -> &[Attributes] {
+        &self.sections
+    }
+
+    /// Returns the section with the given `Name` attribute.
+    pub fn section(&self, name: &str) -> Option<&Attributes> {
+        self.sections.iter().find(|s| s.get("Name") == Some(name))
+    }
+
+    /// Returns the section with the given `Name` attribute for modification, creating it if necessary.
+    pub fn section_mut(&mut self, name: &str) -> Result<&mut Attributes, PropertiesError> {
+        match self
+            .sections
+            .iter()
+            .position(|s| s.get("Name") == Some(name))
+        {
+            Some(i) => Ok(&mut self.sections[i]),
+            None => {
+                let mut section = Attributes::new();
+                section.set("Name", name)?;
+                self.sections.push(section);
+                Ok(self.sections.last_mut().unwrap())
+            }
+        }
+    }
+
+    /// Parses a manifest.
+    pub fn read<R: Read>(mut input: R) -> Result<Self, PropertiesError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let text = String::from_utf8(bytes).map_err(|e| {
+            PropertiesError::new(
+                PropertiesErrorKind::Decode,
+                "Manifest is not valid UTF-8",
+                Some(Box::new(e)),
+                None,
+            )
+        })?;
+
+        let mut manifest = Manifest::new();
+        let mut current = Attributes::new();
+        let mut in_main = true;
+        // The attribute being assembled from continuation lines, with its starting line number.
+        let mut pending: Option<(usize, String)> = None;
+        let mut section_start = 1;
+        let lines = text
+            .split("\r\n")
+            .flat_map(|l| l.split(['\r', '\n']))
+            .enumerate();
+        for (i, line) in lines {
+            let line_number = i + 1;
+            if let Some(continuation) = line.strip_prefix(' ') {
+                match pending {
+                    Some((_, ref mut header)) => header.push_str(continuation),
+                    None => {
+                        return Err(PropertiesError::new(
+                            PropertiesErrorKind::Syntax,
+                            "Manifest continuation line without a preceding attribute",
+                            None,
+                            Some(line_number),
+                        ))
+                    }
+                }
+                continue;
+            }
+            if let Some((n, header)) = pending.take() {
+                add_header(&mut current, &header, n)?;
+            }
+            if line.is_empty() {
+                if in_main || !current.is_empty() {
+                    finish_section(&mut manifest, &mut current, &mut in_main, section_start)?;
+                }
+            } else {
+                if current.is_empty() {
+                    section_start = line_number;
+                }
+                pending = Some((line_number, line.to_string()));
+            }
+        }
+        if let Some((n, header)) = pending.take() {
+            add_header(&mut current, &header, n)?;
+        }
+        if in_main || !current.is_empty() {
+            finish_section(&mut manifest, &mut current, &mut in_main, section_start)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Writes the manifest with `\r\n` line endings, wrapping lines at 72 bytes.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), PropertiesError> {
+        let mut out = String::new();
+        write_section(&mut out, &self.main);
+        out.push_str("\r\n");
+        for section in &self.sections {
+            write_section(&mut out, section);
+            out.push_str("\r\n");
+        }
+        writer.write_all(out.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn add_header(
+    attributes: &mut Attributes,
+    header: &str,
+    line_number: usize,
+) -> Result<(), PropertiesError> {
+    let i = header.find(':').ok_or_else(|| {
+        PropertiesError::new(
+            PropertiesErrorKind::Syntax,
+            format!("Manifest attribute is missing ':': {:?}", header),
+            None,
+            Some(line_number),
+        )
+    })?;
+    let name = &header[..i];
+    check_name(name, Some(line_number))?;
+    let value = &header[i + 1..];
+    let value = value.strip_prefix(' ').unwrap_or(value);
+    attributes
+        .entries
+        .push((name.to_string(), value.to_string()));
+    Ok(())
+}
+
+fn finish_section(
+    manifest: &mut Manifest,
+    current: &mut Attributes,
+    in_main: &mut bool,
+    line_number: usize,
+) -> Result<(), PropertiesError> {
+    let section = std::mem::take(current);
+    if *in_main {
+        manifest.main = section;
+        *in_main = false;
+    } else {
+        match section.entries.first() {
+            Some((name, _)) if name.eq_ignore_ascii_case("Name") => (),
+            _ => {
+                return Err(PropertiesError::new(
+                    PropertiesErrorKind::Syntax,
+                    "Manifest section does not start with a Name attribute",
+                    None,
+                    Some(line_number),
+                ))
+            }
+        }
+        manifest.sections.push(section);
+    }
+    Ok(())
+}
+
+fn write_section(out: &mut String, attributes: &Attributes) {
+    for (name, value) in attributes.iter() {
+        let header = format!("{}: {}", name, value);
+        let mut limit = MAX_LINE_BYTES;
+        let mut line_len = 0;
+        for c in header.chars() {
+            if line_len + c.len_utf8() > limit {
+                out.push_str("\r\n ");
+                // The leading space counts towards the limit.
+                limit = MAX_LINE_BYTES - 1;
+                line_len = 0;
+            }
+            out.push(c);
+            line_len += c.len_utf8();
+        }
+        out.push_str("\r\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+
+    #[test]
+    fn read() {
+        let input = "Manifest-Version: 1.0\r\nClass-Path: a.jar b.jar c.j\r\n ar d.jar\r\nCreated-By: 17 (Oracle)\r\n\r\nName: com/example/\r\nSealed: true\r\n\r\n";
+        let manifest = Manifest::read(input.as_bytes()).unwrap();
+        assert_eq!(manifest.main_attributes().len(), 3);
+        assert_eq!(
+            manifest.main_attributes().get("class-path"),
+            Some("a.jar b.jar c.jar d.jar")
+        );
+        assert_eq!(manifest.sections().len(), 1);
+        assert_eq!(
+            manifest.section("com/example/").unwrap().get("Sealed"),
+            Some("true")
+        );
+    }
+
+    #[test]
+    fn read_errors() {
+        let data = [
+            ("Manifest-Version 1.0\n", Some(1)),
+            (" continued\n", Some(1)),
+            ("A: b\n\nSealed: true\n", Some(3)),
+            ("A: b\nBad Name: x\n", Some(2)),
+        ];
+        for &(input, line_number) in &data {
+            match Manifest::read(input.as_bytes()) {
+                Ok(m) => panic!("Expected an error for {:?}, got {:?}", input, m),
+                Err(e) => assert_eq!(e.line_number(), line_number, "{:?}", input),
+            }
+        }
+    }
+
+    #[test]
+    fn write_wraps_lines() {
+        let mut manifest = Manifest::new();
+        manifest
+            .main_attributes_mut()
+            .set("Manifest-Version", "1.0")
+            .unwrap();
+        let long_value = "x".repeat(100) + "\u{00e9}\u{00e9}";
+        manifest
+            .main_attributes_mut()
+            .set("Class-Path", &long_value)
+            .unwrap();
+        manifest
+            .section_mut("a/")
+            .unwrap()
+            .set("Sealed", "true")
+            .unwrap();
+        assert!(manifest.main_attributes_mut().set("A", "b\nc").is_err());
+        assert!(manifest.main_attributes_mut().set("A B", "c").is_err());
+
+        let mut buf = Vec::new();
+        manifest.write(&mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        for line in text.split("\r\n") {
+            assert!(line.len() <= 72, "line too long: {:?}", line);
+        }
+        assert!(text.starts_with("Manifest-Version: 1.0\r\nClass-Path: xxx"));
+        assert!(text.ends_with("\r\n\r\nName: a/\r\nSealed: true\r\n\r\n"));
+        assert_eq!(Manifest::read(text.as_bytes()).unwrap(), manifest);
+    }
+}
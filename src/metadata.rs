@@ -0,0 +1,313 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Per-key metadata declared through annotation comments.
+//!
+//! An annotation is a comment starting with `@` placed in the comment block directly above a key:
+//!
+//! ```text
+//! #@doc Port the server listens on.
+//! #@type int
+//! #@default 8080
+//! server.port=8080
+//!
+//! #@deprecated use server.port instead
+//! port=8080
+//! ```
+//!
+//! The recognized annotations are:
+//!
+//! * `#@type <type>`: the type of the value, see [`ValueType`]
+//! * `#@default <value>`: the default value
+//! * `#@doc <text>`: documentation; multiple `#@doc` lines are joined with newlines
+//! * `#@deprecated [message]`: marks the key as deprecated
+//!
+//! Unrecognized annotations are ignored.
+
+use crate::Line;
+use crate::LineContent;
+use crate::PropertiesError;
+use crate::PropertiesIter;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io::Read;
+
+/// The declared type of a value.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
+pub enum ValueType {
+    /// Any string, declared as `string` or `str`.
+    String,
+    /// A signed 64-bit integer, declared as `int`, `integer`, or `long`.
+    Integer,
+    /// A floating point number, declared as `float`, `double`, or `number`.
+    Float,
+    /// `true` or `false` (case-insensitive), declared as `bool` or `boolean`.
+    Boolean,
+    /// One of a fixed set of strings, declared as `enum(a|b|c)`.
+    Enum(Vec<String>),
+    /// Any other type name, which is preserved but not interpreted.
+    Other(String),
+}
+
+impl ValueType {
+    /// Parses a type declaration such as `int` or `enum(a|b)`.
+    pub fn parse(s: &str) -> ValueType {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "string" | "str" => return ValueType::String,
+            "int" | "integer" | "long" => return ValueType::Integer,
+            "float" | "double" | "number" => return ValueType::Float,
+            "bool" | "boolean" => return ValueType::Boolean,
+            _ => (),
+        }
+        if let Some(variants) = s.strip_prefix("enum(").and_then(|v| v.strip_suffix(')')) {
+            return ValueType::Enum(variants.split('|').map(|v| v.trim().to_string()).collect());
+        }
+        ValueType::Other(s.to_string())
+    }
+
+    /// Returns true if the value is valid for this type.
+    ///
+    /// Values of [`ValueType::Other`] types are always accepted.
+    pub fn accepts(&self, value: &str) -> bool {
+        let value = value.trim();
+        match *self {
+            ValueType::String | ValueType::Other(_) => true,
+            ValueType::Integer => value.parse::<i64>().is_ok(),
+            ValueType::Float => value.parse::<f64>().is_ok(),
+            ValueType::Boolean => {
+                value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false")
+            }
+            ValueType::Enum(ref variants) => variants.iter().any(|v| v == value),
+        }
+    }
+}
+
+impl Display for ValueType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            ValueType::String => f.write_str("string"),
+            ValueType::Integer => f.write_str("int"),
+            ValueType::Float => f.write_str("float"),
+            ValueType::Boolean => f.write_str("bool"),
+            ValueType::Enum(ref variants) => write!(f, "enum({})", variants.join("|")),
+            ValueType::Other(ref name) => f.write_str(name),
+        }
+    }
+}
+
+/// Metadata attached to a key through annotation comments.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Hash)]
+pub struct KeyMetadata {
+    value_type: Option<ValueType>,
+    default: Option<String>,
+    doc: Option<String>,
+    deprecated: Option<String>,
+}
+
+impl KeyMetadata {
+    /// Creates empty metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the declared type of the value, from `#@type`.
+    pub fn value_type(&self) -> Option<&ValueType> {
+        self.value_type.as_ref()
+    }
+
+    /// Sets the declared type of the value.
+    pub fn set_value_type(&mut self, value_type: Option<ValueType>) {
+        self.value_type = value_type;
+    }
+
+    /// Returns the declared default value, from `#@default`.
+    pub fn default_value(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    /// Sets the declared default value.
+    pub fn set_default_value<S: Into<String>>(&mut self, default: Option<S>) {
+        self.default = default.map(Into::into);
+    }
+
+    /// Returns the documentation, from `#@doc`.
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    /// Sets the documentation.
+    pub fn set_doc<S: Into<String>>(&mut self, doc: Option<S>) {
+        self.doc = doc.map(Into::into);
+    }
+
+    /// Returns the deprecation message, if the key is deprecated.
+    ///
+    /// A `#@deprecated` annotation without a message yields an empty string.
+    pub fn deprecation(&self) -> Option<&str> {
+        self.deprecated.as_deref()
+    }
+
+    /// Marks the key as deprecated with the given message.
+    pub fn set_deprecation<S: Into<String>>(&mut self, message: Option<S>) {
+        self.deprecated = message.map(Into::into);
+    }
+
+    /// Returns true if no metadata is present.
+    pub fn is_empty(&self) -> bool {
+        self == &KeyMetadata::default()
+    }
+
+    fn apply(&mut self, name: &str, value: &str) {
+        match name {
+            "type" => self.value_type = Some(ValueType::parse(value)),
+            "default" => self.default = Some(value.to_string()),
+            "doc" => match self.doc {
+                Some(ref mut doc) => {
+                    doc.push('\n');
+                    doc.push_str(value);
+                }
+                None => self.doc = Some(value.to_string()),
+            },
+            "deprecated" => self.deprecated = Some(value.to_string()),
+            _ => (),
+        }
+    }
+}
+
+/// Accumulates annotations from a stream of lines and attaches them to the following key.
+#[derive(Debug, Default)]
+pub(crate) struct MetadataCollector {
+    pending: KeyMetadata,
+    last_comment_line: Option<usize>,
+}
+
+impl MetadataCollector {
+    /// Feeds a line, returning the key and its metadata if the line is a key/value pair with annotations.
+    pub(crate) fn feed(&mut self, line: &Line) -> Option<(String, KeyMetadata)> {
+        // Annotations only apply if the comment block is directly above the key.
+        if let Some(n) = self.last_comment_line {
+            if line.line_number() != n + 1 {
+                self.pending = KeyMetadata::default();
+            }
+        }
+        match line.content() {
+            LineContent::Comment(text) => {
+                self.last_comment_line = Some(line.line_number());
+                if let Some(annotation) = text.strip_prefix('@') {
+                    let (name, value) = match annotation.find(char::is_whitespace) {
+                        Some(i) => (&annotation[..i], annotation[i..].trim()),
+                        None => (annotation, ""),
+                    };
+                    self.pending.apply(name, value);
+                }
+                None
+            }
+            LineContent::KVPair(key, _) => {
+                self.last_comment_line = None;
+                let metadata = std::mem::take(&mut self.pending);
+                if metadata.is_empty() {
+                    None
+                } else {
+                    Some((key.clone(), metadata))
+                }
+            }
+            LineContent::Blank => None,
+        }
+    }
+}
+
+/// Collects the annotations attached to each key.
+///
+/// Keys without annotations are omitted.
+pub fn collect_metadata<I: IntoIterator<Item = Line>>(lines: I) -> HashMap<String, KeyMetadata> {
+    let mut collector = MetadataCollector::default();
+    lines
+        .into_iter()
+        .filter_map(|line| collector.feed(&line))
+        .collect()
+}
+
+/// Reads a properties file and collects the annotations attached to each key.
+///
+/// Keys without annotations are omitted.
+pub fn read_metadata<R: Read>(input: R) -> Result<HashMap<String, KeyMetadata>, PropertiesError> {
+    let mut collector = MetadataCollector::default();
+    let mut map = HashMap::new();
+    for line in PropertiesIter::new(input) {
+        if let Some((key, metadata)) = collector.feed(&line?) {
+            map.insert(key, metadata);
+        }
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_metadata;
+    use super::ValueType;
+
+    #[test]
+    fn deprecation() {
+        let input = "
+#@deprecated use server.port instead
+port=8080
+# Regular comment
+#@deprecated
+old=1
+
+#@deprecated detached by a blank line
+
+host=localhost
+";
+        let metadata = read_metadata(input.as_bytes()).unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(
+            metadata["port"].deprecation(),
+            Some("use server.port instead")
+        );
+        assert_eq!(metadata["old"].deprecation(), Some(""));
+        assert!(!metadata.contains_key("host"));
+    }
+
+    #[test]
+    fn structured_annotations() {
+        let input = "
+#@doc Port the server
+#@doc listens on.
+#@type int
+#@default 8080
+#@unknown ignored
+server.port=8080
+#@type enum(debug | info|warn)
+log.level=info
+";
+        let metadata = read_metadata(input.as_bytes()).unwrap();
+        let port = &metadata["server.port"];
+        assert_eq!(port.doc(), Some("Port the server\nlistens on."));
+        assert_eq!(port.value_type(), Some(&ValueType::Integer));
+        assert_eq!(port.default_value(), Some("8080"));
+        assert_eq!(port.deprecation(), None);
+        let level = metadata["log.level"].value_type().unwrap();
+        assert_eq!(
+            level,
+            &ValueType::Enum(vec![
+                "debug".to_string(),
+                "info".to_string(),
+                "warn".to_string()
+            ])
+        );
+        assert_eq!(level.to_string(), "enum(debug|info|warn)");
+    }
+
+    #[test]
+    fn value_type_parse() {
+        assert_eq!(ValueType::parse("Boolean"), ValueType::Boolean);
+        assert_eq!(ValueType::parse(" double "), ValueType::Float);
+        assert_eq!(ValueType::parse("str"), ValueType::String);
+        assert_eq!(
+            ValueType::parse("duration"),
+            ValueType::Other("duration".to_string())
+        );
+    }
+}
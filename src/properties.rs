@@ -0,0 +1,921 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! An in-memory properties container.
+
+use crate::case::split_words;
+use crate::case::KeyCase;
+use crate::diff;
+use crate::diff::Fingerprint;
+use crate::metadata::KeyMetadata;
+use crate::metadata::MetadataCollector;
+use crate::write_file_atomically;
+use crate::LineContent;
+use crate::PropertiesError;
+use crate::PropertiesIter;
+use crate::PropertiesWriter;
+use crate::SaveOptions;
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::Read;
+use std::io::Write;
+use std::iter::FromIterator;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+type DeprecationHandler = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// An in-memory set of properties.
+///
+/// Unlike a plain `HashMap`, the container can optionally record which keys are read,
+/// which helps find configuration that is never used.
+#[derive(Default)]
+pub struct Properties {
+    values: HashMap<String, String>,
+    metadata: HashMap<String, KeyMetadata>,
+    accessed: Option<Mutex<HashSet<String>>>,
+    deprecation_handler: Option<DeprecationHandler>,
+    deprecation_warned: Mutex<HashSet<String>>,
+    relaxed_binding: bool,
+    defaults: Option<Box<Properties>>,
+    history: Option<History>,
+}
+
+/// A value a key had before it was changed, recorded when history tracking is enabled with
+/// [`Properties::track_history`].
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Revision {
+    value: Option<String>,
+    sequence: u64,
+    time: SystemTime,
+}
+
+impl Revision {
+    /// Returns the value the key had before the change, or `None` if the key was absent.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// Returns the sequence number of the change.  Changes to all keys are numbered in the order
+    /// they were made, starting at 0.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns when the change was made.
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct History {
+    revisions: HashMap<String, Vec<Revision>>,
+    next_sequence: u64,
+}
+
+impl Properties {
+    /// Creates an empty set of properties.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Captures the process environment variables whose names start with `prefix`.
+    ///
+    /// The prefix is removed from the keys, and the remainder is converted to `case` if given, so with
+    /// a prefix of `MYAPP_` and [`KeyCase::Dot`], `MYAPP_DB_URL` becomes `db.url`.
+    /// Variables whose name or value is not valid Unicode are skipped, as are variables whose key would be empty.
+    pub fn from_env(prefix: &str, case: Option<KeyCase>) -> Self {
+        let vars = env::vars_os()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)));
+        Self::from_env_vars(vars, prefix, case)
+    }
+
+    /// Like [`from_env`](Self::from_env), but reads variables from the given pairs instead of the process environment.
+    pub fn from_env_vars<I, K, V>(vars: I, prefix: &str, case: Option<KeyCase>) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let mut props = Properties::new();
+        for (name, value) in vars {
+            if let Some(rest) = name.as_ref().strip_prefix(prefix) {
+                let key = match case {
+                    Some(case) => case.convert(rest),
+                    None => rest.to_string(),
+                };
+                if !key.is_empty() {
+                    props.values.insert(key, value.into());
+                }
+            }
+        }
+        props
+    }
+
+    /// Creates an empty set of properties which falls back to `defaults` for missing keys, like
+    /// `new Properties(defaults)` in Java.
+    ///
+    /// Only [`get_property`](Self::get_property), [`get_property_or`](Self::get_property_or), and
+    /// [`property_names`](Self::property_names) consult the defaults.
+    pub fn with_defaults(defaults: Properties) -> Self {
+        Properties {
+            defaults: Some(Box::new(defaults)),
+            ..Properties::default()
+        }
+    }
+
+    /// Returns the defaults consulted for missing keys, if any.
+    pub fn defaults(&self) -> Option<&Properties> {
+        self.defaults.as_deref()
+    }
+
+    /// Returns the defaults consulted for missing keys, if any, for modification.
+    pub fn defaults_mut(&mut self) -> Option<&mut Properties> {
+        self.defaults.as_deref_mut()
+    }
+
+    /// Replaces the defaults consulted for missing keys.
+    pub fn set_defaults(&mut self, defaults: Option<Properties>) {
+        self.defaults = defaults.map(Box::new);
+    }
+
+    /// Returns the value for the given key, searching the chain of defaults if it isn't present,
+    /// like `getProperty` in Java.
+    ///
+    /// ```
+    /// use java_properties::Properties;
+    ///
+    /// let mut defaults = Properties::new();
+    /// defaults.set_property("host", "localhost");
+    /// defaults.set_property("port", "80");
+    /// let mut props = Properties::with_defaults(defaults);
+    /// props.set_property("port", "8080");
+    /// assert_eq!(props.get_property("port"), Some("8080"));
+    /// assert_eq!(props.get_property("host"), Some("localhost"));
+    /// assert_eq!(props.get("host"), None);
+    /// assert_eq!(props.get_property_or("user", "nobody"), "nobody");
+    /// assert_eq!(props.property_names(), vec!["host", "port"]);
+    /// ```
+    pub fn get_property(&self, key: &str) -> Option<&str> {
+        match self.get(key) {
+            Some(value) => Some(value),
+            None => self.defaults.as_ref()?.get_property(key),
+        }
+    }
+
+    /// Like [`get_property`](Self::get_property), but returns `default` if the key isn't found.
+    pub fn get_property_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get_property(key).unwrap_or(default)
+    }
+
+    /// Sets the value for a key, returning the previous value, like `setProperty` in Java.
+    ///
+    /// This is the same as [`insert`](Self::insert); the defaults are not modified.
+    pub fn set_property<K: Into<String>, V: Into<String>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Option<String> {
+        self.insert(key, value)
+    }
+
+    /// Returns the sorted list of keys, including keys only present in the chain of defaults, like
+    /// `stringPropertyNames` in Java.
+    pub fn property_names(&self) -> Vec<String> {
+        let mut names = HashSet::new();
+        let mut props = Some(self);
+        while let Some(p) = props {
+            names.extend(p.values.keys().cloned());
+            props = p.defaults();
+        }
+        let mut names: Vec<String> = names.into_iter().collect();
+        names.sort();
+        names
+    }
+
+    /// Writes the properties to a file, sorted by key, like `store` in Java.
+    ///
+    /// If `comments` is given, it is written as a comment first.  Defaults are not written.
+    pub fn store<W: Write>(
+        &self,
+        output: W,
+        comments: Option<&str>,
+    ) -> Result<(), PropertiesError> {
+        let mut writer = PropertiesWriter::new(output);
+        if let Some(comments) = comments {
+            writer.write_comment(comments)?;
+        }
+        let mut keys: Vec<_> = self.values.keys().collect();
+        keys.sort();
+        for key in keys {
+            writer.write(key, &self.values[key])?;
+        }
+        writer.finish()
+    }
+
+    /// Like [`store`](Self::store), but writes to the file at `path`, replacing it atomically.  See
+    /// [`write_file_atomically`].
+    pub fn store_to_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+        comments: Option<&str>,
+    ) -> Result<(), PropertiesError> {
+        write_file_atomically(path, &SaveOptions::new(), |writer| {
+            self.store(writer, comments)
+        })
+    }
+
+    /// Loads key/value pairs and their annotations from a properties file.
+    ///
+    /// Existing values for the same keys are replaced.
+    pub fn load<R: Read>(&mut self, input: R) -> Result<(), PropertiesError> {
+        let mut collector = MetadataCollector::default();
+        for line in PropertiesIter::new(input) {
+            let line = line?;
+            if let Some((key, metadata)) = collector.feed(&line) {
+                self.metadata.insert(key, metadata);
+            }
+            if let LineContent::KVPair(key, value) = line.consume_content() {
+                self.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn record_access(&self, key: &str) {
+        if let Some(ref accessed) = self.accessed {
+            let mut accessed = accessed.lock().unwrap_or_else(|e| e.into_inner());
+            if !accessed.contains(key) {
+                accessed.insert(key.to_string());
+            }
+        }
+        if let Some(ref handler) = self.deprecation_handler {
+            if let Some(message) = self.deprecation_of(key) {
+                // The lock is released before calling the handler, which may read these properties.
+                let first = self
+                    .deprecation_warned
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(key.to_string());
+                if first {
+                    handler(key, message);
+                }
+            }
+        }
+    }
+
+    // Finds the entry for a key, taking relaxed binding into account.
+    fn lookup(&self, key: &str) -> Option<(&str, &str)> {
+        if let Some((k, v)) = self.values.get_key_value(key) {
+            return Some((k, v));
+        }
+        if !self.relaxed_binding {
+            return None;
+        }
+        let words = split_words(key);
+        if words.is_empty() {
+            return None;
+        }
+        self.values
+            .iter()
+            .filter(|(k, _)| split_words(k) == words)
+            .min_by_key(|(k, _)| k.as_str())
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the value for the given key.
+    ///
+    /// If access tracking is enabled, the key is recorded as used.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.record_access(key);
+        let (found, value) = self.lookup(key)?;
+        if found != key {
+            self.record_access(found);
+        }
+        Some(value)
+    }
+
+    /// Returns true if the key is present.
+    ///
+    /// If access tracking is enabled, the key is recorded as used.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.record_access(key);
+        match self.lookup(key) {
+            Some((found, _)) => {
+                if found != key {
+                    self.record_access(found);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enables or disables relaxed binding for `get` and `contains_key`.
+    ///
+    /// With relaxed binding, a key which is not present exactly also matches keys with the same
+    /// words in a different style, following Spring Boot's rules: `db.pool-size` matches
+    /// `DB_POOL_SIZE`, `db.poolSize`, and `db.pool_size`.  See [`case`](crate::case) for how keys are
+    /// split into words.  If several keys match, the lexicographically smallest one is used.
+    ///
+    /// Relaxed matches scan every key, so they are slower than exact lookups.
+    pub fn set_relaxed_binding(&mut self, enabled: bool) {
+        self.relaxed_binding = enabled;
+    }
+
+    /// Returns true if relaxed binding is enabled.
+    pub fn is_relaxed_binding(&self) -> bool {
+        self.relaxed_binding
+    }
+
+    /// Sets the value for a key, returning the previous value.
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> Option<String> {
+        let key = key.into();
+        let value = value.into();
+        if self.history.is_some() && self.values.get(&key) != Some(&value) {
+            let previous = self.values.get(&key).cloned();
+            self.record_revision(&key, previous);
+        }
+        self.values.insert(key, value)
+    }
+
+    /// Returns the properties whose keys start with `prefix`, with the prefix removed, e.g. for
+    /// handing one component its own section of the configuration.
+    ///
+    /// Only this container's own properties are included, not its defaults, and the subset doesn't
+    /// share settings such as access tracking.  Copying a key doesn't count as access.
+    ///
+    /// ```
+    /// use java_properties::Properties;
+    ///
+    /// let mut props = Properties::new();
+    /// props.insert("db.url", "jdbc:h2:mem:");
+    /// props.insert("db.pool.size", "4");
+    /// props.insert("http.port", "80");
+    /// let mut db = props.subset("db.");
+    /// assert_eq!(db.len(), 2);
+    /// assert_eq!(db.get("pool.size"), Some("4"));
+    ///
+    /// db.insert("user", "sa");
+    /// props.merge_subset("db.", &db);
+    /// assert_eq!(props.get("db.user"), Some("sa"));
+    /// assert_eq!(props.len(), 4);
+    /// ```
+    pub fn subset(&self, prefix: &str) -> Properties {
+        self.values
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(prefix)
+                    .map(|rest| (rest.to_string(), value.clone()))
+            })
+            .collect()
+    }
+
+    /// Inserts every property of `subset` with `prefix` added to its key, the reverse of
+    /// [`subset`](Self::subset).  Existing keys under the prefix which aren't in `subset` are kept.
+    pub fn merge_subset(&mut self, prefix: &str, subset: &Properties) {
+        for (key, value) in &subset.values {
+            self.insert(format!("{}{}", prefix, key), value.clone());
+        }
+    }
+
+    /// Removes a key, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let value = self.values.remove(key)?;
+        if self.history.is_some() {
+            self.record_revision(key, Some(value.clone()));
+        }
+        Some(value)
+    }
+
+    fn record_revision(&mut self, key: &str, value: Option<String>) {
+        if let Some(ref mut history) = self.history {
+            let revision = Revision {
+                value,
+                sequence: history.next_sequence,
+                time: SystemTime::now(),
+            };
+            history.next_sequence += 1;
+            history
+                .revisions
+                .entry(key.to_string())
+                .or_default()
+                .push(revision);
+        }
+    }
+
+    /// Starts recording the previous value of a key each time it is changed or removed, so changes
+    /// can be shown and reverted.
+    ///
+    /// Setting a key to the value it already has isn't recorded.  Calling this when tracking is
+    /// already enabled has no effect.
+    ///
+    /// ```
+    /// use java_properties::Properties;
+    ///
+    /// let mut props = Properties::new();
+    /// props.insert("port", "80");
+    /// props.track_history();
+    /// props.insert("port", "8080");
+    /// props.insert("port", "9090");
+    /// props.insert("host", "example.com");
+    /// let values: Vec<_> = props.history("port").iter().map(|r| r.value()).collect();
+    /// assert_eq!(values, [Some("80"), Some("8080")]);
+    ///
+    /// assert!(props.revert("port"));
+    /// assert_eq!(props.get("port"), Some("8080"));
+    /// assert!(props.revert("host"));
+    /// assert_eq!(props.get("host"), None);
+    /// ```
+    pub fn track_history(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(History::default());
+        }
+    }
+
+    /// Returns true if history tracking is enabled.
+    pub fn is_tracking_history(&self) -> bool {
+        self.history.is_some()
+    }
+
+    /// Returns the previous values of a key, oldest first.  This is empty if history tracking is
+    /// not enabled.
+    pub fn history(&self, key: &str) -> &[Revision] {
+        self.history
+            .as_ref()
+            .and_then(|history| history.revisions.get(key))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Undoes the most recent change to a key, restoring the value it had before, and removes that
+    /// change from the history.  Returns false if there is no change to undo.
+    pub fn revert(&mut self, key: &str) -> bool {
+        let revision = match self
+            .history
+            .as_mut()
+            .and_then(|history| history.revisions.get_mut(key))
+            .and_then(Vec::pop)
+        {
+            Some(revision) => revision,
+            None => return false,
+        };
+        match revision.value {
+            Some(value) => self.values.insert(key.to_string(), value),
+            None => self.values.remove(key),
+        };
+        true
+    }
+
+    /// Forgets all recorded changes, keeping history tracking enabled if it is.
+    pub fn clear_history(&mut self) {
+        if let Some(ref mut history) = self.history {
+            history.revisions.clear();
+        }
+    }
+
+    /// Returns the number of properties.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if there are no properties.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Iterates over the key/value pairs in arbitrary order.
+    ///
+    /// Iteration does not count as access for tracking purposes.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            inner: self.values.iter(),
+        }
+    }
+
+    /// Returns the underlying map.
+    ///
+    /// Reading through the map does not count as access for tracking purposes.
+    pub fn as_map(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+
+    /// Returns a digest of the key/value pairs which only changes when they do.  See
+    /// [`diff::fingerprint`].
+    pub fn fingerprint(&self) -> Fingerprint {
+        diff::fingerprint(&self.values)
+    }
+
+    /// Consumes the container, returning the underlying map.
+    pub fn into_map(self) -> HashMap<String, String> {
+        self.values
+    }
+
+    /// Returns the annotation metadata for a key, if any.
+    pub fn metadata(&self, key: &str) -> Option<&KeyMetadata> {
+        self.metadata.get(key)
+    }
+
+    /// Replaces the annotation metadata for a key.
+    pub fn set_metadata<K: Into<String>>(&mut self, key: K, metadata: KeyMetadata) {
+        let key = key.into();
+        if metadata.is_empty() {
+            self.metadata.remove(&key);
+        } else {
+            self.metadata.insert(key, metadata);
+        }
+    }
+
+    /// Returns the deprecation message of a key marked with `#@deprecated`.
+    pub fn deprecation_of(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).and_then(KeyMetadata::deprecation)
+    }
+
+    /// Calls `handler` with the key and deprecation message the first time each deprecated key
+    /// is read through `get` or `contains_key`.
+    pub fn on_deprecated_access<F: Fn(&str, &str) + Send + Sync + 'static>(&mut self, handler: F) {
+        self.deprecation_handler = Some(Arc::new(handler));
+    }
+
+    /// Starts recording which keys are read through `get` and `contains_key`.
+    ///
+    /// Calling this when tracking is already enabled has no effect.
+    pub fn track_access(&mut self) {
+        if self.accessed.is_none() {
+            self.accessed = Some(Mutex::new(HashSet::new()));
+        }
+    }
+
+    /// Returns true if access tracking is enabled.
+    pub fn is_tracking_access(&self) -> bool {
+        self.accessed.is_some()
+    }
+
+    /// Returns the sorted list of keys which have been read since tracking was enabled.
+    ///
+    /// This includes keys which were looked up but not present.
+    pub fn accessed_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = match self.accessed {
+            Some(ref accessed) => accessed
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        keys.sort();
+        keys
+    }
+
+    /// Returns the sorted list of keys which have never been read since tracking was enabled.
+    ///
+    /// If tracking is not enabled, every key is returned.
+    pub fn unused_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = match self.accessed {
+            Some(ref accessed) => {
+                let accessed = accessed.lock().unwrap_or_else(|e| e.into_inner());
+                self.values
+                    .keys()
+                    .filter(|k| !accessed.contains(*k))
+                    .cloned()
+                    .collect()
+            }
+            None => self.values.keys().cloned().collect(),
+        };
+        keys.sort();
+        keys
+    }
+}
+
+impl Clone for Properties {
+    fn clone(&self) -> Self {
+        Properties {
+            values: self.values.clone(),
+            metadata: self.metadata.clone(),
+            accessed: self.accessed.as_ref().map(|accessed| {
+                Mutex::new(accessed.lock().unwrap_or_else(|e| e.into_inner()).clone())
+            }),
+            deprecation_handler: self.deprecation_handler.clone(),
+            deprecation_warned: Mutex::new(
+                self.deprecation_warned
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone(),
+            ),
+            relaxed_binding: self.relaxed_binding,
+            defaults: self.defaults.clone(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl Debug for Properties {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Properties")
+            .field("values", &self.values)
+            .field("metadata", &self.metadata)
+            .field("accessed", &self.accessed)
+            .field("defaults", &self.defaults)
+            .field("history", &self.history)
+            .finish()
+    }
+}
+
+/// Two sets of properties are equal if they contain the same key/value pairs.
+///
+/// As in Java, the defaults are not compared, and neither are metadata, access tracking, history, or
+/// other settings.
+impl PartialEq for Properties {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl Eq for Properties {}
+
+/// Hashes the key/value pairs independently of their order, consistent with `PartialEq`.
+impl Hash for Properties {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut pairs: Vec<_> = self.values.iter().collect();
+        pairs.sort_unstable();
+        pairs.hash(state);
+    }
+}
+
+impl From<HashMap<String, String>> for Properties {
+    fn from(values: HashMap<String, String>) -> Self {
+        Properties {
+            values,
+            ..Properties::default()
+        }
+    }
+}
+
+impl FromIterator<(String, String)> for Properties {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        Properties::from(iter.into_iter().collect::<HashMap<_, _>>())
+    }
+}
+
+impl Extend<(String, String)> for Properties {
+    fn extend<I: IntoIterator<Item = (String, String)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Properties {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over the key/value pairs of a [`Properties`].
+#[derive(Debug, Clone)]
+pub struct Iter<'a> {
+    inner: hash_map::Iter<'a, String, String>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Properties;
+    use crate::case::KeyCase;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[test]
+    // Access tracking is interior mutability, but it isn't part of the hash.
+    #[allow(clippy::mutable_key_type)]
+    fn eq_and_hash() {
+        use std::collections::HashSet;
+
+        let mut a = Properties::new();
+        a.insert("x", "1");
+        a.insert("y", "2");
+        let mut b = Properties::new();
+        b.track_access();
+        b.insert("y", "2");
+        b.insert("x", "1");
+        assert_eq!(a, b);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        let mut set = HashSet::new();
+        set.insert(a.clone());
+        assert!(!set.insert(b));
+        a.insert("x", "3");
+        assert_ne!(a.fingerprint(), Properties::new().fingerprint());
+        assert!(set.insert(a));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn subsets() {
+        let mut props = Properties::new();
+        props.insert("db.url", "u");
+        props.insert("db.", "empty");
+        props.insert("dbx", "no");
+        props.insert("http.port", "80");
+        props.track_access();
+        let db = props.subset("db.");
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.get(""), Some("empty"));
+        assert_eq!(db.get("url"), Some("u"));
+        assert!(props.accessed_keys().is_empty());
+        assert_eq!(props.subset(""), props);
+        assert!(props.subset("x").is_empty());
+
+        props.track_history();
+        let mut db = db;
+        db.insert("url", "v");
+        db.remove("");
+        props.merge_subset("db.", &db);
+        assert_eq!(props.get("db.url"), Some("v"));
+        assert_eq!(props.get("db."), Some("empty"));
+        assert_eq!(props.history("db.url").len(), 1);
+        assert_eq!(props.len(), 4);
+    }
+
+    #[test]
+    fn java_semantics() {
+        let mut base = Properties::new();
+        base.set_property("a", "1");
+        let mut defaults = Properties::with_defaults(base);
+        defaults.set_property("b", "2");
+        let mut props = Properties::with_defaults(defaults);
+        props.load("c=3\nb=20\n".as_bytes()).unwrap();
+        assert_eq!(props.get_property("a"), Some("1"));
+        assert_eq!(props.get_property("b"), Some("20"));
+        assert_eq!(props.get_property("d"), None);
+        assert_eq!(props.property_names(), vec!["a", "b", "c"]);
+        assert_eq!(props.len(), 2);
+
+        props.defaults_mut().unwrap().set_property("d", "4");
+        assert_eq!(props.get_property("d"), Some("4"));
+        props.set_defaults(None);
+        assert_eq!(props.get_property("d"), None);
+
+        let mut buf = Vec::new();
+        props.store(&mut buf, Some("generated")).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "# generated\nb=20\nc=3\n");
+    }
+
+    #[test]
+    fn access_tracking() {
+        let mut props = Properties::new();
+        props.insert("a", "1");
+        props.insert("b", "2");
+        props.insert("c", "3");
+        assert_eq!(props.unused_keys(), vec!["a", "b", "c"]);
+        assert!(props.accessed_keys().is_empty());
+
+        props.track_access();
+        assert_eq!(props.get("a"), Some("1"));
+        assert!(props.contains_key("c"));
+        assert_eq!(props.get("missing"), None);
+        assert_eq!(props.iter().count(), 3);
+        assert_eq!(props.unused_keys(), vec!["b"]);
+        assert_eq!(props.accessed_keys(), vec!["a", "c", "missing"]);
+
+        let copy = props.clone();
+        assert_eq!(copy.unused_keys(), vec!["b"]);
+    }
+
+    #[test]
+    fn deprecation() {
+        let mut props = Properties::new();
+        props
+            .load("#@deprecated use b\na=1\nb=2\n".as_bytes())
+            .unwrap();
+        assert_eq!(props.deprecation_of("a"), Some("use b"));
+        assert_eq!(props.deprecation_of("b"), None);
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        props.on_deprecated_access(move |key, message| {
+            sink.lock().unwrap().push(format!("{}: {}", key, message));
+        });
+        assert_eq!(props.get("a"), Some("1"));
+        assert_eq!(props.get("a"), Some("1"));
+        assert_eq!(props.get("b"), Some("2"));
+        assert_eq!(*warnings.lock().unwrap(), vec!["a: use b".to_string()]);
+    }
+
+    #[test]
+    fn relaxed_binding() {
+        let mut props = Properties::new();
+        props.insert("DB_POOL_SIZE", "10");
+        props.insert("server.connectTimeout", "5");
+        assert_eq!(props.get("db.pool-size"), None);
+
+        props.set_relaxed_binding(true);
+        props.track_access();
+        assert_eq!(props.get("db.pool-size"), Some("10"));
+        assert_eq!(props.get("db.poolSize"), Some("10"));
+        assert_eq!(props.get("server.connect-timeout"), Some("5"));
+        assert!(props.contains_key("SERVER_CONNECT_TIMEOUT"));
+        assert_eq!(props.get("db.pool"), None);
+        assert!(props.unused_keys().is_empty());
+
+        props.insert("db.pool-size", "20");
+        assert_eq!(props.get("db.pool-size"), Some("20"));
+    }
+
+    #[test]
+    fn history() {
+        let mut props = Properties::new();
+        props.insert("a", "1");
+        props.insert("a", "2");
+        assert!(!props.is_tracking_history());
+        assert!(props.history("a").is_empty());
+        assert!(!props.revert("a"));
+
+        props.track_history();
+        props.insert("a", "3");
+        props.insert("a", "3");
+        props.load("b=1\na=4\n".as_bytes()).unwrap();
+        props.extend(vec![("c".to_string(), "1".to_string())]);
+        assert_eq!(props.remove("b"), Some("1".to_string()));
+        assert_eq!(props.remove("b"), None);
+
+        let a = props.history("a");
+        assert_eq!(
+            a.iter().map(|r| r.value()).collect::<Vec<_>>(),
+            [Some("2"), Some("3")]
+        );
+        assert_eq!(
+            props
+                .history("b")
+                .iter()
+                .map(|r| r.value())
+                .collect::<Vec<_>>(),
+            [None, Some("1")]
+        );
+        assert_eq!(
+            [&a[0], &a[1], &props.history("b")[0], &props.history("b")[1]]
+                .iter()
+                .map(|r| r.sequence())
+                .collect::<Vec<_>>(),
+            [0, 2, 1, 4]
+        );
+        assert!(a[0].time() <= a[1].time());
+
+        let copy = props.clone();
+        assert!(props.revert("b"));
+        assert_eq!(props.get("b"), Some("1"));
+        assert!(props.revert("b"));
+        assert_eq!(props.get("b"), None);
+        assert!(!props.revert("b"));
+        assert!(props.revert("a"));
+        assert_eq!(props.get("a"), Some("3"));
+        assert_eq!(props.history("a").len(), 1);
+        assert_eq!(copy.history("a").len(), 2);
+
+        props.clear_history();
+        assert!(props.history("a").is_empty());
+        props.insert("a", "5");
+        assert_eq!(props.history("a")[0].sequence(), 5);
+    }
+
+    #[test]
+    fn from_env_vars() {
+        let vars = vec![
+            ("MYAPP_DB_URL", "jdbc:x"),
+            ("MYAPP_POOL_SIZE", "10"),
+            ("MYAPP_", "ignored"),
+            ("OTHER", "ignored"),
+        ];
+        let props = Properties::from_env_vars(vars.clone(), "MYAPP_", Some(KeyCase::Dot));
+        assert_eq!(props.len(), 2);
+        assert_eq!(props.get("db.url"), Some("jdbc:x"));
+        assert_eq!(props.get("pool.size"), Some("10"));
+
+        let props = Properties::from_env_vars(vars, "MYAPP_", None);
+        assert_eq!(props.get("DB_URL"), Some("jdbc:x"));
+
+        let props = Properties::from_env("JAVA_PROPERTIES_TEST_UNLIKELY_PREFIX_", None);
+        assert!(props.is_empty());
+    }
+}
@@ -0,0 +1,448 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Schemas describing the keys and value types of a properties file.
+//!
+//! A schema is usually generated from a reference file using the annotation comments described in
+//! [`metadata`](crate::metadata), so that validation stays in sync with the documented defaults.
+
+use crate::metadata::KeyMetadata;
+use crate::metadata::MetadataCollector;
+use crate::metadata::ValueType;
+use crate::LineContent;
+use crate::PropertiesError;
+use crate::PropertiesIter;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Write as FmtWrite;
+use std::io::Read;
+
+/// The description of a single key in a [`Schema`].
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct SchemaEntry {
+    key: String,
+    value_type: ValueType,
+    default: Option<String>,
+    doc: Option<String>,
+    deprecated: Option<String>,
+}
+
+impl SchemaEntry {
+    /// Creates an entry with the given key and type.
+    pub fn new<S: Into<String>>(key: S, value_type: ValueType) -> Self {
+        SchemaEntry {
+            key: key.into(),
+            value_type,
+            default: None,
+            doc: None,
+            deprecated: None,
+        }
+    }
+
+    fn from_metadata(key: &str, metadata: &KeyMetadata) -> Self {
+        SchemaEntry {
+            key: key.to_string(),
+            value_type: metadata.value_type().cloned().unwrap_or(ValueType::String),
+            default: metadata.default_value().map(str::to_string),
+            doc: metadata.doc().map(str::to_string),
+            deprecated: metadata.deprecation().map(str::to_string),
+        }
+    }
+
+    /// Returns the key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the type of the value.  Keys without a `#@type` annotation are strings.
+    pub fn value_type(&self) -> &ValueType {
+        &self.value_type
+    }
+
+    /// Returns the default value, if any.
+    pub fn default_value(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    /// Returns the documentation, if any.
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    /// Returns the deprecation message, if the key is deprecated.
+    pub fn deprecation(&self) -> Option<&str> {
+        self.deprecated.as_deref()
+    }
+}
+
+/// The set of keys known for a kind of properties file, along with their types.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Hash)]
+pub struct Schema {
+    entries: BTreeMap<String, SchemaEntry>,
+}
+
+impl Schema {
+    /// Creates an empty schema.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generates a schema from an annotated reference file.
+    ///
+    /// Every key in the file becomes part of the schema, whether or not it is annotated.
+    pub fn from_reader<R: Read>(input: R) -> Result<Self, PropertiesError> {
+        let mut collector = MetadataCollector::default();
+        let mut schema = Schema::new();
+        for line in PropertiesIter::new(input) {
+            let line = line?;
+            let metadata = collector.feed(&line);
+            if let LineContent::KVPair(ref key, _) = *line.content() {
+                let entry = match metadata {
+                    Some((_, ref metadata)) => SchemaEntry::from_metadata(key, metadata),
+                    None => SchemaEntry::new(key.as_str(), ValueType::String),
+                };
+                schema.insert(entry);
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Generates a schema from already collected metadata.
+    pub fn from_metadata(metadata: &HashMap<String, KeyMetadata>) -> Self {
+        let mut schema = Schema::new();
+        for (key, metadata) in metadata {
+            schema.insert(SchemaEntry::from_metadata(key, metadata));
+        }
+        schema
+    }
+
+    /// Adds an entry, replacing any existing entry for the same key.
+    pub fn insert(&mut self, entry: SchemaEntry) {
+        self.entries.insert(entry.key.clone(), entry);
+    }
+
+    /// Returns the entry for a key.
+    pub fn entry(&self, key: &str) -> Option<&SchemaEntry> {
+        self.entries.get(key)
+    }
+
+    /// Iterates over the entries, sorted by key.
+    pub fn entries(&self) -> impl Iterator<Item = &SchemaEntry> {
+        self.entries.values()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the schema has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Renders the schema as a [JSON Schema](https://json-schema.org/) document describing an object
+    /// whose properties are the schema's keys.
+    pub fn to_json_schema(&self) -> String {
+        let mut out = String::new();
+        out.push_str("{\n");
+        out.push_str("  \"$schema\": \"https://json-schema.org/draft/2020-12/schema\",\n");
+        out.push_str("  \"type\": \"object\",\n");
+        out.push_str("  \"properties\": {");
+        for (i, entry) in self.entries.values().enumerate() {
+            out.push_str(if i == 0 { "\n" } else { ",\n" });
+            let mut fields = Vec::new();
+            match entry.value_type {
+                ValueType::String | ValueType::Other(_) => {
+                    fields.push("\"type\": \"string\"".to_string())
+                }
+                ValueType::Integer => fields.push("\"type\": \"integer\"".to_string()),
+                ValueType::Float => fields.push("\"type\": \"number\"".to_string()),
+                ValueType::Boolean => fields.push("\"type\": \"boolean\"".to_string()),
+                ValueType::Enum(ref variants) => {
+                    let variants: Vec<String> = variants.iter().map(|v| json_string(v)).collect();
+                    fields.push(format!("\"enum\": [{}]", variants.join(", ")));
+                }
+            }
+            if let Some(ref default) = entry.default {
+                fields.push(format!(
+                    "\"default\": {}",
+                    json_typed_value(&entry.value_type, default)
+                ));
+            }
+            if let Some(ref doc) = entry.doc {
+                fields.push(format!("\"description\": {}", json_string(doc)));
+            }
+            if entry.deprecated.is_some() {
+                fields.push("\"deprecated\": true".to_string());
+            }
+            let _ = write!(
+                out,
+                "    {}: {{{}}}",
+                json_string(&entry.key),
+                fields.join(", ")
+            );
+        }
+        if !self.entries.is_empty() {
+            out.push_str("\n  ");
+        }
+        out.push_str("},\n");
+        out.push_str("  \"additionalProperties\": false\n");
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// A value which does not match its declared type.
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct Violation {
+    key: String,
+    value: String,
+    line_number: usize,
+    expected: ValueType,
+}
+
+impl Violation {
+    /// Returns the key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the offending value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns the 1-based line number of the key/value pair.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Returns the declared type.
+    pub fn expected(&self) -> &ValueType {
+        &self.expected
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "value {:?} for key {:?} is not a valid {} (line_number = {})",
+            self.value, self.key, self.expected, self.line_number
+        )
+    }
+}
+
+/// The result of a type-checked read.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct CheckedRead {
+    values: HashMap<String, String>,
+    violations: Vec<Violation>,
+}
+
+impl CheckedRead {
+    /// Returns all key/value pairs, including ones with violations.
+    pub fn values(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+
+    /// Consumes the result, returning all key/value pairs.
+    pub fn into_values(self) -> HashMap<String, String> {
+        self.values
+    }
+
+    /// Returns the violations in file order.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Returns true if no violations were found.
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl Schema {
+    /// Reads a properties file, checking every value against its declared type.
+    ///
+    /// Types come from the schema, or from `#@type` annotations in the file itself for keys the schema
+    /// does not know about.  All violations are reported, not just the first one.
+    /// An error is only returned if the file cannot be parsed.
+    pub fn read_checked<R: Read>(&self, input: R) -> Result<CheckedRead, PropertiesError> {
+        read_checked_impl(input, Some(self))
+    }
+}
+
+/// Reads a properties file, checking every value against the type declared by its `#@type` annotation.
+///
+/// All violations are reported, not just the first one.
+/// An error is only returned if the file cannot be parsed.
+pub fn read_checked<R: Read>(input: R) -> Result<CheckedRead, PropertiesError> {
+    read_checked_impl(input, None)
+}
+
+fn read_checked_impl<R: Read>(
+    input: R,
+    schema: Option<&Schema>,
+) -> Result<CheckedRead, PropertiesError> {
+    let mut collector = MetadataCollector::default();
+    let mut result = CheckedRead::default();
+    for line in PropertiesIter::new(input) {
+        let line = line?;
+        let line_number = line.line_number();
+        let metadata = collector.feed(&line);
+        if let LineContent::KVPair(key, value) = line.consume_content() {
+            let declared = schema
+                .and_then(|s| s.entry(&key))
+                .map(SchemaEntry::value_type)
+                .or_else(|| metadata.as_ref().and_then(|(_, m)| m.value_type()));
+            if let Some(expected) = declared {
+                if !expected.accepts(&value) {
+                    result.violations.push(Violation {
+                        key: key.clone(),
+                        value: value.clone(),
+                        line_number,
+                        expected: expected.clone(),
+                    });
+                }
+            }
+            result.values.insert(key, value);
+        }
+    }
+    Ok(result)
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_typed_value(value_type: &ValueType, value: &str) -> String {
+    let typed = match *value_type {
+        ValueType::Integer => value.trim().parse::<i64>().is_ok(),
+        ValueType::Float => value.trim().parse::<f64>().is_ok_and(f64::is_finite),
+        ValueType::Boolean => value == "true" || value == "false",
+        _ => false,
+    };
+    if typed {
+        value.trim().to_string()
+    } else {
+        json_string(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_checked;
+    use super::Schema;
+    use super::SchemaEntry;
+    use crate::metadata::ValueType;
+
+    #[test]
+    fn from_reader() {
+        let input = "
+#@doc The \"port\"
+#@type int
+#@default 8080
+port=8080
+#@type enum(a|b)
+#@deprecated
+mode=a
+name=x
+";
+        let schema = Schema::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(schema.len(), 3);
+        let port = schema.entry("port").unwrap();
+        assert_eq!(port.value_type(), &ValueType::Integer);
+        assert_eq!(port.default_value(), Some("8080"));
+        assert_eq!(port.doc(), Some("The \"port\""));
+        assert_eq!(schema.entry("mode").unwrap().deprecation(), Some(""));
+        assert_eq!(
+            schema.entry("name").unwrap().value_type(),
+            &ValueType::String
+        );
+        let keys: Vec<&str> = schema.entries().map(|e| e.key()).collect();
+        assert_eq!(keys, vec!["mode", "name", "port"]);
+
+        assert_eq!(
+            schema.to_json_schema(),
+            r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "type": "object",
+  "properties": {
+    "mode": {"enum": ["a", "b"], "deprecated": true},
+    "name": {"type": "string"},
+    "port": {"type": "integer", "default": 8080, "description": "The \"port\""}
+  },
+  "additionalProperties": false
+}
+"#
+        );
+    }
+
+    #[test]
+    fn empty_json_schema() {
+        assert_eq!(
+            Schema::new().to_json_schema(),
+            r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "type": "object",
+  "properties": {},
+  "additionalProperties": false
+}
+"#
+        );
+    }
+
+    #[test]
+    fn checked_read() {
+        let input = "
+#@type int
+port=80x
+#@type bool
+debug=TRUE
+#@type enum(a|b)
+mode=c
+#@type float
+ratio=0.5
+name=anything
+";
+        let result = read_checked(input.as_bytes()).unwrap();
+        assert!(!result.is_valid());
+        let found: Vec<(&str, usize)> = result
+            .violations()
+            .iter()
+            .map(|v| (v.key(), v.line_number()))
+            .collect();
+        assert_eq!(found, vec![("port", 3), ("mode", 7)]);
+        assert_eq!(result.values().len(), 5);
+        assert_eq!(
+            result.violations()[0].to_string(),
+            "value \"80x\" for key \"port\" is not a valid int (line_number = 3)"
+        );
+
+        let mut schema = Schema::new();
+        schema.insert(SchemaEntry::new("name", ValueType::Integer));
+        schema.insert(SchemaEntry::new("port", ValueType::String));
+        let result = schema.read_checked(input.as_bytes()).unwrap();
+        let found: Vec<&str> = result.violations().iter().map(|v| v.key()).collect();
+        assert_eq!(found, vec!["mode", "name"]);
+    }
+}
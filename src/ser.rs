@@ -0,0 +1,778 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Serializing Rust values as properties files with serde.
+//!
+//! Nested structs and maps become dotted keys, and sequences and tuples are indexed with `.i`
+//! (`servers.0.host`), as in [`tree`](crate::tree) and the JSON, TOML, and YAML conversions.
+//! `None` values are omitted, enum variants without data are written as their name, and variants
+//! with data are nested under their name.  The top-level value must be a struct or map.
+//!
+//! This module is only available with the `serde` feature.
+//!
+//! ```
+//! use serde::Serialize;
+//!
+//! #[derive(Serialize)]
+//! struct Database {
+//!     url: String,
+//!     pool_size: u32,
+//! }
+//!
+//! #[derive(Serialize)]
+//! struct Config {
+//!     name: String,
+//!     database: Database,
+//! }
+//!
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let config = Config {
+//!     name: "demo".to_string(),
+//!     database: Database {
+//!         url: "jdbc:h2:mem:".to_string(),
+//!         pool_size: 4,
+//!     },
+//! };
+//! let mut buf = Vec::new();
+//! java_properties::ser::to_writer(&mut buf, &config)?;
+//! assert_eq!(
+//!     String::from_utf8(buf).unwrap(),
+//!     "name=demo\ndatabase.url=jdbc\\:h2\\:mem\\:\ndatabase.pool_size=4\n"
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use crate::PropertiesWriter;
+use serde::ser;
+use serde::ser::Impossible;
+use serde::Serialize;
+use std::fmt::Display;
+use std::io::Write;
+
+impl ser::Error for PropertiesError {
+    fn custom<T: Display>(msg: T) -> Self {
+        PropertiesError::new(PropertiesErrorKind::Serialize, msg.to_string(), None, None)
+    }
+}
+
+/// Serializes a value and writes it to a properties file in ISO-8859-1.
+///
+/// Keys are written in the order they are serialized.
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<(), PropertiesError>
+where
+    W: Write,
+    T: Serialize + ?Sized,
+{
+    let mut writer = PropertiesWriter::new(writer);
+    to_properties_writer(&mut writer, value)?;
+    writer.finish()
+}
+
+/// Serializes a value and writes it through an existing `PropertiesWriter`, using its encoding,
+/// separator, and line ending.
+///
+/// The writer is not finished, so more pairs or comments may be written afterwards.
+pub fn to_properties_writer<W, T>(
+    writer: &mut PropertiesWriter<W>,
+    value: &T,
+) -> Result<(), PropertiesError>
+where
+    W: Write,
+    T: Serialize + ?Sized,
+{
+    for (key, value) in to_pairs(value)? {
+        writer.write(&key, &value)?;
+    }
+    Ok(())
+}
+
+/// Serializes a value into flattened key/value pairs, in the order they are serialized.
+pub fn to_pairs<T: Serialize + ?Sized>(
+    value: &T,
+) -> Result<Vec<(String, String)>, PropertiesError> {
+    let mut pairs = Vec::new();
+    value.serialize(Serializer {
+        pairs: &mut pairs,
+        key: None,
+    })?;
+    Ok(pairs)
+}
+
+fn join(prefix: &Option<String>, key: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}.{}", prefix, key),
+        None => key.to_string(),
+    }
+}
+
+struct Serializer<'a> {
+    pairs: &'a mut Vec<(String, String)>,
+    key: Option<String>,
+}
+
+impl<'a> Serializer<'a> {
+    fn scalar<T: Display>(self, value: T) -> Result<(), PropertiesError> {
+        match self.key {
+            Some(key) => {
+                self.pairs.push((key, value.to_string()));
+                Ok(())
+            }
+            None => Err(PropertiesError::new(
+                PropertiesErrorKind::Serialize,
+                "The top-level value must be a struct or map",
+                None,
+                None,
+            )),
+        }
+    }
+
+    fn nested(self, name: &str) -> Serializer<'a> {
+        Serializer {
+            key: Some(join(&self.key, name)),
+            pairs: self.pairs,
+        }
+    }
+
+    fn compound(self) -> Compound<'a> {
+        Compound {
+            pairs: self.pairs,
+            prefix: self.key,
+            index: 0,
+            pending_key: None,
+        }
+    }
+
+    fn indexed(self) -> Result<Compound<'a>, PropertiesError> {
+        if self.key.is_none() {
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::Serialize,
+                "The top-level value must be a struct or map",
+                None,
+                None,
+            ));
+        }
+        Ok(self.compound())
+    }
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = ();
+    type Error = PropertiesError;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), PropertiesError> {
+        self.scalar(v)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), PropertiesError> {
+        Err(PropertiesError::new(
+            PropertiesErrorKind::Serialize,
+            "Byte arrays cannot be serialized as properties",
+            None,
+            None,
+        ))
+    }
+
+    fn serialize_none(self) -> Result<(), PropertiesError> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), PropertiesError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), PropertiesError> {
+        self.scalar("")
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), PropertiesError> {
+        self.scalar("")
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), PropertiesError> {
+        self.scalar(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), PropertiesError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), PropertiesError> {
+        value.serialize(self.nested(variant))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>, PropertiesError> {
+        self.indexed()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a>, PropertiesError> {
+        self.indexed()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, PropertiesError> {
+        self.indexed()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, PropertiesError> {
+        Ok(self.nested(variant).compound())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a>, PropertiesError> {
+        Ok(self.compound())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, PropertiesError> {
+        Ok(self.compound())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, PropertiesError> {
+        Ok(self.nested(variant).compound())
+    }
+}
+
+struct Compound<'a> {
+    pairs: &'a mut Vec<(String, String)>,
+    prefix: Option<String>,
+    index: usize,
+    pending_key: Option<String>,
+}
+
+impl Compound<'_> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PropertiesError> {
+        // The prefix is always set for sequences; see `Serializer::indexed`.
+        let key = join(&self.prefix, &self.index.to_string());
+        self.index += 1;
+        value.serialize(Serializer {
+            pairs: self.pairs,
+            key: Some(key),
+        })
+    }
+
+    fn field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &str,
+        value: &T,
+    ) -> Result<(), PropertiesError> {
+        value.serialize(Serializer {
+            key: Some(join(&self.prefix, key)),
+            pairs: self.pairs,
+        })
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_> {
+    type Ok = ();
+    type Error = PropertiesError;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), PropertiesError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), PropertiesError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_> {
+    type Ok = ();
+    type Error = PropertiesError;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), PropertiesError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), PropertiesError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_> {
+    type Ok = ();
+    type Error = PropertiesError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PropertiesError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), PropertiesError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_> {
+    type Ok = ();
+    type Error = PropertiesError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PropertiesError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), PropertiesError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for Compound<'_> {
+    type Ok = ();
+    type Error = PropertiesError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), PropertiesError> {
+        self.pending_key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PropertiesError> {
+        let key = self.pending_key.take().ok_or_else(|| {
+            PropertiesError::new(
+                PropertiesErrorKind::Serialize,
+                "Map value serialized without a key",
+                None,
+                None,
+            )
+        })?;
+        self.field(&key, value)
+    }
+
+    fn end(self) -> Result<(), PropertiesError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Ok = ();
+    type Error = PropertiesError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), PropertiesError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), PropertiesError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Ok = ();
+    type Error = PropertiesError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), PropertiesError> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), PropertiesError> {
+        Ok(())
+    }
+}
+
+/// Serializes map keys, which must be strings, characters, integers, booleans, or unit variants.
+struct KeySerializer;
+
+fn bad_key() -> PropertiesError {
+    PropertiesError::new(
+        PropertiesErrorKind::Serialize,
+        "Map keys must be strings, characters, integers, or booleans",
+        None,
+        None,
+    )
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = PropertiesError;
+    type SerializeSeq = Impossible<String, PropertiesError>;
+    type SerializeTuple = Impossible<String, PropertiesError>;
+    type SerializeTupleStruct = Impossible<String, PropertiesError>;
+    type SerializeTupleVariant = Impossible<String, PropertiesError>;
+    type SerializeMap = Impossible<String, PropertiesError>;
+    type SerializeStruct = Impossible<String, PropertiesError>;
+    type SerializeStructVariant = Impossible<String, PropertiesError>;
+
+    fn serialize_bool(self, v: bool) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, PropertiesError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_none(self) -> Result<String, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<String, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_unit(self) -> Result<String, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String, PropertiesError> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, PropertiesError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, PropertiesError> {
+        Err(bad_key())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, PropertiesError> {
+        Err(bad_key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_pairs;
+    use super::to_writer;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    enum Mode {
+        Fast,
+        Custom { level: u8 },
+    }
+
+    #[derive(Serialize)]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Serialize)]
+    struct Config {
+        name: String,
+        debug: bool,
+        ratio: f64,
+        timeout: Option<u32>,
+        retries: Option<u32>,
+        mode: Mode,
+        fallback: Mode,
+        servers: Vec<Server>,
+        labels: BTreeMap<String, String>,
+    }
+
+    #[test]
+    fn flatten() {
+        let mut labels = BTreeMap::new();
+        labels.insert("team".to_string(), "core".to_string());
+        labels.insert("tier=1".to_string(), "gold".to_string());
+        let config = Config {
+            name: "demo app".to_string(),
+            debug: true,
+            ratio: 0.5,
+            timeout: None,
+            retries: Some(3),
+            mode: Mode::Fast,
+            fallback: Mode::Custom { level: 2 },
+            servers: vec![
+                Server {
+                    host: "a".to_string(),
+                    port: 80,
+                },
+                Server {
+                    host: "b".to_string(),
+                    port: 81,
+                },
+            ],
+            labels,
+        };
+        let pairs = to_pairs(&config).unwrap();
+        let pairs: Vec<(&str, &str)> = pairs
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("name", "demo app"),
+                ("debug", "true"),
+                ("ratio", "0.5"),
+                ("retries", "3"),
+                ("mode", "Fast"),
+                ("fallback.Custom.level", "2"),
+                ("servers.0.host", "a"),
+                ("servers.0.port", "80"),
+                ("servers.1.host", "b"),
+                ("servers.1.port", "81"),
+                ("labels.team", "core"),
+                ("labels.tier=1", "gold"),
+            ]
+        );
+
+        let mut map = BTreeMap::new();
+        map.insert("a b", "\u{e9}=");
+        map.insert("c", "d");
+        let mut buf = Vec::new();
+        to_writer(&mut buf, &map).unwrap();
+        assert_eq!(buf, b"a\\ b=\xe9\\=\nc=d\n");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(to_pairs(&5).is_err());
+        assert!(to_pairs(&vec![1, 2]).is_err());
+        let mut map = BTreeMap::new();
+        map.insert(vec![1], 2);
+        assert!(to_pairs(&map).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn to_json() {
+        let mut map = BTreeMap::new();
+        map.insert(
+            "servers",
+            vec![
+                Server {
+                    host: "a".to_string(),
+                    port: 80,
+                },
+                Server {
+                    host: "b".to_string(),
+                    port: 81,
+                },
+            ],
+        );
+        let pairs = to_pairs(&map).unwrap().into_iter().collect();
+        assert_eq!(
+            crate::json::to_json_value(&pairs).unwrap(),
+            serde_json::json!({"servers": [{"host": "a", "port": "80"}, {"host": "b", "port": "81"}]})
+        );
+    }
+}
@@ -0,0 +1,91 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Rendering properties as POSIX shell scripts.
+
+use crate::case::KeyCase;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::io::Write;
+
+fn is_shell_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Writes key/value pairs as a POSIX shell snippet of `export KEY='value'` lines, in the order given.
+///
+/// Keys are converted to `case` if given; [`KeyCase::ScreamingSnake`] is the usual choice, turning
+/// `db.url` into `DB_URL`.  The resulting names must be valid shell variable names, or an error is returned.
+/// Values are always single-quoted, so they are never subject to expansion.  Output is UTF-8 with `\n` line endings.
+pub fn write_shell_exports<W, I, K, V>(
+    mut writer: W,
+    pairs: I,
+    case: Option<KeyCase>,
+) -> Result<(), PropertiesError>
+where
+    W: Write,
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    for (key, value) in pairs {
+        let name = match case {
+            Some(case) => case.convert(key.as_ref()),
+            None => key.as_ref().to_string(),
+        };
+        if !is_shell_name(&name) {
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::InvalidKey,
+                format!(
+                    "Key {:?} is not a valid shell variable name: {:?}",
+                    key.as_ref(),
+                    name
+                ),
+                None,
+                None,
+            ));
+        }
+        writeln!(
+            writer,
+            "export {}='{}'",
+            name,
+            value.as_ref().replace('\'', "'\\''")
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_shell_exports;
+    use crate::case::KeyCase;
+
+    #[test]
+    fn exports() {
+        let mut buf = Vec::new();
+        write_shell_exports(
+            &mut buf,
+            vec![
+                ("db.url", "jdbc:x?a=1&b=2"),
+                ("greeting", "it's $HOME"),
+                ("multi.line", "a\nb"),
+            ],
+            Some(KeyCase::ScreamingSnake),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "export DB_URL='jdbc:x?a=1&b=2'\nexport GREETING='it'\\''s $HOME'\nexport MULTI_LINE='a\nb'\n"
+        );
+
+        let mut buf = Vec::new();
+        assert!(write_shell_exports(&mut buf, vec![("db.url", "x")], None).is_err());
+        assert!(write_shell_exports(&mut buf, vec![("1abc", "x")], None).is_err());
+        assert!(write_shell_exports(&mut buf, vec![("_ok1", "x")], None).is_ok());
+    }
+}
@@ -0,0 +1,148 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Converting properties to and from TOML.
+//!
+//! Keys are nested and flattened with the same rules as in [`json`](crate::json): dotted keys
+//! become tables, keys continuing with the indexes `0`, `1`, ... become arrays, and values are
+//! always strings.  Going the other way, numbers, booleans, and dates are written as their TOML
+//! text.  The top level of a TOML document is always a table, even if the keys are list indexes.
+//!
+//! This module is only available with the `toml` feature.
+//!
+//! ```
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let map = java_properties::read("name=app\ndb.url=jdbc:h2:mem:\ndb.pool=4\n".as_bytes())?;
+//! let toml = java_properties::toml::to_toml(&map)?;
+//! assert_eq!(toml, "name = \"app\"\n\n[db]\npool = \"4\"\nurl = \"jdbc:h2:mem:\"\n");
+//! assert_eq!(java_properties::toml::from_toml(&toml)?, map);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::tree;
+use crate::tree::Nested;
+use crate::tree::View;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::collections::HashMap;
+use toml::Table;
+use toml::Value;
+
+/// Converts properties to a TOML document.
+///
+/// Returns an error of kind [`PropertiesErrorKind::KeyConflict`] if a key has a value and also has
+/// keys under it, since TOML can't represent both.  See [`tree::to_tree`].
+pub fn to_toml(map: &HashMap<String, String>) -> Result<String, PropertiesError> {
+    let root = tree::to_tree(map)?;
+    // The top level is a table even if the keys are list indexes.
+    let table = root
+        .children()
+        .iter()
+        .map(|(name, child)| (name.clone(), tree::to_nested(child)))
+        .collect::<Table>();
+    toml::to_string(&table).map_err(|e| {
+        PropertiesError::new(
+            PropertiesErrorKind::Serialize,
+            "Unable to write the properties as TOML",
+            Some(Box::new(e)),
+            None,
+        )
+    })
+}
+
+/// Parses a TOML document and flattens it into properties with dotted keys.
+///
+/// Returns an error of kind [`PropertiesErrorKind::Syntax`] if the document isn't valid TOML.
+pub fn from_toml(input: &str) -> Result<HashMap<String, String>, PropertiesError> {
+    let table = input.parse::<Table>().map_err(|e| {
+        PropertiesError::new(
+            PropertiesErrorKind::Syntax,
+            "Unable to parse TOML",
+            Some(Box::new(e)),
+            None,
+        )
+    })?;
+    tree::flatten(&Value::Table(table))
+}
+
+impl Nested for Value {
+    fn string(value: String) -> Self {
+        Value::String(value)
+    }
+
+    fn list(items: Vec<Self>) -> Self {
+        Value::Array(items)
+    }
+
+    fn map(entries: Vec<(String, Self)>) -> Self {
+        Value::Table(entries.into_iter().collect())
+    }
+
+    fn view(&self) -> Result<View<'_, Self>, PropertiesError> {
+        Ok(match self {
+            Value::String(s) => View::Scalar(s.clone()),
+            Value::Array(items) => View::List(items),
+            Value::Table(fields) => View::Map(fields.iter().map(|(k, v)| (k.clone(), v)).collect()),
+            Value::Datetime(d) => View::Scalar(d.to_string()),
+            scalar => View::Scalar(scalar.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_toml;
+    use super::to_toml;
+    use crate::tree::map;
+    use crate::PropertiesErrorKind;
+    use std::collections::HashMap;
+
+    #[test]
+    fn round_trip() {
+        let flat = map(&[
+            ("name", "app"),
+            ("servers.0.host", "a"),
+            ("servers.1.host", "b"),
+            ("tags.0", "x"),
+            ("tags.1", "y"),
+            ("odd key.", "z"),
+        ]);
+        let toml = to_toml(&flat).unwrap();
+        assert_eq!(
+            toml,
+            "name = \"app\"\ntags = [\"x\", \"y\"]\n\n[\"odd key\"]\n\"\" = \"z\"\n\n\
+             [[servers]]\nhost = \"a\"\n\n[[servers]]\nhost = \"b\"\n"
+        );
+        assert_eq!(from_toml(&toml).unwrap(), flat);
+
+        let list = map(&[("0", "a"), ("1", "b")]);
+        assert_eq!(to_toml(&list).unwrap(), "0 = \"a\"\n1 = \"b\"\n");
+        assert_eq!(to_toml(&HashMap::new()).unwrap(), "");
+    }
+
+    #[test]
+    fn from_toml_values() {
+        let input =
+            "n = 1.0\ni = 2\nb = true\nd = 1979-05-27\nempty = []\n[t.u]\nv = [1, { w = 'x' }]\n";
+        assert_eq!(
+            from_toml(input).unwrap(),
+            map(&[
+                ("n", "1.0"),
+                ("i", "2"),
+                ("b", "true"),
+                ("d", "1979-05-27"),
+                ("t.u.v.0", "1"),
+                ("t.u.v.1.w", "x"),
+            ])
+        );
+        assert_eq!(
+            from_toml("a = ").unwrap_err().kind(),
+            PropertiesErrorKind::Syntax
+        );
+        assert_eq!(
+            to_toml(&map(&[("a", "1"), ("a.b", "2")]))
+                .unwrap_err()
+                .kind(),
+            PropertiesErrorKind::KeyConflict
+        );
+    }
+}
@@ -0,0 +1,342 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Nested views of dotted keys.
+//!
+//! Properties are flat, but dotted keys usually describe a hierarchy: `db.url` and `db.user` are
+//! both settings of `db`.  [`to_tree`] turns the keys into a tree of [`Node`]s, one per segment,
+//! which suits conversion to nested formats, and [`from_tree`] turns a tree back into flat keys.
+//!
+//! A key such as `a.b` which has a value and also has keys under it, such as `a.b.c`, is a
+//! conflict: nested formats can't give one name both a value and children.  [`to_tree`] reports
+//! conflicts as errors, while [`Node::from_map`] keeps both.
+//!
+//! ```
+//! use std::collections::HashMap;
+//!
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let map = java_properties::read("db.url=jdbc:h2:mem:\ndb.user=sa\nname=app\n".as_bytes())?;
+//! let tree = java_properties::tree::to_tree(&map)?;
+//! let db = tree.child("db").unwrap();
+//! assert_eq!(db.children().keys().collect::<Vec<_>>(), ["url", "user"]);
+//! assert_eq!(tree.get("db.user").and_then(|node| node.value()), Some("sa"));
+//! assert_eq!(java_properties::tree::from_tree(&tree), map);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+/// A segment of a dotted key, with the value of the key ending there, if any, and the segments
+/// which follow it.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Node {
+    value: Option<String>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    /// Creates an empty node.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a tree from flat keys, keeping keys which conflict.  See [`to_tree`].
+    ///
+    /// Keys are split at every `.`, so empty segments, as in `a..b` or `a.`, become children with
+    /// empty names, and the tree always converts back to the same keys.
+    pub fn from_map(map: &HashMap<String, String>) -> Self {
+        let mut root = Node::new();
+        for (key, value) in map {
+            root.insert(key, value.clone());
+        }
+        root
+    }
+
+    /// Sets the value of a dotted key below this node, returning the previous value.
+    pub fn insert(&mut self, key: &str, value: String) -> Option<String> {
+        let node = key.split('.').fold(self, |node, segment| {
+            node.children.entry(segment.to_string()).or_default()
+        });
+        node.value.replace(value)
+    }
+
+    /// Returns the value of the key ending at this node, if any.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// Returns the nodes for the next segment of the keys, by segment.
+    pub fn children(&self) -> &BTreeMap<String, Node> {
+        &self.children
+    }
+
+    /// Returns the node for the next segment with the given name.
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        self.children.get(name)
+    }
+
+    /// Returns the node for a dotted path below this node.
+    pub fn get(&self, path: &str) -> Option<&Node> {
+        path.split('.')
+            .try_fold(self, |node, segment| node.children.get(segment))
+    }
+
+    /// Returns the children in index order if they are the items of a list, i.e. if their names are
+    /// exactly `0`, `1`, ..., as in `list.0` and `list.1`.  Indexes are decimal, without leading
+    /// zeros.
+    pub fn as_list(&self) -> Option<Vec<&Node>> {
+        if self.children.is_empty() {
+            return None;
+        }
+        let mut items = vec![None; self.children.len()];
+        for (name, child) in &self.children {
+            let index = name.parse::<usize>().ok()?;
+            if index >= items.len() || index.to_string() != *name {
+                return None;
+            }
+            items[index] = Some(child);
+        }
+        items.into_iter().collect()
+    }
+
+    /// Returns true if no keys continue past this node.
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Returns the dotted keys below this node which have a value and also have keys under them,
+    /// sorted.
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        self.visit(None, &mut |path, node| {
+            if node.value.is_some() && !node.children.is_empty() {
+                conflicts.push(path.to_string());
+            }
+        });
+        conflicts
+    }
+
+    /// Returns the flat keys below this node and their values.
+    pub fn to_map(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        self.visit(None, &mut |path, node| {
+            if let Some(value) = &node.value {
+                map.insert(path.to_string(), value.clone());
+            }
+        });
+        map
+    }
+
+    // Calls `f` for each node below this one, in key order, with its dotted path.  `path` is the
+    // path of this node, or `None` for the node the walk started at.
+    fn visit<F: FnMut(&str, &Node)>(&self, path: Option<&str>, f: &mut F) {
+        for (segment, child) in &self.children {
+            let child_path = join_key(path, segment);
+            f(&child_path, child);
+            child.visit(Some(&child_path), f);
+        }
+    }
+}
+
+/// Appends a segment to a dotted key.  `prefix` is `None` at the top level, which differs from a
+/// top-level segment with an empty name.
+pub(crate) fn join_key(prefix: Option<&str>, segment: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}.{}", prefix, segment),
+        None => segment.to_string(),
+    }
+}
+
+/// Builds a tree from flat dotted keys.
+///
+/// Returns an error of kind [`PropertiesErrorKind::KeyConflict`] listing the conflicting keys if a
+/// key has a value and also has keys under it.  [`Node::from_map`] keeps such keys instead.
+pub fn to_tree(map: &HashMap<String, String>) -> Result<Node, PropertiesError> {
+    let tree = Node::from_map(map);
+    let conflicts = tree.conflicts();
+    if conflicts.is_empty() {
+        return Ok(tree);
+    }
+    Err(PropertiesError::new(
+        PropertiesErrorKind::KeyConflict,
+        format!(
+            "Keys have values and also have keys under them: {}",
+            conflicts
+                .iter()
+                .map(|key| format!("{:?}", key))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        None,
+        None,
+    ))
+}
+
+/// Turns a tree back into flat dotted keys.  The same as [`Node::to_map`].
+pub fn from_tree(tree: &Node) -> HashMap<String, String> {
+    tree.to_map()
+}
+
+/// A value of a nested format such as JSON, which properties are converted to and from with
+/// [`to_nested`] and [`flatten`].
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+pub(crate) trait Nested: Sized {
+    /// Creates a string.
+    fn string(value: String) -> Self;
+
+    /// Creates a list.
+    fn list(items: Vec<Self>) -> Self;
+
+    /// Creates a map.
+    fn map(entries: Vec<(String, Self)>) -> Self;
+
+    /// Returns the shape of the value, or an error if it can't be flattened.
+    fn view(&self) -> Result<View<'_, Self>, PropertiesError>;
+}
+
+/// The shape of a [`Nested`] value.
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+pub(crate) enum View<'a, T> {
+    /// A value which has no key, such as JSON `null`.
+    Absent,
+    /// A string, number, or other value written as text.
+    Scalar(String),
+    /// A list, whose items are keyed by their indexes.
+    List(&'a [T]),
+    /// A map, with its keys as text.
+    Map(Vec<(String, &'a T)>),
+}
+
+/// Converts a tree to a nested value.  Nodes with values become strings, nodes whose children are
+/// list items become lists, and other nodes become maps.
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+pub(crate) fn to_nested<T: Nested>(node: &Node) -> T {
+    if let Some(value) = node.value() {
+        return T::string(value.to_string());
+    }
+    match node.as_list() {
+        Some(items) => T::list(items.into_iter().map(to_nested).collect()),
+        None => T::map(
+            node.children()
+                .iter()
+                .map(|(name, child)| (name.clone(), to_nested(child)))
+                .collect(),
+        ),
+    }
+}
+
+/// Flattens a nested value into dotted keys, with list items keyed by their indexes.  A scalar at
+/// the top level has no key, so it is dropped.
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+pub(crate) fn flatten<T: Nested>(value: &T) -> Result<HashMap<String, String>, PropertiesError> {
+    let mut map = HashMap::new();
+    flatten_into(value, None, &mut map)?;
+    Ok(map)
+}
+
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+fn flatten_into<T: Nested>(
+    value: &T,
+    key: Option<&str>,
+    map: &mut HashMap<String, String>,
+) -> Result<(), PropertiesError> {
+    match value.view()? {
+        View::Absent => (),
+        View::Scalar(text) => {
+            if let Some(key) = key {
+                map.insert(key.to_string(), text);
+            }
+        }
+        View::List(items) => {
+            for (i, item) in items.iter().enumerate() {
+                flatten_into(item, Some(&join_key(key, &i.to_string())), map)?;
+            }
+        }
+        View::Map(entries) => {
+            for (name, entry) in entries {
+                flatten_into(entry, Some(&join_key(key, &name)), map)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Builds a map from pairs, for the tests of this module and the nested formats.
+#[cfg(test)]
+pub(crate) fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_tree;
+    use super::map;
+    use super::to_tree;
+    use super::Node;
+    use crate::PropertiesErrorKind;
+    use std::collections::HashMap;
+
+    #[test]
+    fn round_trip() {
+        let flat = map(&[
+            ("a.b.c", "1"),
+            ("a.d", "2"),
+            (".x", "3"),
+            ("x..", "4"),
+            ("y.", "5"),
+        ]);
+        let tree = to_tree(&flat).unwrap();
+        assert_eq!(from_tree(&tree), flat);
+        assert_eq!(tree.get("a.b.c").unwrap().value(), Some("1"));
+        assert!(tree.get("a.b.c").unwrap().is_leaf());
+        assert_eq!(tree.get("a.b").unwrap().value(), None);
+        assert_eq!(tree.get(".x").unwrap().value(), Some("3"));
+        assert_eq!(tree.get("x..").unwrap().value(), Some("4"));
+        assert_eq!(tree.child("").unwrap().value(), None);
+        assert_eq!(tree.get("y.").unwrap().value(), Some("5"));
+        assert!(tree.get("a.z").is_none());
+        assert_eq!(
+            tree.get("a").unwrap().to_map(),
+            map(&[("b.c", "1"), ("d", "2")])
+        );
+        assert_eq!(to_tree(&HashMap::new()).unwrap(), Node::new());
+    }
+
+    #[test]
+    fn lists() {
+        let tree = Node::from_map(&map(&[
+            ("l.1", "b"),
+            ("l.0", "a"),
+            ("m.0", "a"),
+            ("m.2", "c"),
+        ]));
+        let items = tree.child("l").unwrap().as_list().unwrap();
+        assert_eq!(
+            items.iter().map(|node| node.value()).collect::<Vec<_>>(),
+            [Some("a"), Some("b")]
+        );
+        assert!(tree.child("m").unwrap().as_list().is_none());
+        assert!(tree.get("l.0").unwrap().as_list().is_none());
+        assert!(Node::from_map(&map(&[("00", "a")])).as_list().is_none());
+        assert!(tree.as_list().is_none());
+    }
+
+    #[test]
+    fn conflicts() {
+        let flat = map(&[("a.b", "1"), ("a.b.c", "2"), ("a", "3"), ("d", "4")]);
+        let err = to_tree(&flat).unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::KeyConflict);
+        assert!(err.to_string().contains("\"a\", \"a.b\""), "{}", err);
+
+        let mut tree = Node::from_map(&flat);
+        assert_eq!(tree.conflicts(), ["a", "a.b"]);
+        assert_eq!(tree.to_map(), flat);
+        assert_eq!(tree.insert("a.b", "5".to_string()), Some("1".to_string()));
+        assert_eq!(tree.get("a.b").unwrap().value(), Some("5"));
+    }
+}
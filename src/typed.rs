@@ -0,0 +1,210 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Properties with typed getters and placeholder expansion.
+//!
+//! [`TypedProperties`] wraps the string map returned by [`read`](crate::read), so the raw values
+//! and their interpretation travel together.  Typed getters expand `${key}` placeholders with the
+//! wrapper's [`Resolver`] before parsing.
+//!
+//! ```
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let input = "base.port=8000\nport=${base.port}\ndebug=TRUE\nname=app\n";
+//! let props = java_properties::read_typed(input.as_bytes())?;
+//! assert_eq!(props.get("port"), Some("${base.port}"));
+//! assert_eq!(props.get_as::<u16>("port")?, Some(8000));
+//! assert_eq!(props.get_bool("debug")?, Some(true));
+//! assert_eq!(props.get_or("workers", 4)?, 4);
+//! assert_eq!(props.as_map().len(), 4);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::interpolation::Resolver;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+
+/// A string map of properties with typed getters.  See the [module documentation](self).
+#[derive(Debug, Default)]
+pub struct TypedProperties {
+    values: HashMap<String, String>,
+    resolver: Resolver,
+}
+
+impl TypedProperties {
+    /// Wraps a map of properties, using a [`Resolver`] with the default settings.
+    pub fn new(values: HashMap<String, String>) -> Self {
+        TypedProperties {
+            values,
+            resolver: Resolver::new(),
+        }
+    }
+
+    /// Returns the raw values.
+    pub fn as_map(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+
+    /// Returns the raw values for modification.
+    pub fn as_map_mut(&mut self) -> &mut HashMap<String, String> {
+        &mut self.values
+    }
+
+    /// Consumes the wrapper, returning the raw values.
+    pub fn into_map(self) -> HashMap<String, String> {
+        self.values
+    }
+
+    /// Returns the resolver used to expand placeholders.
+    pub fn resolver(&self) -> &Resolver {
+        &self.resolver
+    }
+
+    /// Returns the resolver used to expand placeholders, for configuration.
+    pub fn resolver_mut(&mut self) -> &mut Resolver {
+        &mut self.resolver
+    }
+
+    /// Returns the raw value of a key, without expanding placeholders.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Returns the value of a key with its placeholders expanded.
+    pub fn get_resolved(&self, key: &str) -> Result<Option<String>, PropertiesError> {
+        self.resolved(key).map(|value| value.map(Cow::into_owned))
+    }
+
+    fn resolved(&self, key: &str) -> Result<Option<Cow<'_, str>>, PropertiesError> {
+        let value = match self.values.get(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        if !value.contains(['$', '%']) {
+            return Ok(Some(Cow::Borrowed(value)));
+        }
+        self.resolver
+            .resolve_value(value, &self.values)
+            .map(|value| Some(Cow::Owned(value)))
+    }
+
+    /// Parses the value of a key, after expanding placeholders and trimming whitespace.
+    ///
+    /// Returns an error of kind [`PropertiesErrorKind::InvalidValue`] if the value can't be parsed.
+    pub fn get_as<T>(&self, key: &str) -> Result<Option<T>, PropertiesError>
+    where
+        T: FromStr,
+        T::Err: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let value = match self.resolved(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        value.trim().parse().map(Some).map_err(|e: T::Err| {
+            PropertiesError::new(
+                PropertiesErrorKind::InvalidValue,
+                format!("Invalid value for key {:?}: {:?}", key, value),
+                Some(e.into()),
+                None,
+            )
+        })
+    }
+
+    /// Like [`get_as`](Self::get_as), but returns `default` if the key is missing.
+    pub fn get_or<T>(&self, key: &str, default: T) -> Result<T, PropertiesError>
+    where
+        T: FromStr,
+        T::Err: Into<Box<dyn Error + Send + Sync>>,
+    {
+        Ok(self.get_as(key)?.unwrap_or(default))
+    }
+
+    /// Like [`get_as`](Self::get_as), but a missing key is an error of kind
+    /// [`PropertiesErrorKind::KeyNotFound`].
+    pub fn require<T>(&self, key: &str) -> Result<T, PropertiesError>
+    where
+        T: FromStr,
+        T::Err: Into<Box<dyn Error + Send + Sync>>,
+    {
+        self.get_as(key)?.ok_or_else(|| {
+            PropertiesError::new(
+                PropertiesErrorKind::KeyNotFound,
+                format!("Key not found: {:?}", key),
+                None,
+                None,
+            )
+        })
+    }
+
+    /// Returns the value of a key as a boolean.  `true` and `false` are accepted in any case, like
+    /// `Boolean.parseBoolean` in Java, but other values are an error.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, PropertiesError> {
+        let value = match self.resolved(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match value.trim() {
+            v if v.eq_ignore_ascii_case("true") => Ok(Some(true)),
+            v if v.eq_ignore_ascii_case("false") => Ok(Some(false)),
+            _ => Err(PropertiesError::new(
+                PropertiesErrorKind::InvalidValue,
+                format!("Invalid boolean for key {:?}: {:?}", key, value),
+                None,
+                None,
+            )),
+        }
+    }
+
+    /// Returns a copy of the values with every placeholder expanded.  See [`Resolver::resolve`].
+    pub fn resolve_all(&self) -> Result<HashMap<String, String>, PropertiesError> {
+        self.resolver.resolve(&self.values)
+    }
+}
+
+impl From<HashMap<String, String>> for TypedProperties {
+    fn from(values: HashMap<String, String>) -> Self {
+        Self::new(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypedProperties;
+    use crate::PropertiesErrorKind;
+
+    #[test]
+    fn getters() {
+        let input = "n= 42 \nf=1.5\nb=False\nbad=x\nref=${n}\nmissing.ref=${nope}\n";
+        let props = crate::read_typed(input.as_bytes()).unwrap();
+        assert_eq!(props.get("n"), Some("42 "));
+        assert_eq!(props.get_as::<i32>("n").unwrap(), Some(42));
+        assert_eq!(props.get_as::<f64>("f").unwrap(), Some(1.5));
+        assert_eq!(props.get_as::<i32>("none").unwrap(), None);
+        assert_eq!(props.get_bool("b").unwrap(), Some(false));
+        assert_eq!(props.get_resolved("ref").unwrap().as_deref(), Some("42 "));
+        assert_eq!(props.require::<u8>("ref").unwrap(), 42);
+        assert_eq!(props.get_or("none", 7u8).unwrap(), 7);
+
+        let err = props.get_as::<i32>("bad").unwrap_err();
+        assert_eq!(err.kind(), PropertiesErrorKind::InvalidValue);
+        assert!(std::error::Error::source(&err).is_some());
+        assert!(props.get_bool("bad").is_err());
+        assert_eq!(
+            props.require::<i32>("none").unwrap_err().kind(),
+            PropertiesErrorKind::KeyNotFound
+        );
+        assert_eq!(
+            props.get_as::<String>("missing.ref").unwrap_err().kind(),
+            PropertiesErrorKind::UnresolvedPlaceholder
+        );
+        assert!(props.resolve_all().is_err());
+
+        let mut props = TypedProperties::from(props.into_map());
+        props.as_map_mut().remove("missing.ref");
+        props.resolver_mut().set_max_depth(0);
+        assert!(props.get_as::<i32>("ref").is_err());
+        assert_eq!(props.get_as::<i32>("n").unwrap(), Some(42));
+    }
+}
@@ -0,0 +1,190 @@
+// -*-  indent-tabs-mode:nil; tab-width:4;  -*-
+//! Converting properties to and from YAML.
+//!
+//! Keys are nested and flattened with the same rules as in [`json`](crate::json): dotted keys
+//! become mappings, keys continuing with the indexes `0`, `1`, ... become sequences, and values are
+//! always strings.  Going the other way, numbers, booleans, and scalar mapping keys are written as
+//! their YAML text, `null` values are omitted, tags are ignored, and an empty document has no keys.
+//!
+//! This module is only available with the `yaml` feature.
+//!
+//! ```
+//! # fn main() -> std::result::Result<(), java_properties::PropertiesError> {
+//! let map = java_properties::read("name=app\nhosts.0=a\nhosts.1=b\n".as_bytes())?;
+//! let yaml = java_properties::yaml::to_yaml(&map)?;
+//! assert_eq!(yaml, "hosts:\n- a\n- b\nname: app\n");
+//! assert_eq!(java_properties::yaml::from_yaml(&yaml)?, map);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::tree;
+use crate::tree::Nested;
+use crate::tree::View;
+use crate::PropertiesError;
+use crate::PropertiesErrorKind;
+use serde_yaml_ng::Value;
+use std::collections::HashMap;
+
+/// Converts properties to a YAML document, which is a mapping unless the keys are list indexes.
+///
+/// Returns an error of kind [`PropertiesErrorKind::KeyConflict`] if a key has a value and also has
+/// keys under it, since YAML can't represent both.  See [`tree::to_tree`].
+pub fn to_yaml(map: &HashMap<String, String>) -> Result<String, PropertiesError> {
+    let value: Value = tree::to_nested(&tree::to_tree(map)?);
+    serde_yaml_ng::to_string(&value).map_err(|e| {
+        PropertiesError::new(
+            PropertiesErrorKind::Serialize,
+            "Unable to write the properties as YAML",
+            Some(Box::new(e)),
+            None,
+        )
+    })
+}
+
+/// Parses a YAML document and flattens it into properties with dotted keys.
+///
+/// Returns an error of kind [`PropertiesErrorKind::Syntax`] if the document isn't valid YAML,
+/// [`PropertiesErrorKind::InvalidValue`] if it is a single scalar, which has no key, or
+/// [`PropertiesErrorKind::InvalidKey`] if a mapping key is a sequence or mapping.
+pub fn from_yaml(input: &str) -> Result<HashMap<String, String>, PropertiesError> {
+    let value = serde_yaml_ng::from_str::<Value>(input).map_err(|e| {
+        PropertiesError::new(
+            PropertiesErrorKind::Syntax,
+            "Unable to parse YAML",
+            Some(Box::new(e)),
+            None,
+        )
+    })?;
+    match value {
+        Value::Null | Value::Sequence(_) | Value::Mapping(_) => (),
+        _ => {
+            return Err(PropertiesError::new(
+                PropertiesErrorKind::InvalidValue,
+                "Only a YAML mapping or sequence can be converted to properties",
+                None,
+                None,
+            ))
+        }
+    }
+    tree::flatten(&value)
+}
+
+// Returns the text of a scalar, or `None` for sequences and mappings.
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => Some("null".to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::String(s) => Some(s.clone()),
+        Value::Tagged(tagged) => scalar_text(&tagged.value),
+        Value::Sequence(_) | Value::Mapping(_) => None,
+    }
+}
+
+impl Nested for Value {
+    fn string(value: String) -> Self {
+        Value::String(value)
+    }
+
+    fn list(items: Vec<Self>) -> Self {
+        Value::Sequence(items)
+    }
+
+    fn map(entries: Vec<(String, Self)>) -> Self {
+        Value::Mapping(
+            entries
+                .into_iter()
+                .map(|(name, value)| (Value::String(name), value))
+                .collect(),
+        )
+    }
+
+    fn view(&self) -> Result<View<'_, Self>, PropertiesError> {
+        Ok(match self {
+            Value::Null => View::Absent,
+            Value::Tagged(tagged) => return tagged.value.view(),
+            Value::Sequence(items) => View::List(items),
+            Value::Mapping(fields) => View::Map(
+                fields
+                    .iter()
+                    .map(|(name, field)| {
+                        let name = scalar_text(name).ok_or_else(|| {
+                            PropertiesError::new(
+                                PropertiesErrorKind::InvalidKey,
+                                format!("YAML mapping key can't be a property key: {:?}", name),
+                                None,
+                                None,
+                            )
+                        })?;
+                        Ok((name, field))
+                    })
+                    .collect::<Result<_, PropertiesError>>()?,
+            ),
+            scalar => View::Scalar(scalar_text(scalar).unwrap_or_default()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_yaml;
+    use super::to_yaml;
+    use crate::tree::map;
+    use crate::PropertiesErrorKind;
+
+    #[test]
+    fn round_trip() {
+        let flat = map(&[
+            ("name", "app"),
+            ("port", "80"),
+            ("servers.0.host", "a"),
+            ("servers.1.host", "b"),
+            ("empty", ""),
+        ]);
+        let yaml = to_yaml(&flat).unwrap();
+        assert_eq!(
+            yaml,
+            "empty: ''\nname: app\nport: '80'\nservers:\n- host: a\n- host: b\n"
+        );
+        assert_eq!(from_yaml(&yaml).unwrap(), flat);
+
+        let list = map(&[("0", "a"), ("1", "b")]);
+        assert_eq!(to_yaml(&list).unwrap(), "- a\n- b\n");
+        assert_eq!(from_yaml("- a\n- b\n").unwrap(), list);
+    }
+
+    #[test]
+    fn from_yaml_values() {
+        let input = "n: 1.5\nb: true\nnull: ~\nempty: []\n1: !tag x\nt:\n  u: [1, {w: x}]\n";
+        assert_eq!(
+            from_yaml(input).unwrap(),
+            map(&[
+                ("n", "1.5"),
+                ("b", "true"),
+                ("1", "x"),
+                ("t.u.0", "1"),
+                ("t.u.1.w", "x"),
+            ])
+        );
+        assert!(from_yaml("").unwrap().is_empty());
+        assert_eq!(
+            from_yaml("a: [").unwrap_err().kind(),
+            PropertiesErrorKind::Syntax
+        );
+        assert_eq!(
+            from_yaml("x").unwrap_err().kind(),
+            PropertiesErrorKind::InvalidValue
+        );
+        assert_eq!(
+            from_yaml("[a]: 1").unwrap_err().kind(),
+            PropertiesErrorKind::InvalidKey
+        );
+        assert_eq!(
+            to_yaml(&map(&[("a", "1"), ("a.b", "2")]))
+                .unwrap_err()
+                .kind(),
+            PropertiesErrorKind::KeyConflict
+        );
+    }
+}